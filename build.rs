@@ -0,0 +1,7 @@
+fn main() {
+    // Embed version, git and build metadata for the /version endpoint
+    built::write_built_file().expect("failed to gather build-time information");
+
+    // Commit for builds without a .git directory (e.g. Docker)
+    println!("cargo:rerun-if-env-changed=MDWN_GIT_COMMIT");
+}
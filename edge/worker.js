@@ -0,0 +1,34 @@
+// Cloudflare Workers entry point for the edge build of mdwn.io
+//
+// Build the wasm package into edge/pkg first:
+//   wasm-pack build --target web --out-dir edge/pkg -- --no-default-features --features edge
+
+import init, { proxy } from "./pkg/mdwnio.js";
+import wasm from "./pkg/mdwnio_bg.wasm";
+
+const ready = init(wasm);
+
+export default {
+  async fetch(request, env) {
+    await ready;
+
+    const url = new URL(request.url);
+    const target = decodeURIComponent(url.pathname.slice(1)) + url.search;
+    if (!target) {
+      return new Response("mdwn.io - prefix any URL with this host to get markdown\n", {
+        headers: { "Content-Type": "text/plain; charset=utf-8" },
+      });
+    }
+
+    const result = await proxy(target, env.MDWN_CACHE, Number(env.CACHE_TTL ?? 3600));
+    const headers = { "Content-Type": "text/markdown; charset=utf-8" };
+    if (result.status !== 200) {
+      headers["Content-Type"] = "text/plain; charset=utf-8";
+    }
+    if (result.source) {
+      headers["X-Mdwn-Source"] = result.source;
+    }
+
+    return new Response(result.body, { status: result.status, headers });
+  },
+};
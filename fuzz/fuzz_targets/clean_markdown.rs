@@ -0,0 +1,18 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use mdwnio::convert::{clean_markdown, normalize_text};
+use mdwnio::parse::parse_markdown_metadata;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(markdown) = std::str::from_utf8(data) else {
+        return;
+    };
+
+    let cleaned = clean_markdown(markdown);
+    // Cleaning must be idempotent
+    assert_eq!(clean_markdown(&cleaned), cleaned);
+
+    let _ = normalize_text(markdown);
+    let _ = parse_markdown_metadata(markdown);
+});
@@ -0,0 +1,17 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use mdwnio::cache::normalize_cache_key;
+use mdwnio::parse::parse_accept_language;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(input) = std::str::from_utf8(data) else {
+        return;
+    };
+
+    let key = normalize_cache_key(input);
+    // Normalization must be idempotent
+    assert_eq!(normalize_cache_key(&key), key);
+
+    let _ = parse_accept_language(input);
+});
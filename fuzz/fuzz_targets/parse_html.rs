@@ -0,0 +1,16 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use mdwnio::parse::{parse_html_for_markdown_link, parse_html_metadata};
+use url::Url;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(html) = std::str::from_utf8(data) else {
+        return;
+    };
+    let base = Url::parse("https://example.com/page").unwrap();
+    let langs = vec!["en".to_string(), "fr-ca".to_string()];
+
+    let _ = parse_html_for_markdown_link(html, &base, &langs);
+    let _ = parse_html_metadata(html);
+});
@@ -0,0 +1,16 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use mdwnio::parse::{parse_json_for_markdown, parse_json_metadata, parse_json_next_link};
+use url::Url;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(json) = std::str::from_utf8(data) else {
+        return;
+    };
+    let base = Url::parse("https://example.com/api/").unwrap();
+
+    let _ = parse_json_for_markdown(json, &base);
+    let _ = parse_json_next_link(json, &base);
+    let _ = parse_json_metadata(json);
+});
@@ -0,0 +1,124 @@
+//! Structured access log
+//!
+//! With `ACCESS_LOG=true` every request is written to stdout as one JSON
+//! line, for log pipelines that can't parse the trace output: the route, the
+//! response's status, size and latency, and for proxied pages the target URL,
+//! what upstream answered and whether the cache served it. The trace output
+//! goes to stderr, so the two never interleave.
+
+use axum::{body::HttpBody, extract::Request, middleware::Next, response::Response};
+use chrono::{SecondsFormat, Utc};
+use mdwnio::cache::CachedContent;
+use mdwnio::error::Result;
+use serde::Serialize;
+use std::time::Instant;
+use url::Url;
+
+/// What a proxy request was for and how it was served, attached to its
+/// response for the access log
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Upstream {
+    /// Target URL, if it was valid
+    pub url: Option<String>,
+    /// Status upstream answered with, `None` if nothing was fetched
+    pub upstream_status: Option<u16>,
+    /// Content category of the upstream response, or `api`
+    pub category: Option<&'static str>,
+    /// `native`, `converted` or `api`
+    pub source: Option<&'static str>,
+    pub cache_hit: Option<bool>,
+}
+
+impl Upstream {
+    /// Details of a proxy request's outcome
+    pub fn new(url: Option<&Url>, result: &Result<(CachedContent, bool)>) -> Self {
+        let mut upstream = Upstream {
+            url: url.map(Url::to_string),
+            ..Self::default()
+        };
+        if let Ok((content, cache_hit)) = result {
+            upstream.upstream_status = content.fetch.map(|fetch| fetch.status);
+            upstream.category = content.fetch.and_then(|fetch| fetch.category);
+            upstream.source = Some(content.source.as_header_value());
+            upstream.cache_hit = Some(*cache_hit);
+        }
+        upstream
+    }
+}
+
+/// One line of the access log
+#[derive(Serialize)]
+struct Entry<'a> {
+    timestamp: String,
+    method: &'a str,
+    path: &'a str,
+    status: u16,
+    /// Size of the response body, if known before it's sent
+    bytes: Option<u64>,
+    latency_ms: f64,
+    #[serde(flatten)]
+    upstream: Option<&'a Upstream>,
+}
+
+/// Middleware writing each request's line once its response is ready
+pub async fn access_log(request: Request, next: Next) -> Response {
+    let start = Instant::now();
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+    let response = next.run(request).await;
+
+    let entry = Entry {
+        timestamp: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+        method: method.as_str(),
+        path: &path,
+        status: response.status().as_u16(),
+        bytes: response.body().size_hint().exact(),
+        latency_ms: start.elapsed().as_secs_f64() * 1000.0,
+        upstream: response.extensions().get::<Upstream>(),
+    };
+    println!(
+        "{}",
+        serde_json::to_string(&entry).expect("entries serialize")
+    );
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entry_is_one_json_line() {
+        let upstream = Upstream {
+            url: Some("https://example.com/a\nb".to_string()),
+            upstream_status: Some(200),
+            category: Some("html"),
+            source: Some("converted"),
+            cache_hit: Some(false),
+        };
+        let entry = Entry {
+            timestamp: "2024-03-01T09:30:00.000Z".to_string(),
+            method: "GET",
+            path: "/example.com/a",
+            status: 200,
+            bytes: Some(42),
+            latency_ms: 1.5,
+            upstream: Some(&upstream),
+        };
+        let line = serde_json::to_string(&entry).unwrap();
+        assert!(!line.contains('\n'));
+        let json: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(json["status"], 200);
+        assert_eq!(json["upstream_status"], 200);
+        assert_eq!(json["category"], "html");
+        assert_eq!(json["cache_hit"], false);
+
+        let entry = Entry {
+            upstream: None,
+            ..entry
+        };
+        let json: serde_json::Value = serde_json::to_value(&entry).unwrap();
+        assert_eq!(json["path"], "/example.com/a");
+        assert!(json.get("cache_hit").is_none());
+    }
+}
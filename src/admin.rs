@@ -0,0 +1,236 @@
+//! Operator endpoints under `/admin`, `/debug`, `/prefetch` and `/graph`,
+//! enabled by setting `ADMIN_TOKEN`
+
+use crate::flags::Flag;
+use crate::{cdn, debug, graph, prefetch, AppState};
+use axum::{
+    extract::{Path, Query, Request, State},
+    http::header,
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{get, post, put},
+    Json, Router,
+};
+use chrono::NaiveDate;
+use mdwnio::error::{MdwnError, Result};
+use mdwnio::secrets::Secret;
+use serde::{Deserialize, Serialize};
+
+/// Admin routes, all behind bearer token authentication
+pub fn routes(state: AppState) -> Router<AppState> {
+    Router::new()
+        .route("/admin/config", get(config_handler))
+        .route("/admin/stats/domains", get(domain_stats_handler))
+        .route("/admin/cache/stats", get(cache_stats_handler))
+        .route("/admin/reports", get(reports_handler))
+        .route("/admin/reports/{date}", get(report_handler))
+        .route("/admin/purge/{*url}", post(purge_handler))
+        .route("/admin/shadow", get(shadow_handler))
+        .route("/admin/flags", get(flags_handler))
+        .route(
+            "/admin/flags/{name}",
+            put(set_flag_handler).delete(reset_flag_handler),
+        )
+        .route("/admin/digests/deliveries", get(deliveries_handler))
+        .route("/debug/{*url}", get(debug::debug_handler))
+        .route("/prefetch", post(prefetch::prefetch_handler))
+        .route("/graph/{id}", get(graph::graph_handler))
+        .route_layer(middleware::from_fn_with_state(state, require_admin))
+}
+
+/// Reject requests without the configured admin bearer token
+///
+/// Without an `ADMIN_TOKEN` the admin routes behave as if they did not exist.
+pub(crate) async fn require_admin(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(expected) = state.config.admin_token.as_ref().map(Secret::expose) else {
+        return MdwnError::NotFound.into_response();
+    };
+
+    let provided = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+
+    match provided {
+        Some(token) if constant_time_eq(token.as_bytes(), expected.as_bytes()) => {
+            next.run(request).await
+        }
+        _ => MdwnError::Unauthorized("Missing or invalid admin token".to_string()).into_response(),
+    }
+}
+
+/// Compare secrets without short-circuiting on the first differing byte
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Effective runtime configuration, with secrets redacted
+async fn config_handler(State(state): State<AppState>) -> Response {
+    Json(state.config.as_ref()).into_response()
+}
+
+/// Request counts, cache hit rates, output sizes and error rates per upstream domain
+async fn domain_stats_handler(State(state): State<AppState>) -> Response {
+    Json(state.stats.summaries()).into_response()
+}
+
+/// Entry count, hit ratio, evictions and memory estimate of the markdown cache
+async fn cache_stats_handler(State(state): State<AppState>) -> Response {
+    Json(state.cache.stats().await).into_response()
+}
+
+/// How the alternative extraction engine compares on sampled pages
+async fn shadow_handler(State(state): State<AppState>) -> Response {
+    Json(state.shadow.summary(state.config.shadow_sample_rate)).into_response()
+}
+
+/// Every feature flag's current rule
+async fn flags_handler(State(state): State<AppState>) -> Response {
+    Json(state.flags.states()).into_response()
+}
+
+/// Change a flag's rule until restart; the body is a rule in `FLAG_<NAME>` syntax
+async fn set_flag_handler(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    body: String,
+) -> Result<Response> {
+    let flag: Flag = name.parse().map_err(MdwnError::ParseError)?;
+    let rule = body
+        .parse()
+        .map_err(|e| MdwnError::ParseError(format!("Invalid rule for {}: {}", name, e)))?;
+    state.flags.set(flag, rule);
+    tracing::info!("Flag {} set to {}", name, state.flags.state(flag).rule);
+    Ok(Json(state.flags.state(flag)).into_response())
+}
+
+/// Put a flag back to its configured rule
+async fn reset_flag_handler(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<Response> {
+    let flag: Flag = name.parse().map_err(MdwnError::ParseError)?;
+    state.flags.reset(flag);
+    tracing::info!("Flag {} reset to {}", name, state.flags.state(flag).rule);
+    Ok(Json(state.flags.state(flag)).into_response())
+}
+
+/// Options for listing digest webhook deliveries
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct DeliveryOptions {
+    /// Only deliveries that carried a change to this page
+    url: Option<String>,
+}
+
+/// Recent digest webhook deliveries and their tries, newest first
+async fn deliveries_handler(
+    State(state): State<AppState>,
+    Query(options): Query<DeliveryOptions>,
+) -> Result<Response> {
+    let digests = state.digests.as_ref().ok_or(MdwnError::NotFound)?;
+    Ok(Json(digests.deliveries(options.url.as_deref())).into_response())
+}
+
+/// Dates with a written usage report, newest first
+async fn reports_handler(State(state): State<AppState>) -> Result<Response> {
+    let usage = state.usage.as_ref().ok_or(MdwnError::NotFound)?;
+    Ok(Json(usage.dates().await).into_response())
+}
+
+/// Options for fetching a usage report
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct ReportOptions {
+    /// `json` (default) or `csv`
+    format: Option<String>,
+}
+
+/// One day's usage report, as JSON or CSV
+async fn report_handler(
+    State(state): State<AppState>,
+    Path(date): Path<String>,
+    Query(options): Query<ReportOptions>,
+) -> Result<Response> {
+    let usage = state.usage.as_ref().ok_or(MdwnError::NotFound)?;
+    let date: NaiveDate = date
+        .parse()
+        .map_err(|_| MdwnError::InvalidUrl("Report date must be YYYY-MM-DD".to_string()))?;
+
+    let (extension, content_type) = match options.format.as_deref() {
+        None | Some("json") => ("json", "application/json"),
+        Some("csv") => ("csv", "text/csv; charset=utf-8"),
+        Some(other) => {
+            return Err(MdwnError::InvalidUrl(format!(
+                "Unknown report format '{}', use json or csv",
+                other
+            )))
+        }
+    };
+
+    // Include everything recorded so far today
+    if date == chrono::Utc::now().date_naive() {
+        usage
+            .flush()
+            .await
+            .map_err(|e| MdwnError::Internal(format!("Failed to write usage report: {}", e)))?;
+    }
+
+    let body = usage
+        .read(date, extension)
+        .await
+        .ok_or(MdwnError::NotFound)?;
+    Ok(([(header::CONTENT_TYPE, content_type)], body).into_response())
+}
+
+/// Result of purging one URL
+#[derive(Serialize)]
+struct PurgeResult {
+    url: String,
+    /// Cached variants dropped locally
+    cache_entries: usize,
+    /// Surrogate key sent to the CDN purge hook, if one is configured
+    cdn_purged: Option<String>,
+}
+
+/// Drop every cached variant of a URL and purge it from the CDN
+async fn purge_handler(
+    State(state): State<AppState>,
+    Path(url_path): Path<String>,
+) -> Result<Response> {
+    let url = state.fetcher.parse_url(&url_path)?;
+    let cache_entries = state.cache.invalidate(url.as_str()).await;
+
+    let cdn_purged = match &state.purger {
+        Some(purger) => {
+            let key = cdn::url_key(&url);
+            purger.purge(std::slice::from_ref(&key)).await?;
+            Some(key)
+        }
+        None => None,
+    };
+
+    Ok(Json(PurgeResult {
+        url: url.to_string(),
+        cache_entries,
+        cdn_purged,
+    })
+    .into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq(b"secret-token", b"secret-token"));
+        assert!(!constant_time_eq(b"secret-token", b"secret-tokeN"));
+        assert!(!constant_time_eq(b"secret", b"secret-token"));
+    }
+}
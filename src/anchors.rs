@@ -0,0 +1,180 @@
+//! Heading anchors of the original page
+//!
+//! Links into a section (`https://example.com/guide#install`) point at an
+//! `id` that conversion throws away. The ids of a page's headings are kept
+//! next to its markdown and, on request, put back: as `{#install}` after the
+//! heading (the attribute syntax of Pandoc, kramdown and most static site
+//! generators), or as a list at the end mapping each anchor to its heading.
+
+use scraper::{ElementRef, Html, Selector};
+use serde::{Deserialize, Serialize};
+
+/// How to show the anchors of headings
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AnchorStyle {
+    /// `## Install {#install}`
+    Inline,
+    /// A closing section listing `#install` → `Install`
+    Map,
+}
+
+/// A heading's `id` in the original page
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct HeadingAnchor {
+    pub id: String,
+    /// Text of the heading, whitespace collapsed
+    pub text: String,
+}
+
+/// Ids of a page's headings, in document order
+///
+/// A heading's own `id` wins; failing that, the first element inside it with
+/// an `id`, or a `<a name>`, as many generators put the anchor there.
+pub fn heading_anchors(html: &str) -> Vec<HeadingAnchor> {
+    let document = Html::parse_document(html);
+    let headings = Selector::parse("h1, h2, h3, h4, h5, h6").expect("valid CSS selector");
+    let inner = Selector::parse("[id], a[name]").expect("valid CSS selector");
+    document
+        .select(&headings)
+        .filter_map(|heading| {
+            let id = anchor_id(&heading)
+                .or_else(|| heading.select(&inner).find_map(|el| anchor_id(&el)))?;
+            let text = heading.text().collect::<String>();
+            let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+            (!text.is_empty()).then_some(HeadingAnchor { id, text })
+        })
+        .collect()
+}
+
+fn anchor_id(element: &ElementRef) -> Option<String> {
+    let value = element.value();
+    value
+        .attr("id")
+        .or_else(|| value.attr("name").filter(|_| value.name() == "a"))
+        .map(str::trim)
+        .filter(|id| !id.is_empty() && !id.contains(char::is_whitespace))
+        .map(str::to_string)
+}
+
+/// Letters and digits of heading text, lowercased, so headings match whatever
+/// formatting the page or its markdown puts around the words
+fn match_key(text: &str) -> String {
+    text.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// Text of an ATX heading line, if it is one
+fn heading_text(line: &str) -> Option<&str> {
+    let rest = line.trim_start_matches('#');
+    let level = line.len() - rest.len();
+    ((1..=6).contains(&level) && rest.starts_with(' ')).then(|| rest.trim())
+}
+
+fn markdown_key(text: &str) -> String {
+    // `[Install](https://example.com/#install)` reads as "Install"
+    let mut plain = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("](") {
+        plain.push_str(&rest[..start]);
+        rest = match rest[start..].find(')') {
+            Some(end) => &rest[start + end + 1..],
+            None => "",
+        };
+    }
+    plain.push_str(rest);
+    match_key(&plain)
+}
+
+/// For each heading line of `markdown` outside code blocks, the anchor of
+/// the page heading it came from; page headings are used once each, in order
+fn matched_anchors<'a>(
+    markdown: &str,
+    anchors: &'a [HeadingAnchor],
+) -> Vec<Option<&'a HeadingAnchor>> {
+    let mut used = vec![false; anchors.len()];
+    let mut in_fence = false;
+    markdown
+        .lines()
+        .map(|line| {
+            if line.trim_start().starts_with("```") {
+                in_fence = !in_fence;
+            }
+            let text = heading_text(line).filter(|_| !in_fence)?;
+            let key = markdown_key(text);
+            let index =
+                (0..anchors.len()).find(|&i| !used[i] && match_key(&anchors[i].text) == key)?;
+            used[index] = true;
+            Some(&anchors[index])
+        })
+        .collect()
+}
+
+/// Put the page's heading anchors back into its markdown
+pub fn with_anchors(markdown: &str, anchors: &[HeadingAnchor], style: AnchorStyle) -> String {
+    let matched = matched_anchors(markdown, anchors);
+    match style {
+        AnchorStyle::Inline => {
+            let mut result = String::with_capacity(markdown.len());
+            for (line, anchor) in markdown.lines().zip(matched) {
+                result.push_str(line);
+                if let Some(anchor) = anchor {
+                    result.push_str(&format!(" {{#{}}}", anchor.id));
+                }
+                result.push('\n');
+            }
+            result
+        }
+        AnchorStyle::Map => {
+            let entries: Vec<_> = matched
+                .into_iter()
+                .flatten()
+                .map(|anchor| format!("- `#{}`: {}\n", anchor.id, anchor.text))
+                .collect();
+            if entries.is_empty() {
+                return markdown.to_string();
+            }
+            format!("{}\n## Anchors\n\n{}", markdown, entries.concat())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HTML: &str = r#"<html><body>
+        <h1 id="guide">Guide</h1>
+        <h2 id="install">Install   the <code>cli</code></h2>
+        <h2><a name="usage"></a>Usage</h2>
+        <h2>No anchor</h2>
+        <h3 id="faq">FAQ</h3>
+        <h3 id="faq-2">FAQ</h3>
+    </body></html>"#;
+
+    #[test]
+    fn test_heading_anchors() {
+        let ids: Vec<_> = heading_anchors(HTML).into_iter().map(|a| a.id).collect();
+        assert_eq!(ids, ["guide", "install", "usage", "faq", "faq-2"]);
+        assert_eq!(heading_anchors(HTML)[1].text, "Install the cli");
+    }
+
+    #[test]
+    fn test_with_anchors() {
+        let anchors = heading_anchors(HTML);
+        let markdown = "# Guide\n\n## Install the `cli`\n\n```\n## Usage\n```\n\n\
+                        ## [Usage](https://example.com/#usage)\n\n### FAQ\n\n### FAQ\n";
+        assert_eq!(
+            with_anchors(markdown, &anchors, AnchorStyle::Inline),
+            "# Guide {#guide}\n\n## Install the `cli` {#install}\n\n```\n## Usage\n```\n\n\
+             ## [Usage](https://example.com/#usage) {#usage}\n\n### FAQ {#faq}\n\n\
+             ### FAQ {#faq-2}\n"
+        );
+
+        let map = with_anchors("## Usage\n\nText\n", &anchors, AnchorStyle::Map);
+        assert_eq!(map, "## Usage\n\nText\n\n## Anchors\n\n- `#usage`: Usage\n");
+        assert_eq!(with_anchors("Text\n", &anchors, AnchorStyle::Map), "Text\n");
+    }
+}
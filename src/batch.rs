@@ -0,0 +1,123 @@
+//! Fetching many URLs in one request
+//!
+//! `POST /batch` converts a list of URLs like the proxy route and returns the
+//! results as JSON, in request order. Mirrors and URL variants often resolve
+//! to the same document, so each distinct body is sent once: later results
+//! with the same content hash carry only the hash. Pages on one site are
+//! spaced by the crawl delay, like the refresh worker's.
+
+use crate::{process_url_throttled, AppState, ProxyOptions};
+use axum::{
+    body::Bytes,
+    extract::{DefaultBodyLimit, State},
+    response::{IntoResponse, Response},
+    routing::post,
+    Json, Router,
+};
+use futures_util::{stream, StreamExt};
+use mdwnio::cache;
+use mdwnio::error::{MdwnError, Result};
+use mdwnio::store::Redirect;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+/// URLs accepted in one batch
+const MAX_BATCH_URLS: usize = 100;
+
+/// URLs of a batch fetched at the same time
+const BATCH_CONCURRENCY: usize = 8;
+
+/// The `/batch` route, under the same limits as `/convert`
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/batch", post(batch_handler))
+        // The body limit layer applies instead of axum's default
+        .layer(DefaultBodyLimit::disable())
+}
+
+/// JSON body for `POST /batch`
+#[derive(Deserialize)]
+struct BatchRequest {
+    urls: Vec<String>,
+}
+
+/// Results in request order
+#[derive(Serialize)]
+struct BatchResponse {
+    results: Vec<BatchResult>,
+}
+
+/// Outcome for one URL of a batch
+#[derive(Debug, PartialEq, Serialize)]
+struct BatchResult {
+    url: String,
+    status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hash: Option<String>,
+    /// Left out when an earlier result has the same hash
+    #[serde(skip_serializing_if = "Option::is_none")]
+    markdown: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    /// Redirects followed to fetch the page
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    redirects: Vec<Redirect>,
+}
+
+/// Convert a JSON list of URLs, sending each distinct document once
+async fn batch_handler(State(state): State<AppState>, body: Bytes) -> Result<Response> {
+    let request: BatchRequest = serde_json::from_slice(&body)
+        .map_err(|e| MdwnError::ParseError(format!("Invalid JSON body: {}", e)))?;
+    if request.urls.len() > MAX_BATCH_URLS {
+        return Err(MdwnError::ParseError(format!(
+            "Batch has {} URLs, at most {} are accepted",
+            request.urls.len(),
+            MAX_BATCH_URLS
+        )));
+    }
+
+    let fetched: Vec<_> = stream::iter(request.urls.clone())
+        .map(|url| {
+            let state = state.clone();
+            async move {
+                let budget = state.fetcher.budget();
+                process_url_throttled(&state, &url, &ProxyOptions::default(), &budget).await
+            }
+        })
+        .buffered(BATCH_CONCURRENCY)
+        .collect()
+        .await;
+
+    let mut seen = HashSet::new();
+    let results = request
+        .urls
+        .into_iter()
+        .zip(fetched)
+        .map(|(url, result)| match result {
+            Ok((content, _)) => {
+                let hash = content
+                    .content_hash
+                    .unwrap_or_else(|| cache::content_hash(&content.markdown));
+                let first = seen.insert(hash.clone());
+                BatchResult {
+                    url,
+                    status: 200,
+                    hash: Some(hash),
+                    markdown: first.then_some(content.markdown),
+                    error: None,
+                    redirects: content.redirects,
+                }
+            }
+            Err(e) => BatchResult {
+                url,
+                status: e.status_code(),
+                hash: None,
+                markdown: None,
+                error: Some(e.to_string()),
+                redirects: Vec::new(),
+            },
+        })
+        .collect();
+
+    Ok(Json(BatchResponse { results }).into_response())
+}
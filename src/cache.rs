@@ -1,44 +1,670 @@
+use crate::convert::{is_near_duplicate, NEAR_DUPLICATE_DISTANCE};
+use crate::images::Image;
+use crate::parse::PageMetadata;
+use crate::store::on_domain;
+use chrono::{DateTime, Utc};
 use moka::future::Cache;
-use std::sync::Arc;
-use std::time::Duration;
+use moka::notification::RemovalCause;
+use moka::Expiry;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use url::Url;
 
-/// Source type indicator for cached content
-#[derive(Clone, Debug, PartialEq)]
-pub enum ContentSource {
-    /// Content was fetched from a native markdown source
-    Native,
-    /// Content was converted from HTML
-    Converted,
+pub use crate::store::{
+    normalize_cache_key, parse_ignored_params, CacheBackend, CacheKeyRules, CachedContent,
+    ContentSource, FetchOutcome, IgnoredParam,
+};
+
+/// How long entries with an `ETag` or `Last-Modified` are kept past their TTL,
+/// so the next request can revalidate them instead of downloading again
+const STALE_RETENTION: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Default bound on the keys and markdown held by the markdown cache
+pub const DEFAULT_CACHE_MAX_BYTES: u64 = 256 * 1024 * 1024; // 256MB
+
+/// Default bound on the keys and markdown held by the version history
+pub const DEFAULT_HISTORY_MAX_BYTES: u64 = 64 * 1024 * 1024; // 64MB
+
+/// Cache wrapper for markdown content
+///
+/// Entries expire `ttl` after they were fetched rather than inserted, so
+/// entries restored from a snapshot keep only their remaining lifetime.
+/// Expired entries the origin can revalidate linger for `STALE_RETENTION`,
+/// served only through `get_stale`. Capacity is weighed in bytes of key and
+/// markdown, so a few huge documents can't crowd out memory the way a flat
+/// entry count would let them. Optionally the last few distinct versions of
+/// each entry are retained too.
+#[derive(Clone)]
+pub struct MarkdownCache {
+    cache: Arc<Cache<String, CachedContent>>,
+    ttl: TtlPolicy,
+    max_bytes: u64,
+    keys: Arc<CacheKeyRules>,
+    history: Option<History>,
+    counters: Arc<Counters>,
+    simhashes: Arc<SimhashIndex>,
 }
 
-impl ContentSource {
-    pub fn as_header_value(&self) -> &'static str {
-        match self {
-            ContentSource::Native => "native",
-            ContentSource::Converted => "converted",
+/// Bands a simhash is split into; fingerprints at most
+/// `NEAR_DUPLICATE_DISTANCE` bits apart agree on at least one whole band
+const SIMHASH_BANDS: u32 = NEAR_DUPLICATE_DISTANCE + 1;
+
+/// Cache keys bucketed by each band of their simhash, so near duplicates
+/// are looked up in a few buckets rather than by scanning every entry
+#[derive(Default)]
+struct SimhashIndex {
+    buckets: Mutex<HashMap<(u32, u64), HashSet<String>>>,
+}
+
+impl SimhashIndex {
+    /// Band number and value of each band of `simhash`
+    fn bands(simhash: u64) -> impl Iterator<Item = (u32, u64)> {
+        (0..SIMHASH_BANDS).map(move |band| {
+            let start = band * 64 / SIMHASH_BANDS;
+            let end = (band + 1) * 64 / SIMHASH_BANDS;
+            (band, simhash >> start & ((1 << (end - start)) - 1))
+        })
+    }
+
+    fn insert(&self, key: &str, simhash: u64) {
+        let mut buckets = self.buckets.lock().expect("simhash lock poisoned");
+        for band in Self::bands(simhash) {
+            buckets.entry(band).or_default().insert(key.to_string());
         }
     }
+
+    fn remove(&self, key: &str, simhash: u64) {
+        let mut buckets = self.buckets.lock().expect("simhash lock poisoned");
+        for band in Self::bands(simhash) {
+            if let Some(keys) = buckets.get_mut(&band) {
+                keys.remove(key);
+                if keys.is_empty() {
+                    buckets.remove(&band);
+                }
+            }
+        }
+    }
+
+    /// Keys sharing at least one band with `simhash`
+    fn candidates(&self, simhash: u64) -> HashSet<String> {
+        let buckets = self.buckets.lock().expect("simhash lock poisoned");
+        Self::bands(simhash)
+            .filter_map(|band| buckets.get(&band))
+            .flatten()
+            .cloned()
+            .collect()
+    }
 }
 
-/// Cached markdown content with metadata
-#[derive(Clone)]
-pub struct CachedContent {
+/// Lookups and removals since startup
+#[derive(Default)]
+struct Counters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+    expirations: AtomicU64,
+}
+
+/// Effectiveness of the markdown cache, for `/admin/cache/stats`
+#[derive(Debug, Serialize)]
+pub struct CacheStats {
+    pub entries: u64,
+    pub hits: u64,
+    pub misses: u64,
+    /// Hits over lookups, `None` before the first lookup
+    pub hit_ratio: Option<f64>,
+    /// Entries dropped to stay under the byte limit
+    pub evictions: u64,
+    /// Entries dropped once expired
+    pub expirations: u64,
+    /// Bytes held by keys and markdown, as weighed against the byte limit
+    pub estimated_bytes: u64,
+}
+
+/// How long an entry stays fresh
+#[derive(Clone, Debug)]
+struct TtlPolicy {
+    /// For content whose origin declared no lifetime
+    default: Duration,
+    /// Floor and ceiling for lifetimes origins declare, so `max-age=0` doesn't
+    /// refetch on every request and a year-long one doesn't pin stale content
+    min: Duration,
+    max: Duration,
+    /// Operator TTLs for domains and their subdomains, taking precedence
+    /// over both of the above
+    hosts: Arc<BTreeMap<String, Duration>>,
+}
+
+impl TtlPolicy {
+    fn ttl(&self, content: &CachedContent) -> Duration {
+        if let Some(ttl) = self.host_ttl(content) {
+            return ttl;
+        }
+        match content.max_age {
+            Some(secs) => Duration::from_secs(secs).clamp(self.min, self.max),
+            None => self.default,
+        }
+    }
+
+    /// TTL of the most specific domain the content's URL is on, if any
+    fn host_ttl(&self, content: &CachedContent) -> Option<Duration> {
+        if self.hosts.is_empty() {
+            return None;
+        }
+        let url = Url::parse(content.url.as_deref()?).ok()?;
+        let host = url.host_str()?;
+        self.hosts
+            .iter()
+            .filter(|(domain, _)| on_domain(host, domain))
+            .max_by_key(|(domain, _)| domain.len())
+            .map(|(_, ttl)| *ttl)
+    }
+
+    /// Time left before the content goes stale
+    fn remaining(&self, content: &CachedContent) -> Duration {
+        remaining_ttl(self.ttl(content), content.fetched_at)
+    }
+
+    /// Time left before the content is dropped, stale or not
+    fn retained(&self, content: &CachedContent) -> Duration {
+        let mut ttl = self.ttl(content);
+        if content.etag.is_some() || content.last_modified.is_some() {
+            ttl += STALE_RETENTION;
+        }
+        remaining_ttl(ttl, content.fetched_at)
+    }
+}
+
+/// One retained version of a document
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Version {
+    pub hash: String,
+    pub fetched_at: DateTime<Utc>,
     pub markdown: String,
-    pub source: ContentSource,
 }
 
-/// Cache wrapper for markdown content
+/// Last `max_versions` distinct versions per cache key, newest first
 #[derive(Clone)]
-pub struct MarkdownCache {
-    cache: Arc<Cache<String, CachedContent>>,
+struct History {
+    versions: Cache<String, Arc<Vec<Version>>>,
+    max_versions: usize,
+}
+
+impl History {
+    /// Record content as the newest version unless it is unchanged
+    async fn record(&self, key: String, content: &CachedContent) {
+        let Some(hash) = &content.content_hash else {
+            return;
+        };
+        let max_versions = self.max_versions;
+
+        self.versions
+            .entry(key)
+            .and_upsert_with(|existing| async move {
+                let mut versions = existing
+                    .map(|entry| entry.into_value().as_ref().clone())
+                    .unwrap_or_default();
+                if versions.first().is_none_or(|latest| &latest.hash != hash) {
+                    versions.insert(
+                        0,
+                        Version {
+                            hash: hash.clone(),
+                            fetched_at: content.fetched_at,
+                            markdown: content.markdown.clone(),
+                        },
+                    );
+                    versions.truncate(max_versions);
+                }
+                Arc::new(versions)
+            })
+            .await;
+    }
+}
+
+/// Drops markdown its TTL (plus any stale retention) after its `fetched_at`
+struct FetchedAtExpiry {
+    ttl: TtlPolicy,
+}
+
+impl Expiry<String, CachedContent> for FetchedAtExpiry {
+    fn expire_after_create(
+        &self,
+        _key: &String,
+        content: &CachedContent,
+        _created_at: Instant,
+    ) -> Option<Duration> {
+        Some(self.ttl.retained(content))
+    }
+
+    fn expire_after_update(
+        &self,
+        _key: &String,
+        content: &CachedContent,
+        _updated_at: Instant,
+        _duration_until_expiry: Option<Duration>,
+    ) -> Option<Duration> {
+        Some(self.ttl.retained(content))
+    }
+}
+
+/// Time left before content fetched at `fetched_at` goes stale
+fn remaining_ttl(ttl: Duration, fetched_at: DateTime<Utc>) -> Duration {
+    let age = (Utc::now() - fetched_at).to_std().unwrap_or_default();
+    ttl.saturating_sub(age)
+}
+
+/// Parse comma-separated `domain=seconds` TTLs; a leading `*.` is allowed
+/// and changes nothing, since subdomains always match
+pub fn parse_host_ttls(s: &str) -> Result<BTreeMap<String, u64>, String> {
+    let mut ttls = BTreeMap::new();
+    for entry in s.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        let (domain, secs) = entry
+            .split_once('=')
+            .ok_or_else(|| format!("'{}' is not domain=seconds", entry))?;
+        let secs = secs
+            .trim()
+            .parse()
+            .map_err(|_| format!("'{}' is not a number of seconds", secs.trim()))?;
+        let domain = domain.trim().trim_start_matches("*.").to_ascii_lowercase();
+        if domain.is_empty() {
+            return Err(format!("'{}' has no domain", entry));
+        }
+        ttls.insert(domain, secs);
+    }
+    Ok(ttls)
+}
+
+/// On-disk form of the markdown cache
+#[derive(Serialize, Deserialize)]
+struct Snapshot {
+    entries: Vec<SnapshotEntry>,
+    #[serde(default)]
+    history: Vec<HistoryEntry>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct HistoryEntry {
+    key: String,
+    versions: Vec<Version>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SnapshotEntry {
+    key: String,
+    content: CachedContent,
 }
 
 impl MarkdownCache {
     /// Create a new cache with the specified TTL
+    pub fn new(ttl_secs: u64) -> Self {
+        let ttl = Duration::from_secs(ttl_secs);
+        Self::with_policy(
+            TtlPolicy {
+                default: ttl,
+                min: ttl,
+                max: ttl,
+                hosts: Arc::default(),
+            },
+            DEFAULT_CACHE_MAX_BYTES,
+            None,
+        )
+    }
+
+    fn with_policy(ttl: TtlPolicy, max_bytes: u64, history: Option<History>) -> Self {
+        let counters = Arc::new(Counters::default());
+        let listener_counters = counters.clone();
+        let simhashes = Arc::new(SimhashIndex::default());
+        let listener_simhashes = simhashes.clone();
+        let cache = Cache::builder()
+            .expire_after(FetchedAtExpiry { ttl: ttl.clone() })
+            .weigher(|key: &String, content: &CachedContent| {
+                u32::try_from(key.len() + content.markdown.len()).unwrap_or(u32::MAX)
+            })
+            .max_capacity(max_bytes)
+            .eviction_listener(move |key, content: CachedContent, cause| {
+                // Replaced entries are reindexed by `insert`
+                if let (Some(simhash), false) = (content.simhash, cause == RemovalCause::Replaced) {
+                    listener_simhashes.remove(&key, simhash);
+                }
+                let counter = match cause {
+                    RemovalCause::Size => &listener_counters.evictions,
+                    RemovalCause::Expired => &listener_counters.expirations,
+                    RemovalCause::Explicit | RemovalCause::Replaced => return,
+                };
+                counter.fetch_add(1, Ordering::Relaxed);
+            })
+            .build();
+
+        Self {
+            cache: Arc::new(cache),
+            ttl,
+            max_bytes,
+            keys: Arc::default(),
+            history,
+            counters,
+            simhashes,
+        }
+    }
+
+    /// Entry count, hit ratio, removals and weighed size
+    pub async fn stats(&self) -> CacheStats {
+        // Apply pending inserts and removals so the counts are current
+        self.cache.run_pending_tasks().await;
+
+        let hits = self.counters.hits.load(Ordering::Relaxed);
+        let misses = self.counters.misses.load(Ordering::Relaxed);
+        let lookups = hits + misses;
+        CacheStats {
+            entries: self.cache.entry_count(),
+            hits,
+            misses,
+            hit_ratio: (lookups > 0).then(|| hits as f64 / lookups as f64),
+            evictions: self.counters.evictions.load(Ordering::Relaxed),
+            expirations: self.counters.expirations.load(Ordering::Relaxed),
+            estimated_bytes: self.cache.weighted_size(),
+        }
+    }
+
+    /// Let the origin's declared lifetime set an entry's TTL, clamped to
+    /// `min_secs..=max_secs`; by default every entry gets the same TTL
+    pub fn with_ttl_bounds(self, min_secs: u64, max_secs: u64) -> Self {
+        let ttl = TtlPolicy {
+            min: Duration::from_secs(min_secs),
+            max: Duration::from_secs(max_secs.max(min_secs)),
+            ..self.ttl.clone()
+        };
+        Self {
+            keys: self.keys,
+            ..Self::with_policy(ttl, self.max_bytes, self.history)
+        }
+    }
+
+    /// Bound the keys and markdown held to `max_bytes` instead of the default
+    pub fn with_max_bytes(self, max_bytes: u64) -> Self {
+        Self {
+            keys: self.keys,
+            ..Self::with_policy(self.ttl, max_bytes, self.history)
+        }
+    }
+
+    /// Give pages on these domains (and their subdomains) their own TTL in
+    /// seconds, whatever their origin declares; the most specific domain wins
+    pub fn with_host_ttls(self, hosts: &BTreeMap<String, u64>) -> Self {
+        let ttl = TtlPolicy {
+            hosts: Arc::new(
+                hosts
+                    .iter()
+                    .map(|(domain, secs)| (domain.clone(), Duration::from_secs(*secs)))
+                    .collect(),
+            ),
+            ..self.ttl.clone()
+        };
+        Self {
+            keys: self.keys,
+            ..Self::with_policy(ttl, self.max_bytes, self.history)
+        }
+    }
+
+    /// Normalize keys with `rules` on top of the default normalization
+    pub fn with_key_rules(mut self, rules: CacheKeyRules) -> Self {
+        self.keys = Arc::new(rules);
+        self
+    }
+
+    /// Also retain the last `max_versions` distinct versions of each entry,
+    /// weighed like the page cache and bounded to `max_bytes` in total
+    pub fn with_history(mut self, max_versions: usize, max_bytes: u64) -> Self {
+        self.history = (max_versions > 0).then(|| History {
+            versions: Cache::builder()
+                .weigher(|key: &String, versions: &Arc<Vec<Version>>| {
+                    let markdown: usize = versions.iter().map(|v| v.markdown.len()).sum();
+                    u32::try_from(key.len() + markdown).unwrap_or(u32::MAX)
+                })
+                .max_capacity(max_bytes)
+                .build(),
+            max_versions,
+        });
+        self
+    }
+
+    /// Key a URL variant is cached under
+    pub fn key(&self, url: &str, variant: &str) -> String {
+        self.keys.variant_key(url, variant)
+    }
+
+    /// How long `content` stays fresh after its `fetched_at`, under this
+    /// cache's TTLs
+    pub fn ttl(&self, content: &CachedContent) -> Duration {
+        self.ttl.ttl(content)
+    }
+
+    /// Retained versions of a URL, newest first, or `None` if history is disabled
+    pub async fn history(&self, url: &str, variant: &str) -> Option<Vec<Version>> {
+        let history = self.history.as_ref()?;
+        let versions = history
+            .versions
+            .get(&self.keys.variant_key(url, variant))
+            .await;
+        Some(versions.map(|v| v.as_ref().clone()).unwrap_or_default())
+    }
+
+    /// An expired entry kept for revalidation, with its `ETag` or `Last-Modified`
+    pub async fn get_stale(&self, url: &str, variant: &str) -> Option<CachedContent> {
+        self.cache
+            .get(&self.keys.variant_key(url, variant))
+            .await
+            .filter(|content| self.ttl.remaining(content).is_zero())
+    }
+
+    /// Drop every variant of a URL, returning how many entries were removed
+    pub async fn invalidate(&self, url: &str) -> usize {
+        let key = self.keys.key(url);
+        let variant_prefix = format!("{} ", key);
+        let keys: Vec<_> = self
+            .cache
+            .iter()
+            .filter(|(k, _)| **k == key || k.starts_with(&variant_prefix))
+            .map(|(k, _)| k)
+            .collect();
+        for k in &keys {
+            self.cache.invalidate(k.as_ref()).await;
+        }
+        keys.len()
+    }
+
+    /// Content hash of another URL's cached content with a fingerprint near
+    /// `simhash`; entries fetched with credentials are never matched
+    pub async fn find_near_duplicate(&self, url: &str, simhash: u64) -> Option<String> {
+        let key = self.keys.key(url);
+        let variant_prefix = format!("{} ", key);
+        for candidate in self.simhashes.candidates(simhash) {
+            if candidate == key || candidate.starts_with(&variant_prefix) {
+                continue;
+            }
+            // The index can briefly lag behind replaced entries
+            let Some(content) = self.cache.get(&candidate).await else {
+                continue;
+            };
+            let near = content
+                .simhash
+                .is_some_and(|h| is_near_duplicate(h, simhash));
+            if near && !content.private && content.content_hash.is_some() {
+                return content.content_hash;
+            }
+        }
+        None
+    }
+
+    /// Store an entry, keeping the simhash index in step
+    async fn insert(&self, key: String, content: CachedContent) {
+        if let Some(previous) = self.cache.get(&key).await {
+            if let Some(simhash) = previous.simhash.filter(|h| Some(*h) != content.simhash) {
+                self.simhashes.remove(&key, simhash);
+            }
+        }
+        if let Some(simhash) = content.simhash {
+            self.simhashes.insert(&key, simhash);
+        }
+        self.cache.insert(key, content).await;
+    }
+
+    /// Write every live entry to `path`, returning how many were saved
+    ///
+    /// The file is written next to `path` and renamed into place, so a crash
+    /// mid-write never leaves a truncated snapshot behind.
+    pub async fn save_snapshot(&self, path: &Path) -> io::Result<usize> {
+        let snapshot = Snapshot {
+            entries: self
+                .cache
+                .iter()
+                .map(|(key, content)| SnapshotEntry {
+                    key: key.as_ref().clone(),
+                    content,
+                })
+                .collect(),
+            history: self
+                .history
+                .iter()
+                .flat_map(|history| history.versions.iter())
+                .map(|(key, versions)| HistoryEntry {
+                    key: key.as_ref().clone(),
+                    versions: versions.as_ref().clone(),
+                })
+                .collect(),
+        };
+        let json = serde_json::to_vec(&snapshot)?;
+
+        let tmp = path.with_extension("tmp");
+        tokio::fs::write(&tmp, json).await?;
+        tokio::fs::rename(&tmp, path).await?;
+        Ok(snapshot.entries.len())
+    }
+
+    /// Restore entries saved by `save_snapshot`, skipping any that have expired
+    /// since, and return how many were loaded
+    pub async fn load_snapshot(&self, path: &Path) -> io::Result<usize> {
+        let json = tokio::fs::read(path).await?;
+        let snapshot: Snapshot = serde_json::from_slice(&json)?;
+
+        let mut loaded = 0;
+        for entry in snapshot.entries {
+            if self.ttl.retained(&entry.content).is_zero() {
+                continue;
+            }
+            let key = self.rekey(&entry.key, entry.content.url.as_deref());
+            self.insert(key, entry.content).await;
+            loaded += 1;
+        }
+
+        if let Some(history) = &self.history {
+            for mut entry in snapshot.history {
+                entry.versions.truncate(history.max_versions);
+                let key = self.rekey(&entry.key, None);
+                history.versions.insert(key, Arc::new(entry.versions)).await;
+            }
+        }
+        Ok(loaded)
+    }
+
+    /// Key of a snapshot entry under the current normalization
+    ///
+    /// Older snapshots lowercased whole URLs, so `README.md` and `readme.md`
+    /// shared a key; the URL the content was requested as, when saved, tells
+    /// them apart again.
+    fn rekey(&self, key: &str, requested_url: Option<&str>) -> String {
+        let (url, variant) = key.split_once(' ').unwrap_or((key, ""));
+        self.keys.variant_key(requested_url.unwrap_or(url), variant)
+    }
+}
+
+impl CacheBackend for MarkdownCache {
+    async fn get(&self, url: &str, variant: &str) -> Option<CachedContent> {
+        let content = self
+            .cache
+            .get(&self.keys.variant_key(url, variant))
+            .await
+            .filter(|content| !self.ttl.remaining(content).is_zero());
+        let counter = match content {
+            Some(_) => &self.counters.hits,
+            None => &self.counters.misses,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        content
+    }
+
+    async fn set(&self, url: &str, variant: &str, content: CachedContent) {
+        let key = self.keys.variant_key(url, variant);
+        if let Some(history) = &self.history {
+            history.record(key.clone(), &content).await;
+        }
+        self.insert(key, content).await;
+    }
+}
+
+/// Hex SHA-256 of markdown, identifying one exact version of a document
+pub fn content_hash(markdown: &str) -> String {
+    format!("{:x}", Sha256::digest(markdown.as_bytes()))
+}
+
+/// Markdown addressed by content hash, bounded by total bytes
+///
+/// Entries never go stale (a hash always names the same content), so they are
+/// only evicted to stay under the byte limit.
+#[derive(Clone)]
+pub struct ContentStore {
+    cache: Arc<Cache<String, Arc<str>>>,
+}
+
+impl ContentStore {
+    /// Create a content store holding up to `max_bytes` of markdown
+    pub fn new(max_bytes: u64) -> Self {
+        let cache = Cache::builder()
+            .weigher(|_hash: &String, markdown: &Arc<str>| {
+                u32::try_from(markdown.len()).unwrap_or(u32::MAX)
+            })
+            .max_capacity(max_bytes)
+            .build();
+
+        Self {
+            cache: Arc::new(cache),
+        }
+    }
+
+    /// Get markdown by content hash
+    pub async fn get(&self, hash: &str) -> Option<Arc<str>> {
+        self.cache.get(hash).await
+    }
+
+    /// Store markdown under its content hash, if not already present
+    pub async fn set(&self, hash: &str, markdown: &str) {
+        if !self.cache.contains_key(hash) {
+            self.cache
+                .insert(hash.to_string(), Arc::from(markdown))
+                .await;
+        }
+    }
+}
+
+/// Cache wrapper for link preview metadata
+///
+/// Kept separate from markdown so metadata can live much longer than full documents.
+#[derive(Clone)]
+pub struct MetadataCache {
+    cache: Arc<Cache<String, PageMetadata>>,
+}
+
+impl MetadataCache {
+    /// Create a new metadata cache with the specified TTL
     pub fn new(ttl_secs: u64) -> Self {
         let cache = Cache::builder()
             .time_to_live(Duration::from_secs(ttl_secs))
-            .max_capacity(10_000) // Max 10k entries
+            .max_capacity(100_000) // Entries are tiny
             .build();
 
         Self {
@@ -46,33 +672,120 @@ impl MarkdownCache {
         }
     }
 
-    /// Get cached content for a URL
-    pub async fn get(&self, url: &str) -> Option<CachedContent> {
+    /// Get cached metadata for a URL
+    pub async fn get(&self, url: &str) -> Option<PageMetadata> {
         self.cache.get(&normalize_cache_key(url)).await
     }
 
-    /// Store content in cache
-    pub async fn set(&self, url: &str, markdown: String, source: ContentSource) {
-        let content = CachedContent { markdown, source };
-        self.cache.insert(normalize_cache_key(url), content).await;
+    /// Store metadata in cache
+    pub async fn set(&self, url: &str, metadata: PageMetadata) {
+        self.cache.insert(normalize_cache_key(url), metadata).await;
     }
+}
 
+/// Cache wrapper for proxied images, bounded by total bytes
+#[derive(Clone)]
+pub struct ImageCache {
+    cache: Arc<Cache<String, Image>>,
 }
 
-/// Normalize URL for cache key
-/// - Lowercase the host
-/// - Remove fragment
-/// - Keep query string as-is (order matters for some APIs)
-fn normalize_cache_key(url: &str) -> String {
-    // Simple normalization: just lowercase and remove fragment
-    let url = url.split('#').next().unwrap_or(url);
-    url.to_lowercase()
+impl ImageCache {
+    /// Create a new image cache with the specified TTL and byte capacity
+    pub fn new(ttl_secs: u64, max_bytes: u64) -> Self {
+        let cache = Cache::builder()
+            .time_to_live(Duration::from_secs(ttl_secs))
+            .weigher(|_key: &String, image: &Image| {
+                u32::try_from(image.body.len()).unwrap_or(u32::MAX)
+            })
+            .max_capacity(max_bytes)
+            .build();
+
+        Self {
+            cache: Arc::new(cache),
+        }
+    }
+
+    /// Get a cached image for a URL
+    pub async fn get(&self, url: &str) -> Option<Image> {
+        self.cache.get(&normalize_cache_key(url)).await
+    }
+
+    /// Store an image in cache
+    pub async fn set(&self, url: &str, image: Image) {
+        self.cache.insert(normalize_cache_key(url), image).await;
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[tokio::test]
+    async fn test_invalidate_all_variants() {
+        let cache = MarkdownCache::new(3600);
+        let content = CachedContent::new("# Hello".to_string(), ContentSource::Native);
+        cache
+            .set("https://example.com/a", "", content.clone())
+            .await;
+        cache
+            .set("https://example.com/a", "lang=de", content.clone())
+            .await;
+        cache.set("https://example.com/ab", "", content).await;
+
+        assert_eq!(cache.invalidate("https://EXAMPLE.com/a").await, 2);
+        assert!(cache
+            .get("https://example.com/a", "lang=de")
+            .await
+            .is_none());
+        assert!(cache.get("https://example.com/ab", "").await.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_find_near_duplicate() {
+        let cache = MarkdownCache::new(3600);
+        let mut content = CachedContent::new("# Hello".to_string(), ContentSource::Native);
+        content.url = Some("https://example.com/a".to_string());
+        content.simhash = Some(0b1010_1010);
+        content.content_hash = Some(content_hash("# Hello"));
+        cache.set("https://example.com/a", "", content).await;
+
+        // Differing bits spread over several bands still leave one in common
+        let near = 0b1010_1010 ^ (1 << 3 | 1 << 20 | 1 << 40 | 1 << 63);
+        assert_eq!(
+            cache
+                .find_near_duplicate("https://mirror.example/a", near)
+                .await,
+            Some(content_hash("# Hello"))
+        );
+        assert_eq!(
+            cache
+                .find_near_duplicate("https://example.com/a", near)
+                .await,
+            None
+        );
+        assert_eq!(
+            cache
+                .find_near_duplicate("https://mirror.example/a", !near)
+                .await,
+            None
+        );
+
+        // Removed and replaced entries leave the index
+        let mut replaced = CachedContent::new("# Changed".to_string(), ContentSource::Native);
+        replaced.simhash = Some(!0b1010_1010);
+        replaced.content_hash = Some(content_hash("# Changed"));
+        cache.set("https://example.com/a", "", replaced).await;
+        assert_eq!(
+            cache
+                .find_near_duplicate("https://mirror.example/a", near)
+                .await,
+            None
+        );
+        cache.invalidate("https://example.com/a").await;
+        cache.cache.run_pending_tasks().await;
+        assert!(cache.simhashes.candidates(!0b1010_1010).is_empty());
+    }
+
     #[tokio::test]
     async fn test_cache_set_get() {
         let cache = MarkdownCache::new(3600);
@@ -80,12 +793,12 @@ mod tests {
         cache
             .set(
                 "https://example.com/article",
-                "# Hello".to_string(),
-                ContentSource::Native,
+                "",
+                CachedContent::new("# Hello".to_string(), ContentSource::Native),
             )
             .await;
 
-        let result = cache.get("https://example.com/article").await;
+        let result = cache.get("https://example.com/article", "").await;
         assert!(result.is_some());
 
         let content = result.unwrap();
@@ -96,34 +809,381 @@ mod tests {
     #[tokio::test]
     async fn test_cache_miss() {
         let cache = MarkdownCache::new(3600);
-        let result = cache.get("https://example.com/nonexistent").await;
+        let result = cache.get("https://example.com/nonexistent", "").await;
         assert!(result.is_none());
     }
 
-    #[test]
-    fn test_normalize_cache_key() {
-        // Should lowercase
-        assert_eq!(
-            normalize_cache_key("HTTPS://EXAMPLE.COM/Path"),
-            "https://example.com/path"
+    #[tokio::test]
+    async fn test_cache_variants() {
+        let cache = MarkdownCache::new(3600);
+        let url = "https://example.com/article";
+
+        cache
+            .set(
+                url,
+                "",
+                CachedContent::new("# Default".to_string(), ContentSource::Native),
+            )
+            .await;
+        cache
+            .set(
+                url,
+                "lang=fr",
+                CachedContent::new("# Fr".to_string(), ContentSource::Native),
+            )
+            .await;
+
+        assert_eq!(cache.get(url, "").await.unwrap().markdown, "# Default");
+        assert_eq!(cache.get(url, "lang=fr").await.unwrap().markdown, "# Fr");
+        assert!(cache.get(url, "lang=de").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_cache_key_rules() {
+        let rules = CacheKeyRules {
+            strip_www: true,
+            ..Default::default()
+        };
+        let cache = MarkdownCache::new(3600).with_key_rules(rules);
+        let content = CachedContent::new("# Docs".to_string(), ContentSource::Native);
+        cache
+            .set("https://www.example.com/Docs/", "", content)
+            .await;
+
+        assert!(cache
+            .get("https://EXAMPLE.com:443/Docs", "")
+            .await
+            .is_some());
+        assert!(cache.get("https://example.com/docs", "").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_cache_expires_from_fetch_time() {
+        let cache = MarkdownCache::new(60);
+        let mut content = CachedContent::new("# Old".to_string(), ContentSource::Native);
+        content.fetched_at = Utc::now() - chrono::Duration::seconds(61);
+
+        cache.set("https://example.com/old", "", content).await;
+        assert!(cache.get("https://example.com/old", "").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_cache_ttl_bounds() {
+        let cache = MarkdownCache::new(600).with_ttl_bounds(60, 3600);
+        let aged = |max_age: Option<u64>, age: i64| {
+            let mut content = CachedContent::new("# Page".to_string(), ContentSource::Native);
+            content.max_age = max_age;
+            content.fetched_at = Utc::now() - chrono::Duration::seconds(age);
+            content
+        };
+
+        // max-age=0 is raised to the floor, a year lowered to the ceiling
+        cache
+            .set("https://example.com/zero", "", aged(Some(0), 30))
+            .await;
+        cache
+            .set("https://example.com/year", "", aged(Some(31_536_000), 3601))
+            .await;
+        cache
+            .set("https://example.com/hour", "", aged(Some(1800), 900))
+            .await;
+        cache
+            .set("https://example.com/none", "", aged(None, 601))
+            .await;
+
+        assert!(cache.get("https://example.com/zero", "").await.is_some());
+        assert!(cache.get("https://example.com/year", "").await.is_none());
+        assert!(cache.get("https://example.com/hour", "").await.is_some());
+        assert!(cache.get("https://example.com/none", "").await.is_none());
+
+        // Without bounds the origin's lifetime is ignored
+        let cache = MarkdownCache::new(600);
+        cache
+            .set("https://example.com/hour", "", aged(Some(1800), 900))
+            .await;
+        assert!(cache.get("https://example.com/hour", "").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_host_ttls() {
+        let hosts = parse_host_ttls("news.example.com=300, *.example.com=86400").unwrap();
+        let cache = MarkdownCache::new(3600)
+            .with_ttl_bounds(60, 7200)
+            .with_host_ttls(&hosts);
+        let aged = |url: &str, age: i64| {
+            let mut content = CachedContent::new("# Page".to_string(), ContentSource::Native);
+            content.url = Some(url.to_string());
+            content.max_age = Some(3600);
+            content.fetched_at = Utc::now() - chrono::Duration::seconds(age);
+            content
+        };
+        for (url, age) in [
+            ("https://news.example.com/today", 600),
+            ("https://docs.example.com/guide", 7200),
+            ("https://other.test/page", 3000),
+        ] {
+            cache.set(url, "", aged(url, age)).await;
+        }
+
+        // The most specific domain wins, over the origin's max-age too
+        assert!(cache
+            .get("https://news.example.com/today", "")
+            .await
+            .is_none());
+        assert!(cache
+            .get("https://docs.example.com/guide", "")
+            .await
+            .is_some());
+        assert!(cache.get("https://other.test/page", "").await.is_some());
+        let ttl = cache.ttl(&aged("https://news.example.com/today", 600));
+        assert_eq!(ttl, Duration::from_secs(300));
+
+        assert!(parse_host_ttls("example.com").is_err());
+        assert!(parse_host_ttls("example.com=soon").is_err());
+        assert!(parse_host_ttls("=60").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_stale_entries_kept_for_revalidation() {
+        let cache = MarkdownCache::new(60);
+        let expired = |etag: Option<&str>| {
+            let mut content = CachedContent::new("# Old".to_string(), ContentSource::Native);
+            content.etag = etag.map(str::to_string);
+            content.fetched_at = Utc::now() - chrono::Duration::seconds(61);
+            content
+        };
+
+        cache
+            .set("https://example.com/tagged", "", expired(Some("\"v1\"")))
+            .await;
+        cache
+            .set("https://example.com/untagged", "", expired(None))
+            .await;
+
+        assert!(cache.get("https://example.com/tagged", "").await.is_none());
+        let stale = cache
+            .get_stale("https://example.com/tagged", "")
+            .await
+            .unwrap();
+        assert_eq!(stale.etag.as_deref(), Some("\"v1\""));
+        assert!(cache
+            .get_stale("https://example.com/untagged", "")
+            .await
+            .is_none());
+
+        // Fresh entries aren't stale
+        let mut fresh = expired(Some("\"v2\""));
+        fresh.fetched_at = Utc::now();
+        cache.set("https://example.com/fresh", "", fresh).await;
+        assert!(cache
+            .get_stale("https://example.com/fresh", "")
+            .await
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_cache_stats() {
+        let cache = MarkdownCache::new(3600);
+        let stats = cache.stats().await;
+        assert_eq!((stats.entries, stats.hits, stats.misses), (0, 0, 0));
+        assert_eq!(stats.hit_ratio, None);
+
+        let url = "https://example.com/a";
+        assert!(cache.get(url, "").await.is_none());
+        cache
+            .set(
+                url,
+                "",
+                CachedContent::new("# A".to_string(), ContentSource::Native),
+            )
+            .await;
+        assert!(cache.get(url, "").await.is_some());
+        assert!(cache.get(url, "").await.is_some());
+
+        let stats = cache.stats().await;
+        assert_eq!((stats.entries, stats.hits, stats.misses), (1, 2, 1));
+        assert_eq!(stats.hit_ratio, Some(2.0 / 3.0));
+        assert_eq!(stats.estimated_bytes, (url.len() + "# A".len()) as u64);
+    }
+
+    #[tokio::test]
+    async fn test_byte_capacity() {
+        let cache = MarkdownCache::new(3600).with_max_bytes(1000);
+        for i in 0..10 {
+            let markdown = format!("# {}\n{}", i, "x".repeat(300));
+            let content = CachedContent::new(markdown, ContentSource::Native);
+            cache
+                .set(&format!("https://example.com/{}", i), "", content)
+                .await;
+        }
+
+        let stats = cache.stats().await;
+        assert!(
+            stats.estimated_bytes <= 1000,
+            "{} bytes",
+            stats.estimated_bytes
         );
+        assert!(stats.entries <= 3);
+        assert!(stats.evictions >= 7);
+    }
 
-        // Should remove fragment
-        assert_eq!(
-            normalize_cache_key("https://example.com/page#section"),
-            "https://example.com/page"
+    #[tokio::test]
+    async fn test_snapshot_roundtrip() {
+        let path = std::env::temp_dir().join(format!("mdwn-snapshot-{}.json", std::process::id()));
+        let cache = MarkdownCache::new(3600).with_history(5, DEFAULT_HISTORY_MAX_BYTES);
+
+        let mut fresh = CachedContent::new("# Fresh".to_string(), ContentSource::Converted);
+        fresh.quality = Some(0.8);
+        fresh.content_hash = Some(content_hash("# Fresh"));
+        cache
+            .set("https://example.com/fresh", "lang=fr", fresh)
+            .await;
+
+        // Still live now, but past its TTL by the time it is reloaded below
+        let mut aging = CachedContent::new("# Aging".to_string(), ContentSource::Native);
+        aging.fetched_at = Utc::now() - chrono::Duration::seconds(3599);
+        cache.set("https://example.com/aging", "", aging).await;
+
+        assert_eq!(cache.save_snapshot(&path).await.unwrap(), 2);
+
+        let restored = MarkdownCache::new(1800).with_history(5, DEFAULT_HISTORY_MAX_BYTES);
+        assert_eq!(restored.load_snapshot(&path).await.unwrap(), 1);
+        let versions = restored
+            .history("https://example.com/fresh", "lang=fr")
+            .await
+            .unwrap();
+        assert_eq!(versions.len(), 1);
+        let content = restored
+            .get("https://example.com/fresh", "lang=fr")
+            .await
+            .unwrap();
+        assert_eq!(content.markdown, "# Fresh");
+        assert_eq!(content.quality, Some(0.8));
+        assert!(restored
+            .get("https://example.com/aging", "")
+            .await
+            .is_none());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_keys_renormalized() {
+        let path = std::env::temp_dir().join(format!("mdwn-old-keys-{}.json", std::process::id()));
+        // Saved when keys were the whole URL lowercased
+        let mut readme = CachedContent::new("# Readme".to_string(), ContentSource::Native);
+        readme.url = Some("https://Example.com/README.md".to_string());
+        let snapshot = Snapshot {
+            entries: vec![SnapshotEntry {
+                key: "https://example.com/readme.md".to_string(),
+                content: readme,
+            }],
+            history: Vec::new(),
+        };
+        std::fs::write(&path, serde_json::to_vec(&snapshot).unwrap()).unwrap();
+
+        let cache = MarkdownCache::new(3600);
+        assert_eq!(cache.load_snapshot(&path).await.unwrap(), 1);
+        assert!(cache
+            .get("https://example.com/README.md", "")
+            .await
+            .is_some());
+        assert!(cache
+            .get("https://example.com/readme.md", "")
+            .await
+            .is_none());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_history_keeps_distinct_versions() {
+        let cache = MarkdownCache::new(3600).with_history(2, DEFAULT_HISTORY_MAX_BYTES);
+        let url = "https://example.com/changing";
+        let version = |markdown: &str| {
+            let mut content = CachedContent::new(markdown.to_string(), ContentSource::Native);
+            content.content_hash = Some(content_hash(markdown));
+            content
+        };
+
+        for markdown in ["# v1", "# v2", "# v2", "# v3"] {
+            cache.set(url, "", version(markdown)).await;
+        }
+
+        let versions = cache.history(url, "").await.unwrap();
+        let markdown: Vec<_> = versions.iter().map(|v| v.markdown.as_str()).collect();
+        assert_eq!(markdown, ["# v3", "# v2"]);
+        assert_eq!(versions[0].hash, content_hash("# v3"));
+
+        assert!(cache
+            .history("https://example.com/other", "")
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(MarkdownCache::new(3600).history(url, "").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_history_byte_capacity() {
+        let cache = MarkdownCache::new(3600).with_history(5, 2000);
+        for i in 0..10 {
+            let markdown = format!("# {}\n{}", i, "x".repeat(300));
+            let mut content = CachedContent::new(markdown.clone(), ContentSource::Native);
+            content.content_hash = Some(content_hash(&markdown));
+            let url = format!("https://example.com/{}", i);
+            cache.set(&url, "", content).await;
+        }
+
+        let versions = &cache.history.as_ref().unwrap().versions;
+        versions.run_pending_tasks().await;
+        assert!(
+            versions.weighted_size() <= 2000,
+            "{} bytes",
+            versions.weighted_size()
         );
+        assert!(versions.entry_count() <= 6);
+    }
 
-        // Should keep query string
+    #[tokio::test]
+    async fn test_content_store() {
+        let store = ContentStore::new(1024);
+        let hash = content_hash("# Hello");
         assert_eq!(
-            normalize_cache_key("https://example.com/page?a=1&b=2"),
-            "https://example.com/page?a=1&b=2"
+            hash,
+            "01c8de44e04d2f7a304f50963545a2aff58c33e9c44a1f33fdcb978fb224cb74"
         );
+
+        store.set(&hash, "# Hello").await;
+        assert_eq!(store.get(&hash).await.as_deref(), Some("# Hello"));
+        assert!(store.get(&content_hash("# Other")).await.is_none());
     }
 
-    #[test]
-    fn test_content_source_header() {
-        assert_eq!(ContentSource::Native.as_header_value(), "native");
-        assert_eq!(ContentSource::Converted.as_header_value(), "converted");
+    #[tokio::test]
+    async fn test_metadata_cache() {
+        let cache = MetadataCache::new(3600);
+        let metadata = PageMetadata {
+            title: Some("Title".to_string()),
+            description: None,
+        };
+
+        cache
+            .set("https://example.com/page#top", metadata.clone())
+            .await;
+        assert_eq!(cache.get("https://example.com/page").await, Some(metadata));
+        assert_eq!(cache.get("https://example.com/other").await, None);
+    }
+
+    #[tokio::test]
+    async fn test_image_cache() {
+        let cache = ImageCache::new(3600, 1024);
+        let image = Image {
+            mime_type: "image/png".to_string(),
+            body: vec![0; 16],
+        };
+
+        cache.set("https://example.com/a.png", image).await;
+        let cached = cache.get("https://example.com/a.png").await.unwrap();
+        assert_eq!(cached.mime_type, "image/png");
+        assert_eq!(cached.body.len(), 16);
     }
 }
@@ -0,0 +1,190 @@
+//! Headers and invalidation for a CDN in front of mdwn
+//!
+//! Successful markdown responses carry `s-maxage=...` in their `Cache-Control`
+//! and surrogate keys for their domain and URL (`Surrogate-Key` for Fastly
+//! and friends, `Cache-Tag` for Cloudflare). When a cached page is purged or a
+//! refresh finds it changed, its URL key is POSTed to `CDN_PURGE_URL` so the
+//! CDN drops just that page.
+
+use crate::config::Config;
+use axum::http::{header, HeaderMap};
+use mdwnio::cache::{content_hash, normalize_cache_key};
+use mdwnio::error::{MdwnError, Result};
+use mdwnio::proxy;
+use mdwnio::secrets::Secret;
+use serde::Serialize;
+use std::time::Duration;
+use url::Url;
+
+/// Surrogate keys for a page: its domain and a hash of its normalized URL
+pub fn surrogate_keys(url: &Url) -> Vec<String> {
+    let host = url.host_str().unwrap_or_default().to_ascii_lowercase();
+    vec![format!("domain:{}", host), url_key(url)]
+}
+
+/// Surrogate key naming exactly one page
+pub fn url_key(url: &Url) -> String {
+    let hash = content_hash(&normalize_cache_key(url.as_str()));
+    format!("url:{}", &hash[..16])
+}
+
+/// Add shared-cache headers for a successful response; `max_age` 0 adds none
+///
+/// `s-maxage` is appended to any `Cache-Control` already set for clients.
+pub fn insert_cdn_headers(headers: &mut HeaderMap, url: &Url, max_age: u64) {
+    if max_age == 0 {
+        return;
+    }
+    let keys = surrogate_keys(url);
+    let cache_control = match headers
+        .get(header::CACHE_CONTROL)
+        .and_then(|v| v.to_str().ok())
+    {
+        Some(client) => format!("{}, s-maxage={}", client, max_age),
+        None => format!("public, s-maxage={}", max_age),
+    };
+    headers.insert(
+        header::CACHE_CONTROL,
+        cache_control.parse().expect("valid header value"),
+    );
+    headers.insert(
+        "Surrogate-Key",
+        keys.join(" ").parse().expect("valid header value"),
+    );
+    headers.insert(
+        "Cache-Tag",
+        keys.join(",").parse().expect("valid header value"),
+    );
+}
+
+/// Purge request body
+#[derive(Serialize)]
+struct PurgeRequest<'a> {
+    surrogate_keys: &'a [String],
+}
+
+/// Sends surrogate keys to the operator's purge hook
+#[derive(Clone)]
+pub struct Purger {
+    url: Secret,
+    token: Option<Secret>,
+    client: reqwest::Client,
+}
+
+impl Purger {
+    /// Purger for `CDN_PURGE_URL`, or `None` if it isn't set
+    pub fn from_config(config: &Config) -> Result<Option<Self>> {
+        let Some(url) = &config.cdn_purge_url else {
+            return Ok(None);
+        };
+
+        let client = proxy::client_builder(config.outbound_proxy.as_ref())
+            .user_agent(&config.user_agent)
+            .timeout(Duration::from_secs(config.request_timeout))
+            .build()
+            .map_err(|e| MdwnError::Internal(format!("Failed to create HTTP client: {}", e)))?;
+
+        Ok(Some(Self {
+            url: url.clone(),
+            token: config.cdn_purge_token.clone(),
+            client,
+        }))
+    }
+
+    /// Ask the CDN to drop everything tagged with any of `keys`
+    pub async fn purge(&self, keys: &[String]) -> Result<()> {
+        let body = serde_json::to_vec(&PurgeRequest {
+            surrogate_keys: keys,
+        })
+        .expect("purge request serializes");
+
+        let mut request = self
+            .client
+            .post(&*self.url.expose())
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(body);
+        if let Some(token) = &self.token {
+            request = request.bearer_auth(token.expose());
+        }
+
+        request
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| {
+                MdwnError::FetchFailed(format!("CDN purge failed: {}", e.without_url()))
+            })?;
+        tracing::info!("Purged {} from the CDN", keys.join(" "));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mdwnio::proxy::OutboundProxy;
+    use wiremock::matchers::{body_json, header as has_header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[test]
+    fn test_surrogate_keys() {
+        let url = Url::parse("https://Example.com/Docs#intro").unwrap();
+        let keys = surrogate_keys(&url);
+        assert_eq!(keys[0], "domain:example.com");
+        assert_eq!(
+            keys[1],
+            url_key(&Url::parse("https://example.com/Docs/").unwrap())
+        );
+        assert_eq!(keys[1].len(), "url:".len() + 16);
+
+        let mut headers = HeaderMap::new();
+        insert_cdn_headers(&mut headers, &url, 600);
+        assert_eq!(headers[header::CACHE_CONTROL], "public, s-maxage=600");
+        assert_eq!(headers["Surrogate-Key"], keys.join(" "));
+
+        let mut headers = HeaderMap::new();
+        insert_cdn_headers(&mut headers, &url, 0);
+        assert!(headers.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_purge_hook() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/purge"))
+            .and(has_header("authorization", "Bearer purge-secret"))
+            .and(body_json(
+                serde_json::json!({ "surrogate_keys": ["url:abc"] }),
+            ))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mut config = Config::for_tests();
+        config.cdn_purge_url = Some(Secret::new(format!("{}/purge", server.uri())));
+        config.cdn_purge_token = Some(Secret::new("purge-secret"));
+        let purger = Purger::from_config(&config).unwrap().unwrap();
+
+        purger.purge(&["url:abc".to_string()]).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_purge_through_outbound_proxy() {
+        let proxy = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/purge"))
+            .and(has_header("host", "cdn-hook.example.test"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&proxy)
+            .await;
+
+        let mut config = Config::for_tests();
+        config.cdn_purge_url = Some(Secret::new("http://cdn-hook.example.test/purge"));
+        config.outbound_proxy = Some(OutboundProxy::parse(&proxy.uri(), None).unwrap());
+        let purger = Purger::from_config(&config).unwrap().unwrap();
+
+        purger.purge(&["url:abc".to_string()]).await.unwrap();
+    }
+}
@@ -0,0 +1,168 @@
+//! Request coalescing for concurrent identical fetches
+//!
+//! When many clients ask for the same uncached page at once, only the first
+//! fetches and converts it; the rest wait for its result instead of sending
+//! their own requests upstream.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use tokio::sync::watch;
+
+/// In-flight work by key, shared with every caller asking for the same key
+#[derive(Clone)]
+pub struct Coalescer<T> {
+    flights: Arc<Mutex<HashMap<String, watch::Receiver<Option<T>>>>>,
+}
+
+impl<T> Default for Coalescer<T> {
+    fn default() -> Self {
+        Self {
+            flights: Arc::default(),
+        }
+    }
+}
+
+/// Clears a flight when its leader finishes or is cancelled
+struct Landing<'a, T> {
+    flights: &'a Mutex<HashMap<String, watch::Receiver<Option<T>>>>,
+    key: &'a str,
+}
+
+impl<T> Drop for Landing<'_, T> {
+    fn drop(&mut self) {
+        self.flights
+            .lock()
+            .expect("flights lock poisoned")
+            .remove(self.key);
+    }
+}
+
+impl<T: Clone> Coalescer<T> {
+    /// Run `work` unless the same key is already in flight, in which case
+    /// wait for that result; the flag is whether the result was shared
+    ///
+    /// If the running caller goes away (a client disconnects) before
+    /// finishing, one of the waiters takes over and runs its `work`, and the
+    /// rest wait for that instead.
+    pub async fn run<F, Fut>(&self, key: &str, work: F) -> (T, bool)
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = T>,
+    {
+        loop {
+            let joined = {
+                let mut flights = self.flights.lock().expect("flights lock poisoned");
+                match flights.get(key) {
+                    Some(receiver) => Err(receiver.clone()),
+                    None => {
+                        let (sender, receiver) = watch::channel(None);
+                        flights.insert(key.to_string(), receiver);
+                        Ok(sender)
+                    }
+                }
+            };
+
+            match joined {
+                Ok(sender) => {
+                    let _landing = Landing {
+                        flights: &self.flights,
+                        key,
+                    };
+                    let value = work().await;
+                    sender.send_replace(Some(value.clone()));
+                    return (value, false);
+                }
+                Err(mut receiver) => {
+                    if let Ok(value) = receiver.wait_for(Option::is_some).await {
+                        if let Some(value) = value.clone() {
+                            return (value, true);
+                        }
+                    }
+                    // The leader was cancelled and its flight cleared: race
+                    // the other waiters to lead the next one
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_concurrent_calls_share_one_run() {
+        let coalescer = Coalescer::default();
+        let runs = AtomicUsize::new(0);
+        let work = || async {
+            runs.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            "done".to_string()
+        };
+
+        let results =
+            futures_util::future::join_all((0..10).map(|_| coalescer.run("a", work))).await;
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        assert!(results.iter().all(|(value, _)| value == "done"));
+        assert_eq!(results.iter().filter(|(_, shared)| *shared).count(), 9);
+
+        // Finished flights aren't reused
+        coalescer.run("a", work).await;
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_leader() {
+        let coalescer = Coalescer::default();
+        let leader = coalescer.run("a", || async {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            1
+        });
+        let waiter = async {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            coalescer.run("a", || async { 2 }).await
+        };
+
+        // The leader is dropped when the timeout fires; the waiter runs its own work
+        let (cancelled, result) = tokio::join!(
+            tokio::time::timeout(Duration::from_millis(50), leader),
+            waiter
+        );
+        assert!(cancelled.is_err());
+        assert_eq!(result, (2, false));
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_leader_hands_over_once() {
+        let coalescer = Coalescer::default();
+        let runs = AtomicUsize::new(0);
+        let leader = coalescer.run("a", || async {
+            runs.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            1
+        });
+        let waiters = futures_util::future::join_all((0..5).map(|_| async {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            coalescer
+                .run("a", || async {
+                    runs.fetch_add(1, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                    2
+                })
+                .await
+        }));
+
+        let (cancelled, results) = tokio::join!(
+            tokio::time::timeout(Duration::from_millis(50), leader),
+            waiters
+        );
+        assert!(cancelled.is_err());
+        // The leader's run plus one takeover, not one per waiter
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+        assert!(results.iter().all(|(value, _)| *value == 2));
+        assert_eq!(results.iter().filter(|(_, shared)| *shared).count(), 4);
+    }
+}
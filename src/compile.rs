@@ -0,0 +1,560 @@
+//! Whole documentation sites as one markdown document
+//!
+//! `POST /compile` reads the navigation menu of a site's page and converts
+//! the pages it lists, in menu order, into a single document: a table of
+//! contents, then each page under its own heading with the URL it came from.
+//! Pages the menu leaves out but compiled pages link to follow after it, so a
+//! site without a menu is compiled from the first page's links. Only pages
+//! under the first page's directory are included (`/guide/` for
+//! `https://example.com/guide/intro`), at most `max_pages` of them, and links
+//! between included pages point at their sections.
+//!
+//! A compilation fetches up to hundreds of pages, so like `/prefetch` it takes
+//! the admin token. Its pages wait for their site's crawl delay, and all the
+//! upstream work shares one budget and cookie jar, as large as the pages'
+//! own budgets together.
+
+use crate::admin::require_admin;
+use crate::discover::{is_asset, site_host};
+use crate::graph::page_links;
+use crate::{process_url_in, process_url_throttled, record_usage, AppState, ProxyOptions};
+use axum::{
+    body::Bytes,
+    extract::{DefaultBodyLimit, State},
+    http::{header, HeaderMap},
+    middleware,
+    response::{IntoResponse, Response},
+    routing::post,
+    Router,
+};
+use futures_util::{stream, StreamExt};
+use mdwnio::cache::CachedContent;
+use mdwnio::convert::{self, MAX_HEADING_LEVEL};
+use mdwnio::error::{MdwnError, Result};
+use mdwnio::fetch::FetchBudget;
+use regex::{Captures, Regex};
+use scraper::{ElementRef, Html, Selector};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::sync::LazyLock;
+use std::time::Duration;
+use url::Url;
+
+/// Pages compiled unless the request asks for fewer or more
+const DEFAULT_MAX_PAGES: usize = 50;
+
+/// Upper bound on `max_pages`, the batch timeout still applies
+const MAX_COMPILE_PAGES: usize = 200;
+
+/// Pages of a compilation fetched at the same time
+const COMPILE_CONCURRENCY: usize = 4;
+
+/// Levels of a nested navigation menu kept in the table of contents
+const MAX_NAV_DEPTH: usize = 3;
+
+/// Link targets: the `(url)` of `[text](url)`, with or without `<>`
+static LINK_TARGET_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\]\(<?([^)\s>]+)>?").expect("valid regex"));
+
+/// The `/compile` route, under the same limits as `/batch` and gated like
+/// the admin routes
+pub fn routes(state: AppState) -> Router<AppState> {
+    Router::new()
+        .route("/compile", post(compile_handler))
+        .route_layer(middleware::from_fn_with_state(state, require_admin))
+        // The body limit layer applies instead of axum's default
+        .layer(DefaultBodyLimit::disable())
+}
+
+/// JSON body for `POST /compile`
+#[derive(Deserialize)]
+struct CompileRequest {
+    url: String,
+    max_pages: Option<usize>,
+}
+
+/// A page to compile
+#[derive(Clone, Debug, PartialEq)]
+struct Entry {
+    url: Url,
+    /// Link text in the navigation menu
+    label: Option<String>,
+    /// Nesting in the menu, 0 for top-level and linked pages
+    depth: usize,
+}
+
+/// A converted page of the document
+struct Section {
+    url: Url,
+    title: String,
+    depth: usize,
+    markdown: String,
+}
+
+/// Pages under the directory of the first page
+struct Scope {
+    host: Option<String>,
+    prefix: String,
+}
+
+impl Scope {
+    fn new(start: &Url) -> Self {
+        let path = start.path();
+        let prefix = &path[..path.rfind('/').map_or(0, |i| i + 1)];
+        Self {
+            host: site_host(start),
+            prefix: prefix.to_string(),
+        }
+    }
+
+    fn contains(&self, url: &Url) -> bool {
+        matches!(url.scheme(), "http" | "https")
+            && site_host(url) == self.host
+            && url.path().starts_with(&self.prefix)
+            && !is_asset(url)
+    }
+}
+
+/// Crawl a site from a JSON `{"url": ..., "max_pages": n}` into one document
+async fn compile_handler(State(state): State<AppState>, body: Bytes) -> Result<Response> {
+    let request: CompileRequest = serde_json::from_slice(&body)
+        .map_err(|e| MdwnError::ParseError(format!("Invalid JSON body: {}", e)))?;
+    let max_pages = request.max_pages.unwrap_or(DEFAULT_MAX_PAGES);
+    if !(1..=MAX_COMPILE_PAGES).contains(&max_pages) {
+        return Err(MdwnError::ParseError(format!(
+            "max_pages must be between 1 and {}",
+            MAX_COMPILE_PAGES
+        )));
+    }
+
+    let budget = FetchBudget::new(
+        state.config.max_upstream_requests.saturating_mul(max_pages),
+        state.config.max_upstream_bytes.saturating_mul(max_pages),
+    )
+    .with_deadline(Duration::from_secs(state.config.batch_timeout));
+
+    // The menu is in the page's HTML, which conversion throws away, so the
+    // first page is fetched here and its conversion reuses the response
+    let requested = state.fetcher.parse_url(&request.url)?;
+    state.crawl_throttle.wait(&state.fetcher, &requested).await;
+    let response = state.fetcher.fetch(&requested, &budget).await?;
+    let mut start = response.final_url.clone();
+    start.set_fragment(None);
+    let scope = Scope::new(&start);
+    let menu = navigation(&response.body_as_string(), &start, &scope);
+    let mut start_response = Some(response);
+
+    let mut entries = Vec::new();
+    if !menu.iter().any(|entry| entry.url == start) {
+        entries.push(Entry {
+            url: start.clone(),
+            label: None,
+            depth: 0,
+        });
+    }
+    entries.extend(menu);
+    let mut known: HashSet<String> = entries.iter().map(|entry| entry.url.to_string()).collect();
+
+    // Fetch in rounds, queueing pages the last round linked to
+    let options = ProxyOptions {
+        notice: Some(false),
+        ..ProxyOptions::default()
+    };
+    let mut outcomes: Vec<Result<CachedContent>> = Vec::new();
+    while outcomes.len() < entries.len().min(max_pages) {
+        let round = &entries[outcomes.len()..entries.len().min(max_pages)];
+        let fetched: Vec<_> = stream::iter(round.to_vec())
+            .map(|entry| {
+                let (state, options, budget) = (state.clone(), options.clone(), &budget);
+                let fetched = (entry.url == start)
+                    .then(|| start_response.take())
+                    .flatten();
+                async move {
+                    let url = entry.url.as_str();
+                    let result = match fetched {
+                        // Already waited for its crawl slot
+                        Some(response) => {
+                            let result =
+                                process_url_in(&state, url, &options, &[], budget, Some(response))
+                                    .await;
+                            let domain = entry.url.host_str().unwrap_or_default().to_lowercase();
+                            record_usage(&state, &domain, &result);
+                            result
+                        }
+                        None => process_url_throttled(&state, url, &options, budget).await,
+                    };
+                    (entry, result.map(|(content, _)| content))
+                }
+            })
+            .buffered(COMPILE_CONCURRENCY)
+            .collect()
+            .await;
+        for (entry, result) in fetched {
+            if let Ok(content) = &result {
+                for link in page_links(&entry.url, &content.markdown) {
+                    if scope.contains(&link) && known.insert(link.to_string()) {
+                        entries.push(Entry {
+                            url: link,
+                            label: None,
+                            depth: 0,
+                        });
+                    }
+                }
+            }
+            outcomes.push(result);
+        }
+    }
+    let left_out = entries.len() - outcomes.len();
+
+    let mut title = None;
+    let mut sections = Vec::new();
+    let mut skipped = Vec::new();
+    for (entry, outcome) in entries.into_iter().zip(outcomes) {
+        match outcome {
+            Ok(content) => {
+                if entry.url == start {
+                    title = content.title.clone();
+                }
+                let page_title = entry
+                    .label
+                    .or(content.title)
+                    .unwrap_or_else(|| entry.url.path().to_string());
+                sections.push(Section {
+                    url: entry.url,
+                    title: page_title,
+                    depth: entry.depth,
+                    markdown: content.markdown,
+                });
+            }
+            Err(e) if entry.url == start => return Err(e),
+            Err(e) => {
+                tracing::debug!("Compiling {} left out {}: {}", start, entry.url, e);
+                skipped.push((entry.url, e.to_string()));
+            }
+        }
+    }
+
+    let title = title
+        .unwrap_or_else(|| format!("{}{}", start.host_str().unwrap_or_default(), scope.prefix));
+    let markdown = render(&title, &start, &sections, &skipped, left_out);
+
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::CONTENT_TYPE,
+        "text/markdown; charset=utf-8"
+            .parse()
+            .expect("valid header value"),
+    );
+    headers.insert("X-Mdwn-Compiled-Pages", sections.len().into());
+    Ok((headers, markdown).into_response())
+}
+
+/// In-scope links of a page's navigation menus, in menu order, with how
+/// deeply each is nested in the menu's lists
+fn navigation(html: &str, base: &Url, scope: &Scope) -> Vec<Entry> {
+    let document = Html::parse_document(html);
+    let links = Selector::parse("nav a[href], [role=navigation] a[href], aside a[href]")
+        .expect("valid CSS selector");
+
+    let mut seen = HashSet::new();
+    let mut entries: Vec<Entry> = Vec::new();
+    for link in document.select(&links) {
+        let Some(mut url) = link
+            .value()
+            .attr("href")
+            .and_then(|href| base.join(href).ok())
+        else {
+            continue;
+        };
+        url.set_fragment(None);
+        if !scope.contains(&url) || !seen.insert(url.to_string()) {
+            continue;
+        }
+        let label = link.text().collect::<Vec<_>>().join(" ");
+        let label = label.split_whitespace().collect::<Vec<_>>().join(" ");
+        let depth = link
+            .ancestors()
+            .filter_map(ElementRef::wrap)
+            .filter(|element| matches!(element.value().name(), "ul" | "ol"))
+            .count();
+        entries.push(Entry {
+            url,
+            label: (!label.is_empty()).then_some(label),
+            depth,
+        });
+    }
+
+    // Outermost list at 0, each entry at most one level below the one before
+    let outermost = entries.iter().map(|entry| entry.depth).min().unwrap_or(0);
+    let mut deepest = 0;
+    for entry in &mut entries {
+        entry.depth = (entry.depth - outermost).min(deepest).min(MAX_NAV_DEPTH);
+        deepest = entry.depth + 1;
+    }
+    entries
+}
+
+/// The compiled document: title, contents, then each page in order
+fn render(
+    title: &str,
+    start: &Url,
+    sections: &[Section],
+    skipped: &[(Url, String)],
+    left_out: usize,
+) -> String {
+    let mut used = HashSet::new();
+    let slugs: Vec<String> = sections
+        .iter()
+        .map(|section| unique_slug(&section.title, &mut used))
+        .collect();
+    let targets: HashMap<String, &str> = sections
+        .iter()
+        .zip(&slugs)
+        .map(|(section, slug)| (section.url.to_string(), slug.as_str()))
+        .collect();
+
+    let mut markdown = format!("# {}\n\n", title);
+    markdown.push_str(&format!(
+        "Compiled from {} pages of <{}>.\n\n",
+        sections.len(),
+        start
+    ));
+    markdown.push_str("## Contents\n\n");
+    for (section, slug) in sections.iter().zip(&slugs) {
+        let indent = "  ".repeat(section.depth);
+        markdown.push_str(&format!("{}- [{}](#{})\n", indent, section.title, slug));
+    }
+
+    for section in sections {
+        // Top-level pages are `##`, each menu level one deeper
+        let level = (2 + section.depth).min(MAX_HEADING_LEVEL - 1);
+        let body = without_title(&section.markdown);
+        let body = demote_headings(body, level - 1);
+        let body = link_sections(&body, &section.url, &targets);
+        markdown.push_str(&format!("\n{} {}\n\n", "#".repeat(level), section.title));
+        markdown.push_str(&format!("> Source: <{}>\n\n", section.url));
+        markdown.push_str(body.trim());
+        markdown.push('\n');
+    }
+
+    if !skipped.is_empty() || left_out > 0 {
+        markdown.push_str("\n## Left out\n\n");
+        for (url, error) in skipped {
+            markdown.push_str(&format!("- <{}>: {}\n", url, error));
+        }
+        if left_out > 0 {
+            markdown.push_str(&format!(
+                "- {} more linked pages, past the page limit\n",
+                left_out
+            ));
+        }
+    }
+    markdown
+}
+
+/// A page's markdown without its leading `# Title`, which its section heading replaces
+fn without_title(markdown: &str) -> &str {
+    let markdown = markdown.trim_start();
+    match markdown.strip_prefix("# ") {
+        Some(rest) => rest.split_once('\n').map_or("", |(_, rest)| rest),
+        None => markdown,
+    }
+}
+
+/// Move every ATX heading `by` levels deeper, flattening those that would
+/// pass the deepest level into bold paragraphs
+fn demote_headings(markdown: &str, by: usize) -> String {
+    let flattened = convert::flatten_headings(markdown, MAX_HEADING_LEVEL - by);
+    let prefix = "#".repeat(by);
+    let mut result = String::with_capacity(flattened.len());
+    let mut in_fence = false;
+    for line in flattened.lines() {
+        if line.trim_start().starts_with("```") {
+            in_fence = !in_fence;
+        }
+        let rest = line.trim_start_matches('#');
+        let level = line.len() - rest.len();
+        let is_heading = (1..=MAX_HEADING_LEVEL).contains(&level)
+            && (rest.is_empty() || rest.starts_with([' ', '\t']));
+        if !in_fence && is_heading {
+            result.push_str(&prefix);
+        }
+        result.push_str(line);
+        result.push('\n');
+    }
+    result
+}
+
+/// Point links to compiled pages at their sections, outside code blocks
+fn link_sections(markdown: &str, page: &Url, targets: &HashMap<String, &str>) -> String {
+    let mut result = String::with_capacity(markdown.len());
+    let mut in_fence = false;
+    for line in markdown.lines() {
+        if line.trim_start().starts_with("```") {
+            in_fence = !in_fence;
+        }
+        if in_fence || !line.contains("](") {
+            result.push_str(line);
+        } else {
+            let line = LINK_TARGET_RE.replace_all(line, |caps: &Captures| {
+                let slug = page.join(&caps[1]).ok().and_then(|mut url| {
+                    url.set_fragment(None);
+                    targets.get(url.as_str()).copied()
+                });
+                match slug {
+                    Some(slug) => format!("](#{}", slug),
+                    None => caps[0].to_string(),
+                }
+            });
+            result.push_str(&line);
+        }
+        result.push('\n');
+    }
+    result
+}
+
+/// GitHub's id for a heading, numbered like GitHub's when already taken
+fn unique_slug(title: &str, used: &mut HashSet<String>) -> String {
+    let slug: String = title
+        .trim()
+        .to_lowercase()
+        .chars()
+        .filter_map(|c| match c {
+            ' ' => Some('-'),
+            c if c.is_alphanumeric() || c == '-' || c == '_' => Some(c),
+            _ => None,
+        })
+        .collect();
+    let mut candidate = slug.clone();
+    let mut n = 0;
+    while !used.insert(candidate.clone()) {
+        n += 1;
+        candidate = format!("{}-{}", slug, n);
+    }
+    candidate
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(s: &str) -> Url {
+        Url::parse(s).unwrap()
+    }
+
+    #[test]
+    fn test_navigation() {
+        let base = url("https://docs.example.com/guide/intro");
+        let html = r#"<html><body>
+            <header><nav><a href="/">Home</a><a href="/blog/">Blog</a></nav></header>
+            <aside><ul>
+                <li><a href="intro">Introduction</a></li>
+                <li><a href="install">Installing</a>
+                    <ul><li><a href="install-linux#apt">On Linux</a></li></ul></li>
+                <li><a href="install-linux">Linux again</a></li>
+                <li><a href="config"> Configuration
+                    files </a></li>
+                <li><a href="/guide/logo.png">Logo</a></li>
+            </ul></aside>
+            <p><a href="/guide/faq">FAQ</a></p>
+            </body></html>"#;
+
+        let entries: Vec<String> = navigation(html, &base, &Scope::new(&base))
+            .into_iter()
+            .map(|e| {
+                format!(
+                    "{} {} {}",
+                    e.depth,
+                    e.url.path(),
+                    e.label.unwrap_or_default()
+                )
+            })
+            .collect();
+        assert_eq!(
+            entries,
+            [
+                "0 /guide/intro Introduction",
+                "0 /guide/install Installing",
+                "1 /guide/install-linux On Linux",
+                "0 /guide/config Configuration files",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_scope() {
+        let scope = Scope::new(&url("https://www.example.com/docs/start"));
+        assert!(scope.contains(&url("https://example.com/docs/api/")));
+        assert!(!scope.contains(&url("https://example.com/blog/")));
+        assert!(!scope.contains(&url("https://example.com/docs/manual.pdf")));
+        assert!(!scope.contains(&url("https://docs.example.com/docs/")));
+    }
+
+    #[test]
+    fn test_demote_headings() {
+        let markdown = "## Setup\n\n```\n# comment\n```\n##### Deep\n#hashtag\n";
+        assert_eq!(
+            demote_headings(markdown, 2),
+            "#### Setup\n\n```\n# comment\n```\n**Deep**\n#hashtag\n"
+        );
+    }
+
+    #[test]
+    fn test_unique_slug() {
+        let mut used = HashSet::new();
+        assert_eq!(
+            unique_slug("Getting Started!", &mut used),
+            "getting-started"
+        );
+        assert_eq!(
+            unique_slug("Getting started", &mut used),
+            "getting-started-1"
+        );
+        assert_eq!(unique_slug("C++ & you", &mut used), "c--you");
+    }
+
+    #[test]
+    fn test_render() {
+        let section = |path: &str, title: &str, depth, markdown: &str| Section {
+            url: url("https://example.com/guide/").join(path).unwrap(),
+            title: title.to_string(),
+            depth,
+            markdown: markdown.to_string(),
+        };
+        let sections = [
+            section(
+                "intro",
+                "Intro",
+                0,
+                "# Welcome\n\nRead [install](install#apt) next.\n",
+            ),
+            section(
+                "install",
+                "Install",
+                1,
+                "# Install\n\n## Apt\n\nSee [home](/).\n",
+            ),
+        ];
+        let skipped = [(
+            url("https://example.com/guide/gone"),
+            "NOT_FOUND: gone".to_string(),
+        )];
+
+        assert_eq!(
+            render("Guide", &sections[0].url, &sections, &skipped, 3),
+            "# Guide\n\n\
+             Compiled from 2 pages of <https://example.com/guide/intro>.\n\n\
+             ## Contents\n\n\
+             - [Intro](#intro)\n  - [Install](#install)\n\n\
+             ## Intro\n\n\
+             > Source: <https://example.com/guide/intro>\n\n\
+             Read [install](#install) next.\n\n\
+             ### Install\n\n\
+             > Source: <https://example.com/guide/install>\n\n\
+             #### Apt\n\nSee [home](/).\n\n\
+             ## Left out\n\n\
+             - <https://example.com/guide/gone>: NOT_FOUND: gone\n\
+             - 3 more linked pages, past the page limit\n"
+        );
+    }
+}
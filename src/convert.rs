@@ -1,44 +1,281 @@
 use crate::error::{MdwnError, Result};
+use chrono::{DateTime, SecondsFormat, Utc};
 use readability::extractor;
+use scraper::{Html, Selector};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use tracing::field::Empty;
+use tracing::Level;
 use url::Url;
 
-/// Notice prepended to converted markdown content
-const CONVERSION_NOTICE: &str =
-    "<!-- mdwn.io: Converted from HTML. Original may have richer formatting. -->\n\n";
+/// Text of the HTML comment prepended to converted markdown by default
+pub const DEFAULT_CONVERSION_NOTICE: &str =
+    "mdwn.io: Converted from HTML. Original may have richer formatting.";
 
-/// Convert HTML to markdown using readability extraction
+/// Convert HTML to markdown using readability extraction, with the default notice
 pub fn html_to_markdown(html: &str, base_url: &Url) -> Result<String> {
-    // Use readability to extract main content
-    let product = extractor::extract(&mut html.as_bytes(), base_url)
-        .map_err(|e| MdwnError::ParseError(format!("Readability extraction failed: {}", e)))?;
+    html_to_markdown_with_notice(html, base_url, Some(DEFAULT_CONVERSION_NOTICE))
+}
 
-    // Convert the extracted HTML to markdown
-    let markdown = htmd::convert(&product.content)
-        .map_err(|e| MdwnError::ParseError(format!("HTML to Markdown conversion failed: {}", e)))?;
+/// Convert HTML to markdown, prepending `notice` as an HTML comment unless `None`
+pub fn html_to_markdown_with_notice(
+    html: &str,
+    base_url: &Url,
+    notice: Option<&str>,
+) -> Result<String> {
+    let article = extract_article(html, base_url)?;
+    let markdown = article_to_markdown(&article.content)?;
 
     // Clean up the markdown
-    let markdown = clean_markdown(&markdown);
+    let span = tracing::debug_span!("clean", bytes_in = markdown.len(), bytes_out = Empty);
+    let markdown = span.in_scope(|| {
+        let markdown = clean_markdown(&markdown);
+        span.record("bytes_out", markdown.len());
+        markdown
+    });
+
+    Ok(with_title_and_notice(markdown, &article.title, notice))
+}
 
-    // Prepend title if available
-    let markdown = if !product.title.is_empty() {
-        format!("# {}\n\n{}", product.title, markdown)
+/// Put a title heading (unless empty) and the conversion notice (unless
+/// `None`) before converted markdown
+pub fn with_title_and_notice(markdown: String, title: &str, notice: Option<&str>) -> String {
+    let markdown = if !title.is_empty() {
+        format!("# {}\n\n{}", title, markdown)
     } else {
         markdown
     };
 
-    // Add conversion notice
-    let markdown = format!("{}{}", CONVERSION_NOTICE, markdown);
+    match notice {
+        Some(notice) => format!("<!-- {} -->\n\n{}", notice, markdown),
+        None => markdown,
+    }
+}
+
+/// Put a line under the title heading of converted markdown, or at the top
+/// (after the conversion notice) if it has no title
+pub fn with_attribution(mut markdown: String, attribution: &str) -> String {
+    let mut at = 0;
+    if markdown.starts_with("<!--") {
+        if let Some(end) = markdown.find("-->\n\n") {
+            at = end + "-->\n\n".len();
+        }
+    }
+    if markdown[at..].starts_with("# ") {
+        if let Some(end) = markdown[at..].find("\n\n") {
+            at += end + "\n\n".len();
+        }
+    }
+    markdown.insert_str(at, &format!("{}\n\n", attribution));
+    markdown
+}
+
+/// Main content of a page as readability picked it
+pub struct Article {
+    /// Readability's title, or a fallback if it found none; empty if neither
+    pub title: String,
+    pub title_source: Option<TitleSource>,
+    /// Extracted HTML
+    pub content: String,
+}
+
+/// Extract a page's main content with readability
+pub fn extract_article(html: &str, base_url: &Url) -> Result<Article> {
+    let span = tracing::debug_span!(
+        "readability",
+        html_bytes = html.len(),
+        content_bytes = Empty
+    );
+    span.in_scope(|| {
+        let product = extractor::extract(&mut html.as_bytes(), base_url)
+            .map_err(|e| MdwnError::ParseError(format!("Readability extraction failed: {}", e)))?;
+        span.record("content_bytes", product.content.len());
+        if tracing::enabled!(target: "mdwnio::readability", Level::DEBUG) {
+            log_selector_counts(&product.content, base_url);
+        }
+        let (title, title_source) = if !product.title.trim().is_empty() {
+            (product.title, Some(TitleSource::Readability))
+        } else if let Some((title, source)) = fallback_title(html) {
+            tracing::debug!(
+                target: "mdwnio::readability",
+                ?source,
+                "No title from readability, using fallback"
+            );
+            (title, Some(source))
+        } else {
+            (String::new(), None)
+        };
+        Ok(Article {
+            title,
+            title_source,
+            content: product.content,
+        })
+    })
+}
+
+/// Where an article's title came from
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TitleSource {
+    Readability,
+    /// The document's `<title>`
+    TitleTag,
+    /// `og:title`
+    OpenGraph,
+    /// The first `<h1>`
+    Heading,
+}
+
+/// Title for a page readability found none for: its `<title>`, `og:title`
+/// or first `<h1>`, whichever is first non-empty
+pub fn fallback_title(html: &str) -> Option<(String, TitleSource)> {
+    let document = Html::parse_document(html);
+    [
+        ("title", TitleSource::TitleTag),
+        ("meta[property='og:title'][content]", TitleSource::OpenGraph),
+        ("h1", TitleSource::Heading),
+    ]
+    .into_iter()
+    .find_map(|(selector, source)| {
+        let selector = Selector::parse(selector).expect("valid CSS selector");
+        document.select(&selector).find_map(|element| {
+            let text = match element.value().attr("content") {
+                Some(content) => collapse_whitespace(content),
+                None => collapse_whitespace(&element.text().collect::<String>()),
+            };
+            (!text.is_empty()).then_some((text, source))
+        })
+    })
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Convert extracted HTML to markdown, before [`clean_markdown`]
+pub fn article_to_markdown(content: &str) -> Result<String> {
+    let span = tracing::debug_span!("htmd", markdown_bytes = Empty);
+    span.in_scope(|| {
+        let markdown = htmd::convert(content).map_err(|e| {
+            MdwnError::ParseError(format!("HTML to Markdown conversion failed: {}", e))
+        })?;
+        span.record("markdown_bytes", markdown.len());
+        Ok(markdown)
+    })
+}
+
+/// Log how many of each kind of element readability kept, to tell a good
+/// extraction from one that dropped the article body
+fn log_selector_counts(content: &str, url: &Url) {
+    let fragment = Html::parse_fragment(content);
+    let count = |selector: &str| {
+        let selector = Selector::parse(selector).expect("valid selector");
+        fragment.select(&selector).count()
+    };
+    tracing::debug!(
+        target: "mdwnio::readability",
+        %url,
+        headings = count("h1, h2, h3, h4, h5, h6"),
+        paragraphs = count("p"),
+        links = count("a[href]"),
+        images = count("img"),
+        code_blocks = count("pre"),
+        tables = count("table"),
+        "Readability kept"
+    );
+}
+
+/// Provenance footer for `?citation=1`: source, title, retrieval time and license
+///
+/// Pass no retrieval time for byte-identical output across fetches.
+pub fn citation_footer(
+    url: &str,
+    title: Option<&str>,
+    retrieved: Option<DateTime<Utc>>,
+    license: Option<&str>,
+) -> String {
+    let mut footer = format!("\n\n---\n\n- Source: <{}>\n", url);
+    if let Some(title) = title {
+        footer.push_str(&format!("- Title: {}\n", title));
+    }
+    if let Some(retrieved) = retrieved {
+        footer.push_str(&format!(
+            "- Retrieved: {}\n",
+            retrieved.to_rfc3339_opts(SecondsFormat::Secs, true)
+        ));
+    }
+    if let Some(license) = license {
+        footer.push_str(&format!("- License: {}\n", license));
+    }
+    footer
+}
+
+/// YAML front matter from key/value pairs, skipping missing values
+///
+/// Values are written as JSON strings, which YAML reads as plain strings.
+pub fn front_matter(entries: &[(&str, Option<&str>)]) -> String {
+    let mut yaml = String::from("---\n");
+    for (key, value) in entries {
+        if let Some(value) = value {
+            let quoted = serde_json::to_string(value).expect("strings serialize");
+            yaml.push_str(&format!("{}: {}\n", key, quoted));
+        }
+    }
+    yaml.push_str("---\n\n");
+    yaml
+}
 
-    Ok(markdown)
+/// Deepest heading level markdown can express
+pub const MAX_HEADING_LEVEL: usize = 6;
+
+/// Turn ATX headings deeper than `max_depth` into bold paragraphs
+///
+/// `##### Options` becomes `**Options**` with a `max_depth` of 4, for chunkers
+/// and renderers that handle deeply nested sections poorly. Code blocks are
+/// left alone.
+pub fn flatten_headings(markdown: &str, max_depth: usize) -> String {
+    if max_depth >= MAX_HEADING_LEVEL {
+        return markdown.to_string();
+    }
+    let mut result = String::with_capacity(markdown.len());
+    let mut in_fence = false;
+    for line in markdown.lines() {
+        if line.trim_start().starts_with("```") {
+            in_fence = !in_fence;
+        }
+        let rest = line.trim_start_matches('#');
+        let level = line.len() - rest.len();
+        let is_heading = (1..=MAX_HEADING_LEVEL).contains(&level)
+            && (rest.is_empty() || rest.starts_with([' ', '\t']));
+        if !in_fence && is_heading && level > max_depth {
+            // A closing sequence of `#`s isn't part of the text
+            let text = rest.trim();
+            let text = match text.trim_end_matches('#') {
+                stripped if stripped.is_empty() || stripped.ends_with([' ', '\t']) => {
+                    stripped.trim_end()
+                }
+                _ => text,
+            };
+            if !text.is_empty() {
+                result.push_str(&format!("**{}**", text));
+            }
+        } else {
+            result.push_str(line);
+        }
+        result.push('\n');
+    }
+    result
 }
 
 /// Clean up converted markdown
-fn clean_markdown(md: &str) -> String {
+pub fn clean_markdown(md: &str) -> String {
     let mut result = String::with_capacity(md.len());
     let mut prev_blank = false;
     let lines = md.lines().peekable();
 
     for line in lines {
+        // Stray carriage returns would otherwise pair up with the next newline
+        let line = line.trim_end_matches('\r');
         let trimmed = line.trim();
 
         // Skip excessive blank lines (keep max 2 consecutive)
@@ -60,24 +297,573 @@ fn clean_markdown(md: &str) -> String {
     format!("{}\n", result)
 }
 
-/// Check if the HTML content appears to be meaningful (not just a JS shell)
-pub fn is_meaningful_html(html: &str) -> bool {
-    // Simple heuristic: check if there's actual text content
-    // JS-rendered pages often have very little text in the initial HTML
-    let text_content: String = html
-        .chars()
-        .filter(|c| c.is_alphanumeric() || c.is_whitespace())
-        .collect();
+/// Normalize text for downstream markdown renderers
+/// - Strip byte order marks (and stray zero-width no-break spaces)
+/// - Convert CRLF and lone CR line endings to LF
+/// - Drop NUL and other control characters, keeping tabs and newlines
+pub fn normalize_text(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\u{FEFF}' => {}
+            '\r' => {
+                // Collapse CRLF into a single newline
+                if chars.peek() == Some(&'\n') {
+                    chars.next();
+                }
+                result.push('\n');
+            }
+            '\n' | '\t' => result.push(c),
+            c if c.is_control() => {}
+            c => result.push(c),
+        }
+    }
+
+    result
+}
+
+/// Elements whose text is never visible content
+const SKIPPED_TAGS: &[&str] = &["head", "script", "style", "noscript", "template", "svg"];
+
+/// Elements that usually hold navigation and page chrome rather than content
+const BOILERPLATE_TAGS: &[&str] = &[
+    "nav", "header", "footer", "aside", "menu", "form", "button", "select",
+];
+
+/// Prose words at which a page counts as fully meaningful
+const FULL_CONTENT_WORDS: f32 = 100.0;
+
+/// Default minimum score for a page to be worth converting
+pub const DEFAULT_MIN_CONTENT_SCORE: f32 = 0.15;
+
+/// Text density assessment of an HTML page
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ContentQuality {
+    /// Confidence (0.0 - 1.0) that the page has real readable content
+    pub score: f32,
+    /// Visible words on the page
+    pub word_count: usize,
+    /// Share of visible words that are link text
+    pub link_density: f32,
+    /// Visible words outside links and navigation chrome
+    pub prose_words: usize,
+}
+
+impl ContentQuality {
+    /// Whether the page appears to be meaningful (not just a JS shell)
+    pub fn is_meaningful(&self, min_score: f32) -> bool {
+        self.score >= min_score
+    }
+}
+
+/// Assess text and link density over the DOM
+///
+/// Prose words (outside links and navigation chrome) drive the score, which is
+/// then scaled down by link density so nav-heavy shells score low even when
+/// they contain plenty of words.
+pub fn assess_html(html: &str) -> ContentQuality {
+    let document = Html::parse_document(html);
+
+    let mut word_count = 0;
+    let mut link_words = 0;
+    let mut prose_words = 0;
+
+    for node in document.tree.root().descendants() {
+        let words = match node.value().as_text() {
+            Some(text) => text.split_whitespace().count(),
+            None => continue,
+        };
+        if words == 0 {
+            continue;
+        }
+
+        let mut in_link = false;
+        let mut in_boilerplate = false;
+        let mut skipped = false;
+        for ancestor in node.ancestors() {
+            if let Some(element) = ancestor.value().as_element() {
+                let name = element.name();
+                if SKIPPED_TAGS.contains(&name) {
+                    skipped = true;
+                    break;
+                }
+                in_link |= name == "a";
+                in_boilerplate |= BOILERPLATE_TAGS.contains(&name);
+            }
+        }
+        if skipped {
+            continue;
+        }
+
+        word_count += words;
+        if in_link {
+            link_words += words;
+        } else if !in_boilerplate {
+            prose_words += words;
+        }
+    }
+
+    let link_density = if word_count == 0 {
+        0.0
+    } else {
+        link_words as f32 / word_count as f32
+    };
+    let score = (prose_words as f32 / FULL_CONTENT_WORDS).min(1.0) * (1.0 - link_density);
+
+    ContentQuality {
+        score,
+        word_count,
+        link_density,
+        prose_words,
+    }
+}
+
+/// Title and heading phrases of "not found" templates
+const NOT_FOUND_PHRASES: &[&str] = &[
+    "not found",
+    "page doesn't exist",
+    "page does not exist",
+    "cannot be found",
+    "can't be found",
+    "could not be found",
+    "couldn't be found",
+    "no longer available",
+    "no longer exists",
+];
+
+/// Pages with more prose than this are real content even if they say "not found"
+const SOFT_NOT_FOUND_MAX_PROSE_WORDS: usize = 150;
+
+/// Whether a page served with 200 is really a "not found" template
+///
+/// The title or first heading has to read like an error and the page has to be
+/// short, so articles that merely mention 404s aren't caught.
+pub fn is_soft_not_found(html: &str, quality: &ContentQuality) -> bool {
+    if quality.prose_words > SOFT_NOT_FOUND_MAX_PROSE_WORDS {
+        return false;
+    }
+
+    let document = Html::parse_document(html);
+    let selector = Selector::parse("title, h1").expect("valid CSS selector");
+    document.select(&selector).any(|el| {
+        let text = el.text().collect::<String>().to_lowercase();
+        let has_404 = text
+            .split(|c: char| !c.is_ascii_alphanumeric())
+            .any(|word| word == "404");
+        has_404 || NOT_FOUND_PHRASES.iter().any(|phrase| text.contains(phrase))
+    })
+}
+
+/// Kind of page, from DOM heuristics, for routing pages to different handling
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PageType {
+    /// A single piece of prose, the fallback
+    Article,
+    /// Category, archive or listing page, converted to a list of its links
+    Index,
+    /// Technical documentation or API reference
+    Docs,
+    /// Forum thread or Q&A page
+    Forum,
+    /// Product page of a shop
+    Product,
+    /// Podcast episode, converted to its metadata and show notes
+    Podcast,
+    /// Upstream error page or "not found" template
+    Error,
+}
+
+impl PageType {
+    pub fn as_header_value(&self) -> &'static str {
+        match self {
+            PageType::Article => "article",
+            PageType::Index => "index",
+            PageType::Docs => "docs",
+            PageType::Forum => "forum",
+            PageType::Product => "product",
+            PageType::Podcast => "podcast",
+            PageType::Error => "error",
+        }
+    }
+}
+
+/// `generator` meta values of forum software
+const FORUM_GENERATORS: &[&str] = &[
+    "discourse",
+    "phpbb",
+    "vbulletin",
+    "xenforo",
+    "flarum",
+    "nodebb",
+];
+
+/// `generator` meta values of documentation site generators
+const DOCS_GENERATORS: &[&str] = &[
+    "sphinx",
+    "mkdocs",
+    "docusaurus",
+    "gitbook",
+    "vitepress",
+    "rustdoc",
+    "docfx",
+    "antora",
+];
+
+/// URL path segments of forum threads and Q&A pages
+const FORUM_PATH_SEGMENTS: &[&str] = &[
+    "forum",
+    "forums",
+    "thread",
+    "threads",
+    "topic",
+    "t",
+    "questions",
+];
+
+/// URL path segments of documentation
+const DOCS_PATH_SEGMENTS: &[&str] = &["docs", "doc", "documentation", "reference", "manual", "api"];
+
+/// Classify a page that isn't an index or error page
+///
+/// Structured data (JSON-LD `@type`, microdata `itemtype`, `og:type`) is
+/// trusted first, then the generator meta tag, then the URL.
+pub fn classify_page(html: &str, url: &Url) -> PageType {
+    let document = Html::parse_document(html);
+
+    let mut types = Vec::new();
+    let json_ld =
+        Selector::parse("script[type='application/ld+json']").expect("valid CSS selector");
+    for script in document.select(&json_ld) {
+        if let Ok(value) = serde_json::from_str(&script.text().collect::<String>()) {
+            collect_schema_types(&value, &mut types);
+        }
+    }
+    let itemtypes = Selector::parse("[itemtype]").expect("valid CSS selector");
+    for element in document.select(&itemtypes) {
+        let itemtype = element.value().attr("itemtype").unwrap_or_default();
+        types.extend(
+            itemtype
+                .split_whitespace()
+                .filter_map(|t| t.rsplit('/').next())
+                .map(str::to_lowercase),
+        );
+    }
+    let meta = |selector: &str| {
+        let selector = Selector::parse(selector).expect("valid CSS selector");
+        document
+            .select(&selector)
+            .find_map(|el| el.value().attr("content"))
+            .map(str::to_lowercase)
+            .unwrap_or_default()
+    };
+    let og_type = meta("meta[property='og:type'][content]");
+    let generator = meta("meta[name='generator'][content]");
+    let has_type = |wanted: &[&str]| types.iter().any(|t| wanted.contains(&t.as_str()));
+    let segments: Vec<String> = url
+        .path_segments()
+        .map(|segments| segments.map(str::to_lowercase).collect())
+        .unwrap_or_default();
+    let in_path = |wanted: &[&str]| segments.iter().any(|s| wanted.contains(&s.as_str()));
+
+    if has_type(&["product"]) || og_type == "product" || og_type.starts_with("product.") {
+        PageType::Product
+    } else if has_type(&["podcastepisode"]) {
+        PageType::Podcast
+    } else if has_type(&["discussionforumposting", "qapage", "question"])
+        || FORUM_GENERATORS.iter().any(|g| generator.contains(g))
+        || in_path(FORUM_PATH_SEGMENTS)
+    {
+        PageType::Forum
+    } else if has_type(&["techarticle", "apireference"])
+        || DOCS_GENERATORS.iter().any(|g| generator.contains(g))
+        || url.host_str().is_some_and(|host| host.starts_with("docs."))
+        || in_path(DOCS_PATH_SEGMENTS)
+    {
+        PageType::Docs
+    } else {
+        PageType::Article
+    }
+}
+
+/// Lowercased schema.org `@type` values anywhere in a JSON-LD document
+fn collect_schema_types(value: &serde_json::Value, types: &mut Vec<String>) {
+    match value {
+        serde_json::Value::Object(object) => {
+            for (key, value) in object {
+                match (key.as_str(), value) {
+                    ("@type", serde_json::Value::String(t)) => types.push(t.to_lowercase()),
+                    ("@type", serde_json::Value::Array(ts)) => {
+                        types.extend(ts.iter().filter_map(|t| t.as_str()).map(str::to_lowercase))
+                    }
+                    _ => collect_schema_types(value, types),
+                }
+            }
+        }
+        serde_json::Value::Array(values) => {
+            for value in values {
+                collect_schema_types(value, types);
+            }
+        }
+        _ => {}
+    }
+}
 
-    // If there's very little text, it's probably a JS shell
-    let word_count = text_content.split_whitespace().count();
-    word_count > 20 // Threshold for meaningful content
+/// An article link on an index page
+#[derive(Clone, Debug, PartialEq)]
+pub struct IndexLink {
+    pub title: String,
+    pub url: Url,
+    /// `datetime` (or text) of the `<time>` in the link's card, if it has one
+    pub date: Option<String>,
+}
+
+/// Fewest article links for a page to count as an index
+const MIN_INDEX_LINKS: usize = 5;
+
+/// Prose words per article link below which the prose is only excerpts
+const INDEX_PROSE_WORDS_PER_LINK: usize = 20;
+
+/// Prose words above which a page is an article however many links it has,
+/// e.g. one ending in a long list of references
+const INDEX_MAX_PROSE_WORDS: usize = 1000;
+
+/// Links outside navigation chrome that look like article titles, in page order
+///
+/// A title is link text of at least three words, or any link inside a heading.
+/// Fragment, `mailto:` and `javascript:` links and links back to the page
+/// itself are skipped, and each target is listed once.
+pub fn index_links(html: &str, base_url: &Url) -> Vec<IndexLink> {
+    let document = Html::parse_document(html);
+    let anchors = Selector::parse("a[href]").expect("valid CSS selector");
+    let times = Selector::parse("time").expect("valid CSS selector");
+
+    let mut links: Vec<IndexLink> = Vec::new();
+    for anchor in document.select(&anchors) {
+        let mut in_heading = false;
+        let mut in_boilerplate = false;
+        let ancestors = anchor
+            .ancestors()
+            .filter_map(|a| a.value().as_element().map(|e| e.name()));
+        for ancestor in ancestors {
+            in_heading |= matches!(ancestor, "h1" | "h2" | "h3" | "h4" | "h5" | "h6");
+            in_boilerplate |= BOILERPLATE_TAGS.contains(&ancestor);
+        }
+        let title = anchor.text().collect::<Vec<_>>().join(" ");
+        let title = title.split_whitespace().collect::<Vec<_>>().join(" ");
+        if in_boilerplate || title.is_empty() || (!in_heading && title.split(' ').count() < 3) {
+            continue;
+        }
+
+        let href = anchor.value().attr("href").unwrap_or_default().trim();
+        if href.starts_with('#') || href.starts_with("mailto:") || href.starts_with("javascript:") {
+            continue;
+        }
+        let Ok(mut url) = base_url.join(href) else {
+            continue;
+        };
+        url.set_fragment(None);
+        if url == *base_url || links.iter().any(|link| link.url == url) {
+            continue;
+        }
+
+        // The card is the nearest ancestor with a `<time>`, unless it already
+        // holds several and so spans more than one article
+        let date = anchor
+            .ancestors()
+            .filter_map(scraper::ElementRef::wrap)
+            .map(|card| card.select(&times).collect::<Vec<_>>())
+            .find(|found| !found.is_empty())
+            .filter(|found| found.len() == 1)
+            .and_then(|found| {
+                let time = found[0];
+                let text = time.text().collect::<String>();
+                time.value()
+                    .attr("datetime")
+                    .map(str::to_string)
+                    .or(Some(text))
+                    .map(|date| date.trim().to_string())
+                    .filter(|date| !date.is_empty())
+            });
+
+        links.push(IndexLink { title, url, date });
+    }
+    links
+}
+
+/// Whether a page is a listing of articles rather than one article
+///
+/// It needs several article links, not much prose overall, and either mostly
+/// link text or only about an excerpt's worth of prose per link.
+pub fn is_index_page(links: &[IndexLink], quality: &ContentQuality) -> bool {
+    links.len() >= MIN_INDEX_LINKS
+        && quality.prose_words <= INDEX_MAX_PROSE_WORDS
+        && (quality.link_density >= 0.5
+            || quality.prose_words < links.len() * INDEX_PROSE_WORDS_PER_LINK)
+}
+
+/// Markdown list of an index page's article links, with their dates
+pub fn index_to_markdown(title: Option<&str>, links: &[IndexLink], notice: Option<&str>) -> String {
+    let mut markdown = String::new();
+    if let Some(notice) = notice {
+        markdown.push_str(&format!("<!-- {} -->\n\n", notice));
+    }
+    if let Some(title) = title {
+        markdown.push_str(&format!("# {}\n\n", title));
+    }
+    for link in links {
+        let title = link.title.replace('[', "\\[").replace(']', "\\]");
+        markdown.push_str(&format!("- [{}]({})", title, link.url));
+        if let Some(date) = &link.date {
+            markdown.push_str(&format!(" ({})", date));
+        }
+        markdown.push('\n');
+    }
+    markdown
+}
+
+/// Backslash-escape characters markdown would read as formatting, so `text`
+/// renders literally inside a paragraph, list item or link text
+pub fn escape_markdown(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(
+            c,
+            '\\' | '`' | '*' | '_' | '[' | ']' | '<' | '>' | '#' | '!' | '&' | '|' | '~'
+        ) {
+            escaped.push('\\');
+        }
+        escaped.push(if c.is_control() { ' ' } else { c });
+    }
+    escaped
+}
+
+/// Percent-encode characters that would end or break a markdown link
+/// destination: whitespace, controls, parentheses and angle brackets
+pub fn encode_link_destination(url: &str) -> String {
+    let mut encoded = String::with_capacity(url.len());
+    for c in url.chars() {
+        if c.is_whitespace() || c.is_control() || matches!(c, '(' | ')' | '<' | '>' | '\\') {
+            let mut buf = [0; 4];
+            for byte in c.encode_utf8(&mut buf).bytes() {
+                encoded.push_str(&format!("%{:02X}", byte));
+            }
+        } else {
+            encoded.push(c);
+        }
+    }
+    encoded
+}
+
+/// Words per shingle for [`simhash`]
+const SHINGLE_WORDS: usize = 3;
+
+/// Documents shorter than this have too few shingles for a meaningful fingerprint
+const SIMHASH_MIN_WORDS: usize = 50;
+
+/// Bits two fingerprints may differ by and still count as near-identical;
+/// unrelated documents differ in about half of the 64
+pub const NEAR_DUPLICATE_DISTANCE: u32 = 6;
+
+/// 64-bit simhash of overlapping word shingles, `None` for very short text
+///
+/// Near-identical documents (mirrors, syndicated copies, pages differing
+/// only in a timestamp) get fingerprints a few bits apart.
+pub fn simhash(text: &str) -> Option<u64> {
+    let lowercase = text.to_lowercase();
+    let words = words(&lowercase);
+    if words.len() < SIMHASH_MIN_WORDS {
+        return None;
+    }
+
+    let mut weights = [0i32; 64];
+    for shingle in words.windows(SHINGLE_WORDS) {
+        let hash = fnv1a(shingle);
+        for (bit, weight) in weights.iter_mut().enumerate() {
+            *weight += if hash >> bit & 1 == 1 { 1 } else { -1 };
+        }
+    }
+
+    let fingerprint = weights
+        .iter()
+        .enumerate()
+        .filter(|(_, weight)| **weight > 0)
+        .fold(0u64, |acc, (bit, _)| acc | 1 << bit);
+    Some(fingerprint)
+}
+
+/// Whether two simhash fingerprints are within [`NEAR_DUPLICATE_DISTANCE`]
+pub fn is_near_duplicate(a: u64, b: u64) -> bool {
+    (a ^ b).count_ones() <= NEAR_DUPLICATE_DISTANCE
+}
+
+/// Jaccard similarity of two texts' word shingles, from 0 (nothing shared)
+/// to 1 (the same shingles, in whatever order)
+pub fn similarity(a: &str, b: &str) -> f32 {
+    let shingles = |text: &str| {
+        let lowercase = text.to_lowercase();
+        let words = words(&lowercase);
+        let mut shingles: HashSet<u64> = words.windows(SHINGLE_WORDS).map(fnv1a).collect();
+        if shingles.is_empty() && !words.is_empty() {
+            shingles.insert(fnv1a(&words));
+        }
+        shingles
+    };
+    let (a, b) = (shingles(a), shingles(b));
+    let union = a.union(&b).count();
+    if union == 0 {
+        return 1.0;
+    }
+    a.intersection(&b).count() as f32 / union as f32
+}
+
+/// Alphanumeric words of a text
+fn words(text: &str) -> Vec<&str> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .collect()
+}
+
+/// FNV-1a over space-joined words, stable across builds unlike `DefaultHasher`
+fn fnv1a(words: &[&str]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for (i, word) in words.iter().enumerate() {
+        let separator = if i > 0 { &b" "[..] } else { &[] };
+        for byte in separator.iter().chain(word.as_bytes()) {
+            hash ^= u64::from(*byte);
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+    hash
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_escape_markdown() {
+        assert_eq!(
+            escape_markdown("a_b *c* [d](e)"),
+            "a\\_b \\*c\\* \\[d\\](e)"
+        );
+        assert_eq!(
+            escape_markdown("<img src=x> & #1"),
+            "\\<img src=x\\> \\& \\#1"
+        );
+        assert_eq!(escape_markdown("line\nbreak"), "line break");
+        assert_eq!(
+            encode_link_destination("https://example.com/a b/(c)<d>"),
+            "https://example.com/a%20b/%28c%29%3Cd%3E"
+        );
+        assert_eq!(
+            encode_link_destination("https://example.com/é"),
+            "https://example.com/é"
+        );
+    }
+
     #[test]
     fn test_html_to_markdown() {
         let html = r#"
@@ -101,6 +887,69 @@ mod tests {
 
         assert!(result.contains("<!-- mdwn.io:"));
         assert!(result.contains("**bold**") || result.contains("bold"));
+
+        let custom = html_to_markdown_with_notice(html, &base, Some("via proxy")).unwrap();
+        assert!(custom.starts_with("<!-- via proxy -->\n\n"));
+        let bare = html_to_markdown_with_notice(html, &base, None).unwrap();
+        assert!(!bare.contains("<!--"));
+    }
+
+    #[test]
+    fn test_front_matter() {
+        assert_eq!(
+            front_matter(&[("title", Some("Say \"hi\": now")), ("license", None)]),
+            "---\ntitle: \"Say \\\"hi\\\": now\"\n---\n\n"
+        );
+    }
+
+    #[test]
+    fn test_flatten_headings() {
+        let markdown = "# Guide\n\n#### Setup\n\n##### Options ##\n\n###### \n\n\
+                        ```\n##### kept\n```\n\n#####not a heading\n";
+        assert_eq!(
+            flatten_headings(markdown, 4),
+            "# Guide\n\n#### Setup\n\n**Options**\n\n\n\n```\n##### kept\n```\n\n\
+             #####not a heading\n"
+        );
+        assert_eq!(flatten_headings("## C# tips\n", 1), "**C# tips**\n");
+        assert_eq!(flatten_headings(markdown, 6), markdown);
+    }
+
+    #[test]
+    fn test_with_attribution() {
+        let line = "*By Jane Doe*";
+        assert_eq!(
+            with_attribution("<!-- note -->\n\n# Title\n\nText\n".to_string(), line),
+            "<!-- note -->\n\n# Title\n\n*By Jane Doe*\n\nText\n"
+        );
+        assert_eq!(
+            with_attribution("Text\n".to_string(), line),
+            "*By Jane Doe*\n\nText\n"
+        );
+    }
+
+    /// Golden tests over the saved pages in `tests/fixtures/html`
+    ///
+    /// Each fixture records its quality assessment and converted markdown, so
+    /// converter changes show up as reviewable snapshot diffs (`cargo insta review`).
+    #[test]
+    fn test_conversion_snapshots() {
+        insta::glob!("../tests/fixtures/html", "*.html", |path| {
+            let html = std::fs::read_to_string(path).expect("readable fixture");
+            let base = Url::parse("https://example.com/fixture").unwrap();
+
+            let quality = assess_html(&html);
+            let markdown = html_to_markdown(&html, &base).expect("fixture converts");
+
+            insta::assert_snapshot!(format!(
+                "score: {:.2}\nwords: {}\nlink_density: {:.2}\nmeaningful: {}\n---\n{}",
+                quality.score,
+                quality.word_count,
+                quality.link_density,
+                quality.is_meaningful(DEFAULT_MIN_CONTENT_SCORE),
+                markdown
+            ));
+        });
     }
 
     #[test]
@@ -112,6 +961,49 @@ mod tests {
         assert!(!clean.contains("\n\n\n\n"));
     }
 
+    #[test]
+    fn test_clean_markdown_idempotent() {
+        // Found by fuzzing: a stray CR before CRLF was only removed on a second pass
+        for input in ["a\r\r\nb", "\n\na\n \n\n\nb\r", "", "x\n\r\n"] {
+            let clean = clean_markdown(input);
+            assert_eq!(clean_markdown(&clean), clean, "input: {:?}", input);
+        }
+    }
+
+    #[test]
+    fn test_citation_footer() {
+        let retrieved = "2026-01-02T03:04:05.678Z".parse().unwrap();
+        assert_eq!(
+            citation_footer(
+                "https://example.com/a",
+                Some("A"),
+                Some(retrieved),
+                Some("CC0")
+            ),
+            "\n\n---\n\n- Source: <https://example.com/a>\n- Title: A\n\
+             - Retrieved: 2026-01-02T03:04:05Z\n- License: CC0\n"
+        );
+        assert_eq!(
+            citation_footer("https://example.com/a", None, None, None),
+            "\n\n---\n\n- Source: <https://example.com/a>\n"
+        );
+    }
+
+    #[test]
+    fn test_normalize_text() {
+        // BOM and NUL bytes removed
+        assert_eq!(normalize_text("\u{FEFF}# Title\0\n"), "# Title\n");
+
+        // Line endings normalized
+        assert_eq!(normalize_text("a\r\nb\rc\n"), "a\nb\nc\n");
+
+        // Tabs kept, other C0/C1 controls dropped
+        assert_eq!(normalize_text("a\tb\x07c\u{1B}d\u{85}e"), "a\tbcde");
+
+        // Regular unicode untouched
+        assert_eq!(normalize_text("caf\u{e9} \u{1F600}"), "caf\u{e9} \u{1F600}");
+    }
+
     #[test]
     fn test_is_meaningful_html() {
         // Meaningful HTML
@@ -125,7 +1017,7 @@ mod tests {
             </body>
             </html>
         "#;
-        assert!(is_meaningful_html(good_html));
+        assert!(assess_html(good_html).is_meaningful(DEFAULT_MIN_CONTENT_SCORE));
 
         // JS shell (minimal content)
         let js_shell = r#"
@@ -134,6 +1026,227 @@ mod tests {
             <body><div id="root"></div></body>
             </html>
         "#;
-        assert!(!is_meaningful_html(js_shell));
+        assert!(!assess_html(js_shell).is_meaningful(DEFAULT_MIN_CONTENT_SCORE));
+    }
+
+    #[test]
+    fn test_assess_html_nav_heavy_shell() {
+        // Lots of words, but all navigation links
+        let links: String = (0..40)
+            .map(|i| {
+                format!(
+                    "<li><a href=\"/section/{}\">Section number {}</a></li>",
+                    i, i
+                )
+            })
+            .collect();
+        let html = format!(
+            "<html><body><nav><ul>{}</ul></nav><div id=\"app\"></div></body></html>",
+            links
+        );
+
+        let quality = assess_html(&html);
+        assert!(quality.word_count > 100);
+        assert!(quality.link_density > 0.9);
+        assert!(!quality.is_meaningful(DEFAULT_MIN_CONTENT_SCORE));
+    }
+
+    #[test]
+    fn test_assess_html_ignores_scripts() {
+        let script: String = "var x = 1; ".repeat(200);
+        let html = format!(
+            "<html><body><script>{}</script><p>Hi</p></body></html>",
+            script
+        );
+
+        let quality = assess_html(&html);
+        assert_eq!(quality.word_count, 1);
+        assert!(!quality.is_meaningful(DEFAULT_MIN_CONTENT_SCORE));
+    }
+
+    #[test]
+    fn test_is_soft_not_found() {
+        let check = |html: &str| is_soft_not_found(html, &assess_html(html));
+
+        assert!(check(
+            "<html><head><title>Page Not Found | Example</title></head>\
+             <body><h1>Oops!</h1><p>Try the search.</p></body></html>"
+        ));
+        assert!(check("<html><body><h1>Error 404</h1></body></html>"));
+        assert!(!check("<html><body><h1>Error 4040</h1></body></html>"));
+        assert!(!check(
+            "<html><head><title>Docs</title></head><body><h1>Intro</h1></body></html>"
+        ));
+
+        let article = format!(
+            "<html><body><h1>Handling 404 errors</h1><p>{}</p></body></html>",
+            "word ".repeat(200)
+        );
+        assert!(!check(&article));
+    }
+
+    #[test]
+    fn test_index_page() {
+        let card = |n: u32| {
+            format!(
+                "<li><h2><a href=\"/posts/{n}\">Post {n}</a></h2>\
+                 <time datetime=\"2024-01-{n:02}\">Jan {n}</time><p>Short excerpt.</p></li>"
+            )
+        };
+        let html = format!(
+            "<html><body><nav><a href=\"/about\">About this site and us</a></nav>\
+             <ul>{}</ul><a href=\"#top\">Back to the top</a>\
+             <a href=\"/archive\">Older posts in the archive</a></body></html>",
+            (1..=5).map(card).collect::<String>()
+        );
+        let base = Url::parse("https://blog.example.com/").unwrap();
+
+        let links = index_links(&html, &base);
+        assert_eq!(links.len(), 6);
+        assert_eq!(links[0].title, "Post 1");
+        assert_eq!(links[0].url.as_str(), "https://blog.example.com/posts/1");
+        assert_eq!(links[0].date.as_deref(), Some("2024-01-01"));
+        assert_eq!(links[5].date, None);
+        assert!(is_index_page(&links, &assess_html(&html)));
+
+        let markdown = index_to_markdown(Some("Blog"), &links[..1], None);
+        assert_eq!(
+            markdown,
+            "# Blog\n\n- [Post 1](https://blog.example.com/posts/1) (2024-01-01)\n"
+        );
+
+        let prose = "word ".repeat(300);
+        let article = format!("<html><body><p>{}</p>{}</body></html>", prose, card(1));
+        let links = index_links(&article, &base);
+        assert!(!is_index_page(&links, &assess_html(&article)));
+
+        // A long article is one, references and all
+        let paragraph = format!(
+            "<p>{}</p>",
+            "Findings were consistent across cohorts. ".repeat(30)
+        );
+        let references = (1..=80)
+            .map(|n| {
+                format!("<li><a href=\"https://doi.org/10.1000/{n}\">Study of cohort {n}</a></li>")
+            })
+            .collect::<String>();
+        let article = format!(
+            "<html><body><article>{}<h2>References</h2><ol>{}</ol></article></body></html>",
+            paragraph.repeat(8),
+            references
+        );
+        let links = index_links(&article, &base);
+        let quality = assess_html(&article);
+        assert!(quality.prose_words < links.len() * INDEX_PROSE_WORDS_PER_LINK);
+        assert!(!is_index_page(&links, &quality));
+    }
+
+    #[test]
+    fn test_classify_page() {
+        let url = Url::parse("https://example.com/2024/post").unwrap();
+        let page = |head: &str| format!("<html><head>{}</head><body>x</body></html>", head);
+
+        assert_eq!(classify_page(&page(""), &url), PageType::Article);
+        let product = page(
+            r#"<script type="application/ld+json">
+            {"@context": "https://schema.org", "@graph": [{"@type": ["Product"]}]}</script>"#,
+        );
+        assert_eq!(classify_page(&product, &url), PageType::Product);
+        let thread = page(r#"<meta name="generator" content="Discourse 3.2">"#);
+        assert_eq!(classify_page(&thread, &url), PageType::Forum);
+        let docs = page(r#"<meta name="generator" content="Docusaurus v3">"#);
+        assert_eq!(classify_page(&docs, &url), PageType::Docs);
+
+        let docs_url = Url::parse("https://example.com/docs/install").unwrap();
+        assert_eq!(classify_page(&page(""), &docs_url), PageType::Docs);
+        let microdata = r#"<div itemscope itemtype="https://schema.org/QAPage">Q</div>"#;
+        assert_eq!(classify_page(microdata, &url), PageType::Forum);
+        let episode = r#"<div itemscope itemtype="https://schema.org/PodcastEpisode">E</div>"#;
+        assert_eq!(classify_page(episode, &url), PageType::Podcast);
+    }
+
+    #[test]
+    fn test_simhash() {
+        let article = "The committee met on Tuesday to discuss the new budget proposal, which \
+                       would increase funding for public libraries and extend opening hours \
+                       across the city while keeping the overall spending flat next year. \
+                       Members raised concerns about staffing and the timeline for hiring. \
+                       The chair said a final vote is expected before the end of the month, \
+                       after residents have had a chance to comment at two public meetings \
+                       scheduled for the library's main branch and the community centre. \
+                       Several councillors asked for a breakdown of costs by neighbourhood.";
+        let mirror = article.replace("Tuesday", "Wednesday");
+        let other = "Rust is a systems programming language focused on safety, speed and \
+                     concurrency. It achieves memory safety without garbage collection by \
+                     using a borrow checker that validates references at compile time. \
+                     Cargo, its package manager, downloads dependencies, compiles packages \
+                     and runs tests, while the standard library provides collections, \
+                     threads, file and network I/O and a small set of synchronization \
+                     primitives shared by most programs written in the language today.";
+
+        let original = simhash(article).unwrap();
+        assert_eq!(simhash(article), Some(original));
+        assert!(is_near_duplicate(original, simhash(&mirror).unwrap()));
+        assert!(!is_near_duplicate(original, simhash(other).unwrap()));
+        assert_eq!(simhash("Too short to fingerprint"), None);
+    }
+
+    #[test]
+    fn test_similarity() {
+        let text = "The chair said a final vote is expected before the end of the month.";
+        assert_eq!(similarity(text, text), 1.0);
+        assert_eq!(similarity(text, &text.to_uppercase()), 1.0);
+        assert_eq!(
+            similarity(text, "Rust is a systems programming language."),
+            0.0
+        );
+        let partial = similarity(text, "The chair said a final vote is expected soon.");
+        assert!(partial > 0.3 && partial < 0.7, "{}", partial);
+        assert_eq!(similarity("", ""), 1.0);
+        assert_eq!(similarity("Short", "short"), 1.0);
+    }
+
+    #[test]
+    fn test_assess_html_short_page() {
+        // A short legitimate page should still pass
+        let html = r#"
+            <html><body>
+                <header><a href="/">Home</a></header>
+                <main><p>Our office is closed on public holidays. Please email us and we will
+                reply on the next working day.</p></main>
+            </body></html>
+        "#;
+        let quality = assess_html(html);
+        assert!(
+            quality.is_meaningful(DEFAULT_MIN_CONTENT_SCORE),
+            "score was {}",
+            quality.score
+        );
+        assert!(quality.score < 0.5);
+    }
+
+    #[test]
+    fn test_fallback_title() {
+        let html = r#"<html><head><title>
+            Release   notes</title><meta property="og:title" content="Notes"></head>
+            <body><h1>Heading</h1></body></html>"#;
+        assert_eq!(
+            fallback_title(html),
+            Some(("Release notes".to_string(), TitleSource::TitleTag))
+        );
+
+        let html = r#"<head><title> </title><meta property="og:title" content="Notes"></head>
+            <body><h1>Heading</h1></body>"#;
+        assert_eq!(
+            fallback_title(html),
+            Some(("Notes".to_string(), TitleSource::OpenGraph))
+        );
+
+        let html = "<body><h1></h1><h1>Getting <em>started</em></h1></body>";
+        assert_eq!(
+            fallback_title(html),
+            Some(("Getting started".to_string(), TitleSource::Heading))
+        );
+        assert_eq!(fallback_title("<p>No title here</p>"), None);
     }
 }
@@ -0,0 +1,246 @@
+//! Cookies for the fetches of one operation
+//!
+//! Consent walls and session-gated sites set a cookie on the first response
+//! (often a redirect) and expect it back on the next request. Each
+//! [`FetchBudget`](crate::fetch::FetchBudget) carries its own jar, so cookies
+//! live exactly as long as the client request, pagination walk or refresh
+//! that received them and are never shared between operations.
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use std::sync::Mutex;
+use url::Url;
+
+/// Cookies kept per operation, so a misbehaving site can't grow the jar forever
+const MAX_COOKIES: usize = 50;
+
+#[derive(Clone, Debug, PartialEq)]
+struct Cookie {
+    name: String,
+    value: String,
+    /// Lowercase host or `Domain` attribute
+    domain: String,
+    /// Sent to `domain` only, not its subdomains (no `Domain` attribute)
+    host_only: bool,
+    path: String,
+    secure: bool,
+}
+
+/// In-memory cookie jar for one operation
+#[derive(Debug, Default)]
+pub struct CookieJar {
+    cookies: Mutex<Vec<Cookie>>,
+}
+
+impl CookieJar {
+    /// Store the cookies of `Set-Cookie` headers received from `url`
+    pub fn store<'a>(&self, url: &Url, set_cookies: impl IntoIterator<Item = &'a str>) {
+        let Some(host) = url.host_str().map(str::to_ascii_lowercase) else {
+            return;
+        };
+        let mut cookies = self.cookies.lock().expect("cookie jar lock poisoned");
+        for header in set_cookies {
+            let Some((cookie, expired)) = parse_set_cookie(header, &host, url.path()) else {
+                continue;
+            };
+            cookies.retain(|c| {
+                (&c.name, &c.domain, &c.path) != (&cookie.name, &cookie.domain, &cookie.path)
+            });
+            if !expired && cookies.len() < MAX_COOKIES {
+                cookies.push(cookie);
+            }
+        }
+    }
+
+    /// `Cookie` header value for a request to `url`, if any cookies apply
+    pub fn header_for(&self, url: &Url) -> Option<String> {
+        let host = url.host_str()?.to_ascii_lowercase();
+        let cookies = self.cookies.lock().expect("cookie jar lock poisoned");
+        let matching: Vec<String> = cookies
+            .iter()
+            .filter(|c| {
+                let domain =
+                    host == c.domain || (!c.host_only && host.ends_with(&format!(".{}", c.domain)));
+                domain
+                    && path_matches(url.path(), &c.path)
+                    && (!c.secure || url.scheme() == "https")
+            })
+            .map(|c| format!("{}={}", c.name, c.value))
+            .collect();
+        (!matching.is_empty()).then(|| matching.join("; "))
+    }
+}
+
+/// Parse a `Set-Cookie` header from `host`, with whether it deletes the cookie
+///
+/// Returns `None` for malformed cookies and ones claiming a domain `host`
+/// isn't part of, or a public suffix such as `co.uk` that would share them
+/// with unrelated sites. Expiry dates aren't tracked: an operation is over
+/// long before any cookie would expire, so only deletions (a `Max-Age` of 0
+/// or less, or else an `Expires` in the past) matter.
+fn parse_set_cookie(header: &str, host: &str, request_path: &str) -> Option<(Cookie, bool)> {
+    let mut parts = header.split(';');
+    let (name, value) = parts.next()?.split_once('=')?;
+    let name = name.trim();
+    if name.is_empty() {
+        return None;
+    }
+
+    let mut cookie = Cookie {
+        name: name.to_string(),
+        value: value.trim().to_string(),
+        domain: host.to_string(),
+        host_only: true,
+        path: default_path(request_path),
+        secure: false,
+    };
+    let mut max_age = None;
+    let mut expires = None;
+    for attribute in parts {
+        let (key, value) = attribute.split_once('=').unwrap_or((attribute, ""));
+        let value = value.trim();
+        match key.trim().to_ascii_lowercase().as_str() {
+            "domain" if !value.is_empty() => {
+                let domain = value.trim_start_matches('.').to_ascii_lowercase();
+                if host != domain && !host.ends_with(&format!(".{}", domain)) {
+                    return None;
+                }
+                // A suffix the host itself is (`localhost`) keeps the cookie
+                // host-only (RFC 6265 5.3 step 5)
+                if is_public_suffix(&domain) {
+                    if host != domain {
+                        return None;
+                    }
+                    continue;
+                }
+                cookie.domain = domain;
+                cookie.host_only = false;
+            }
+            "path" if value.starts_with('/') => cookie.path = value.to_string(),
+            "secure" => cookie.secure = true,
+            "max-age" => max_age = value.parse::<i64>().ok().or(max_age),
+            "expires" => expires = cookie_date(value).or(expires),
+            _ => {}
+        }
+    }
+    // Max-Age takes precedence over Expires
+    let expired = match (max_age, expires) {
+        (Some(secs), _) => secs <= 0,
+        (None, Some(expires)) => expires <= Utc::now(),
+        (None, None) => false,
+    };
+    Some((cookie, expired))
+}
+
+/// Whether cookies may not be scoped to `domain`: a public suffix, or a
+/// single label, which the list's implicit `*` rule makes one
+fn is_public_suffix(domain: &str) -> bool {
+    psl::suffix(domain.as_bytes()).is_none_or(|suffix| suffix.as_bytes() == domain.as_bytes())
+}
+
+/// `Expires` date, in the preferred IMF-fixdate form or the `dd-Mon-yyyy`
+/// variant many servers still send
+fn cookie_date(value: &str) -> Option<DateTime<Utc>> {
+    if let Ok(date) = DateTime::parse_from_rfc2822(value) {
+        return Some(date.to_utc());
+    }
+    NaiveDateTime::parse_from_str(value, "%a, %d-%b-%Y %H:%M:%S GMT")
+        .ok()
+        .map(|date| date.and_utc())
+}
+
+/// Directory of the request path, the default cookie path (RFC 6265 5.1.4)
+fn default_path(request_path: &str) -> String {
+    match request_path.rfind('/') {
+        Some(0) | None => "/".to_string(),
+        Some(end) => request_path[..end].to_string(),
+    }
+}
+
+/// Whether a cookie with `cookie_path` is sent for `request_path`
+fn path_matches(request_path: &str, cookie_path: &str) -> bool {
+    request_path == cookie_path
+        || (request_path.starts_with(cookie_path)
+            && (cookie_path.ends_with('/') || request_path[cookie_path.len()..].starts_with('/')))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cookie_jar() {
+        let jar = CookieJar::default();
+        let url = |s: &str| Url::parse(s).unwrap();
+        jar.store(
+            &url("https://www.example.com/consent"),
+            [
+                "consent=yes; Path=/; Domain=example.com; Max-Age=31536000",
+                "session=abc; Secure; HttpOnly",
+                "tracker=1; Domain=other.example",
+                "docs=1; Path=/docs",
+            ],
+        );
+
+        assert_eq!(
+            jar.header_for(&url("https://www.example.com/article"))
+                .as_deref(),
+            Some("consent=yes; session=abc")
+        );
+        // Host-only and secure cookies stay on their host and scheme
+        assert_eq!(
+            jar.header_for(&url("http://news.example.com/")).as_deref(),
+            Some("consent=yes")
+        );
+        assert_eq!(
+            jar.header_for(&url("https://www.example.com/docs/intro"))
+                .as_deref(),
+            Some("consent=yes; session=abc; docs=1")
+        );
+        assert_eq!(
+            jar.header_for(&url("https://www.example.com/docsx"))
+                .as_deref(),
+            Some("consent=yes; session=abc")
+        );
+        assert_eq!(jar.header_for(&url("https://other.example/")), None);
+
+        // Max-Age=0 deletes
+        jar.store(&url("https://www.example.com/"), ["session=; Max-Age=0"]);
+        assert_eq!(
+            jar.header_for(&url("https://www.example.com/")).as_deref(),
+            Some("consent=yes")
+        );
+    }
+
+    #[test]
+    fn test_domain_and_expiry() {
+        let parse = |header: &str, host: &str| parse_set_cookie(header, host, "/");
+
+        // Public suffixes and single labels would reach other sites
+        assert!(parse("a=1; Domain=co.uk", "shop.example.co.uk").is_none());
+        assert!(parse("a=1; Domain=github.io", "someone.github.io").is_none());
+        assert!(parse("a=1; Domain=internal", "wiki.internal").is_none());
+        let (cookie, _) = parse("a=1; Domain=example.co.uk", "shop.example.co.uk").unwrap();
+        assert!(!cookie.host_only);
+        // Unless the host is that suffix, which keeps the cookie to itself
+        let (cookie, _) = parse("a=1; Domain=localhost", "localhost").unwrap();
+        assert!(cookie.host_only);
+
+        let expired = |header: &str| parse(header, "example.com").unwrap().1;
+        assert!(expired("a=; Expires=Thu, 01 Jan 1970 00:00:00 GMT"));
+        assert!(expired("a=; expires=Thu, 01-Jan-1970 00:00:01 GMT"));
+        assert!(!expired("a=1; Expires=Fri, 01 Jan 2100 00:00:00 GMT"));
+        assert!(expired("a=1; Max-Age=-1"));
+        // Max-Age wins over Expires
+        assert!(!expired(
+            "a=1; Expires=Thu, 01 Jan 1970 00:00:00 GMT; Max-Age=60"
+        ));
+        assert!(!expired("a=1; Expires=soon"));
+    }
+
+    #[test]
+    fn test_default_path() {
+        assert_eq!(default_path("/"), "/");
+        assert_eq!(default_path("/consent"), "/");
+        assert_eq!(default_path("/docs/page"), "/docs");
+    }
+}
@@ -0,0 +1,259 @@
+//! HTTP Basic credentials for upstream hosts
+//!
+//! Internal documentation behind HTTP Basic auth can be converted without
+//! putting a password in the requested URL (which is rejected anyway): the
+//! operator lists credentials per domain in `UPSTREAM_BASIC_AUTH` or in the
+//! file at `UPSTREAM_BASIC_AUTH_FILE`, as `domain=user:password` entries,
+//! and the fetcher sends them to hosts matching the domain. A domain is a
+//! pattern as in `ALLOWED_DOMAINS`, covering its subdomains, and the first
+//! matching entry wins. Each hop of a redirect chain gets the credentials of
+//! its own host, so they never follow a redirect to another site. A rotated
+//! file replaces the entries of every copy (see [`crate::secrets`]).
+//!
+//! Credentials only go over HTTPS to the default port, where nobody on the
+//! way can read them, unless the entry names another scheme or port, as in
+//! `http://wiki.corp.example:8080=reader:s3cret`.
+
+use crate::domains::{parse_domain_pattern, pattern_matches};
+use crate::secrets::REDACTED;
+use serde::ser::{Serialize, SerializeMap, Serializer};
+use std::sync::{Arc, RwLock};
+use url::Url;
+
+/// A user and password for the hosts matching a domain pattern, on one
+/// scheme and port
+#[derive(Clone)]
+struct Entry {
+    pattern: String,
+    scheme: String,
+    port: u16,
+    user: String,
+    password: String,
+}
+
+impl Entry {
+    /// The entry's `[scheme://]domain[:port]`, leaving out the defaults
+    fn target(&self) -> String {
+        let mut target = self.pattern.clone();
+        if self.port != default_port(&self.scheme) {
+            target = format!("{}:{}", target, self.port);
+        }
+        if self.scheme != "https" || self.port != 443 {
+            target = format!("{}://{}", self.scheme, target);
+        }
+        target
+    }
+}
+
+/// Basic auth credentials by upstream domain, shared by every copy
+#[derive(Clone, Default)]
+pub struct UpstreamCredentials {
+    entries: Arc<RwLock<Vec<Entry>>>,
+}
+
+impl UpstreamCredentials {
+    /// Parse `domain=user:password` entries separated by commas or newlines,
+    /// skipping blank lines and `#` comments
+    ///
+    /// The password is everything after the first `:`, so it may contain
+    /// colons but not commas.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let entries = s
+            .split(['\n', ','])
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty() && !entry.starts_with('#'))
+            .map(|entry| {
+                let (domain, login) = entry
+                    .split_once('=')
+                    .ok_or_else(|| format!("'{}' is not domain=user:password", redact(entry)))?;
+                let (user, password) = login
+                    .split_once(':')
+                    .ok_or_else(|| format!("no password for '{}'", domain.trim()))?;
+                if user.trim().is_empty() {
+                    return Err(format!("no user for '{}'", domain.trim()));
+                }
+                let (scheme, pattern, port) = parse_target(domain)?;
+                Ok(Entry {
+                    pattern,
+                    scheme,
+                    port,
+                    user: user.trim().to_string(),
+                    password: password.to_string(),
+                })
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(Self {
+            entries: Arc::new(RwLock::new(entries)),
+        })
+    }
+
+    /// Credentials from both sources, the entries of `self` first
+    pub fn merged(self, other: Self) -> Self {
+        let other = other
+            .entries
+            .read()
+            .expect("credentials lock poisoned")
+            .clone();
+        self.entries
+            .write()
+            .expect("credentials lock poisoned")
+            .extend(other);
+        self
+    }
+
+    /// Swap in the entries of `other`, for this and every copy
+    pub fn replace(&self, other: &Self) {
+        let entries = other
+            .entries
+            .read()
+            .expect("credentials lock poisoned")
+            .clone();
+        *self.entries.write().expect("credentials lock poisoned") = entries;
+    }
+
+    /// User and password to send with a request for `url`, if any are
+    /// configured for its host, scheme and port
+    pub fn for_url(&self, url: &Url) -> Option<(String, String)> {
+        let host = url.host_str()?.trim_end_matches('.').to_ascii_lowercase();
+        let port = url.port_or_known_default()?;
+        let entries = self.entries.read().expect("credentials lock poisoned");
+        entries
+            .iter()
+            .filter(|entry| entry.scheme == url.scheme() && entry.port == port)
+            .find(|entry| pattern_matches(&entry.pattern, &host))
+            .map(|entry| (entry.user.clone(), entry.password.clone()))
+    }
+}
+
+/// Split `[scheme://]domain[:port]` into the scheme, domain pattern and port,
+/// HTTPS and its default port unless given
+fn parse_target(s: &str) -> Result<(String, String, u16), String> {
+    let s = s.trim();
+    let (scheme, rest) = match s.split_once("://") {
+        Some((scheme, rest)) => (scheme.to_ascii_lowercase(), rest),
+        None => ("https".to_string(), s),
+    };
+    if !matches!(scheme.as_str(), "http" | "https") {
+        return Err(format!("unsupported scheme '{}' for '{}'", scheme, s));
+    }
+    let (domain, port) = match rest.rsplit_once(':') {
+        Some((domain, port)) => {
+            let port = port
+                .parse()
+                .map_err(|_| format!("invalid port for '{}'", s))?;
+            (domain, port)
+        }
+        None => (rest, default_port(&scheme)),
+    };
+    Ok((scheme, parse_domain_pattern(domain)?, port))
+}
+
+fn default_port(scheme: &str) -> u16 {
+    if scheme == "http" {
+        80
+    } else {
+        443
+    }
+}
+
+/// An entry with whatever follows the first `:` hidden, for error messages
+fn redact(entry: &str) -> String {
+    match entry.split_once(':') {
+        Some((before, _)) => format!("{}:…", before),
+        None => entry.to_string(),
+    }
+}
+
+/// Domains and users only, passwords redacted
+impl Serialize for UpstreamCredentials {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let entries = self.entries.read().expect("credentials lock poisoned");
+        let mut map = serializer.serialize_map(Some(entries.len()))?;
+        for entry in entries.iter() {
+            map.serialize_entry(&entry.target(), &format!("{}:{}", entry.user, REDACTED))?;
+        }
+        map.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_match() {
+        let credentials = UpstreamCredentials::parse(
+            "# internal docs\nwiki.corp.example=reader:pa:ss word\n\n,*.staging.example = ci:s3cret",
+        )
+        .unwrap();
+        let login =
+            |host: &str| credentials.for_url(&Url::parse(&format!("https://{}/", host)).unwrap());
+        let expected = |user: &str, password: &str| Some((user.to_string(), password.to_string()));
+        assert_eq!(login("wiki.corp.example"), expected("reader", "pa:ss word"));
+        assert_eq!(
+            login("EN.Wiki.Corp.Example."),
+            expected("reader", "pa:ss word")
+        );
+        assert_eq!(login("docs.staging.example"), expected("ci", "s3cret"));
+        assert_eq!(login("corp.example"), None);
+
+        let copy = credentials.clone();
+        copy.replace(&UpstreamCredentials::parse("corp.example=admin:rotated").unwrap());
+        assert_eq!(login("corp.example"), expected("admin", "rotated"));
+        assert_eq!(login("wiki.corp.example"), expected("admin", "rotated"));
+    }
+
+    #[test]
+    fn test_scheme_and_port() {
+        let credentials = UpstreamCredentials::parse(
+            "wiki.example=reader:hunter2, http://legacy.example:8080=old:pass, https://api.example:8443=ci:key",
+        )
+        .unwrap();
+        let login = |url: &str| {
+            credentials
+                .for_url(&Url::parse(url).unwrap())
+                .map(|(user, _)| user)
+        };
+        assert_eq!(
+            login("https://wiki.example/page").as_deref(),
+            Some("reader")
+        );
+        assert_eq!(
+            login("https://wiki.example:443/page").as_deref(),
+            Some("reader")
+        );
+        // Never in the clear, nor to another service on the host
+        assert_eq!(login("http://wiki.example/page"), None);
+        assert_eq!(login("https://wiki.example:8443/page"), None);
+        assert_eq!(login("http://legacy.example:8080/").as_deref(), Some("old"));
+        assert_eq!(login("http://legacy.example/"), None);
+        assert_eq!(login("https://api.example:8443/").as_deref(), Some("ci"));
+
+        let json = serde_json::to_value(&credentials).unwrap();
+        assert_eq!(json["http://legacy.example:8080"], "old:<redacted>");
+        assert_eq!(json["https://api.example:8443"], "ci:<redacted>");
+        assert!(UpstreamCredentials::parse("ftp://files.example=a:b").is_err());
+        assert!(UpstreamCredentials::parse("wiki.example:port=a:b").is_err());
+    }
+
+    #[test]
+    fn test_parse_errors_hide_passwords() {
+        let err = UpstreamCredentials::parse("wiki.example:hunter2")
+            .err()
+            .unwrap();
+        assert!(!err.contains("hunter2"), "{}", err);
+        assert!(UpstreamCredentials::parse("wiki.example=reader").is_err());
+        assert!(UpstreamCredentials::parse("wiki.example=:hunter2").is_err());
+        assert!(UpstreamCredentials::parse("*=reader:hunter2").is_err());
+    }
+
+    #[test]
+    fn test_serialize_redacts_passwords() {
+        let credentials = UpstreamCredentials::parse("wiki.example=reader:hunter2").unwrap();
+        let json = serde_json::to_value(&credentials).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({"wiki.example": "reader:<redacted>"})
+        );
+    }
+}
@@ -0,0 +1,231 @@
+//! Resolution of relative dates in converted text
+//!
+//! "3 days ago" in a cached page means three days before it was fetched, not
+//! before it is read, and agents reading it later get that wrong. Resolving
+//! appends the absolute date: `3 days ago (2024-03-01)`, to the day for
+//! seconds to weeks, the month for months and the year for years.
+//!
+//! Phrases resolve against the fetch time. A page can't mention something
+//! that happened after its own last change, though, so when that would put a
+//! date past the page's declared modified (or published) date, the page was
+//! evidently rendered earlier and the phrase resolves against that date.
+
+use chrono::{DateTime, Days, Months, Utc};
+use regex::{Captures, Regex};
+use std::ops::Range;
+use std::sync::LazyLock;
+
+static RELATIVE_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"(?ix)\b(?:
+            (\d{1,4}|an?|one|two|three|four|five|six|seven|eight|nine|ten)\s+
+            (second|minute|hour|day|week|month|year)s?\s+ago
+            | yesterday
+            | last\s+(week|month|year)
+        )\b",
+    )
+    .expect("valid regex")
+});
+
+/// How far back a phrase points
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Offset {
+    Seconds(u64),
+    Days(u64),
+    Months(u32),
+    Years(u32),
+}
+
+impl Offset {
+    fn parse(caps: &Captures) -> Option<Self> {
+        let phrase = caps[0].to_ascii_lowercase();
+        let (count, unit) = if phrase == "yesterday" {
+            (1, "day")
+        } else if let Some(unit) = caps.get(3) {
+            (1, unit.as_str())
+        } else {
+            (count(&caps[1])?, &caps[2])
+        };
+        Some(match unit.to_ascii_lowercase().as_str() {
+            "second" => Offset::Seconds(count),
+            "minute" => Offset::Seconds(count * 60),
+            "hour" => Offset::Seconds(count * 3600),
+            "day" => Offset::Days(count),
+            "week" => Offset::Days(count * 7),
+            "month" => Offset::Months(count.try_into().ok()?),
+            _ => Offset::Years(count.try_into().ok()?),
+        })
+    }
+
+    /// The date `self` before `reference`, as precise as the phrase is
+    fn before(self, reference: DateTime<Utc>) -> Option<(DateTime<Utc>, &'static str)> {
+        match self {
+            Offset::Seconds(secs) => {
+                let secs = i64::try_from(secs).ok()?;
+                Some((reference - chrono::Duration::seconds(secs), "%Y-%m-%d"))
+            }
+            Offset::Days(days) => Some((reference.checked_sub_days(Days::new(days))?, "%Y-%m-%d")),
+            Offset::Months(months) => {
+                Some((reference.checked_sub_months(Months::new(months))?, "%Y-%m"))
+            }
+            Offset::Years(years) => Some((
+                reference.checked_sub_months(Months::new(years.checked_mul(12)?))?,
+                "%Y",
+            )),
+        }
+    }
+}
+
+fn count(word: &str) -> Option<u64> {
+    const WORDS: [&str; 10] = [
+        "one", "two", "three", "four", "five", "six", "seven", "eight", "nine", "ten",
+    ];
+    let word = word.to_ascii_lowercase();
+    if word == "a" || word == "an" {
+        return Some(1);
+    }
+    match WORDS.iter().position(|w| *w == word) {
+        Some(index) => Some(index as u64 + 1),
+        None => word.parse().ok(),
+    }
+}
+
+/// Append the absolute date after each relative date in `markdown`, outside
+/// code blocks and code spans, resolved against `fetched_at` or, if that
+/// would put it past the page's `declared` last change, against that
+pub fn resolve_relative_dates(
+    markdown: &str,
+    fetched_at: DateTime<Utc>,
+    declared: Option<DateTime<Utc>>,
+) -> String {
+    let mut result = String::with_capacity(markdown.len());
+    // Marker of the open code fence, which only the same marker closes
+    let mut fence = None;
+    for line in markdown.split_inclusive('\n') {
+        let trimmed = line.trim_start();
+        let marker = ["```", "~~~"].into_iter().find(|m| trimmed.starts_with(m));
+        match (fence, marker) {
+            (None, Some(marker)) => fence = Some(marker),
+            (Some(open), Some(marker)) if open == marker => {
+                fence = None;
+                result.push_str(line);
+                continue;
+            }
+            _ => {}
+        }
+        if fence.is_some() || !RELATIVE_RE.is_match(line) {
+            result.push_str(line);
+            continue;
+        }
+
+        let code = code_spans(line);
+        let mut last = 0;
+        for caps in RELATIVE_RE.captures_iter(line) {
+            let phrase = caps.get(0).expect("whole match");
+            result.push_str(&line[last..phrase.end()]);
+            last = phrase.end();
+            if code.iter().any(|span| span.contains(&phrase.start())) {
+                continue;
+            }
+            // Already resolved, or resolved by the page itself
+            if line[last..].starts_with(" (") {
+                continue;
+            }
+            if let Some(date) = resolve(&caps, fetched_at, declared) {
+                result.push_str(&format!(" ({})", date));
+            }
+        }
+        result.push_str(&line[last..]);
+    }
+    result
+}
+
+/// Byte ranges of the inline code spans in `line`, backticks included: a run
+/// of backticks up to the next run of the same length
+fn code_spans(line: &str) -> Vec<Range<usize>> {
+    let bytes = line.as_bytes();
+    let mut runs = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let start = i;
+        while i < bytes.len() && bytes[i] == b'`' {
+            i += 1;
+        }
+        if i > start {
+            runs.push((start, i - start));
+        } else {
+            i += 1;
+        }
+    }
+
+    let mut spans = Vec::new();
+    let mut open = 0;
+    while open < runs.len() {
+        let (start, len) = runs[open];
+        match runs[open + 1..].iter().position(|&(_, l)| l == len) {
+            Some(offset) => {
+                let (end, _) = runs[open + 1 + offset];
+                spans.push(start..end + len);
+                open += offset + 2;
+            }
+            // Unmatched backticks are literal
+            None => open += 1,
+        }
+    }
+    spans
+}
+
+fn resolve(
+    caps: &Captures,
+    fetched_at: DateTime<Utc>,
+    declared: Option<DateTime<Utc>>,
+) -> Option<String> {
+    let offset = Offset::parse(caps)?;
+    let (mut date, mut format) = offset.before(fetched_at)?;
+    if let Some(declared) = declared.filter(|d| d.date_naive() < date.date_naive()) {
+        (date, format) = offset.before(declared)?;
+    }
+    Some(date.format(format).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_resolve_relative_dates() {
+        let fetched = Utc.with_ymd_and_hms(2024, 3, 10, 12, 0, 0).unwrap();
+        let text = "Posted 3 days ago, edited an hour ago.\n\
+                    Launched two months ago, founded 4 years ago; updated yesterday.\n\
+                    ```\nlast week\n```\n\
+                    ~~~\n```\nlast week\n~~~\n\
+                    Run `touch -d \"2 days ago\"` or ``a day ago``, not `one day ago.\n\
+                    Checked last week (2024-03-03). Nothing 3 days agoing here.\n";
+        assert_eq!(
+            resolve_relative_dates(text, fetched, None),
+            "Posted 3 days ago (2024-03-07), edited an hour ago (2024-03-10).\n\
+             Launched two months ago (2024-01), founded 4 years ago (2020); \
+             updated yesterday (2024-03-09).\n\
+             ```\nlast week\n```\n\
+             ~~~\n```\nlast week\n~~~\n\
+             Run `touch -d \"2 days ago\"` or ``a day ago``, not `one day ago (2024-03-09).\n\
+             Checked last week (2024-03-03). Nothing 3 days agoing here.\n"
+        );
+    }
+
+    #[test]
+    fn test_declared_date_bounds_resolution() {
+        let fetched = Utc.with_ymd_and_hms(2024, 3, 10, 12, 0, 0).unwrap();
+        let modified = Utc.with_ymd_and_hms(2023, 6, 1, 0, 0, 0).unwrap();
+        assert_eq!(
+            resolve_relative_dates("Released 2 weeks ago", fetched, Some(modified)),
+            "Released 2 weeks ago (2023-05-18)"
+        );
+        // Far enough back to fit before the page's last change
+        assert_eq!(
+            resolve_relative_dates("Founded 3 years ago", fetched, Some(modified)),
+            "Founded 3 years ago (2021)"
+        );
+    }
+}
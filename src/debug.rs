@@ -0,0 +1,239 @@
+//! `/debug/{url}`: how the pipeline handled a page, for diagnosing bad
+//! conversions without a local checkout
+//!
+//! The page is fetched fresh, bypassing the cache, and taken through the same
+//! stages as a proxied HTML page, reporting what each one saw and how long it
+//! took. Markdown alternates are listed rather than followed.
+
+use crate::AppState;
+use axum::{
+    extract::{Path, State},
+    http::{header, HeaderMap},
+    response::{IntoResponse, Response},
+    Json,
+};
+use mdwnio::convert::{self, PageType};
+use mdwnio::error::{MdwnError, Result};
+use mdwnio::parse::{
+    categorize_content_type, parse_accept_language, parse_html_for_markdown_link,
+    parse_html_metadata, parse_request_url, HtmlParseResult,
+};
+use mdwnio::store::Redirect;
+use scraper::{Html, Selector};
+use serde::Serialize;
+use std::time::Instant;
+use url::Url;
+
+/// Pipeline breakdown for one URL
+#[derive(Serialize)]
+struct Breakdown {
+    url: String,
+    /// Target of the rewrite rule the URL matched, if any
+    rewritten_to: Option<String>,
+    /// Redirects followed, in order
+    redirects: Vec<Redirect>,
+    /// URL after redirects
+    final_url: String,
+    status: u16,
+    content_type: Option<String>,
+    /// How the content type is handled: `markdown`, `plain_text`, `html`,
+    /// `json` or `unsupported`
+    category: &'static str,
+    bytes: usize,
+    html: Option<HtmlBreakdown>,
+    /// Post-processing rules that apply to the host, in order
+    site_rules: Vec<String>,
+    /// Duration of each stage, in order
+    timings: Vec<Timing>,
+}
+
+#[derive(Serialize)]
+struct HtmlBreakdown {
+    title: Option<String>,
+    page_type: PageType,
+    alternates: Vec<Alternate>,
+    /// The markdown alternate that would be fetched instead of converting
+    markdown_alternate: Option<String>,
+    score: f32,
+    min_score: f32,
+    word_count: usize,
+    prose_words: usize,
+    link_density: f32,
+    soft_not_found: bool,
+    index_page: bool,
+    /// Title of the converted markdown
+    article_title: Option<String>,
+    /// Where `article_title` came from: readability, or a fallback when it
+    /// found none
+    article_title_source: Option<convert::TitleSource>,
+    /// Size of the HTML readability kept
+    extracted_bytes: usize,
+    /// Markdown before and after cleanup
+    markdown_bytes: usize,
+    cleaned_bytes: usize,
+}
+
+/// A `<link rel="alternate">` of the page
+#[derive(Serialize)]
+struct Alternate {
+    href: String,
+    #[serde(rename = "type")]
+    content_type: Option<String>,
+    hreflang: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Timing {
+    stage: &'static str,
+    ms: f64,
+}
+
+/// Records how long each stage takes
+#[derive(Default)]
+struct Timings(Vec<Timing>);
+
+impl Timings {
+    fn time<T>(&mut self, stage: &'static str, work: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let value = work();
+        self.record(stage, start);
+        value
+    }
+
+    fn record(&mut self, stage: &'static str, start: Instant) {
+        let ms = start.elapsed().as_secs_f64() * 1000.0;
+        self.0.push(Timing { stage, ms });
+    }
+}
+
+/// JSON breakdown of how a page goes through the pipeline
+pub async fn debug_handler(
+    State(state): State<AppState>,
+    Path(url_path): Path<String>,
+    headers: HeaderMap,
+) -> Result<Response> {
+    let langs = headers
+        .get(header::ACCEPT_LANGUAGE)
+        .and_then(|v| v.to_str().ok())
+        .map(parse_accept_language)
+        .unwrap_or_default();
+    Ok(Json(breakdown(&state, &url_path, &langs).await?).into_response())
+}
+
+async fn breakdown(state: &AppState, url_path: &str, langs: &[String]) -> Result<Breakdown> {
+    let mut timings = Timings::default();
+    let requested = parse_request_url(url_path)?;
+    let url = state.fetcher.parse_url(url_path)?;
+    let rewritten_to = (url != requested).then(|| url.to_string());
+
+    let start = Instant::now();
+    let budget = state.fetcher.budget();
+    let response = state.fetcher.fetch_allowing_errors(&url, &budget).await?;
+    timings.record("fetch", start);
+
+    let category = categorize_content_type(response.mime_type()).name();
+    let html = if category == "html" {
+        let html = response.body_as_string();
+        let min_score = state.config.min_content_score;
+        Some(html_breakdown(
+            &html,
+            &response.final_url,
+            langs,
+            min_score,
+            &mut timings,
+        )?)
+    } else {
+        None
+    };
+
+    let host = response.final_url.host_str().unwrap_or_default();
+    let site_rules = state
+        .config
+        .postprocess
+        .rules_for(host)
+        .map(|rule| rule.name.clone())
+        .collect();
+
+    Ok(Breakdown {
+        url: requested.to_string(),
+        rewritten_to,
+        redirects: response.redirects.clone(),
+        final_url: response.final_url.to_string(),
+        status: response.status,
+        content_type: response.content_type.clone(),
+        category,
+        bytes: response.body.len(),
+        html,
+        site_rules,
+        timings: timings.0,
+    })
+}
+
+fn html_breakdown(
+    html: &str,
+    url: &Url,
+    langs: &[String],
+    min_score: f32,
+    timings: &mut Timings,
+) -> Result<HtmlBreakdown> {
+    let (title, page_type, alternates, markdown_alternate) = timings.time("parse", || {
+        let alternate = match parse_html_for_markdown_link(html, url, langs)? {
+            HtmlParseResult::MarkdownLink(md_url) => Some(md_url.to_string()),
+            HtmlParseResult::NeedsConversion => None,
+        };
+        Ok::<_, MdwnError>((
+            parse_html_metadata(html).title,
+            convert::classify_page(html, url),
+            alternates(html, url),
+            alternate,
+        ))
+    })?;
+
+    let (quality, soft_not_found, index_page) = timings.time("quality", || {
+        let quality = convert::assess_html(html);
+        let soft_not_found = convert::is_soft_not_found(html, &quality);
+        let links = convert::index_links(html, url);
+        let index_page = convert::is_index_page(&links, &quality);
+        (quality, soft_not_found, index_page)
+    });
+
+    let article = timings.time("readability", || convert::extract_article(html, url))?;
+    let markdown = timings.time("htmd", || convert::article_to_markdown(&article.content))?;
+    let cleaned = timings.time("clean", || convert::clean_markdown(&markdown));
+
+    Ok(HtmlBreakdown {
+        title,
+        page_type,
+        alternates,
+        markdown_alternate,
+        score: quality.score,
+        min_score,
+        word_count: quality.word_count,
+        prose_words: quality.prose_words,
+        link_density: quality.link_density,
+        soft_not_found,
+        index_page,
+        article_title: Some(article.title).filter(|title| !title.is_empty()),
+        article_title_source: article.title_source,
+        extracted_bytes: article.content.len(),
+        markdown_bytes: markdown.len(),
+        cleaned_bytes: cleaned.len(),
+    })
+}
+
+/// Every `<link rel="alternate">` of a page, resolved against its URL
+fn alternates(html: &str, url: &Url) -> Vec<Alternate> {
+    let document = Html::parse_document(html);
+    let selector = Selector::parse("link[rel~='alternate'][href]").expect("valid selector");
+    document
+        .select(&selector)
+        .filter_map(|link| {
+            let href = url.join(link.value().attr("href")?).ok()?;
+            Some(Alternate {
+                href: href.to_string(),
+                content_type: link.value().attr("type").map(str::to_string),
+                hreflang: link.value().attr("hreflang").map(str::to_string),
+            })
+        })
+        .collect()
+}
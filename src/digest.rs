@@ -0,0 +1,492 @@
+//! Change digests for pages kept warm by the refresh scheduler
+//!
+//! When a refresh finds that a page's markdown changed, the change is queued.
+//! Every `DIGEST_INTERVAL` the queued changes go out together as one webhook
+//! POST and/or one summary email, instead of one notification per page.
+//!
+//! Webhooks carry an `X-Mdwn-Delivery` id and an `X-Mdwn-Timestamp`, and with
+//! `DIGEST_WEBHOOK_SECRET` an `X-Mdwn-Signature` of both, so receivers can
+//! check where a digest came from and drop replays. Deliveries that fail with
+//! a connection error, a 5xx or a 429 are tried again with growing delays,
+//! and every try is kept in a log of recent deliveries for the admin API.
+
+use crate::config::Config;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use lettre::message::Mailbox;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use mdwnio::error::{MdwnError, Result};
+use mdwnio::proxy;
+use mdwnio::secrets::Secret;
+use serde::Serialize;
+use sha2::Sha256;
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Webhook deliveries kept for `/admin/digests/deliveries`
+const DELIVERY_LOG_LEN: usize = 100;
+
+/// Longest wait between two tries of a webhook delivery
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// A page whose markdown changed during the digest window
+#[derive(Clone, Debug, Serialize)]
+pub struct Change {
+    pub url: String,
+    /// Content hash before the first change in the window
+    pub previous_hash: String,
+    /// Content hash after the latest change in the window
+    pub hash: String,
+    pub detected_at: DateTime<Utc>,
+}
+
+/// Webhook body: every change in one window
+#[derive(Debug, Serialize)]
+pub struct DigestPayload {
+    pub window_start: DateTime<Utc>,
+    pub window_end: DateTime<Utc>,
+    pub changes: Vec<Change>,
+}
+
+impl DigestPayload {
+    /// Plain-text summary for email
+    fn summary(&self) -> String {
+        let mut text = format!(
+            "{} page(s) changed between {} and {}:\n\n",
+            self.changes.len(),
+            self.window_start.format("%Y-%m-%d %H:%M UTC"),
+            self.window_end.format("%Y-%m-%d %H:%M UTC"),
+        );
+        for change in &self.changes {
+            let _ = writeln!(
+                text,
+                "- {} ({})",
+                change.url,
+                change.detected_at.to_rfc3339()
+            );
+        }
+        text
+    }
+}
+
+/// Changes queued since the last send
+struct Window {
+    start: DateTime<Utc>,
+    changes: Vec<Change>,
+}
+
+/// One try at delivering a digest webhook
+#[derive(Clone, Debug, Serialize)]
+pub struct Attempt {
+    pub at: DateTime<Utc>,
+    /// Response status, `None` if none came
+    pub status: Option<u16>,
+    /// What went wrong, `None` if the webhook accepted the digest
+    pub error: Option<String>,
+}
+
+/// A digest sent to the webhook, with every try at it
+#[derive(Clone, Debug, Serialize)]
+pub struct Delivery {
+    /// `X-Mdwn-Delivery` header, the same on every try
+    pub id: String,
+    /// Pages whose changes the digest carried
+    pub urls: Vec<String>,
+    pub delivered: bool,
+    pub attempts: Vec<Attempt>,
+}
+
+/// Webhook delivery settings
+struct Webhook {
+    url: Secret,
+    secret: Option<Secret>,
+    max_attempts: usize,
+    backoff: Duration,
+}
+
+/// SMTP delivery settings
+struct Email {
+    /// Read when sending, so a rotated `DIGEST_SMTP_URL_FILE` takes effect
+    url: Secret,
+    from: Mailbox,
+    to: Vec<Mailbox>,
+}
+
+/// Batches detected changes and sends them as periodic digests
+#[derive(Clone)]
+pub struct Digests {
+    window: Arc<Mutex<Window>>,
+    webhook: Option<Arc<Webhook>>,
+    email: Option<Arc<Email>>,
+    client: reqwest::Client,
+    /// Recent webhook deliveries, oldest first
+    deliveries: Arc<Mutex<VecDeque<Delivery>>>,
+}
+
+impl Digests {
+    /// Digest sender for the configured destinations, or `None` if there are none
+    pub fn from_config(config: &Config) -> Result<Option<Self>> {
+        let email = match &config.digest_smtp_url {
+            Some(smtp_url) => {
+                AsyncSmtpTransport::<Tokio1Executor>::from_url(&smtp_url.expose())
+                    .map_err(|e| MdwnError::Internal(format!("Invalid DIGEST_SMTP_URL: {}", e)))?;
+                let parse = |address: &str| {
+                    address
+                        .trim()
+                        .parse::<Mailbox>()
+                        .map_err(|e| MdwnError::Internal(format!("Invalid address: {}", e)))
+                };
+                Some(Arc::new(Email {
+                    url: smtp_url.clone(),
+                    from: parse(config.digest_email_from.as_deref().unwrap_or_default())?,
+                    to: config
+                        .digest_email_to
+                        .as_deref()
+                        .unwrap_or_default()
+                        .split(',')
+                        .map(parse)
+                        .collect::<Result<_>>()?,
+                }))
+            }
+            None => None,
+        };
+
+        if config.digest_webhook_url.is_none() && email.is_none() {
+            return Ok(None);
+        }
+
+        let client = proxy::client_builder(config.outbound_proxy.as_ref())
+            .user_agent(&config.user_agent)
+            .timeout(Duration::from_secs(config.request_timeout))
+            .build()
+            .map_err(|e| MdwnError::Internal(format!("Failed to create HTTP client: {}", e)))?;
+
+        Ok(Some(Self {
+            window: Arc::new(Mutex::new(Window {
+                start: Utc::now(),
+                changes: Vec::new(),
+            })),
+            webhook: config.digest_webhook_url.clone().map(|url| {
+                Arc::new(Webhook {
+                    url,
+                    secret: config.digest_webhook_secret.clone(),
+                    max_attempts: config.digest_webhook_attempts,
+                    backoff: Duration::from_millis(config.digest_webhook_backoff_ms),
+                })
+            }),
+            email,
+            client,
+            deliveries: Arc::default(),
+        }))
+    }
+
+    /// Queue a change, merging it with an earlier change to the same URL
+    pub fn record(&self, url: &str, previous_hash: &str, hash: &str) {
+        let mut window = self.window.lock().expect("digest lock poisoned");
+        match window.changes.iter_mut().find(|change| change.url == url) {
+            Some(change) => {
+                change.hash = hash.to_string();
+                change.detected_at = Utc::now();
+            }
+            None => window.changes.push(Change {
+                url: url.to_string(),
+                previous_hash: previous_hash.to_string(),
+                hash: hash.to_string(),
+                detected_at: Utc::now(),
+            }),
+        }
+    }
+
+    /// Close the current window, returning its changes if there were any
+    fn take(&self) -> Option<DigestPayload> {
+        let mut window = self.window.lock().expect("digest lock poisoned");
+        let now = Utc::now();
+        let start = std::mem::replace(&mut window.start, now);
+        let changes = std::mem::take(&mut window.changes);
+        // Pages that changed and then changed back aren't news
+        let changes: Vec<_> = changes
+            .into_iter()
+            .filter(|change| change.hash != change.previous_hash)
+            .collect();
+
+        (!changes.is_empty()).then_some(DigestPayload {
+            window_start: start,
+            window_end: now,
+            changes,
+        })
+    }
+
+    /// Send the changes queued since the last send to every destination
+    pub async fn send(&self) {
+        let Some(payload) = self.take() else {
+            return;
+        };
+
+        if let Some(webhook) = &self.webhook {
+            let delivery = self.deliver(webhook, &payload).await;
+            let tries = delivery.attempts.len();
+            match delivery
+                .attempts
+                .last()
+                .and_then(|attempt| attempt.error.as_ref())
+            {
+                None => tracing::info!(
+                    "Sent digest {} of {} change(s)",
+                    delivery.id,
+                    payload.changes.len()
+                ),
+                Some(error) => tracing::error!(
+                    "Failed to send digest webhook {} after {} tries: {}",
+                    delivery.id,
+                    tries,
+                    error
+                ),
+            }
+            let mut deliveries = self.deliveries.lock().expect("delivery log lock poisoned");
+            if deliveries.len() == DELIVERY_LOG_LEN {
+                deliveries.pop_front();
+            }
+            deliveries.push_back(delivery);
+        }
+
+        if let Some(email) = &self.email {
+            if let Err(e) = send_email(email, &payload).await {
+                tracing::error!("Failed to send digest email: {}", e);
+            }
+        }
+    }
+
+    /// POST a digest to the webhook, trying again while it fails transiently
+    async fn deliver(&self, webhook: &Webhook, payload: &DigestPayload) -> Delivery {
+        let body = serde_json::to_vec(payload).expect("digest serializes");
+        let mut delivery = Delivery {
+            id: format!("{:016x}", rand::random::<u64>()),
+            urls: payload
+                .changes
+                .iter()
+                .map(|change| change.url.clone())
+                .collect(),
+            delivered: false,
+            attempts: Vec::new(),
+        };
+        loop {
+            let at = Utc::now();
+            let timestamp = at.timestamp().to_string();
+            let mut request = self
+                .client
+                .post(&*webhook.url.expose())
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .header("X-Mdwn-Delivery", &delivery.id)
+                .header("X-Mdwn-Timestamp", &timestamp)
+                .body(body.clone());
+            if let Some(secret) = &webhook.secret {
+                let signature = signature(&secret.expose(), &timestamp, &body);
+                request = request.header("X-Mdwn-Signature", signature);
+            }
+
+            // Webhook URLs may hold credentials, so errors leave them out
+            let (status, error, transient) = match request.send().await {
+                Ok(response) if response.status().is_success() => {
+                    (Some(response.status().as_u16()), None, false)
+                }
+                Ok(response) => {
+                    let status = response.status();
+                    let transient = status.is_server_error()
+                        || status == reqwest::StatusCode::TOO_MANY_REQUESTS;
+                    (
+                        Some(status.as_u16()),
+                        Some(format!("status {}", status)),
+                        transient,
+                    )
+                }
+                Err(e) => (None, Some(e.without_url().to_string()), true),
+            };
+            delivery.delivered = error.is_none();
+            delivery.attempts.push(Attempt { at, status, error });
+
+            let tries = delivery.attempts.len();
+            if !transient || tries >= webhook.max_attempts {
+                return delivery;
+            }
+            tokio::time::sleep(retry_delay(webhook.backoff, tries)).await;
+        }
+    }
+
+    /// Recent webhook deliveries, newest first, only those that carried a
+    /// change to `url` if given
+    pub fn deliveries(&self, url: Option<&str>) -> Vec<Delivery> {
+        let deliveries = self.deliveries.lock().expect("delivery log lock poisoned");
+        deliveries
+            .iter()
+            .rev()
+            .filter(|delivery| url.is_none_or(|url| delivery.urls.iter().any(|u| u == url)))
+            .cloned()
+            .collect()
+    }
+
+    /// Send a digest every `interval` for the life of the process
+    pub fn spawn(self, interval: Duration) {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                self.send().await;
+            }
+        });
+    }
+}
+
+/// `sha256=` and the hex HMAC-SHA256 of `{timestamp}.{body}` under `secret`
+fn signature(secret: &str, timestamp: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any length");
+    mac.update(timestamp.as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    format!("sha256={:x}", mac.finalize().into_bytes())
+}
+
+/// Wait after try `tries`: `backoff`, doubled for each try before, capped
+fn retry_delay(backoff: Duration, tries: usize) -> Duration {
+    let doublings = (tries.saturating_sub(1)).min(16) as u32;
+    backoff.saturating_mul(1 << doublings).min(MAX_RETRY_DELAY)
+}
+
+async fn send_email(email: &Email, payload: &DigestPayload) -> anyhow::Result<()> {
+    let mut message = Message::builder().from(email.from.clone()).subject(format!(
+        "mdwn.io: {} page(s) changed",
+        payload.changes.len()
+    ));
+    for to in &email.to {
+        message = message.to(to.clone());
+    }
+    let transport = AsyncSmtpTransport::<Tokio1Executor>::from_url(&email.url.expose())?.build();
+    transport.send(message.body(payload.summary())?).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn test_webhook_digest_batches_changes() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/hook"))
+            .respond_with(ResponseTemplate::new(204))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mut config = Config::for_tests();
+        config.digest_webhook_url = Some(Secret::new(format!("{}/hook", server.uri())));
+        let digests = Digests::from_config(&config).unwrap().unwrap();
+
+        digests.record("https://example.com/a", "h1", "h2");
+        digests.record("https://example.com/a", "h2", "h3");
+        digests.record("https://example.com/b", "x1", "x2");
+        digests.record("https://example.com/c", "c1", "c2");
+        digests.record("https://example.com/c", "c2", "c1");
+        digests.send().await;
+        // Nothing new: no second request
+        digests.send().await;
+
+        let requests = server.received_requests().await.unwrap();
+        let body: serde_json::Value = requests[0].body_json().unwrap();
+        let changes = body["changes"].as_array().unwrap();
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[0]["url"], "https://example.com/a");
+        assert_eq!(changes[0]["previous_hash"], "h1");
+        assert_eq!(changes[0]["hash"], "h3");
+    }
+
+    #[tokio::test]
+    async fn test_webhook_retries_and_signs() {
+        let server = MockServer::start().await;
+        for status in [503, 204] {
+            Mock::given(method("POST"))
+                .respond_with(ResponseTemplate::new(status))
+                .up_to_n_times(1)
+                .mount(&server)
+                .await;
+        }
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(400))
+            .mount(&server)
+            .await;
+
+        let mut config = Config::for_tests();
+        config.digest_webhook_url = Some(Secret::new(format!("{}/hook", server.uri())));
+        config.digest_webhook_secret = Some(Secret::new("whsec-test"));
+        config.digest_webhook_backoff_ms = 1;
+        let digests = Digests::from_config(&config).unwrap().unwrap();
+
+        digests.record("https://example.com/a", "h1", "h2");
+        digests.send().await;
+        let requests = server.received_requests().await.unwrap();
+        assert_eq!(requests.len(), 2);
+        let header = |i: usize, name: &str| requests[i].headers[name].to_str().unwrap().to_string();
+        assert_eq!(header(0, "x-mdwn-delivery"), header(1, "x-mdwn-delivery"));
+        let timestamp = header(1, "x-mdwn-timestamp");
+        let expected = signature("whsec-test", &timestamp, &requests[1].body);
+        assert_eq!(header(1, "x-mdwn-signature"), expected);
+
+        // A client error won't go away by trying again
+        digests.record("https://example.com/b", "x1", "x2");
+        digests.send().await;
+        assert_eq!(server.received_requests().await.unwrap().len(), 3);
+
+        let deliveries = digests.deliveries(None);
+        assert_eq!(deliveries.len(), 2);
+        assert!(!deliveries[0].delivered);
+        assert_eq!(deliveries[0].attempts[0].status, Some(400));
+        let deliveries = digests.deliveries(Some("https://example.com/a"));
+        assert_eq!(deliveries.len(), 1);
+        assert!(deliveries[0].delivered);
+        let statuses: Vec<_> = deliveries[0].attempts.iter().map(|a| a.status).collect();
+        assert_eq!(statuses, [Some(503), Some(204)]);
+    }
+
+    #[test]
+    fn test_signature() {
+        assert_eq!(
+            signature("whsec-test", "1767225600", br#"{"changes":[]}"#),
+            "sha256=248f17a5c4b37a9ed9c81992ee5eaaba63a75acf091a9053dd8502784e84d13a"
+        );
+        let backoff = Duration::from_secs(1);
+        assert_eq!(retry_delay(backoff, 1), backoff);
+        assert_eq!(retry_delay(backoff, 3), Duration::from_secs(4));
+        assert_eq!(retry_delay(backoff, 40), MAX_RETRY_DELAY);
+    }
+
+    #[test]
+    fn test_disabled_without_destinations() {
+        let config = Config::for_tests();
+        assert!(Digests::from_config(&config).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_email_summary() {
+        let payload = DigestPayload {
+            window_start: "2026-01-01T00:00:00Z".parse().unwrap(),
+            window_end: "2026-01-01T01:00:00Z".parse().unwrap(),
+            changes: vec![Change {
+                url: "https://example.com/a".to_string(),
+                previous_hash: "h1".to_string(),
+                hash: "h2".to_string(),
+                detected_at: "2026-01-01T00:30:00Z".parse().unwrap(),
+            }],
+        };
+        assert_eq!(
+            payload.summary(),
+            "1 page(s) changed between 2026-01-01 00:00 UTC and 2026-01-01 01:00 UTC:\n\n\
+             - https://example.com/a (2026-01-01T00:30:00+00:00)\n"
+        );
+    }
+}
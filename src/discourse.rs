@@ -0,0 +1,186 @@
+//! Discourse topics through the forum's JSON API
+//!
+//! Topic pages load posts with infinite scroll, so their HTML only holds the
+//! first batch. `/t/{id}.json` returns the topic with its first posts and the
+//! IDs of all the others, which are then fetched in batches from
+//! `/t/{id}/posts.json`.
+
+use crate::fetch::{FetchBudget, Fetcher};
+use crate::forum::Post;
+use crate::parse::{categorize_content_type, ContentCategory};
+use serde::Deserialize;
+use url::Url;
+
+/// Post IDs requested per `posts.json` call
+const POSTS_PER_REQUEST: usize = 50;
+
+/// A topic rendered from the API
+#[derive(Debug)]
+pub struct Topic {
+    pub title: String,
+    pub posts: Vec<Post>,
+    /// Posts left out by the post limit
+    pub omitted: usize,
+}
+
+#[derive(Deserialize)]
+struct ApiTopic {
+    title: String,
+    post_stream: ApiPostStream,
+}
+
+#[derive(Deserialize)]
+struct ApiPostStream {
+    posts: Vec<ApiPost>,
+    #[serde(default)]
+    stream: Vec<u64>,
+}
+
+#[derive(Deserialize)]
+struct ApiPosts {
+    post_stream: ApiPostStream,
+}
+
+#[derive(Deserialize)]
+struct ApiPost {
+    id: u64,
+    username: Option<String>,
+    created_at: Option<String>,
+    /// Rendered HTML, empty for small actions like "closed the topic"
+    #[serde(default)]
+    cooked: String,
+}
+
+impl From<ApiPost> for Post {
+    fn from(post: ApiPost) -> Self {
+        Post {
+            author: post.username,
+            time: post.created_at,
+            body: post.cooked,
+        }
+    }
+}
+
+/// Topic JSON URL for `/t/{slug}/{id}` style paths, keeping any subfolder the
+/// forum is installed under
+pub fn topic_api_url(url: &Url) -> Option<Url> {
+    let segments: Vec<&str> = url.path_segments()?.collect();
+    let t = segments.iter().position(|s| *s == "t")?;
+    // `/t/{id}`, `/t/{slug}/{id}` and either followed by a post number
+    let id: u64 = segments[t + 1..]
+        .iter()
+        .take(2)
+        .find_map(|s| s.parse().ok())?;
+
+    let prefix = segments[..t].join("/");
+    let mut api = url.clone();
+    api.set_path(&format!("{}/t/{}.json", prefix, id));
+    api.set_query(None);
+    api.set_fragment(None);
+    Some(api)
+}
+
+/// Fetch a topic and up to `max_posts` of its posts, or `None` if the URL isn't
+/// a Discourse topic after all
+///
+/// Failing to fetch later batches isn't an error: the posts fetched so far are
+/// returned with the rest counted as omitted.
+pub async fn fetch_topic(
+    fetcher: &Fetcher,
+    budget: &FetchBudget,
+    url: &Url,
+    max_posts: usize,
+) -> Option<Topic> {
+    let api_url = topic_api_url(url)?;
+    let response = match fetcher.fetch(&api_url, budget).await {
+        Ok(response) => response,
+        Err(e) => {
+            tracing::debug!("No Discourse API at {}: {}", api_url, e);
+            return None;
+        }
+    };
+    if categorize_content_type(response.mime_type()) != ContentCategory::Json {
+        return None;
+    }
+    let topic: ApiTopic = serde_json::from_slice(&response.body).ok()?;
+
+    let stream = if topic.post_stream.stream.is_empty() {
+        topic.post_stream.posts.iter().map(|p| p.id).collect()
+    } else {
+        topic.post_stream.stream
+    };
+    let wanted: Vec<u64> = stream.iter().copied().take(max_posts).collect();
+
+    let mut posts: Vec<ApiPost> = topic.post_stream.posts;
+    let missing: Vec<u64> = wanted
+        .iter()
+        .copied()
+        .filter(|id| !posts.iter().any(|p| p.id == *id))
+        .collect();
+    for batch in missing.chunks(POSTS_PER_REQUEST) {
+        let mut batch_url = api_url.clone();
+        batch_url.set_path(&format!(
+            "{}/posts.json",
+            api_url.path().trim_end_matches(".json")
+        ));
+        for post_id in batch {
+            batch_url
+                .query_pairs_mut()
+                .append_pair("post_ids[]", &post_id.to_string());
+        }
+
+        let page = match fetcher.fetch(&batch_url, budget).await {
+            Ok(page) => page,
+            Err(e) => {
+                tracing::warn!("Stopped fetching posts of Discourse topic {}: {}", url, e);
+                break;
+            }
+        };
+        match serde_json::from_slice::<ApiPosts>(&page.body) {
+            Ok(page) => posts.extend(page.post_stream.posts),
+            Err(e) => {
+                tracing::warn!("Unexpected posts.json from {}: {}", batch_url, e);
+                break;
+            }
+        }
+    }
+
+    // Stream order, limited to the wanted posts that were actually fetched
+    let mut ordered = Vec::with_capacity(wanted.len());
+    for post_id in &wanted {
+        if let Some(index) = posts.iter().position(|p| p.id == *post_id) {
+            ordered.push(posts.swap_remove(index));
+        }
+    }
+    let omitted = stream.len() - ordered.len();
+
+    Some(Topic {
+        title: topic.title,
+        posts: ordered
+            .into_iter()
+            .filter(|post| !post.cooked.trim().is_empty())
+            .map(Post::from)
+            .collect(),
+        omitted,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_topic_api_url() {
+        let api = |url: &str| topic_api_url(&Url::parse(url).unwrap()).map(String::from);
+        assert_eq!(
+            api("https://forum.example.com/t/some-topic/123/4?u=x#reply").as_deref(),
+            Some("https://forum.example.com/t/123.json")
+        );
+        assert_eq!(
+            api("https://example.com/community/t/123").as_deref(),
+            Some("https://example.com/community/t/123.json")
+        );
+        assert_eq!(api("https://example.com/t/slug-only"), None);
+        assert_eq!(api("https://example.com/blog/123"), None);
+    }
+}
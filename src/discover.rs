@@ -0,0 +1,355 @@
+//! Site outlines for planning which pages to fetch
+//!
+//! `/discover/{domain}` lists a site's pages grouped by their first path
+//! segment. The pages come from the site's sitemap.xml when it has one, and
+//! otherwise from the links on its homepage, navigation first. Outlines are
+//! cached per origin for the cache TTL.
+
+use crate::refresh::sitemap_urls;
+use crate::{record_request, AppState};
+use axum::{
+    extract::{Path, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use mdwnio::error::{MdwnError, Result};
+use moka::future::Cache;
+use scraper::{Html, Selector};
+use std::collections::BTreeMap;
+use std::time::Duration;
+use tokio::time::Instant;
+use url::Url;
+
+/// Pages listed at most
+const MAX_PAGES: usize = 500;
+
+/// Pages listed per section before the rest are only counted
+const MAX_SECTION_PAGES: usize = 50;
+
+/// Bound on the bytes of outlines kept
+const OUTLINE_CACHE_MAX_BYTES: u64 = 16 * 1024 * 1024; // 16MB
+
+/// Extensions of files that aren't pages
+const ASSET_EXTENSIONS: &[&str] = &[
+    "css", "js", "json", "xml", "png", "jpg", "jpeg", "gif", "svg", "webp", "ico", "pdf", "zip",
+    "mp3", "mp4", "woff", "woff2",
+];
+
+/// A page of the outline
+#[derive(Debug, PartialEq)]
+struct Page {
+    url: Url,
+    /// Link text, or the path for sitemap entries
+    label: String,
+}
+
+/// Where the outline's pages came from
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Source {
+    Sitemap,
+    Homepage,
+}
+
+impl Source {
+    fn as_header_value(self) -> &'static str {
+        match self {
+            Source::Sitemap => "sitemap",
+            Source::Homepage => "homepage",
+        }
+    }
+}
+
+/// Recent outlines and their source, keyed by origin
+#[derive(Clone)]
+pub struct Outlines {
+    cache: Cache<String, (String, Source)>,
+}
+
+impl Outlines {
+    /// Create an outline cache with the specified TTL
+    pub fn new(ttl_secs: u64) -> Self {
+        let cache = Cache::builder()
+            .time_to_live(Duration::from_secs(ttl_secs))
+            .weigher(|origin: &String, (markdown, _): &(String, Source)| {
+                u32::try_from(origin.len() + markdown.len()).unwrap_or(u32::MAX)
+            })
+            .max_capacity(OUTLINE_CACHE_MAX_BYTES)
+            .build();
+        Self { cache }
+    }
+}
+
+/// Outline of a site as markdown
+pub async fn discover_handler(
+    State(state): State<AppState>,
+    Path(domain): Path<String>,
+) -> Response {
+    match discover(&state, &domain).await {
+        Ok((markdown, source)) => {
+            let mut headers = HeaderMap::new();
+            headers.insert(
+                header::CONTENT_TYPE,
+                "text/markdown; charset=utf-8"
+                    .parse()
+                    .expect("valid header value"),
+            );
+            headers.insert(
+                "X-Mdwn-Discover-Source",
+                source
+                    .as_header_value()
+                    .parse()
+                    .expect("valid header value"),
+            );
+            (StatusCode::OK, headers, markdown).into_response()
+        }
+        Err(e) => e.into_response(),
+    }
+}
+
+async fn discover(state: &AppState, domain: &str) -> Result<(String, Source)> {
+    // A bare domain or a URL on it; only the origin matters
+    let target = if domain.contains("://") {
+        domain.to_string()
+    } else {
+        format!("https://{}", domain)
+    };
+    let mut home = state.fetcher.parse_url(&target)?;
+    home.set_path("/");
+    home.set_query(None);
+    home.set_fragment(None);
+
+    let origin = home.origin().ascii_serialization();
+    let cached = state.outlines.cache.get(&origin).await;
+    let cache_hit = cached.is_some();
+    let result = match cached {
+        Some(outline) => Ok(outline),
+        None => crawl(state, &home).await,
+    };
+    // Counted like a page request, so crawling a site shows in the stats
+    if let Some(host) = home.host_str() {
+        let outcome = result
+            .as_ref()
+            .ok()
+            .map(|(markdown, _)| (cache_hit, markdown.len()));
+        record_request(state, &host.to_lowercase(), outcome);
+    }
+
+    let outline = result?;
+    if !cache_hit {
+        state.outlines.cache.insert(origin, outline.clone()).await;
+    }
+    Ok(outline)
+}
+
+/// Outline a site from its sitemap or homepage
+///
+/// Sitemaps get half the request timeout, so the homepage fallback still
+/// has time to run when a large sitemap index is slow.
+async fn crawl(state: &AppState, home: &Url) -> Result<(String, Source)> {
+    let deadline = Instant::now() + Duration::from_secs(state.config.proxy_timeout) / 2;
+    let sitemap = home.join("/sitemap.xml").expect("valid sitemap path");
+    let sitemap_pages = match sitemap_urls(state, sitemap.as_str(), Some(deadline)).await {
+        Ok(entries) => entries
+            .into_iter()
+            .map(|entry| Page {
+                label: entry.loc.path().to_string(),
+                url: entry.loc,
+            })
+            .collect(),
+        Err(e) => {
+            tracing::debug!("No sitemap for {}: {}", home, e);
+            Vec::new()
+        }
+    };
+    let (pages, source) = if sitemap_pages.is_empty() {
+        state.crawl_throttle.wait(&state.fetcher, home).await;
+        let budget = state.fetcher.budget();
+        let response = state.fetcher.fetch(home, &budget).await?;
+        let pages = homepage_links(&response.body_as_string(), &response.final_url);
+        if pages.is_empty() {
+            return Err(MdwnError::NoMarkdown(
+                "No sitemap.xml and no internal links on the homepage".to_string(),
+            ));
+        }
+        (pages, Source::Homepage)
+    } else {
+        (sitemap_pages, Source::Sitemap)
+    };
+
+    let host = home.host_str().unwrap_or_default();
+    Ok((outline(host, &pages, source), source))
+}
+
+/// Internal page links of a homepage, navigation links first, without duplicates
+fn homepage_links(html: &str, base: &Url) -> Vec<Page> {
+    let document = Html::parse_document(html);
+    let nav = Selector::parse("nav a[href], header a[href]").expect("valid CSS selector");
+    let all = Selector::parse("a[href]").expect("valid CSS selector");
+    let site = site_host(base);
+
+    let mut pages: Vec<Page> = Vec::new();
+    for link in document.select(&nav).chain(document.select(&all)) {
+        let Some(mut url) = link
+            .value()
+            .attr("href")
+            .and_then(|href| base.join(href).ok())
+        else {
+            continue;
+        };
+        url.set_fragment(None);
+        let internal = matches!(url.scheme(), "http" | "https") && site_host(&url) == site;
+        let asset = is_asset(&url);
+        // `www.` or not, the same path is the same page
+        let seen = pages
+            .iter()
+            .any(|page| page.url.path() == url.path() && page.url.query() == url.query());
+        if !internal || asset || seen {
+            continue;
+        }
+
+        let text = link.text().collect::<Vec<_>>().join(" ");
+        let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+        let label = if text.is_empty() {
+            link.value()
+                .attr("title")
+                .or_else(|| link.value().attr("aria-label"))
+                .map(str::to_string)
+                .unwrap_or_else(|| url.path().to_string())
+        } else {
+            text
+        };
+        pages.push(Page { url, label });
+        if pages.len() >= MAX_PAGES {
+            break;
+        }
+    }
+    pages
+}
+
+/// Whether a URL is a stylesheet, image, download or other file that isn't a page
+pub(crate) fn is_asset(url: &Url) -> bool {
+    let extension = url
+        .path()
+        .rsplit_once('.')
+        .map(|(_, ext)| ext.to_ascii_lowercase());
+    extension.is_some_and(|ext| ASSET_EXTENSIONS.contains(&ext.as_str()))
+}
+
+/// Host without a leading `www.`, so both spellings of a site match
+pub(crate) fn site_host(url: &Url) -> Option<String> {
+    url.host_str()
+        .map(|host| host.trim_start_matches("www.").to_ascii_lowercase())
+}
+
+/// Render pages grouped by first path segment, top-level pages first
+fn outline(host: &str, pages: &[Page], source: Source) -> String {
+    let mut markdown = format!("# Site outline: {}\n\n", host);
+    markdown.push_str(match source {
+        Source::Sitemap => "Pages from sitemap.xml.\n\n",
+        Source::Homepage => "No sitemap.xml; pages linked from the homepage.\n\n",
+    });
+
+    let first_segment = |page: &Page| {
+        page.url
+            .path_segments()
+            .and_then(|mut segments| segments.next())
+            .unwrap_or_default()
+            .to_string()
+    };
+    let has_children = |segment: &str| {
+        pages.iter().any(|page| {
+            let mut segments = page.url.path_segments().into_iter().flatten();
+            segments.next() == Some(segment) && segments.any(|s| !s.is_empty())
+        })
+    };
+
+    let mut top_level = Vec::new();
+    let mut sections: BTreeMap<String, Vec<&Page>> = BTreeMap::new();
+    for page in pages.iter().take(MAX_PAGES) {
+        let segment = first_segment(page);
+        if segment.is_empty() || !has_children(&segment) {
+            top_level.push(page);
+        } else {
+            sections.entry(segment).or_default().push(page);
+        }
+    }
+
+    if !top_level.is_empty() {
+        push_pages(&mut markdown, &top_level);
+    }
+    for (segment, pages) in &sections {
+        markdown.push_str(&format!("## /{}/\n\n", segment));
+        push_pages(&mut markdown, pages);
+    }
+
+    format!("{}\n", markdown.trim_end())
+}
+
+/// Append a section's page links, counting those past the section limit
+fn push_pages(markdown: &mut String, pages: &[&Page]) {
+    for page in pages.iter().take(MAX_SECTION_PAGES) {
+        markdown.push_str(&format!("- [{}]({})\n", page.label, page.url));
+    }
+    if pages.len() > MAX_SECTION_PAGES {
+        markdown.push_str(&format!("- *{} more*\n", pages.len() - MAX_SECTION_PAGES));
+    }
+    markdown.push('\n');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_homepage_links() {
+        let base = Url::parse("https://example.com/").unwrap();
+        let html = r#"<html><body>
+            <a href="/blog/first-post">First post</a>
+            <nav><a href="/about">About</a><a href="/blog/">Blog</a></nav>
+            <a href="https://www.example.com/about#team">About us</a>
+            <a href="https://other.example/">Elsewhere</a>
+            <a href="/logo.png">Logo</a>
+            <a href="mailto:hi@example.com">Mail</a>
+            </body></html>"#;
+
+        let labels: Vec<String> = homepage_links(html, &base)
+            .into_iter()
+            .map(|page| format!("{} {}", page.label, page.url.path()))
+            .collect();
+        assert_eq!(
+            labels,
+            ["About /about", "Blog /blog/", "First post /blog/first-post"]
+        );
+    }
+
+    #[test]
+    fn test_outline() {
+        let page = |path: &str, label: &str| Page {
+            url: Url::parse("https://example.com")
+                .unwrap()
+                .join(path)
+                .unwrap(),
+            label: label.to_string(),
+        };
+        let pages = [
+            page("/", "Home"),
+            page("/about", "About"),
+            page("/docs/install", "Install"),
+            page("/blog/", "Blog"),
+            page("/blog/first-post", "First post"),
+        ];
+
+        assert_eq!(
+            outline("example.com", &pages, Source::Homepage),
+            "# Site outline: example.com\n\n\
+             No sitemap.xml; pages linked from the homepage.\n\n\
+             - [Home](https://example.com/)\n\
+             - [About](https://example.com/about)\n\n\
+             ## /blog/\n\n\
+             - [Blog](https://example.com/blog/)\n\
+             - [First post](https://example.com/blog/first-post)\n\n\
+             ## /docs/\n\n\
+             - [Install](https://example.com/docs/install)\n"
+        );
+    }
+}
@@ -0,0 +1,318 @@
+//! Hostname resolution for SSRF checks and upstream connections
+//!
+//! Every lookup goes through one [`Resolve`] implementation. The HTTP client
+//! checks the addresses it gets against the SSRF rules as part of the lookup
+//! it connects with, so a rebinding DNS server can't answer a check with a
+//! public address and the connection with a private one. The default is
+//! hickory-dns using the system configuration, custom nameservers or
+//! DNS-over-HTTPS, with optional fixed per-host overrides on top.
+
+use crate::error::{MdwnError, Result};
+use futures_util::future::BoxFuture;
+use hickory_resolver::config::{NameServerConfig, ResolverConfig};
+use hickory_resolver::name_server::TokioConnectionProvider;
+use hickory_resolver::proto::xfer::Protocol;
+use hickory_resolver::TokioResolver;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// Resolves hostnames to IP addresses
+pub trait Resolve: Send + Sync {
+    /// Every address for `host`
+    fn resolve<'a>(&'a self, host: &'a str) -> BoxFuture<'a, io::Result<Vec<IpAddr>>>;
+}
+
+/// Public DNS-over-HTTPS providers
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DohProvider {
+    Cloudflare,
+    Google,
+    Quad9,
+}
+
+impl FromStr for DohProvider {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "cloudflare" => Ok(Self::Cloudflare),
+            "google" => Ok(Self::Google),
+            "quad9" => Ok(Self::Quad9),
+            _ => Err("expected cloudflare, google or quad9".to_string()),
+        }
+    }
+}
+
+impl fmt::Display for DohProvider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Cloudflare => "cloudflare",
+            Self::Google => "google",
+            Self::Quad9 => "quad9",
+        })
+    }
+}
+
+/// Where DNS queries go
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct DnsConfig {
+    /// Plain DNS nameservers; empty uses the system configuration
+    pub servers: Vec<SocketAddr>,
+    /// Resolve over HTTPS instead (exclusive with `servers`)
+    pub over_https: Option<DohProvider>,
+    /// Fixed addresses for specific hosts, checked before any lookup
+    pub overrides: BTreeMap<String, Vec<IpAddr>>,
+}
+
+/// Parse a comma-separated list of nameservers (`ip` or `ip:port`, port 53 by default)
+pub fn parse_name_servers(s: &str) -> std::result::Result<Vec<SocketAddr>, String> {
+    s.split(',')
+        .map(str::trim)
+        .filter(|server| !server.is_empty())
+        .map(|server| {
+            server
+                .parse::<SocketAddr>()
+                .or_else(|_| server.parse::<IpAddr>().map(|ip| SocketAddr::new(ip, 53)))
+                .map_err(|_| format!("'{}' is not an IP address", server))
+        })
+        .collect()
+}
+
+/// Parse comma-separated `host=ip` overrides; repeat a host for several addresses
+pub fn parse_overrides(s: &str) -> std::result::Result<BTreeMap<String, Vec<IpAddr>>, String> {
+    let mut overrides: BTreeMap<String, Vec<IpAddr>> = BTreeMap::new();
+    for entry in s.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        let (host, ip) = entry
+            .split_once('=')
+            .ok_or_else(|| format!("'{}' is not host=ip", entry))?;
+        let ip = ip
+            .trim()
+            .parse::<IpAddr>()
+            .map_err(|_| format!("'{}' is not an IP address", ip.trim()))?;
+        overrides
+            .entry(host.trim().trim_end_matches('.').to_ascii_lowercase())
+            .or_default()
+            .push(ip);
+    }
+    Ok(overrides)
+}
+
+/// Build the resolver described by a configuration
+pub fn resolver(config: &DnsConfig) -> Result<Arc<dyn Resolve>> {
+    let hickory: Arc<dyn Resolve> = Arc::new(HickoryResolver::new(config)?);
+    if config.overrides.is_empty() {
+        Ok(hickory)
+    } else {
+        Ok(Arc::new(Overrides {
+            hosts: config.overrides.clone(),
+            inner: hickory,
+        }))
+    }
+}
+
+/// hickory-dns resolver
+pub struct HickoryResolver {
+    resolver: TokioResolver,
+}
+
+impl HickoryResolver {
+    pub fn new(config: &DnsConfig) -> Result<Self> {
+        let provider = TokioConnectionProvider::default();
+        let resolver_config = match config.over_https {
+            Some(DohProvider::Cloudflare) => Some(ResolverConfig::cloudflare_https()),
+            Some(DohProvider::Google) => Some(ResolverConfig::google_https()),
+            Some(DohProvider::Quad9) => Some(ResolverConfig::quad9_https()),
+            None if !config.servers.is_empty() => {
+                let servers: Vec<_> = config
+                    .servers
+                    .iter()
+                    .flat_map(|&addr| {
+                        [
+                            NameServerConfig::new(addr, Protocol::Udp),
+                            NameServerConfig::new(addr, Protocol::Tcp),
+                        ]
+                    })
+                    .collect();
+                Some(ResolverConfig::from_parts(None, Vec::new(), servers))
+            }
+            None => None,
+        };
+
+        let builder = match resolver_config {
+            Some(resolver_config) => TokioResolver::builder_with_config(resolver_config, provider),
+            None => TokioResolver::builder(provider).map_err(|e| {
+                MdwnError::Internal(format!("Failed to read system DNS configuration: {}", e))
+            })?,
+        };
+
+        Ok(Self {
+            resolver: builder.build(),
+        })
+    }
+}
+
+impl Resolve for HickoryResolver {
+    fn resolve<'a>(&'a self, host: &'a str) -> BoxFuture<'a, io::Result<Vec<IpAddr>>> {
+        Box::pin(async move {
+            let lookup = self
+                .resolver
+                .lookup_ip(host)
+                .await
+                .map_err(io::Error::other)?;
+            Ok(lookup.iter().collect())
+        })
+    }
+}
+
+/// Fixed addresses for some hosts, deferring to another resolver for the rest
+pub struct Overrides {
+    pub hosts: BTreeMap<String, Vec<IpAddr>>,
+    pub inner: Arc<dyn Resolve>,
+}
+
+impl Resolve for Overrides {
+    fn resolve<'a>(&'a self, host: &'a str) -> BoxFuture<'a, io::Result<Vec<IpAddr>>> {
+        let key = host.trim_end_matches('.').to_ascii_lowercase();
+        match self.hosts.get(&key) {
+            Some(addrs) => Box::pin(std::future::ready(Ok(addrs.clone()))),
+            None => self.inner.resolve(host),
+        }
+    }
+}
+
+/// A lookup answered with an address the client may not connect to
+#[derive(Debug)]
+pub(crate) struct BlockedAddress {
+    pub host: String,
+    pub ip: IpAddr,
+}
+
+impl fmt::Display for BlockedAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} resolved to blocked address {}", self.host, self.ip)
+    }
+}
+
+impl std::error::Error for BlockedAddress {}
+
+/// A lookup that failed, for the client's connect error
+#[derive(Debug)]
+pub(crate) struct LookupFailed(pub io::Error);
+
+impl fmt::Display for LookupFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "DNS resolution failed: {}", self.0)
+    }
+}
+
+impl std::error::Error for LookupFailed {}
+
+/// Adapter so reqwest connects to the addresses our resolver returns, and
+/// only if `permit` accepts every one of them
+pub(crate) struct ReqwestResolver {
+    pub resolver: Arc<dyn Resolve>,
+    pub permit: fn(IpAddr) -> bool,
+    /// Host connected to whatever its addresses, i.e. the outbound proxy
+    pub trusted_host: Option<String>,
+}
+
+impl reqwest::dns::Resolve for ReqwestResolver {
+    fn resolve(&self, name: reqwest::dns::Name) -> reqwest::dns::Resolving {
+        let resolver = self.resolver.clone();
+        let permit = self.permit;
+        let trusted = self.trusted_host.as_deref() == Some(name.as_str());
+        Box::pin(async move {
+            let addrs = resolver
+                .resolve(name.as_str())
+                .await
+                .map_err(LookupFailed)?;
+            if let Some(&ip) = addrs.iter().find(|&&ip| !trusted && !permit(ip)) {
+                let host = name.as_str().to_string();
+                tracing::debug!("Refusing to connect to {} for {}", ip, host);
+                return Err(Box::new(BlockedAddress { host, ip }) as Box<_>);
+            }
+            let addrs: reqwest::dns::Addrs =
+                Box::new(addrs.into_iter().map(|ip| SocketAddr::new(ip, 0)));
+            Ok(addrs)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Resolver that fails every lookup
+    struct Unreachable;
+
+    impl Resolve for Unreachable {
+        fn resolve<'a>(&'a self, host: &'a str) -> BoxFuture<'a, io::Result<Vec<IpAddr>>> {
+            Box::pin(async move { Err(io::Error::other(format!("no lookup for {}", host))) })
+        }
+    }
+
+    #[test]
+    fn test_parse_name_servers() {
+        assert_eq!(
+            parse_name_servers("10.0.0.2, 10.0.0.3:5353,[::1]:53").unwrap(),
+            vec![
+                "10.0.0.2:53".parse().unwrap(),
+                "10.0.0.3:5353".parse().unwrap(),
+                "[::1]:53".parse().unwrap(),
+            ]
+        );
+        assert!(parse_name_servers("dns.example").is_err());
+    }
+
+    #[test]
+    fn test_parse_overrides() {
+        let overrides =
+            parse_overrides("Intranet.corp=10.1.2.3, intranet.corp.=10.1.2.4,db=::1").unwrap();
+        assert_eq!(overrides.len(), 2);
+        assert_eq!(overrides["intranet.corp"].len(), 2);
+        assert_eq!(overrides["db"], vec!["::1".parse::<IpAddr>().unwrap()]);
+
+        assert!(parse_overrides("intranet.corp").is_err());
+        assert!(parse_overrides("intranet.corp=nope").is_err());
+    }
+
+    #[test]
+    fn test_doh_provider() {
+        assert_eq!(
+            "Cloudflare".parse::<DohProvider>(),
+            Ok(DohProvider::Cloudflare)
+        );
+        assert!("opendns".parse::<DohProvider>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_overrides_before_inner() {
+        let resolver = Overrides {
+            hosts: parse_overrides("upstream.test=127.0.0.1").unwrap(),
+            inner: Arc::new(Unreachable),
+        };
+
+        let addrs = resolver.resolve("UPSTREAM.test.").await.unwrap();
+        assert_eq!(addrs, vec!["127.0.0.1".parse::<IpAddr>().unwrap()]);
+        assert!(resolver.resolve("other.test").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_hickory_custom_servers() {
+        let config = DnsConfig {
+            servers: parse_name_servers("127.0.0.1:1").unwrap(),
+            ..Default::default()
+        };
+        let resolver = HickoryResolver::new(&config).unwrap();
+
+        // IP literals are answered without a query
+        let addrs = resolver.resolve("192.0.2.1").await.unwrap();
+        assert_eq!(addrs, vec!["192.0.2.1".parse::<IpAddr>().unwrap()]);
+    }
+}
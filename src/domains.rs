@@ -0,0 +1,150 @@
+//! Operator allow and block lists of upstream domains
+//!
+//! `ALLOWED_DOMAINS` restricts the proxy to the listed domains (a private
+//! documentation mirror, say) and `BLOCKED_DOMAINS` turns away known-abusive
+//! targets. Both are comma-separated patterns: a plain domain covers itself
+//! and its subdomains, while a pattern with `*` is matched against the whole
+//! host, `*` standing for any run of characters (`docs.*.example.com`,
+//! `*.internal`). A blocked match wins over an allowed one.
+
+use crate::error::{MdwnError, Result};
+use serde::Serialize;
+
+/// Allowed and blocked domain patterns; no allowed patterns allows every domain
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct DomainPolicy {
+    pub allowed: Vec<String>,
+    pub blocked: Vec<String>,
+}
+
+impl DomainPolicy {
+    /// Whether a URL with this host may be fetched
+    pub fn permits(&self, host: &str) -> bool {
+        let host = host.trim_end_matches('.').to_ascii_lowercase();
+        let matches = |pattern: &String| pattern_matches(pattern, &host);
+        !self.blocked.iter().any(matches)
+            && (self.allowed.is_empty() || self.allowed.iter().any(matches))
+    }
+
+    /// [`DomainPolicy::permits`] as an error for the client
+    pub fn check(&self, host: &str) -> Result<()> {
+        if self.permits(host) {
+            Ok(())
+        } else {
+            Err(MdwnError::BlockedDomain(host.to_string()))
+        }
+    }
+}
+
+/// Parse a comma-separated list of domain patterns, lowercased
+pub fn parse_domain_patterns(s: &str) -> std::result::Result<Vec<String>, String> {
+    s.split(',')
+        .filter(|pattern| !pattern.trim().is_empty())
+        .map(parse_domain_pattern)
+        .collect()
+}
+
+/// Parse one domain pattern, lowercased
+pub fn parse_domain_pattern(s: &str) -> std::result::Result<String, String> {
+    let pattern = s.trim().trim_end_matches('.').to_ascii_lowercase();
+    let literal = pattern.replace('*', "");
+    if literal.trim_matches('.').is_empty() {
+        Err(format!("'{}' matches every domain", pattern))
+    } else if pattern.contains(['/', ':', '@', ' ']) {
+        Err(format!("'{}' is not a domain pattern", pattern))
+    } else {
+        Ok(pattern)
+    }
+}
+
+/// Whether a lowercased host matches a pattern from [`parse_domain_pattern`]
+pub fn pattern_matches(pattern: &str, host: &str) -> bool {
+    if pattern.contains('*') {
+        wildcard_matches(pattern.as_bytes(), host.as_bytes())
+    } else {
+        host == pattern
+            || host
+                .strip_suffix(pattern)
+                .is_some_and(|sub| sub.ends_with('.'))
+    }
+}
+
+/// Glob match where `*` is any run of characters, including none
+fn wildcard_matches(pattern: &[u8], text: &[u8]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    // Position after the last `*` and the text position it is tried from
+    let mut backtrack = None;
+    while t < text.len() {
+        if p < pattern.len() && pattern[p] == b'*' {
+            p += 1;
+            backtrack = Some((p, t));
+        } else if p < pattern.len() && pattern[p] == text[t] {
+            p += 1;
+            t += 1;
+        } else if let Some((star_p, star_t)) = backtrack {
+            p = star_p;
+            t = star_t + 1;
+            backtrack = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(allowed: &str, blocked: &str) -> DomainPolicy {
+        DomainPolicy {
+            allowed: parse_domain_patterns(allowed).unwrap(),
+            blocked: parse_domain_patterns(blocked).unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_allow_and_block() {
+        let open = DomainPolicy::default();
+        assert!(open.permits("example.com"));
+
+        let docs = policy("Example.com, docs.*.org", "private.example.com");
+        assert!(docs.permits("example.com"));
+        assert!(docs.permits("www.EXAMPLE.com."));
+        assert!(docs.permits("docs.rust.org"));
+        assert!(docs.permits("docs.a.b.org"));
+        assert!(!docs.permits("notexample.com"));
+        assert!(!docs.permits("docs.org"));
+        assert!(!docs.permits("api.private.example.com"));
+        assert!(matches!(
+            docs.check("evil.test"),
+            Err(MdwnError::BlockedDomain(host)) if host == "evil.test"
+        ));
+
+        let blocklist = policy("", "*.tk, bad.example");
+        assert!(blocklist.permits("example.com"));
+        assert!(!blocklist.permits("free.tk"));
+        assert!(!blocklist.permits("cdn.bad.example"));
+    }
+
+    #[test]
+    fn test_parse_domain_patterns() {
+        assert_eq!(
+            parse_domain_patterns(" a.com, ,*.B.org. ").unwrap(),
+            ["a.com", "*.b.org"]
+        );
+        assert!(parse_domain_patterns("*").is_err());
+        assert!(parse_domain_patterns("*.").is_err());
+        assert!(parse_domain_patterns("https://a.com").is_err());
+    }
+
+    #[test]
+    fn test_wildcard_matches() {
+        let glob = |p: &str, t: &str| wildcard_matches(p.as_bytes(), t.as_bytes());
+        assert!(glob("*.example.com", "a.b.example.com"));
+        assert!(!glob("*.example.com", "example.com"));
+        assert!(glob("docs-*", "docs-"));
+        assert!(glob("a*b*c", "axxbyyc"));
+        assert!(!glob("a*b*c", "axxbyy"));
+    }
+}
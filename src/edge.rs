@@ -0,0 +1,364 @@
+//! Cloudflare Workers build of the proxy
+//!
+//! Upstream requests go through the runtime's `fetch` and results are cached in
+//! a Workers KV namespace, so the parsing and conversion pipeline runs at the
+//! edge without tokio or reqwest. Build with
+//! `wasm-pack build --target web -- --no-default-features --features edge` and
+//! call `proxy` from the worker script (`edge/worker.js`).
+//!
+//! Prerendering, image embedding, post-processing rules and the admin routes
+//! need the full server and aren't available here.
+
+use crate::convert::{self, DEFAULT_MIN_CONTENT_SCORE};
+use crate::error::{MdwnError, Result};
+use crate::parse::{self, ContentCategory, HtmlParseResult, JsonParseResult};
+use crate::store::{variant_cache_key, CacheBackend, CachedContent, ContentSource};
+use js_sys::{Object, Promise, Reflect, Uint8Array};
+use url::Url;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::JsFuture;
+
+/// Workers KV rejects expirations shorter than a minute
+const MIN_KV_TTL_SECS: u64 = 60;
+
+/// Largest upstream body read, matching the server's default `MAX_CONTENT_LENGTH`
+const MAX_CONTENT_LENGTH: usize = 10 * 1024 * 1024; // 10MB
+
+#[wasm_bindgen]
+extern "C" {
+    /// A Workers KV namespace binding
+    #[derive(Clone)]
+    pub type KvNamespace;
+
+    #[wasm_bindgen(method, catch, js_name = get)]
+    fn get_text(this: &KvNamespace, key: &str, kind: &str)
+        -> std::result::Result<Promise, JsValue>;
+
+    #[wasm_bindgen(method, catch)]
+    fn put(
+        this: &KvNamespace,
+        key: &str,
+        value: &str,
+        options: &JsValue,
+    ) -> std::result::Result<Promise, JsValue>;
+
+    /// Fetch API `Response`
+    type FetchResponse;
+
+    #[wasm_bindgen(method, getter)]
+    fn status(this: &FetchResponse) -> u16;
+
+    #[wasm_bindgen(method, getter)]
+    fn url(this: &FetchResponse) -> String;
+
+    #[wasm_bindgen(method, getter)]
+    fn headers(this: &FetchResponse) -> FetchHeaders;
+
+    #[wasm_bindgen(method, getter)]
+    fn body(this: &FetchResponse) -> Option<BodyStream>;
+
+    /// Streams API `ReadableStream` of a response body
+    type BodyStream;
+
+    #[wasm_bindgen(method, catch, js_name = getReader)]
+    fn get_reader(this: &BodyStream) -> std::result::Result<BodyReader, JsValue>;
+
+    /// Streams API `ReadableStreamDefaultReader`
+    type BodyReader;
+
+    #[wasm_bindgen(method, catch)]
+    fn read(this: &BodyReader) -> std::result::Result<Promise, JsValue>;
+
+    #[wasm_bindgen(method)]
+    fn cancel(this: &BodyReader) -> Promise;
+
+    /// Fetch API `Headers`
+    type FetchHeaders;
+
+    #[wasm_bindgen(method)]
+    fn get(this: &FetchHeaders, name: &str) -> Option<String>;
+
+    #[wasm_bindgen(catch, js_name = fetch)]
+    fn js_fetch(url: &str, init: &JsValue) -> std::result::Result<Promise, JsValue>;
+}
+
+/// Describe a JavaScript exception for an error message
+fn js_error(e: JsValue) -> String {
+    e.as_string()
+        .or_else(|| {
+            e.dyn_ref::<js_sys::Error>()
+                .map(|e| String::from(e.message()))
+        })
+        .unwrap_or_else(|| format!("{:?}", e))
+}
+
+/// Markdown cache stored in Workers KV
+///
+/// Entries are JSON-encoded `CachedContent` and expire through KV's own TTL.
+#[derive(Clone)]
+pub struct KvCache {
+    namespace: KvNamespace,
+    ttl_secs: u64,
+}
+
+impl KvCache {
+    pub fn new(namespace: KvNamespace, ttl_secs: u64) -> Self {
+        Self {
+            namespace,
+            ttl_secs: ttl_secs.max(MIN_KV_TTL_SECS),
+        }
+    }
+}
+
+impl CacheBackend for KvCache {
+    async fn get(&self, url: &str, variant: &str) -> Option<CachedContent> {
+        let key = variant_cache_key(url, variant);
+        let promise = self.namespace.get_text(&key, "text").ok()?;
+        let value = JsFuture::from(promise).await.ok()?.as_string()?;
+        serde_json::from_str(&value).ok()
+    }
+
+    async fn set(&self, url: &str, variant: &str, content: CachedContent) {
+        let key = variant_cache_key(url, variant);
+        let Ok(value) = serde_json::to_string(&content) else {
+            return;
+        };
+
+        let options = Object::new();
+        let _ = Reflect::set(
+            &options,
+            &"expirationTtl".into(),
+            &JsValue::from_f64(self.ttl_secs as f64),
+        );
+
+        let result = match self.namespace.put(&key, &value, &options) {
+            Ok(promise) => JsFuture::from(promise).await.map(|_| ()),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            tracing::warn!("Failed to store {} in KV: {}", key, js_error(e));
+        }
+    }
+}
+
+/// Response for the worker script to send
+#[wasm_bindgen]
+pub struct ProxyResponse {
+    status: u16,
+    body: String,
+    source: Option<String>,
+    cache_hit: bool,
+}
+
+#[wasm_bindgen]
+impl ProxyResponse {
+    #[wasm_bindgen(getter)]
+    pub fn status(&self) -> u16 {
+        self.status
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn body(&self) -> String {
+        self.body.clone()
+    }
+
+    /// `X-Mdwn-Source` value: "native" or "converted"
+    #[wasm_bindgen(getter)]
+    pub fn source(&self) -> Option<String> {
+        self.source.clone()
+    }
+
+    /// Whether the markdown was served from KV
+    #[wasm_bindgen(getter, js_name = cacheHit)]
+    pub fn cache_hit(&self) -> bool {
+        self.cache_hit
+    }
+}
+
+/// Serve markdown for a target URL (the request path without its leading `/`)
+///
+/// `kv` is the cache namespace binding; without one every request goes upstream.
+#[wasm_bindgen]
+pub async fn proxy(url_path: String, kv: Option<KvNamespace>, cache_ttl: u32) -> ProxyResponse {
+    let cache = kv.map(|namespace| KvCache::new(namespace, cache_ttl.into()));
+
+    match process_url(&url_path, cache.as_ref()).await {
+        Ok((content, cache_hit)) => ProxyResponse {
+            status: 200,
+            source: Some(content.source.as_header_value().to_string()),
+            body: content.markdown,
+            cache_hit,
+        },
+        Err(e) => ProxyResponse {
+            status: e.status_code(),
+            body: e.body(),
+            source: None,
+            cache_hit: false,
+        },
+    }
+}
+
+/// Edge counterpart of the server's `process_url`
+async fn process_url(url_path: &str, cache: Option<&KvCache>) -> Result<(CachedContent, bool)> {
+    let url = parse::parse_request_url(url_path)?;
+
+    if let Some(cached) = match cache {
+        Some(cache) => cache.get(url.as_str(), "").await,
+        None => None,
+    } {
+        return Ok((cached, true));
+    }
+
+    let (final_url, mime_type, body) = fetch(&url).await?;
+
+    let mut content = match parse::categorize_content_type(mime_type.as_deref()) {
+        ContentCategory::Markdown | ContentCategory::PlainText => {
+            CachedContent::new(body, ContentSource::Native)
+        }
+
+        ContentCategory::Html => process_html(&body, &final_url).await?,
+
+        ContentCategory::Json => match parse::parse_json_for_markdown(&body, &final_url)? {
+            JsonParseResult::MarkdownContent(markdown) => {
+                CachedContent::new(markdown, ContentSource::Native)
+            }
+            JsonParseResult::MarkdownUrl(md_url) => {
+                let (_, _, markdown) = fetch(&md_url).await?;
+                CachedContent::new(markdown, ContentSource::Native)
+            }
+            JsonParseResult::NotFound => {
+                return Err(MdwnError::NoMarkdown(
+                    "JSON has no 'mdwn' or 'markdown' field".to_string(),
+                ))
+            }
+        },
+
+        ContentCategory::Unsupported(mime) => return Err(MdwnError::UnsupportedType(mime)),
+    };
+
+    content.markdown = convert::normalize_text(&content.markdown);
+
+    if let Some(cache) = cache {
+        cache.set(url.as_str(), "", content.clone()).await;
+    }
+
+    Ok((content, false))
+}
+
+/// Use the page's markdown alternate if it has one, otherwise convert it
+async fn process_html(html: &str, base_url: &Url) -> Result<CachedContent> {
+    if let HtmlParseResult::MarkdownLink(md_url) =
+        parse::parse_html_for_markdown_link(html, base_url, &[])?
+    {
+        let (_, mime_type, body) = fetch(&md_url).await?;
+        if parse::is_markdown_alternate(mime_type.as_deref(), body.as_bytes()) {
+            return Ok(CachedContent::new(body, ContentSource::Native));
+        }
+        tracing::warn!("Markdown alternate {} is not markdown, converting", md_url);
+    }
+
+    let quality = convert::assess_html(html);
+    if !quality.is_meaningful(DEFAULT_MIN_CONTENT_SCORE) {
+        return Err(MdwnError::NoMarkdown(
+            "Page appears to require JavaScript to render content".to_string(),
+        ));
+    }
+
+    let markdown = convert::html_to_markdown(html, base_url)?;
+    let mut content = CachedContent::new(markdown, ContentSource::Converted);
+    content.quality = Some(quality.score);
+    Ok(content)
+}
+
+/// Fetch a URL with the runtime's `fetch`, returning the final URL, MIME type and body
+///
+/// Redirects are followed by the runtime, which also refuses to connect to
+/// private addresses, so the server's SSRF checks aren't repeated here.
+async fn fetch(url: &Url) -> Result<(Url, Option<String>, String)> {
+    let headers = Object::new();
+    let _ = Reflect::set(
+        &headers,
+        &"Accept".into(),
+        &"text/markdown, text/html;q=0.9, */*;q=0.8".into(),
+    );
+    let init = Object::new();
+    let _ = Reflect::set(&init, &"headers".into(), &headers);
+
+    let promise = js_fetch(url.as_str(), &init).map_err(|e| MdwnError::FetchFailed(js_error(e)))?;
+    let response: FetchResponse = JsFuture::from(promise)
+        .await
+        .map_err(|e| MdwnError::FetchFailed(js_error(e)))?
+        .unchecked_into();
+
+    match response.status() {
+        200..=299 => {}
+        404 => return Err(MdwnError::NotFound),
+        403 => return Err(MdwnError::Forbidden),
+        status => {
+            return Err(MdwnError::FetchFailed(format!(
+                "Upstream returned {}",
+                status
+            )));
+        }
+    }
+
+    let final_url = Url::parse(&response.url()).unwrap_or_else(|_| url.clone());
+    let mime_type = response
+        .headers()
+        .get("content-type")
+        .and_then(|ct| ct.split(';').next().map(|mt| mt.trim().to_lowercase()));
+
+    if let Some(length) = response.headers().get("content-length") {
+        if length
+            .trim()
+            .parse::<usize>()
+            .is_ok_and(|l| l > MAX_CONTENT_LENGTH)
+        {
+            return Err(MdwnError::TooLarge(MAX_CONTENT_LENGTH));
+        }
+    }
+    let body = read_body(&response).await?;
+
+    Ok((final_url, mime_type, body))
+}
+
+/// Read a response body chunk by chunk, giving up past `MAX_CONTENT_LENGTH`
+///
+/// `Content-Length` can be missing or wrong, so the limit is enforced on the
+/// bytes actually received rather than buffering the whole body first.
+async fn read_body(response: &FetchResponse) -> Result<String> {
+    let Some(stream) = response.body() else {
+        return Ok(String::new());
+    };
+    let reader = stream
+        .get_reader()
+        .map_err(|e| MdwnError::FetchFailed(js_error(e)))?;
+
+    let mut bytes = Vec::new();
+    loop {
+        let promise = reader
+            .read()
+            .map_err(|e| MdwnError::FetchFailed(js_error(e)))?;
+        let chunk = JsFuture::from(promise)
+            .await
+            .map_err(|e| MdwnError::FetchFailed(format!("Read error: {}", js_error(e))))?;
+
+        let done = Reflect::get(&chunk, &"done".into())
+            .map(|d| d.is_truthy())
+            .unwrap_or(true);
+        if done {
+            break;
+        }
+        if let Ok(value) = Reflect::get(&chunk, &"value".into()) {
+            bytes.extend(Uint8Array::new(&value).to_vec());
+        }
+
+        if bytes.len() > MAX_CONTENT_LENGTH {
+            // Stop the runtime from downloading the rest
+            let _ = reader.cancel();
+            return Err(MdwnError::TooLarge(MAX_CONTENT_LENGTH));
+        }
+    }
+
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
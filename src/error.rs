@@ -1,18 +1,27 @@
+use crate::i18n::{self, Locale};
+#[cfg(feature = "server")]
 use axum::{
-    http::StatusCode,
+    http::{header, StatusCode},
     response::{IntoResponse, Response},
+    Extension,
 };
 use thiserror::Error;
 
 /// Error types for mdwn.io - designed to be LLM-friendly (short, parseable, actionable)
-#[derive(Error, Debug)]
+#[derive(Clone, Error, Debug)]
 pub enum MdwnError {
+    #[error("UNAUTHORIZED: {0}")]
+    Unauthorized(String),
+
     #[error("INVALID_URL: {0}")]
     InvalidUrl(String),
 
     #[error("BLOCKED_URL: URL points to a private/internal address")]
     BlockedUrl,
 
+    #[error("BLOCKED_DOMAIN: Fetching from '{0}' is not allowed on this server")]
+    BlockedDomain(String),
+
     #[error("FETCH_FAILED: {0}")]
     FetchFailed(String),
 
@@ -22,18 +31,33 @@ pub enum MdwnError {
     #[error("NOT_FOUND: Upstream returned 404")]
     NotFound,
 
+    #[error("SOFT_NOT_FOUND: Page is a \"not found\" template served with status 200")]
+    SoftNotFound,
+
     #[error("FORBIDDEN: Upstream returned 403")]
     Forbidden,
 
+    #[error("LANGUAGE_MISMATCH: {0}")]
+    LanguageMismatch(String),
+
     #[error("NO_MARKDOWN: {0}")]
     NoMarkdown(String),
 
+    #[error("NO_METADATA: {0}")]
+    NoMetadata(String),
+
     #[error("UNSUPPORTED_TYPE: Content-Type '{0}' is not supported")]
     UnsupportedType(String),
 
     #[error("TOO_LARGE: Content exceeds {0} byte limit")]
     TooLarge(usize),
 
+    #[error("BUDGET_EXCEEDED: {0}")]
+    BudgetExceeded(String),
+
+    #[error("RATE_LIMITED: Too many requests, retry in {0} seconds")]
+    RateLimited(u64),
+
     #[error("PARSE_ERROR: {0}")]
     ParseError(String),
 
@@ -41,26 +65,61 @@ pub enum MdwnError {
     Internal(String),
 }
 
+impl MdwnError {
+    /// HTTP status code reported for this error
+    pub fn status_code(&self) -> u16 {
+        match self {
+            MdwnError::Unauthorized(_) => 401,
+            MdwnError::InvalidUrl(_) => 400,
+            MdwnError::BlockedUrl => 403,
+            MdwnError::BlockedDomain(_) => 403,
+            MdwnError::FetchFailed(_) => 502,
+            MdwnError::Timeout(_) => 504,
+            MdwnError::NotFound => 404,
+            MdwnError::SoftNotFound => 404,
+            MdwnError::Forbidden => 403,
+            MdwnError::LanguageMismatch(_) => 406,
+            MdwnError::NoMarkdown(_) => 404,
+            MdwnError::NoMetadata(_) => 404,
+            MdwnError::UnsupportedType(_) => 415,
+            MdwnError::TooLarge(_) => 413,
+            MdwnError::BudgetExceeded(_) => 502,
+            MdwnError::RateLimited(_) => 429,
+            MdwnError::ParseError(_) => 422,
+            MdwnError::Internal(_) => 500,
+        }
+    }
+
+    /// LLM-friendly error body: short, parseable
+    pub fn body(&self) -> String {
+        format!("ERROR: {}\n", self)
+    }
+
+    /// Error body with the detail translated for `locale`; the code stays as is
+    pub fn localized_body(&self, locale: Locale) -> String {
+        let Some(detail) = i18n::error_detail(self, locale) else {
+            return self.body();
+        };
+        let message = self.to_string();
+        let code = message
+            .split_once(": ")
+            .map_or(message.as_str(), |(code, _)| code);
+        format!("ERROR: {}: {}\n", code, detail)
+    }
+}
+
+#[cfg(feature = "server")]
 impl IntoResponse for MdwnError {
     fn into_response(self) -> Response {
-        let status = match &self {
-            MdwnError::InvalidUrl(_) => StatusCode::BAD_REQUEST,
-            MdwnError::BlockedUrl => StatusCode::FORBIDDEN,
-            MdwnError::FetchFailed(_) => StatusCode::BAD_GATEWAY,
-            MdwnError::Timeout(_) => StatusCode::GATEWAY_TIMEOUT,
-            MdwnError::NotFound => StatusCode::NOT_FOUND,
-            MdwnError::Forbidden => StatusCode::FORBIDDEN,
-            MdwnError::NoMarkdown(_) => StatusCode::NOT_FOUND,
-            MdwnError::UnsupportedType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
-            MdwnError::TooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
-            MdwnError::ParseError(_) => StatusCode::UNPROCESSABLE_ENTITY,
-            MdwnError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        let status =
+            StatusCode::from_u16(self.status_code()).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        let body = self.body();
+        let retry_after = match self {
+            MdwnError::RateLimited(secs) => Some([(header::RETRY_AFTER, secs.to_string())]),
+            _ => None,
         };
-
-        // LLM-friendly error format: short, parseable
-        let body = format!("ERROR: {}\n", self);
-
-        (status, body).into_response()
+        // Kept so the body can be translated for the client on the way out
+        (status, retry_after, Extension(self), body).into_response()
     }
 }
 
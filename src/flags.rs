@@ -0,0 +1,307 @@
+//! Feature flags for rolling out risky behavior gradually
+//!
+//! Each flag has a rule, set with `FLAG_<NAME>` and changeable at runtime
+//! through `/admin/flags`: a share of domains it is on for (`on`, `off` or
+//! `25%`), plus domains it is always on (`docs.rs`) or off (`-example.com`)
+//! for, subdomains included. A domain's share is picked by hashing it with
+//! the flag's name, so the same sites stay in (or out of) a rollout as it
+//! widens and different flags roll out to different sites.
+
+use mdwnio::store::on_domain;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+use url::Url;
+
+/// Behaviors behind a flag
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Flag {
+    /// Render JavaScript shells with `PRERENDER_URL`
+    Prerender,
+    /// Serve the landmark engine's output instead of readability's
+    SemanticEngine,
+}
+
+impl Flag {
+    pub const ALL: [Flag; 2] = [Flag::Prerender, Flag::SemanticEngine];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Flag::Prerender => "prerender",
+            Flag::SemanticEngine => "semantic_engine",
+        }
+    }
+
+    /// Environment variable setting the flag's rule
+    pub fn env_var(self) -> String {
+        format!("FLAG_{}", self.name().to_ascii_uppercase())
+    }
+
+    /// Rule when none is configured
+    pub fn default_rule(self) -> FlagRule {
+        match self {
+            Flag::Prerender => FlagRule::on(),
+            Flag::SemanticEngine => FlagRule::default(),
+        }
+    }
+}
+
+impl FromStr for Flag {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Flag::ALL
+            .into_iter()
+            .find(|flag| flag.name() == s)
+            .ok_or_else(|| {
+                let names: Vec<_> = Flag::ALL.iter().map(|flag| flag.name()).collect();
+                format!("unknown flag '{}', expected one of {}", s, names.join(", "))
+            })
+    }
+}
+
+/// Where a flag is on
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct FlagRule {
+    /// Share of domains the flag is on for, 0-100
+    pub percent: u8,
+    /// Domains the flag is always on for
+    pub domains: Vec<String>,
+    /// Domains the flag is always off for, whatever else matches
+    pub excluded: Vec<String>,
+}
+
+impl FlagRule {
+    fn on() -> Self {
+        Self {
+            percent: 100,
+            ..Self::default()
+        }
+    }
+
+    /// Whether the flag is on for a host
+    fn applies(&self, flag: Flag, host: &str) -> bool {
+        if self.excluded.iter().any(|domain| on_domain(host, domain)) {
+            return false;
+        }
+        if self.domains.iter().any(|domain| on_domain(host, domain)) {
+            return true;
+        }
+        match self.percent {
+            0 => false,
+            100.. => true,
+            percent => bucket(flag, host) < percent,
+        }
+    }
+}
+
+/// Parse a comma-separated rule: `on`, `off` or `N%`, and domains to
+/// include or (with a leading `-`) exclude
+impl FromStr for FlagRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut rule = FlagRule::default();
+        for part in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let part = part.to_ascii_lowercase();
+            if part == "on" {
+                rule.percent = 100;
+            } else if part == "off" {
+                rule.percent = 0;
+            } else if let Some(percent) = part.strip_suffix('%') {
+                rule.percent = percent
+                    .trim()
+                    .parse()
+                    .ok()
+                    .filter(|p| *p <= 100)
+                    .ok_or_else(|| format!("'{}' is not a percentage from 0 to 100", part))?;
+            } else if let Some(domain) = part.strip_prefix('-') {
+                rule.excluded.push(parse_domain(domain)?);
+            } else {
+                rule.domains.push(parse_domain(&part)?);
+            }
+        }
+        Ok(rule)
+    }
+}
+
+impl fmt::Display for FlagRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let share = match self.percent {
+            0 => "off".to_string(),
+            100.. => "on".to_string(),
+            percent => format!("{}%", percent),
+        };
+        let domains = self.domains.iter().cloned();
+        let excluded = self.excluded.iter().map(|domain| format!("-{}", domain));
+        let parts: Vec<_> = std::iter::once(share)
+            .chain(domains)
+            .chain(excluded)
+            .collect();
+        f.write_str(&parts.join(","))
+    }
+}
+
+fn parse_domain(domain: &str) -> Result<String, String> {
+    let domain = domain.trim().trim_start_matches("*.");
+    if !domain.contains('.') || domain.contains(['/', ':', ' ']) {
+        return Err(format!("'{}' is not a domain", domain));
+    }
+    Ok(domain.to_string())
+}
+
+/// Stable 0-99 bucket of a host for a flag
+fn bucket(flag: Flag, host: &str) -> u8 {
+    let digest = Sha256::new()
+        .chain_update(flag.name())
+        .chain_update(b"\n")
+        .chain_update(host)
+        .finalize();
+    let value = u64::from_be_bytes(digest[..8].try_into().expect("8 bytes"));
+    (value % 100) as u8
+}
+
+/// Flag state as served by `/admin/flags`
+#[derive(Debug, Serialize)]
+pub struct FlagState {
+    pub name: &'static str,
+    /// The rule in `FLAG_<NAME>` syntax
+    pub rule: String,
+    #[serde(flatten)]
+    pub details: FlagRule,
+    /// `default`, `config` or `runtime`
+    pub source: &'static str,
+}
+
+/// Current rule of every flag, shared across handlers
+#[derive(Clone)]
+pub struct Flags {
+    configured: Arc<BTreeMap<Flag, FlagRule>>,
+    overrides: Arc<RwLock<BTreeMap<Flag, FlagRule>>>,
+}
+
+impl Flags {
+    /// Flags with the rules from the configuration
+    pub fn new(configured: BTreeMap<Flag, FlagRule>) -> Self {
+        Self {
+            configured: Arc::new(configured),
+            overrides: Arc::default(),
+        }
+    }
+
+    /// Whether a flag is on for a URL's host
+    pub fn enabled(&self, flag: Flag, url: &Url) -> bool {
+        let host = url.host_str().unwrap_or_default().to_ascii_lowercase();
+        self.rule(flag).0.applies(flag, &host)
+    }
+
+    /// A flag's current rule and where it came from
+    pub fn state(&self, flag: Flag) -> FlagState {
+        let (details, source) = self.rule(flag);
+        FlagState {
+            name: flag.name(),
+            rule: details.to_string(),
+            details,
+            source,
+        }
+    }
+
+    fn rule(&self, flag: Flag) -> (FlagRule, &'static str) {
+        let overrides = self.overrides.read().expect("flags lock poisoned");
+        match (overrides.get(&flag), self.configured.get(&flag)) {
+            (Some(rule), _) => (rule.clone(), "runtime"),
+            (None, Some(rule)) => (rule.clone(), "config"),
+            (None, None) => (flag.default_rule(), "default"),
+        }
+    }
+
+    /// Every flag's state
+    pub fn states(&self) -> Vec<FlagState> {
+        Flag::ALL.into_iter().map(|flag| self.state(flag)).collect()
+    }
+
+    /// Replace a flag's rule until restart
+    pub fn set(&self, flag: Flag, rule: FlagRule) {
+        let mut overrides = self.overrides.write().expect("flags lock poisoned");
+        overrides.insert(flag, rule);
+    }
+
+    /// Go back to the configured rule
+    pub fn reset(&self, flag: Flag) {
+        let mut overrides = self.overrides.write().expect("flags lock poisoned");
+        overrides.remove(&flag);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(host: &str) -> Url {
+        Url::parse(&format!("https://{}/page", host)).unwrap()
+    }
+
+    #[test]
+    fn test_parse_rule() {
+        let rule: FlagRule = "25%, docs.rs, -Blog.Example.com".parse().unwrap();
+        assert_eq!(rule.percent, 25);
+        assert_eq!(rule.domains, ["docs.rs"]);
+        assert_eq!(rule.excluded, ["blog.example.com"]);
+        assert_eq!(rule.to_string(), "25%,docs.rs,-blog.example.com");
+        assert_eq!("on".parse::<FlagRule>().unwrap(), FlagRule::on());
+        assert_eq!("".parse::<FlagRule>().unwrap(), FlagRule::default());
+        assert!("150%".parse::<FlagRule>().is_err());
+        assert!("https://docs.rs/".parse::<FlagRule>().is_err());
+        assert!("preview"
+            .parse::<Flag>()
+            .unwrap_err()
+            .contains("semantic_engine"));
+    }
+
+    #[test]
+    fn test_rule_applies() {
+        let rule: FlagRule = "off,docs.rs,-private.docs.rs".parse().unwrap();
+        assert!(rule.applies(Flag::Prerender, "docs.rs"));
+        assert!(rule.applies(Flag::Prerender, "api.docs.rs"));
+        assert!(!rule.applies(Flag::Prerender, "private.docs.rs"));
+        assert!(!rule.applies(Flag::Prerender, "example.com"));
+
+        // A percentage covers about that share of domains, the same ones each time
+        let rule: FlagRule = "30%".parse().unwrap();
+        let hosts: Vec<_> = (0..1000).map(|i| format!("site{}.example", i)).collect();
+        let on = hosts
+            .iter()
+            .filter(|h| rule.applies(Flag::Prerender, h))
+            .count();
+        assert!((250..350).contains(&on), "{}", on);
+        let wider: FlagRule = "60%".parse().unwrap();
+        assert!(hosts
+            .iter()
+            .filter(|h| rule.applies(Flag::Prerender, h))
+            .all(|h| wider.applies(Flag::Prerender, h)));
+    }
+
+    #[test]
+    fn test_overrides() {
+        let flags = Flags::new(BTreeMap::from([(
+            Flag::SemanticEngine,
+            "docs.rs".parse().unwrap(),
+        )]));
+        assert!(flags.enabled(Flag::Prerender, &url("example.com")));
+        assert!(flags.enabled(Flag::SemanticEngine, &url("docs.rs")));
+        assert!(!flags.enabled(Flag::SemanticEngine, &url("example.com")));
+        assert_eq!(flags.state(Flag::SemanticEngine).source, "config");
+
+        flags.set(Flag::SemanticEngine, "on".parse().unwrap());
+        assert!(flags.enabled(Flag::SemanticEngine, &url("example.com")));
+        assert_eq!(flags.state(Flag::SemanticEngine).source, "runtime");
+
+        flags.reset(Flag::SemanticEngine);
+        assert!(!flags.enabled(Flag::SemanticEngine, &url("example.com")));
+        assert_eq!(flags.state(Flag::Prerender).source, "default");
+    }
+}
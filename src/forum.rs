@@ -0,0 +1,283 @@
+//! Thread-aware conversion of forum pages
+//!
+//! Readability picks the single densest block of a page, which on a forum
+//! thread is usually just the first post. For pages classified as forums this
+//! finds every post instead, using the markup of common forum software, and
+//! renders each as a quoted section headed by its author and timestamp.
+//! Links and images in posts point at the thread's URL, as they would inline.
+
+use crate::convert::clean_markdown;
+use scraper::{ElementRef, Html, Node, Selector};
+use url::Url;
+
+/// Where one forum engine puts its posts and their parts
+struct ThreadLayout {
+    post: &'static str,
+    author: &'static str,
+    time: &'static str,
+    body: &'static str,
+}
+
+/// Known layouts, tried in order until one finds posts
+const LAYOUTS: &[ThreadLayout] = &[
+    // Discourse's server-rendered view for clients without JavaScript
+    ThreadLayout {
+        post: "div.crawler-post",
+        author: "[itemprop='author'] [itemprop='name'], .creator",
+        time: "time[datetime], [itemprop='datePublished']",
+        body: "[itemprop='text'], div.post",
+    },
+    // phpBB
+    ThreadLayout {
+        post: "div.post",
+        author: ".author .username, .author .username-coloured",
+        time: ".author time",
+        body: ".postbody .content",
+    },
+    // vBulletin
+    ThreadLayout {
+        post: "li.postcontainer, div.postcontainer",
+        author: ".username",
+        time: ".postdate .date, .date",
+        body: ".postcontent",
+    },
+    // XenForo
+    ThreadLayout {
+        post: "article.message",
+        author: ".message-name .username",
+        time: "time[datetime]",
+        body: ".message-body .bbWrapper",
+    },
+    // schema.org microdata
+    ThreadLayout {
+        post: "[itemtype$='DiscussionForumPosting'], [itemtype$='Comment']",
+        author: "[itemprop='author'] [itemprop='name'], [itemprop='author']",
+        time: "[itemprop='datePublished']",
+        body: "[itemprop='text'], [itemprop='articleBody']",
+    },
+];
+
+/// One post of a thread
+#[derive(Clone, Debug, PartialEq)]
+pub struct Post {
+    pub author: Option<String>,
+    pub time: Option<String>,
+    /// Post content as HTML
+    pub body: String,
+}
+
+/// Posts of a forum thread in page order, empty if no known layout matches
+pub fn thread_posts(html: &str) -> Vec<Post> {
+    let document = Html::parse_document(html);
+
+    for layout in LAYOUTS {
+        let selector = |css: &str| Selector::parse(css).expect("valid CSS selector");
+        let (author, time, body) = (
+            selector(layout.author),
+            selector(layout.time),
+            selector(layout.body),
+        );
+
+        let posts: Vec<Post> = document
+            .select(&selector(layout.post))
+            .filter_map(|post| {
+                let body = post.select(&body).next()?.inner_html();
+                let author = post.select(&author).next().map(element_text);
+                let time = post.select(&time).next().map(|el| {
+                    let value = el.value();
+                    value
+                        .attr("datetime")
+                        .or_else(|| value.attr("content"))
+                        .map(|t| t.trim().to_string())
+                        .unwrap_or_else(|| element_text(el))
+                });
+                Some(Post {
+                    author: author.filter(|a| !a.is_empty()),
+                    time: time.filter(|t| !t.is_empty()),
+                    body,
+                })
+            })
+            .collect();
+
+        if !posts.is_empty() {
+            return posts;
+        }
+    }
+    Vec::new()
+}
+
+/// Markdown for a thread: a quoted section per post, or `None` if no posts were found
+pub fn thread_to_markdown(
+    html: &str,
+    base_url: &Url,
+    title: Option<&str>,
+    notice: Option<&str>,
+) -> Option<String> {
+    let posts = thread_posts(html);
+    (!posts.is_empty()).then(|| posts_to_markdown(&posts, base_url, title, notice))
+}
+
+/// Render posts as quoted sections headed by their author and timestamp,
+/// relative links resolved against `base_url`
+pub fn posts_to_markdown(
+    posts: &[Post],
+    base_url: &Url,
+    title: Option<&str>,
+    notice: Option<&str>,
+) -> String {
+    let mut markdown = String::new();
+    if let Some(notice) = notice {
+        markdown.push_str(&format!("<!-- {} -->\n\n", notice));
+    }
+    if let Some(title) = title {
+        markdown.push_str(&format!("# {}\n\n", title));
+    }
+
+    for post in posts {
+        let html = absolute_links(&post.body, base_url);
+        let body = htmd::convert(&html).unwrap_or(html);
+        push_quoted_post(
+            &mut markdown,
+            post.author.as_deref(),
+            post.time.as_deref(),
+            &body,
+        );
+    }
+
+    clean_markdown(&markdown)
+}
+
+/// Append a post with a markdown body as a quoted section headed by its byline
+pub fn push_quoted_post(
+    markdown: &mut String,
+    author: Option<&str>,
+    time: Option<&str>,
+    body: &str,
+) {
+    let byline = match (author, time) {
+        (Some(author), Some(time)) => Some(format!("**{}** · {}", author, time)),
+        (Some(author), None) => Some(format!("**{}**", author)),
+        (None, Some(time)) => Some(time.to_string()),
+        (None, None) => None,
+    };
+    if let Some(byline) = byline {
+        markdown.push_str(&format!("> {}\n>\n", byline));
+    }
+
+    for line in clean_markdown(body).lines() {
+        match line {
+            "" => markdown.push_str(">\n"),
+            line => markdown.push_str(&format!("> {}\n", line)),
+        }
+    }
+    markdown.push('\n');
+}
+
+/// Post HTML with `href` and `src` attributes made absolute
+fn absolute_links(html: &str, base_url: &Url) -> String {
+    let mut fragment = Html::parse_fragment(html);
+    for node in fragment.tree.values_mut() {
+        let Node::Element(element) = node else {
+            continue;
+        };
+        for (name, value) in element.attrs.iter_mut() {
+            if !matches!(&*name.local, "href" | "src") {
+                continue;
+            }
+            if let Ok(url) = base_url.join(value.trim()) {
+                *value = url.as_str().into();
+            }
+        }
+    }
+    fragment.root_element().inner_html()
+}
+
+/// Element text with whitespace collapsed
+fn element_text(element: ElementRef) -> String {
+    element
+        .text()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_discourse_crawler_view() {
+        let post = |n: u32, user: &str| {
+            format!(
+                "<div id=\"post_{n}\" class=\"topic-body crawler-post\">\
+                 <div class=\"crawler-post-meta\"><span class=\"creator\" itemprop=\"author\">\
+                 <a href=\"/u/{user}\"><span itemprop=\"name\">{user}</span></a></span>\
+                 <time itemprop=\"datePublished\" datetime=\"2024-05-0{n}T10:00:00Z\" \
+                 class=\"post-time\">May {n}</time></div>\
+                 <div class=\"post\" itemprop=\"text\">Reply number {n}</div></div>"
+            )
+        };
+        let html = format!(
+            "<html><body>{}{}</body></html>",
+            post(1, "alice"),
+            post(2, "bob")
+        );
+
+        let posts = thread_posts(&html);
+        assert_eq!(posts.len(), 2);
+        assert_eq!(posts[1].author.as_deref(), Some("bob"));
+        assert_eq!(posts[1].time.as_deref(), Some("2024-05-02T10:00:00Z"));
+
+        let base_url = Url::parse("https://forum.example.com/t/help/7").unwrap();
+        let markdown = thread_to_markdown(&html, &base_url, Some("Help"), None).unwrap();
+        assert_eq!(
+            markdown,
+            "# Help\n\n\
+             > **alice** · 2024-05-01T10:00:00Z\n>\n> Reply number 1\n\n\
+             > **bob** · 2024-05-02T10:00:00Z\n>\n> Reply number 2\n"
+        );
+    }
+
+    #[test]
+    fn test_phpbb_and_no_posts() {
+        let html = "<html><body><div class=\"post\"><div class=\"postbody\">\
+                    <p class=\"author\"><a class=\"username\">carol</a></p>\
+                    <div class=\"content\">First!</div></div></div></body></html>";
+        let posts = thread_posts(html);
+        assert_eq!(posts.len(), 1);
+        assert_eq!(posts[0].author.as_deref(), Some("carol"));
+        let base_url = Url::parse("https://forum.example.com/viewtopic.php?t=1").unwrap();
+        assert_eq!(posts[0].time, None);
+
+        assert_eq!(
+            thread_to_markdown("<html><body><p>Hi</p></body></html>", &base_url, None, None),
+            None
+        );
+    }
+
+    #[test]
+    fn test_relative_links_resolved() {
+        let posts = [Post {
+            author: Some("dave".to_string()),
+            time: None,
+            body: "<p>See <a href=\"/t/setup/12\">setup</a> and <a href=\"#post_3\">above</a>, \
+                   or <a href=\"https://docs.example.org/\">the docs</a>.</p>\
+                   <p><img src=\"uploads/diagram.png\" alt=\"diagram\"></p>"
+                .to_string(),
+        }];
+        let base_url = Url::parse("https://forum.example.com/t/help/7").unwrap();
+        let markdown = posts_to_markdown(&posts, &base_url, None, None);
+        assert!(
+            markdown.contains("[setup](https://forum.example.com/t/setup/12)"),
+            "{}",
+            markdown
+        );
+        assert!(markdown.contains("[above](https://forum.example.com/t/help/7#post_3)"));
+        assert!(markdown.contains("[the docs](https://docs.example.org/)"));
+        assert!(
+            markdown.contains("![diagram](https://forum.example.com/t/help/uploads/diagram.png)")
+        );
+    }
+}
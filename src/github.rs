@@ -0,0 +1,618 @@
+//! GitHub issues, pull requests and directories through the REST API
+//!
+//! Issue pages are heavy client-rendered HTML and github.com rate-limits
+//! anonymous page loads hard. The API serves the same thread with bodies
+//! already in markdown, so `/{owner}/{repo}/issues/{n}` and `/pull/{n}` URLs
+//! are rendered from `/repos/{owner}/{repo}/issues/{n}` and its comments.
+//! `/tree/{ref}/{path}` URLs list the directory from `/contents/{path}`, and
+//! gists are read from `/gists/{id}`.
+//!
+//! With `GITHUB_TOKEN` the API answers for whatever the token can see, but
+//! the result goes to whoever asked, so issues and directories are only read
+//! from repositories the API reports as public. Secret gists need no token,
+//! being readable by anyone with their URL.
+
+use crate::convert::clean_markdown;
+use crate::error::{MdwnError, Result};
+use crate::fetch::{FetchBudget, Fetcher};
+use crate::forum::push_quoted_post;
+use crate::paste::PasteFile;
+use crate::repo::{EntryKind, Tree, TreeEntry, MAX_ENTRIES};
+use reqwest::header::{HeaderMap, HeaderValue, ACCEPT, AUTHORIZATION};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use url::Url;
+
+/// Default API root
+pub const DEFAULT_API_URL: &str = "https://api.github.com";
+
+/// Comments per page, the API's maximum
+const COMMENTS_PER_PAGE: usize = 100;
+
+/// Comments rendered at most, the rest are counted as omitted
+const MAX_COMMENTS: usize = 500;
+
+/// An issue or pull request with its conversation
+#[derive(Debug)]
+pub struct Issue {
+    pub title: String,
+    pub number: u64,
+    pub pull_request: bool,
+    /// `open` or `closed`
+    pub state: String,
+    /// Opening post followed by the comments, bodies in markdown
+    pub posts: Vec<Comment>,
+    /// Comments left out by the comment limit
+    pub omitted: usize,
+}
+
+/// One post of the conversation
+#[derive(Debug, Deserialize)]
+pub struct Comment {
+    pub user: Option<User>,
+    pub created_at: Option<String>,
+    /// `null` when left empty
+    pub body: Option<String>,
+}
+
+/// Author of a post
+#[derive(Debug, Deserialize)]
+pub struct User {
+    pub login: String,
+}
+
+#[derive(Deserialize)]
+struct ApiIssue {
+    title: String,
+    number: u64,
+    state: String,
+    #[serde(default)]
+    comments: usize,
+    /// Present only on pull requests
+    pull_request: Option<serde_json::Value>,
+    #[serde(flatten)]
+    post: Comment,
+}
+
+#[derive(Deserialize)]
+struct ApiRepository {
+    #[serde(default)]
+    private: bool,
+    /// `public`, `private` or `internal`, missing on older GitHub Enterprise
+    visibility: Option<String>,
+}
+
+/// Owner, repository and number of a github.com issue or pull request URL
+fn issue_path(url: &Url) -> Option<(&str, &str, u64)> {
+    if !matches!(url.host_str(), Some("github.com" | "www.github.com")) {
+        return None;
+    }
+    let segments: Vec<&str> = url.path_segments()?.collect();
+    // Anything after the number (`/files`, `/commits`) still names the same thread
+    let [owner, repo, "issues" | "pull", number, ..] = segments.as_slice() else {
+        return None;
+    };
+    let number: u64 = number.parse().ok()?;
+    if owner.is_empty() || repo.is_empty() {
+        return None;
+    }
+    Some((owner, repo, number))
+}
+
+/// API URL of the issue behind a github.com issue or pull request URL
+pub fn issue_api_url(url: &Url, api_base: &Url) -> Option<Url> {
+    let (owner, repo, number) = issue_path(url)?;
+    let mut api = repository_api_url(api_base, owner, repo);
+    api.set_path(&format!("{}/issues/{}", api.path(), number));
+    Some(api)
+}
+
+fn repository_api_url(api_base: &Url, owner: &str, repo: &str) -> Url {
+    let mut api = api_base.clone();
+    let root = api_base.path().trim_end_matches('/');
+    api.set_path(&format!("{}/repos/{}/{}", root, owner, repo));
+    api
+}
+
+/// Refuse a repository that isn't public when a token is sent, as the token
+/// may see private ones; `Ok(false)` if the API couldn't say
+///
+/// Without a token the API itself refuses private repositories.
+async fn check_public(
+    fetcher: &Fetcher,
+    budget: &FetchBudget,
+    api_base: &Url,
+    (owner, repo): (&str, &str),
+    headers: &HeaderMap,
+) -> Result<bool> {
+    if !headers.contains_key(AUTHORIZATION) {
+        return Ok(true);
+    }
+    let api_url = repository_api_url(api_base, owner, repo);
+    let response = match fetcher.fetch_with_headers(&api_url, budget, headers).await {
+        Ok(response) => response,
+        Err(e) => {
+            tracing::warn!("GitHub API unavailable for {}/{}: {}", owner, repo, e);
+            return Ok(false);
+        }
+    };
+    let repository: ApiRepository = match serde_json::from_slice(&response.body) {
+        Ok(repository) => repository,
+        Err(e) => {
+            tracing::warn!("Unexpected GitHub API response for {}: {}", api_url, e);
+            return Ok(false);
+        }
+    };
+    let public = !repository.private
+        && repository
+            .visibility
+            .as_deref()
+            .is_none_or(|v| v == "public");
+    if !public {
+        tracing::debug!("Not serving {}/{}: not a public repository", owner, repo);
+        return Err(MdwnError::NotFound);
+    }
+    Ok(true)
+}
+
+/// Fetch an issue or pull request and its comments, or `None` if the URL isn't
+/// one or the API refuses (private repository, rate limit)
+///
+/// Failing to fetch later comment pages isn't an error: the comments fetched
+/// so far are returned with the rest counted as omitted. Issues of a
+/// repository that isn't public are `NotFound` when a token is sent.
+pub async fn fetch_issue(
+    fetcher: &Fetcher,
+    budget: &FetchBudget,
+    url: &Url,
+    api_base: &Url,
+    token: Option<&str>,
+) -> Result<Option<Issue>> {
+    let Some((owner, repo, _)) = issue_path(url) else {
+        return Ok(None);
+    };
+    let (Some(api_url), Some(headers)) = (issue_api_url(url, api_base), api_headers(token)) else {
+        return Ok(None);
+    };
+    if !check_public(fetcher, budget, api_base, (owner, repo), &headers).await? {
+        return Ok(None);
+    }
+
+    let response = match fetcher.fetch_with_headers(&api_url, budget, &headers).await {
+        Ok(response) => response,
+        Err(e) => {
+            tracing::warn!("GitHub API unavailable for {}: {}", url, e);
+            return Ok(None);
+        }
+    };
+    let issue: ApiIssue = match serde_json::from_slice(&response.body) {
+        Ok(issue) => issue,
+        Err(e) => {
+            tracing::warn!("Unexpected GitHub API response for {}: {}", api_url, e);
+            return Ok(None);
+        }
+    };
+
+    let wanted = issue.comments.min(MAX_COMMENTS);
+    let mut comments: Vec<Comment> = Vec::with_capacity(wanted);
+    for page in 1..=wanted.div_ceil(COMMENTS_PER_PAGE) {
+        let mut page_url = api_url.clone();
+        page_url.set_path(&format!("{}/comments", api_url.path()));
+        page_url
+            .query_pairs_mut()
+            .append_pair("per_page", &COMMENTS_PER_PAGE.to_string())
+            .append_pair("page", &page.to_string());
+
+        let page = match fetcher
+            .fetch_with_headers(&page_url, budget, &headers)
+            .await
+        {
+            Ok(page) => page,
+            Err(e) => {
+                tracing::warn!("Stopped fetching comments of {}: {}", url, e);
+                break;
+            }
+        };
+        match serde_json::from_slice::<Vec<Comment>>(&page.body) {
+            Ok(page) if page.is_empty() => break,
+            Ok(page) => comments.extend(page),
+            Err(e) => {
+                tracing::warn!("Unexpected GitHub comments from {}: {}", page_url, e);
+                break;
+            }
+        }
+    }
+    comments.truncate(wanted);
+    let omitted = issue.comments.saturating_sub(comments.len());
+
+    let mut posts = vec![issue.post];
+    posts.extend(comments);
+    Ok(Some(Issue {
+        title: issue.title,
+        number: issue.number,
+        pull_request: issue.pull_request.is_some(),
+        state: issue.state,
+        posts,
+        omitted,
+    }))
+}
+
+/// Request headers for the API, `None` if the token can't be sent in a header
+fn api_headers(token: Option<&str>) -> Option<HeaderMap> {
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        ACCEPT,
+        HeaderValue::from_static("application/vnd.github+json"),
+    );
+    if let Some(token) = token {
+        let mut value = HeaderValue::from_str(&format!("Bearer {}", token)).ok()?;
+        value.set_sensitive(true);
+        headers.insert(AUTHORIZATION, value);
+    }
+    Some(headers)
+}
+
+#[derive(Deserialize)]
+struct ApiContent {
+    name: String,
+    path: String,
+    #[serde(rename = "type")]
+    kind: String,
+    html_url: Option<String>,
+    download_url: Option<String>,
+}
+
+/// A `/tree/` URL split into its parts
+struct TreePath<'a> {
+    owner: &'a str,
+    repo: &'a str,
+    git_ref: &'a str,
+    path: String,
+}
+
+fn tree_path(url: &Url) -> Option<TreePath<'_>> {
+    if !matches!(url.host_str(), Some("github.com" | "www.github.com")) {
+        return None;
+    }
+    let segments: Vec<&str> = url.path_segments()?.collect();
+    // Branch names with slashes can't be told apart from the path without an
+    // extra lookup, so the ref is taken to be a single segment
+    let [owner, repo, "tree", git_ref, path @ ..] = segments.as_slice() else {
+        return None;
+    };
+    if owner.is_empty() || repo.is_empty() || git_ref.is_empty() {
+        return None;
+    }
+    Some(TreePath {
+        owner,
+        repo,
+        git_ref,
+        path: path.join("/").trim_end_matches('/').to_string(),
+    })
+}
+
+/// Contents API URL of the directory behind a github.com `/tree/` URL
+pub fn tree_api_url(url: &Url, api_base: &Url) -> Option<Url> {
+    let tree = tree_path(url)?;
+    let mut api = repository_api_url(api_base, tree.owner, tree.repo);
+    api.set_path(&format!("{}/contents/{}", api.path(), tree.path));
+    api.query_pairs_mut().append_pair("ref", tree.git_ref);
+    Some(api)
+}
+
+/// Fetch the listing of a repository directory, or `None` if the URL isn't one
+/// or the API refuses
+///
+/// Directories of a repository that isn't public are `NotFound` when a token
+/// is sent.
+pub async fn fetch_tree(
+    fetcher: &Fetcher,
+    budget: &FetchBudget,
+    url: &Url,
+    api_base: &Url,
+    token: Option<&str>,
+) -> Result<Option<Tree>> {
+    let (Some(tree), Some(api_url), Some(headers)) = (
+        tree_path(url),
+        tree_api_url(url, api_base),
+        api_headers(token),
+    ) else {
+        return Ok(None);
+    };
+    if !check_public(fetcher, budget, api_base, (tree.owner, tree.repo), &headers).await? {
+        return Ok(None);
+    }
+
+    let response = match fetcher.fetch_with_headers(&api_url, budget, &headers).await {
+        Ok(response) => response,
+        Err(e) => {
+            tracing::warn!("GitHub API unavailable for {}: {}", url, e);
+            return Ok(None);
+        }
+    };
+    // A file path answers with a single object rather than a listing
+    let Ok(contents) = serde_json::from_slice::<Vec<ApiContent>>(&response.body) else {
+        return Ok(None);
+    };
+    // The contents API silently stops at this many entries
+    let truncated = contents.len() >= MAX_ENTRIES;
+
+    let entries = contents
+        .into_iter()
+        .filter_map(|content| {
+            let (kind, target) = match content.kind.as_str() {
+                "dir" => (
+                    EntryKind::Directory,
+                    format!(
+                        "https://github.com/{}/{}/tree/{}/{}",
+                        tree.owner, tree.repo, tree.git_ref, content.path
+                    ),
+                ),
+                "file" | "symlink" => (EntryKind::File, content.download_url?),
+                "submodule" => (EntryKind::Submodule, content.html_url?),
+                _ => return None,
+            };
+            Some(TreeEntry {
+                name: content.name,
+                kind,
+                url: target,
+            })
+        })
+        .collect();
+
+    Ok(Some(Tree {
+        repository: format!("{}/{}", tree.owner, tree.repo),
+        git_ref: tree.git_ref.to_string(),
+        path: tree.path,
+        entries,
+        truncated,
+    }))
+}
+
+#[derive(Deserialize)]
+struct ApiGist {
+    description: Option<String>,
+    files: BTreeMap<String, ApiGistFile>,
+}
+
+#[derive(Deserialize)]
+struct ApiGistFile {
+    filename: String,
+    language: Option<String>,
+    raw_url: Option<String>,
+    #[serde(default)]
+    content: String,
+    /// Content over 1MB is cut short and has to be read from `raw_url`
+    #[serde(default)]
+    truncated: bool,
+}
+
+/// A gist with its files
+#[derive(Debug)]
+pub struct Gist {
+    /// Description, or the first file name when there is none
+    pub title: String,
+    pub files: Vec<PasteFile>,
+}
+
+/// API URL of the gist behind a gist.github.com URL
+pub fn gist_api_url(url: &Url, api_base: &Url) -> Option<Url> {
+    if url.host_str() != Some("gist.github.com") {
+        return None;
+    }
+    let segments: Vec<&str> = url.path_segments()?.filter(|s| !s.is_empty()).collect();
+    // `/{user}/{id}` or the anonymous `/{id}`, possibly followed by a revision
+    let id = match segments.as_slice() {
+        [id] | [_, id] | [_, id, _] => *id,
+        _ => return None,
+    };
+    if id.len() < 7 || !id.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    let mut api = api_base.clone();
+    let root = api_base.path().trim_end_matches('/');
+    api.set_path(&format!("{}/gists/{}", root, id));
+    Some(api)
+}
+
+/// Fetch a gist and its files, or `None` if the URL isn't one or the API refuses
+pub async fn fetch_gist(
+    fetcher: &Fetcher,
+    budget: &FetchBudget,
+    url: &Url,
+    api_base: &Url,
+    token: Option<&str>,
+) -> Option<Gist> {
+    let api_url = gist_api_url(url, api_base)?;
+    let headers = api_headers(token)?;
+    let response = match fetcher.fetch_with_headers(&api_url, budget, &headers).await {
+        Ok(response) => response,
+        Err(e) => {
+            tracing::warn!("GitHub API unavailable for {}: {}", url, e);
+            return None;
+        }
+    };
+    let gist: ApiGist = match serde_json::from_slice(&response.body) {
+        Ok(gist) => gist,
+        Err(e) => {
+            tracing::warn!("Unexpected GitHub API response for {}: {}", api_url, e);
+            return None;
+        }
+    };
+
+    let mut files = Vec::with_capacity(gist.files.len());
+    for file in gist.files.into_values() {
+        let mut content = file.content;
+        if file.truncated {
+            let raw_url = file.raw_url.as_deref().and_then(|raw| Url::parse(raw).ok());
+            if let Some(raw_url) = raw_url {
+                match fetcher.fetch(&raw_url, budget).await {
+                    Ok(raw) => content = raw.body_as_string(),
+                    Err(e) => tracing::warn!("Gist file {} kept truncated: {}", raw_url, e),
+                }
+            }
+        }
+        files.push(PasteFile {
+            name: Some(file.filename),
+            language: file.language,
+            content,
+        });
+    }
+
+    let description = gist.description.filter(|d| !d.trim().is_empty());
+    let title = description
+        .or_else(|| files.first().and_then(|f| f.name.clone()))
+        .unwrap_or_else(|| "Gist".to_string());
+    Some(Gist { title, files })
+}
+
+/// Render an issue as a heading and a quoted section per post
+pub fn issue_to_markdown(issue: &Issue, notice: Option<&str>) -> String {
+    let mut markdown = String::new();
+    if let Some(notice) = notice {
+        markdown.push_str(&format!("<!-- {} -->\n\n", notice));
+    }
+    markdown.push_str(&format!("# {} (#{})\n\n", issue.title, issue.number));
+    let kind = if issue.pull_request {
+        "Pull request"
+    } else {
+        "Issue"
+    };
+    markdown.push_str(&format!("*{}, {}*\n\n", kind, issue.state));
+
+    for post in &issue.posts {
+        let author = post.user.as_ref().map(|u| format!("@{}", u.login));
+        let body = post
+            .body
+            .as_deref()
+            .unwrap_or_default()
+            .replace("\r\n", "\n");
+        let body = if body.trim().is_empty() {
+            "*No description provided.*"
+        } else {
+            &body
+        };
+        push_quoted_post(
+            &mut markdown,
+            author.as_deref(),
+            post.created_at.as_deref(),
+            body,
+        );
+    }
+    if issue.omitted > 0 {
+        let comments = if issue.omitted == 1 {
+            "comment"
+        } else {
+            "comments"
+        };
+        markdown.push_str(&format!(
+            "*{} more {} not shown*\n",
+            issue.omitted, comments
+        ));
+    }
+
+    clean_markdown(&markdown)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_issue_api_url() {
+        let base = Url::parse(DEFAULT_API_URL).unwrap();
+        let api = |url: &str| issue_api_url(&Url::parse(url).unwrap(), &base).map(String::from);
+        assert_eq!(
+            api("https://github.com/rust-lang/rust/issues/12345#issuecomment-1").as_deref(),
+            Some("https://api.github.com/repos/rust-lang/rust/issues/12345")
+        );
+        assert_eq!(
+            api("https://github.com/tokio-rs/axum/pull/42/files").as_deref(),
+            Some("https://api.github.com/repos/tokio-rs/axum/issues/42")
+        );
+        assert_eq!(api("https://github.com/rust-lang/rust/issues"), None);
+        assert_eq!(
+            api("https://github.com/rust-lang/rust/blob/main/README.md"),
+            None
+        );
+        assert_eq!(api("https://gitlab.com/group/project/issues/1"), None);
+
+        // Enterprise-style API roots keep their path
+        let base = Url::parse("https://ghe.example.com/api/v3/").unwrap();
+        assert_eq!(
+            issue_api_url(
+                &Url::parse("https://github.com/a/b/issues/1").unwrap(),
+                &base
+            )
+            .map(String::from)
+            .as_deref(),
+            Some("https://ghe.example.com/api/v3/repos/a/b/issues/1")
+        );
+    }
+
+    #[test]
+    fn test_tree_api_url() {
+        let base = Url::parse(DEFAULT_API_URL).unwrap();
+        let api = |url: &str| tree_api_url(&Url::parse(url).unwrap(), &base).map(String::from);
+        assert_eq!(
+            api("https://github.com/tokio-rs/axum/tree/main/axum/src").as_deref(),
+            Some("https://api.github.com/repos/tokio-rs/axum/contents/axum/src?ref=main")
+        );
+        assert_eq!(
+            api("https://github.com/tokio-rs/axum/tree/v0.8.0").as_deref(),
+            Some("https://api.github.com/repos/tokio-rs/axum/contents/?ref=v0.8.0")
+        );
+        assert_eq!(api("https://github.com/tokio-rs/axum"), None);
+        assert_eq!(
+            api("https://github.com/tokio-rs/axum/blob/main/README.md"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_gist_api_url() {
+        let base = Url::parse(DEFAULT_API_URL).unwrap();
+        let api = |url: &str| gist_api_url(&Url::parse(url).unwrap(), &base).map(String::from);
+        assert_eq!(
+            api("https://gist.github.com/octocat/6cad326836d38bd3a7ae").as_deref(),
+            Some("https://api.github.com/gists/6cad326836d38bd3a7ae")
+        );
+        assert_eq!(
+            api("https://gist.github.com/6cad326836d38bd3a7ae#file-hello-rs").as_deref(),
+            Some("https://api.github.com/gists/6cad326836d38bd3a7ae")
+        );
+        assert_eq!(api("https://gist.github.com/octocat"), None);
+        assert_eq!(api("https://gist.github.com/discover"), None);
+    }
+
+    #[test]
+    fn test_issue_to_markdown() {
+        let post = |login: &str, body: Option<&str>| Comment {
+            user: Some(User {
+                login: login.to_string(),
+            }),
+            created_at: Some("2024-03-01T09:00:00Z".to_string()),
+            body: body.map(str::to_string),
+        };
+        let issue = Issue {
+            title: "Crash on empty input".to_string(),
+            number: 7,
+            pull_request: false,
+            state: "open".to_string(),
+            posts: vec![
+                post("alice", None),
+                post("bob", Some("Same here.\r\n\r\n```\nlog\n```")),
+            ],
+            omitted: 1,
+        };
+
+        assert_eq!(
+            issue_to_markdown(&issue, None),
+            "# Crash on empty input (#7)\n\n*Issue, open*\n\n\
+             > **@alice** · 2024-03-01T09:00:00Z\n>\n> *No description provided.*\n\n\
+             > **@bob** · 2024-03-01T09:00:00Z\n>\n> Same here.\n>\n> ```\n> log\n> ```\n\n\
+             *1 more comment not shown*\n"
+        );
+    }
+}
@@ -0,0 +1,301 @@
+//! Link graphs of prefetched page sets
+//!
+//! A prefetch is the closest thing to a crawl here, so each `POST /prefetch`
+//! gets an id, and once its pages are fetched `/graph/{id}` serves the links
+//! between them as JSON: pages as nodes, links as edges, for ranking pages by
+//! centrality before deciding what to ingest. Links to other pages on the
+//! same hosts are nodes too, marked as only linked, as candidates for the
+//! next prefetch. Graphs of the last few prefetches are kept until restart.
+
+use crate::AppState;
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use mdwnio::error::{MdwnError, Result};
+use regex::Regex;
+use serde::Serialize;
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::sync::{Arc, LazyLock, Mutex};
+use url::Url;
+
+/// Prefetches whose graphs are kept, oldest dropped first
+const MAX_GRAPHS: usize = 32;
+
+/// Inline links and images; `!` marks an image
+static LINK_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(!?)\[[^\]]*\]\(\s*<?([^)\s>]+)").expect("valid regex"));
+
+/// A prefetched page and where it links
+pub struct Page {
+    pub url: Url,
+    pub title: Option<String>,
+    /// Pages on the same host the markdown links to, `None` if the fetch failed
+    pub links: Option<Vec<Url>>,
+}
+
+impl Page {
+    /// A fetched page, with the links of its markdown
+    pub fn fetched(url: Url, title: Option<String>, markdown: &str) -> Self {
+        let links = page_links(&url, markdown);
+        Self {
+            url,
+            title,
+            links: Some(links),
+        }
+    }
+
+    pub fn failed(url: Url) -> Self {
+        Self {
+            url,
+            title: None,
+            links: None,
+        }
+    }
+}
+
+/// Absolute http(s) links of `markdown` outside code blocks, fragments
+/// dropped, without images, duplicates or links back to the page itself
+pub(crate) fn page_links(url: &Url, markdown: &str) -> Vec<Url> {
+    let mut seen = HashSet::new();
+    let mut in_fence = false;
+    let mut links = Vec::new();
+    for line in markdown.lines() {
+        if line.trim_start().starts_with("```") {
+            in_fence = !in_fence;
+        }
+        if in_fence {
+            continue;
+        }
+        for caps in LINK_RE.captures_iter(line) {
+            if !caps[1].is_empty() {
+                continue;
+            }
+            let Ok(mut link) = url.join(&caps[2]) else {
+                continue;
+            };
+            link.set_fragment(None);
+            if matches!(link.scheme(), "http" | "https")
+                && link.as_str() != url.as_str()
+                && seen.insert(link.to_string())
+            {
+                links.push(link);
+            }
+        }
+    }
+    links
+}
+
+/// A page in the graph
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Node {
+    pub url: String,
+    pub title: Option<String>,
+    /// `fetched`, `failed`, or `linked` for pages only linked to
+    pub status: &'static str,
+    pub in_degree: usize,
+    pub out_degree: usize,
+}
+
+/// A link, by index into the nodes
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Edge {
+    pub source: usize,
+    pub target: usize,
+}
+
+/// Internal links between the pages of one prefetch
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct LinkGraph {
+    pub nodes: Vec<Node>,
+    pub edges: Vec<Edge>,
+}
+
+impl LinkGraph {
+    /// The graph of a prefetch's pages, in the order they were queued, then
+    /// the pages only linked to, in the order they were first linked
+    ///
+    /// Links are internal if they point at a host one of the pages is on.
+    pub fn build(pages: Vec<Page>) -> Self {
+        let mut graph = LinkGraph::default();
+        let mut index = HashMap::new();
+        for page in &pages {
+            let status = if page.links.is_some() {
+                "fetched"
+            } else {
+                "failed"
+            };
+            index.insert(page.url.to_string(), graph.nodes.len());
+            graph.nodes.push(Node {
+                url: page.url.to_string(),
+                title: page.title.clone(),
+                status,
+                in_degree: 0,
+                out_degree: 0,
+            });
+        }
+
+        let hosts: BTreeSet<_> = pages
+            .iter()
+            .filter_map(|page| page.url.host_str())
+            .collect();
+        for (source, page) in pages.iter().enumerate() {
+            let links = page.links.iter().flatten();
+            for link in links.filter(|link| link.host_str().is_some_and(|h| hosts.contains(h))) {
+                let target = *index.entry(link.to_string()).or_insert_with(|| {
+                    graph.nodes.push(Node {
+                        url: link.to_string(),
+                        title: None,
+                        status: "linked",
+                        in_degree: 0,
+                        out_degree: 0,
+                    });
+                    graph.nodes.len() - 1
+                });
+                graph.nodes[source].out_degree += 1;
+                graph.nodes[target].in_degree += 1;
+                graph.edges.push(Edge { source, target });
+            }
+        }
+        graph
+    }
+}
+
+/// A prefetch's id and its graph, `None` while it's still running
+type Entry = (String, Option<Arc<LinkGraph>>);
+
+/// Graphs of recent prefetches, oldest first
+#[derive(Clone, Default)]
+pub struct LinkGraphs {
+    graphs: Arc<Mutex<VecDeque<Entry>>>,
+}
+
+impl LinkGraphs {
+    /// Register a prefetch that's starting, returning its id
+    pub fn start(&self) -> String {
+        let id = format!("{:016x}", rand::random::<u64>());
+        let mut graphs = self.graphs.lock().expect("graphs lock poisoned");
+        graphs.push_back((id.clone(), None));
+        while graphs.len() > MAX_GRAPHS {
+            graphs.pop_front();
+        }
+        id
+    }
+
+    /// Keep a finished prefetch's graph, unless it was dropped meanwhile
+    pub fn finish(&self, id: &str, graph: LinkGraph) {
+        let mut graphs = self.graphs.lock().expect("graphs lock poisoned");
+        if let Some((_, slot)) = graphs.iter_mut().find(|(key, _)| key == id) {
+            *slot = Some(Arc::new(graph));
+        }
+    }
+
+    fn get(&self, id: &str) -> Option<Option<Arc<LinkGraph>>> {
+        let graphs = self.graphs.lock().expect("graphs lock poisoned");
+        graphs
+            .iter()
+            .find(|(key, _)| key == id)
+            .map(|(_, graph)| graph.clone())
+    }
+}
+
+/// Body of `/graph/{id}`
+#[derive(Serialize)]
+struct GraphResponse<'a> {
+    id: &'a str,
+    /// `running` or `done`
+    status: &'static str,
+    #[serde(flatten)]
+    graph: Option<&'a LinkGraph>,
+}
+
+/// A prefetch's link graph, or `202 Accepted` while it's still running
+pub async fn graph_handler(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Response> {
+    let graph = state.graphs.get(&id).ok_or(MdwnError::NotFound)?;
+    let (code, status) = match graph {
+        Some(_) => (StatusCode::OK, "done"),
+        None => (StatusCode::ACCEPTED, "running"),
+    };
+    let body = GraphResponse {
+        id: &id,
+        status,
+        graph: graph.as_deref(),
+    };
+    Ok((code, Json(body)).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(s: &str) -> Url {
+        Url::parse(s).unwrap()
+    }
+
+    #[test]
+    fn test_page_links() {
+        let markdown = "See [install](install#steps), [home](/), [self](#top) and \
+                        [mail](mailto:a@example.com).\n\
+                        ![logo](/logo.png) [again](https://example.com/docs/install)\n\
+                        ```\n[code](/code)\n```\n";
+        let links: Vec<_> = page_links(&url("https://example.com/docs/"), markdown)
+            .into_iter()
+            .map(String::from)
+            .collect();
+        assert_eq!(
+            links,
+            ["https://example.com/docs/install", "https://example.com/"]
+        );
+    }
+
+    #[test]
+    fn test_build_graph() {
+        let a = url("https://example.com/a");
+        let b = url("https://example.com/b");
+        let pages = vec![
+            Page::fetched(
+                a.clone(),
+                Some("A".to_string()),
+                "[b](/b) [c](/c) [out](https://other.example/)",
+            ),
+            Page::fetched(b.clone(), None, "[a](/a) [c](/c)"),
+            Page::failed(url("https://example.com/d")),
+        ];
+        let graph = LinkGraph::build(pages);
+        let nodes: Vec<_> = graph
+            .nodes
+            .iter()
+            .map(|n| (n.url.as_str(), n.status, n.in_degree, n.out_degree))
+            .collect();
+        assert_eq!(
+            nodes,
+            [
+                ("https://example.com/a", "fetched", 1, 2),
+                ("https://example.com/b", "fetched", 1, 2),
+                ("https://example.com/d", "failed", 0, 0),
+                ("https://example.com/c", "linked", 2, 0),
+            ]
+        );
+        let edges: Vec<_> = graph.edges.iter().map(|e| (e.source, e.target)).collect();
+        assert_eq!(edges, [(0, 1), (0, 3), (1, 0), (1, 3)]);
+    }
+
+    #[test]
+    fn test_graphs_keep_recent() {
+        let graphs = LinkGraphs::default();
+        let first = graphs.start();
+        assert_eq!(graphs.get(&first), Some(None));
+        graphs.finish(&first, LinkGraph::default());
+        assert_eq!(graphs.get(&first), Some(Some(Arc::default())));
+
+        for _ in 0..MAX_GRAPHS {
+            graphs.start();
+        }
+        assert_eq!(graphs.get(&first), None);
+    }
+}
@@ -0,0 +1,142 @@
+//! Liveness and readiness probes
+//!
+//! `/healthz` answers as long as the process serves requests, so an
+//! orchestrator only restarts it when it's wedged. With `CANARY_URL` set,
+//! `/readyz` also checks that pages can be fetched: the canary's host has to
+//! resolve with the upstream resolver, and the URL has to fetch through the
+//! upstream client, SSRF checks included. While a check fails it answers 503,
+//! so traffic goes to other replicas without this one being restarted over a
+//! network outage. The results are reused for `CANARY_TTL` seconds, so probes
+//! from every kubelet and load balancer don't each fetch the canary.
+
+use crate::{selftest, AppState};
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+use url::Url;
+
+/// Outcome of one readiness check
+#[derive(Clone, Debug, Serialize)]
+struct Check {
+    /// `ok`, `fail` or `skipped`
+    status: &'static str,
+    detail: String,
+    ms: u64,
+}
+
+/// Body of `/readyz`
+#[derive(Debug, Serialize)]
+struct Readiness {
+    /// `ok` if no check failed, else `fail`
+    status: &'static str,
+    checks: BTreeMap<&'static str, Check>,
+}
+
+/// Last results of the canary checks, shared by every `/readyz` request
+#[derive(Clone, Default)]
+pub struct CanaryCache(Arc<Mutex<Option<CanaryChecks>>>);
+
+struct CanaryChecks {
+    url: Url,
+    checked: Instant,
+    dns: Check,
+    egress: Check,
+}
+
+/// Liveness: the process is up and serving
+pub async fn healthz_handler() -> &'static str {
+    "OK"
+}
+
+/// Readiness: DNS and outbound HTTPS work, as JSON per check
+pub async fn readyz_handler(State(state): State<AppState>) -> Response {
+    let canary = state.config.canary_url.as_deref();
+    let (dns, egress) = match canary.and_then(|canary| Url::parse(canary).ok()) {
+        Some(url) => canary_checks(&state, url).await,
+        None => (skipped(), skipped()),
+    };
+
+    let checks = BTreeMap::from([("dns", dns), ("egress", egress)]);
+    let ready = checks.values().all(|check| check.status != "fail");
+    let status = if ready { "ok" } else { "fail" };
+    let code = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (code, Json(Readiness { status, checks })).into_response()
+}
+
+/// Check DNS and egress with the canary, or reuse the results of the last
+/// check within `CANARY_TTL`
+async fn canary_checks(state: &AppState, url: Url) -> (Check, Check) {
+    let ttl = Duration::from_secs(state.config.canary_ttl);
+    // Held while checking, so concurrent probes wait for the same check
+    let mut last = state.canary.0.lock().await;
+    if let Some(last) = last
+        .as_ref()
+        .filter(|l| l.url == url && l.checked.elapsed() < ttl)
+    {
+        return (last.dns.clone(), last.egress.clone());
+    }
+    let (dns, egress) = tokio::join!(
+        run(check_dns(state, &url)),
+        run(selftest::check_egress(state, url.as_str()))
+    );
+    *last = Some(CanaryChecks {
+        url,
+        checked: Instant::now(),
+        dns: dns.clone(),
+        egress: egress.clone(),
+    });
+    (dns, egress)
+}
+
+/// Resolve the canary's host like upstream hosts are resolved
+async fn check_dns(state: &AppState, url: &Url) -> Result<String, String> {
+    let host = url.host_str().ok_or("canary URL has no host")?;
+    if host.trim_matches(['[', ']']).parse::<IpAddr>().is_ok() {
+        return Ok(format!("{} is an address, nothing to resolve", host));
+    }
+    match state.fetcher.resolve(host).await {
+        Ok(addrs) if !addrs.is_empty() => Ok(format!("{} resolved to {:?}", host, addrs)),
+        Ok(_) => Err(format!("{} resolved to no addresses", host)),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+async fn run(check: impl Future<Output = Result<String, String>>) -> Check {
+    let start = Instant::now();
+    let result = check.await;
+    let ms = start.elapsed().as_millis() as u64;
+    match result {
+        Ok(detail) => Check {
+            status: "ok",
+            detail,
+            ms,
+        },
+        Err(detail) => Check {
+            status: "fail",
+            detail,
+            ms,
+        },
+    }
+}
+
+fn skipped() -> Check {
+    Check {
+        status: "skipped",
+        detail: "CANARY_URL is off".to_string(),
+        ms: 0,
+    }
+}
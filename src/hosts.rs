@@ -0,0 +1,165 @@
+//! Per-host politeness for upstream fetches
+//!
+//! Many clients asking for different pages of one site would otherwise all
+//! hit that origin at once. Each host gets a semaphore of
+//! `UPSTREAM_HOST_CONCURRENCY` permits, and with `UPSTREAM_HOST_DELAY_MS` set
+//! requests to it start at least that far apart. Other hosts are never held
+//! up by a busy one.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::Instant;
+
+/// Hosts tracked before idle ones are dropped; grows with the hosts still busy
+const MIN_PRUNE_SIZE: usize = 1024;
+
+/// Limits for one host
+struct Host {
+    permits: Arc<Semaphore>,
+    /// Earliest time the next request may start
+    next_slot: Instant,
+}
+
+struct Hosts {
+    hosts: HashMap<String, Host>,
+    prune_at: usize,
+}
+
+/// Turn to make a request to a host, held until the response is read
+pub struct HostPermit {
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+/// Concurrency limit and pacing per upstream host
+pub struct HostLimiter {
+    /// Requests in flight per host, 0 for no limit
+    max_concurrent: usize,
+    /// Least time between the starts of two requests to a host
+    min_interval: Duration,
+    hosts: Mutex<Hosts>,
+}
+
+impl HostLimiter {
+    pub fn new(max_concurrent: usize, min_interval: Duration) -> Self {
+        Self {
+            max_concurrent,
+            min_interval,
+            hosts: Mutex::new(Hosts {
+                hosts: HashMap::new(),
+                prune_at: MIN_PRUNE_SIZE,
+            }),
+        }
+    }
+
+    /// Whether requests are ever held back
+    fn is_limited(&self) -> bool {
+        self.max_concurrent > 0 || !self.min_interval.is_zero()
+    }
+
+    /// Wait for a free slot at `host` and for its pacing delay to pass
+    pub async fn acquire(&self, host: &str) -> HostPermit {
+        if !self.is_limited() {
+            return HostPermit { _permit: None };
+        }
+        let host = host.to_ascii_lowercase();
+
+        let permits = self.with_host(&host, |entry| entry.permits.clone());
+        let permit = if self.max_concurrent > 0 {
+            let permit = permits
+                .acquire_owned()
+                .await
+                .expect("host semaphore never closed");
+            Some(permit)
+        } else {
+            None
+        };
+
+        // Pace by when requests start, once there's a free slot to start in
+        if !self.min_interval.is_zero() {
+            let slot = self.with_host(&host, |entry| {
+                let slot = entry.next_slot.max(Instant::now());
+                entry.next_slot = slot + self.min_interval;
+                slot
+            });
+            let now = Instant::now();
+            if slot > now {
+                tracing::debug!("Waiting {:?} before fetching from {}", slot - now, host);
+            }
+            tokio::time::sleep_until(slot).await;
+        }
+
+        HostPermit { _permit: permit }
+    }
+
+    /// Run `f` on a host's entry, creating it (and pruning idle hosts) if needed
+    fn with_host<T>(&self, host: &str, f: impl FnOnce(&mut Host) -> T) -> T {
+        let mut hosts = self.hosts.lock().expect("host limiter lock poisoned");
+        let now = Instant::now();
+        if hosts.hosts.len() >= hosts.prune_at {
+            hosts
+                .hosts
+                .retain(|_, entry| Arc::strong_count(&entry.permits) > 1 || entry.next_slot > now);
+            hosts.prune_at = (hosts.hosts.len() * 2).max(MIN_PRUNE_SIZE);
+        }
+
+        let max_concurrent = self.max_concurrent.max(1);
+        let entry = hosts.hosts.entry(host.to_string()).or_insert_with(|| Host {
+            permits: Arc::new(Semaphore::new(max_concurrent)),
+            next_slot: now,
+        });
+        f(entry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_concurrency_per_host() {
+        let limiter = HostLimiter::new(1, Duration::ZERO);
+
+        let first = limiter.acquire("example.com").await;
+        let blocked =
+            tokio::time::timeout(Duration::from_millis(50), limiter.acquire("Example.COM")).await;
+        assert!(
+            blocked.is_err(),
+            "second request to a busy host should wait"
+        );
+
+        // Other hosts are unaffected
+        let other =
+            tokio::time::timeout(Duration::from_millis(50), limiter.acquire("example.org")).await;
+        assert!(other.is_ok());
+
+        drop(first);
+        let next =
+            tokio::time::timeout(Duration::from_millis(50), limiter.acquire("example.com")).await;
+        assert!(next.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_min_interval_spaces_requests() {
+        let limiter = HostLimiter::new(0, Duration::from_millis(100));
+
+        let start = Instant::now();
+        for _ in 0..3 {
+            limiter.acquire("example.com").await;
+        }
+        assert!(start.elapsed() >= Duration::from_millis(200));
+
+        let start = Instant::now();
+        limiter.acquire("example.org").await;
+        assert!(start.elapsed() < Duration::from_millis(100));
+    }
+
+    #[tokio::test]
+    async fn test_unlimited() {
+        let limiter = HostLimiter::new(0, Duration::ZERO);
+        let _held: Vec<_> =
+            futures_util::future::join_all((0..100).map(|_| limiter.acquire("example.com"))).await;
+        assert!(limiter.hosts.lock().unwrap().hosts.is_empty());
+    }
+}
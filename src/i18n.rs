@@ -0,0 +1,297 @@
+//! Translated error messages
+//!
+//! Error bodies keep their machine-readable code (`NOT_FOUND`, `TIMEOUT`, ...)
+//! in every language; only the human-readable detail after it is translated,
+//! for clients whose `Accept-Language` prefers a supported locale. Details
+//! that come from elsewhere (an upstream error, a parser) stay in English,
+//! after a translated summary.
+
+use crate::error::MdwnError;
+use crate::parse::parse_accept_language;
+
+/// Languages error messages are available in
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Locale {
+    #[default]
+    En,
+    De,
+    Es,
+    Fr,
+    Ja,
+    Pt,
+}
+
+impl Locale {
+    /// Most preferred supported locale of an `Accept-Language` header,
+    /// English if none is supported
+    pub fn negotiate(accept_language: &str) -> Self {
+        parse_accept_language(accept_language)
+            .iter()
+            .find_map(|tag| Self::from_tag(tag))
+            .unwrap_or_default()
+    }
+
+    /// Locale of a language tag, matched on its primary subtag
+    fn from_tag(tag: &str) -> Option<Self> {
+        match tag.split('-').next()? {
+            "en" => Some(Locale::En),
+            "de" => Some(Locale::De),
+            "es" => Some(Locale::Es),
+            "fr" => Some(Locale::Fr),
+            "ja" => Some(Locale::Ja),
+            "pt" => Some(Locale::Pt),
+            _ => None,
+        }
+    }
+
+    /// Language tag for `Content-Language`
+    pub fn tag(self) -> &'static str {
+        match self {
+            Locale::En => "en",
+            Locale::De => "de",
+            Locale::Es => "es",
+            Locale::Fr => "fr",
+            Locale::Ja => "ja",
+            Locale::Pt => "pt",
+        }
+    }
+}
+
+/// Human-readable detail of an error in `locale`, `None` for English
+///
+/// `{}` in a template takes the error's value; other details follow the
+/// summary in parentheses.
+pub fn error_detail(error: &MdwnError, locale: Locale) -> Option<String> {
+    // Templates in de, es, fr, ja, pt order
+    let (templates, value): ([&str; 5], Option<String>) = match error {
+        MdwnError::Unauthorized(detail) => (
+            [
+                "Nicht autorisiert",
+                "No autorizado",
+                "Non autorisé",
+                "認証されていません",
+                "Não autorizado",
+            ],
+            Some(detail.clone()),
+        ),
+        MdwnError::InvalidUrl(detail) => (
+            [
+                "Ungültige URL",
+                "URL no válida",
+                "URL invalide",
+                "無効なURLです",
+                "URL inválida",
+            ],
+            Some(detail.clone()),
+        ),
+        MdwnError::BlockedUrl => (
+            [
+                "Die URL verweist auf eine private/interne Adresse",
+                "La URL apunta a una dirección privada/interna",
+                "L'URL pointe vers une adresse privée/interne",
+                "URLがプライベート/内部アドレスを指しています",
+                "A URL aponta para um endereço privado/interno",
+            ],
+            None,
+        ),
+        MdwnError::BlockedDomain(host) => (
+            [
+                "Abrufe von '{}' sind auf diesem Server nicht erlaubt",
+                "No se permite obtener contenido de '{}' en este servidor",
+                "La récupération depuis '{}' n'est pas autorisée sur ce serveur",
+                "このサーバーでは '{}' からの取得は許可されていません",
+                "Não é permitido obter conteúdo de '{}' neste servidor",
+            ],
+            Some(host.clone()),
+        ),
+        MdwnError::FetchFailed(detail) => (
+            [
+                "Die Seite konnte nicht abgerufen werden",
+                "No se pudo obtener la página",
+                "Impossible de récupérer la page",
+                "ページを取得できませんでした",
+                "Não foi possível obter a página",
+            ],
+            Some(detail.clone()),
+        ),
+        MdwnError::Timeout(secs) => (
+            [
+                "Zeitüberschreitung nach {} Sekunden",
+                "Tiempo de espera agotado tras {} segundos",
+                "Délai dépassé après {} secondes",
+                "{}秒後にタイムアウトしました",
+                "Tempo esgotado após {} segundos",
+            ],
+            Some(secs.to_string()),
+        ),
+        MdwnError::NotFound => (
+            [
+                "Der Ursprungsserver hat 404 zurückgegeben",
+                "El servidor de origen devolvió 404",
+                "Le serveur d'origine a renvoyé 404",
+                "配信元が404を返しました",
+                "O servidor de origem retornou 404",
+            ],
+            None,
+        ),
+        MdwnError::SoftNotFound => (
+            [
+                "Die Seite ist eine „nicht gefunden“-Vorlage mit Status 200",
+                "La página es una plantilla de «no encontrado» servida con estado 200",
+                "La page est un modèle « introuvable » servi avec le statut 200",
+                "ページはステータス200で返された「見つかりません」テンプレートです",
+                "A página é um modelo de \"não encontrado\" servido com status 200",
+            ],
+            None,
+        ),
+        MdwnError::Forbidden => (
+            [
+                "Der Ursprungsserver hat 403 zurückgegeben",
+                "El servidor de origen devolvió 403",
+                "Le serveur d'origine a renvoyé 403",
+                "配信元が403を返しました",
+                "O servidor de origem retornou 403",
+            ],
+            None,
+        ),
+        MdwnError::LanguageMismatch(detail) => (
+            [
+                "Die Sprache des Dokuments passt nicht",
+                "El idioma del documento no coincide",
+                "La langue du document ne correspond pas",
+                "文書の言語が一致しません",
+                "O idioma do documento não corresponde",
+            ],
+            Some(detail.clone()),
+        ),
+        MdwnError::NoMarkdown(detail) => (
+            [
+                "Kein Markdown verfügbar",
+                "No hay markdown disponible",
+                "Aucun markdown disponible",
+                "Markdownがありません",
+                "Nenhum markdown disponível",
+            ],
+            Some(detail.clone()),
+        ),
+        MdwnError::NoMetadata(detail) => (
+            [
+                "Keine Metadaten gefunden",
+                "No se encontraron metadatos",
+                "Aucune métadonnée trouvée",
+                "メタデータが見つかりません",
+                "Nenhum metadado encontrado",
+            ],
+            Some(detail.clone()),
+        ),
+        MdwnError::UnsupportedType(content_type) => (
+            [
+                "Content-Type '{}' wird nicht unterstützt",
+                "El Content-Type '{}' no es compatible",
+                "Le Content-Type '{}' n'est pas pris en charge",
+                "Content-Type '{}' はサポートされていません",
+                "O Content-Type '{}' não é suportado",
+            ],
+            Some(content_type.clone()),
+        ),
+        MdwnError::TooLarge(limit) => (
+            [
+                "Der Inhalt überschreitet die Grenze von {} Bytes",
+                "El contenido supera el límite de {} bytes",
+                "Le contenu dépasse la limite de {} octets",
+                "コンテンツが{}バイトの上限を超えています",
+                "O conteúdo excede o limite de {} bytes",
+            ],
+            Some(limit.to_string()),
+        ),
+        MdwnError::BudgetExceeded(detail) => (
+            [
+                "Upstream-Budget überschritten",
+                "Se superó el límite de solicitudes al origen",
+                "Budget de requêtes en amont dépassé",
+                "上流リクエストの上限を超えました",
+                "Limite de requisições à origem excedido",
+            ],
+            Some(detail.clone()),
+        ),
+        MdwnError::RateLimited(secs) => (
+            [
+                "Zu viele Anfragen, erneut versuchen in {} Sekunden",
+                "Demasiadas solicitudes, reintente en {} segundos",
+                "Trop de requêtes, réessayez dans {} secondes",
+                "リクエストが多すぎます。{}秒後に再試行してください",
+                "Muitas requisições, tente novamente em {} segundos",
+            ],
+            Some(secs.to_string()),
+        ),
+        MdwnError::ParseError(detail) => (
+            [
+                "Verarbeitungsfehler",
+                "Error de análisis",
+                "Erreur d'analyse",
+                "解析エラー",
+                "Erro de análise",
+            ],
+            Some(detail.clone()),
+        ),
+        MdwnError::Internal(detail) => (
+            [
+                "Interner Fehler",
+                "Error interno",
+                "Erreur interne",
+                "内部エラー",
+                "Erro interno",
+            ],
+            Some(detail.clone()),
+        ),
+    };
+
+    let template = match locale {
+        Locale::En => return None,
+        Locale::De => templates[0],
+        Locale::Es => templates[1],
+        Locale::Fr => templates[2],
+        Locale::Ja => templates[3],
+        Locale::Pt => templates[4],
+    };
+    Some(match value {
+        Some(value) if template.contains("{}") => template.replace("{}", &value),
+        Some(detail) if !detail.is_empty() => format!("{} ({})", template, detail),
+        _ => template.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_negotiate() {
+        assert_eq!(Locale::negotiate("de-DE,de;q=0.9,en;q=0.8"), Locale::De);
+        assert_eq!(Locale::negotiate("nl, fr-CA;q=0.7"), Locale::Fr);
+        assert_eq!(Locale::negotiate("en-GB, ja;q=0.5"), Locale::En);
+        assert_eq!(Locale::negotiate("nl, sv"), Locale::En);
+        assert_eq!(Locale::negotiate(""), Locale::En);
+    }
+
+    #[test]
+    fn test_error_detail() {
+        assert_eq!(error_detail(&MdwnError::NotFound, Locale::En), None);
+        assert_eq!(
+            error_detail(&MdwnError::Timeout(10), Locale::Fr).as_deref(),
+            Some("Délai dépassé après 10 secondes")
+        );
+        assert_eq!(
+            error_detail(
+                &MdwnError::FetchFailed("connection refused".into()),
+                Locale::Es
+            )
+            .as_deref(),
+            Some("No se pudo obtener la página (connection refused)")
+        );
+        assert_eq!(
+            error_detail(&MdwnError::UnsupportedType("image/png".into()), Locale::Ja).as_deref(),
+            Some("Content-Type 'image/png' はサポートされていません")
+        );
+    }
+}
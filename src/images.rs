@@ -0,0 +1,145 @@
+use crate::error::{MdwnError, Result};
+use crate::fetch::{FetchBudget, Fetcher};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use regex::{Captures, Regex};
+use std::collections::HashMap;
+use std::sync::LazyLock;
+use url::Url;
+
+/// Markdown image syntax: ![alt](url "optional title")
+static IMAGE_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"!\[(?P<alt>[^\]]*)\]\((?P<url><[^>]*>|[^)\s]+)(?P<title>\s+"[^"]*")?\)"#)
+        .expect("valid regex")
+});
+
+/// Raster image types safe to inline or proxy (SVG is excluded since it can carry scripts)
+const RASTER_IMAGE_TYPES: &[&str] = &[
+    "image/png",
+    "image/jpeg",
+    "image/gif",
+    "image/webp",
+    "image/avif",
+];
+
+/// Inline small images referenced by markdown as base64 data URIs
+///
+/// Images that fail to fetch, exceed `max_bytes`, or aren't raster images keep
+/// their original URL. Fetches share the request budget; once it runs out the
+/// remaining images are left as links.
+pub async fn embed_images(
+    fetcher: &Fetcher,
+    budget: &FetchBudget,
+    markdown: &str,
+    base_url: &Url,
+    max_bytes: usize,
+) -> String {
+    let mut data_uris: HashMap<String, String> = HashMap::new();
+
+    for caps in IMAGE_RE.captures_iter(markdown) {
+        let raw = &caps["url"];
+        if raw.starts_with("data:") || data_uris.contains_key(raw) {
+            continue;
+        }
+
+        let url = match base_url.join(raw.trim_start_matches('<').trim_end_matches('>')) {
+            Ok(url) => url,
+            Err(_) => continue,
+        };
+
+        match fetch_data_uri(fetcher, budget, &url, max_bytes).await {
+            Ok(data_uri) => {
+                data_uris.insert(raw.to_string(), data_uri);
+            }
+            Err(MdwnError::BudgetExceeded(_)) => {
+                tracing::debug!("Fetch budget exhausted, leaving remaining images as links");
+                break;
+            }
+            Err(e) => tracing::debug!("Not embedding image {}: {}", url, e),
+        }
+    }
+
+    replace_image_urls(markdown, |raw| data_uris.get(raw).cloned())
+}
+
+/// Fetch an image and encode it as a data URI
+async fn fetch_data_uri(
+    fetcher: &Fetcher,
+    budget: &FetchBudget,
+    url: &Url,
+    max_bytes: usize,
+) -> Result<String> {
+    let image = fetch_image(fetcher, budget, url, max_bytes).await?;
+    Ok(format!(
+        "data:{};base64,{}",
+        image.mime_type,
+        STANDARD.encode(&image.body)
+    ))
+}
+
+/// A fetched raster image
+#[derive(Clone)]
+pub struct Image {
+    pub mime_type: String,
+    pub body: Vec<u8>,
+}
+
+/// Fetch an image, rejecting anything that isn't a raster image type
+pub async fn fetch_image(
+    fetcher: &Fetcher,
+    budget: &FetchBudget,
+    url: &Url,
+    max_bytes: usize,
+) -> Result<Image> {
+    let response = fetcher.fetch_with_limit(url, budget, max_bytes).await?;
+
+    let mime_type = response.mime_type().unwrap_or("unknown").to_lowercase();
+    if !RASTER_IMAGE_TYPES.contains(&mime_type.as_str()) {
+        return Err(MdwnError::UnsupportedType(mime_type));
+    }
+
+    Ok(Image {
+        mime_type,
+        body: response.body,
+    })
+}
+
+/// Rewrite image URLs in markdown, keeping those the mapping returns None for
+pub fn replace_image_urls(markdown: &str, mut map: impl FnMut(&str) -> Option<String>) -> String {
+    IMAGE_RE
+        .replace_all(markdown, |caps: &Captures| match map(&caps["url"]) {
+            Some(url) => format!(
+                "![{}]({}{})",
+                &caps["alt"],
+                url,
+                caps.name("title").map_or("", |t| t.as_str())
+            ),
+            None => caps[0].to_string(),
+        })
+        .into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_replace_image_urls() {
+        let md = r#"Intro ![Logo](/logo.png "The logo") and ![](<a b.gif>) plus [link](/x)."#;
+        let result = replace_image_urls(md, |url| match url {
+            "/logo.png" => Some("data:image/png;base64,AAAA".to_string()),
+            _ => None,
+        });
+
+        assert_eq!(
+            result,
+            r#"Intro ![Logo](data:image/png;base64,AAAA "The logo") and ![](<a b.gif>) plus [link](/x)."#
+        );
+    }
+
+    #[test]
+    fn test_replace_image_urls_ignores_links() {
+        let md = "[not an image](/photo.png)";
+        assert_eq!(replace_image_urls(md, |_| Some("x".to_string())), md);
+    }
+}
@@ -0,0 +1,188 @@
+//! Keyword extraction from converted documents
+//!
+//! RAKE (Rapid Automatic Keyword Extraction): stopwords and punctuation split
+//! the text into candidate phrases, each word scores its co-occurrence degree
+//! over its frequency, and a phrase scores the sum of its words. Phrases that
+//! recur are weighted by how often they appear, so a term the document keeps
+//! coming back to beats a long phrase used once. No model, no external data.
+
+use regex::Regex;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::sync::LazyLock;
+
+/// Longest candidate phrase, in words
+const MAX_PHRASE_WORDS: usize = 3;
+
+/// Words that separate candidate phrases
+const STOPWORDS: &[&str] = &[
+    "a", "about", "above", "after", "again", "against", "all", "also", "am", "an", "and", "any",
+    "are", "as", "at", "be", "because", "been", "before", "being", "below", "between", "both",
+    "but", "by", "can", "could", "did", "do", "does", "doing", "down", "during", "each", "even",
+    "every", "few", "for", "from", "further", "get", "gets", "had", "has", "have", "having", "he",
+    "her", "here", "hers", "him", "his", "how", "however", "i", "if", "in", "into", "is", "it",
+    "its", "itself", "just", "let", "like", "made", "make", "many", "may", "me", "might", "more",
+    "most", "much", "must", "my", "new", "no", "nor", "not", "now", "of", "off", "often", "on",
+    "once", "one", "only", "or", "other", "our", "ours", "out", "over", "own", "same", "see",
+    "she", "should", "so", "some", "such", "than", "that", "the", "their", "them", "then", "there",
+    "these", "they", "this", "those", "through", "to", "too", "two", "under", "until", "up", "us",
+    "use", "used", "using", "very", "was", "way", "we", "well", "were", "what", "when", "where",
+    "which", "while", "who", "whom", "why", "will", "with", "within", "without", "would", "yet",
+    "you", "your", "yours",
+];
+
+static IMAGE_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"!\[[^\]]*\]\([^)]*\)").expect("valid regex"));
+static LINK_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\[([^\]]*)\]\([^)]*\)").expect("valid regex"));
+static NOISE_RE: LazyLock<Regex> = LazyLock::new(|| {
+    // Inline code, HTML tags and comments, bare URLs
+    Regex::new(r"`[^`]*`|<!--.*?-->|<[^>]+>|https?://\S+").expect("valid regex")
+});
+
+/// A scored keyword phrase
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Keyword {
+    pub term: String,
+    pub score: f32,
+}
+
+/// Top `limit` keyword phrases of a markdown document, best first
+pub fn extract_keywords(markdown: &str, limit: usize) -> Vec<Keyword> {
+    let text = prose_text(markdown);
+
+    let mut phrases: Vec<Vec<String>> = Vec::new();
+    for fragment in text.split(|c: char| !is_word_char(c) && !c.is_whitespace()) {
+        let mut phrase = Vec::new();
+        for word in fragment.split_whitespace() {
+            let word = word.to_lowercase();
+            let word = word.trim_matches(|c| c == '-' || c == '\'');
+            let boundary = word.chars().count() < 2
+                || STOPWORDS.contains(&word)
+                || word.chars().all(|c| c.is_numeric());
+            if boundary {
+                phrases.push(std::mem::take(&mut phrase));
+            } else {
+                phrase.push(word.to_string());
+            }
+        }
+        phrases.push(phrase);
+    }
+    phrases.retain(|p| !p.is_empty());
+
+    // Long runs are rarely keywords themselves, but they mention ones that
+    // stand alone elsewhere ("the borrow checker" in a heading)
+    let (mut candidates, long_runs): (Vec<_>, Vec<_>) = phrases
+        .into_iter()
+        .partition(|p| p.len() <= MAX_PHRASE_WORDS);
+    let known: HashSet<Vec<String>> = candidates.iter().cloned().collect();
+    for run in long_runs {
+        let mut start = 0;
+        while start < run.len() {
+            let found = (1..=MAX_PHRASE_WORDS)
+                .rev()
+                .filter_map(|len| run.get(start..start + len))
+                .find(|words| known.contains(*words));
+            match found {
+                Some(words) => {
+                    start += words.len();
+                    candidates.push(words.to_vec());
+                }
+                None => start += 1,
+            }
+        }
+    }
+    let phrases = candidates;
+
+    let mut frequency: HashMap<&str, f32> = HashMap::new();
+    let mut degree: HashMap<&str, f32> = HashMap::new();
+    let mut occurrences: HashMap<String, f32> = HashMap::new();
+    for phrase in &phrases {
+        for word in phrase {
+            *frequency.entry(word).or_default() += 1.0;
+            *degree.entry(word).or_default() += phrase.len() as f32;
+        }
+        *occurrences.entry(phrase.join(" ")).or_default() += 1.0;
+    }
+
+    let mut keywords: Vec<Keyword> = occurrences
+        .into_iter()
+        .map(|(term, count)| {
+            let words: f32 = term.split(' ').map(|w| degree[w] / frequency[w]).sum();
+            Keyword {
+                score: (words * count * 100.0).round() / 100.0,
+                term,
+            }
+        })
+        .collect();
+    keywords.sort_by(|a, b| {
+        b.score
+            .total_cmp(&a.score)
+            .then_with(|| a.term.cmp(&b.term))
+    });
+    keywords.truncate(limit);
+    keywords
+}
+
+/// Markdown reduced to its prose: no code, URLs, images or markup
+fn prose_text(markdown: &str) -> String {
+    let mut prose = String::with_capacity(markdown.len());
+    let mut in_fence = false;
+    for line in markdown.lines() {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            in_fence = !in_fence;
+            // Code never joins a phrase with the text around it
+            prose.push_str(".\n");
+            continue;
+        }
+        if !in_fence {
+            prose.push_str(line);
+            // Lines are headings, list items or paragraph ends as often as not
+            prose.push_str(".\n");
+        }
+    }
+
+    let prose = IMAGE_RE.replace_all(&prose, ".");
+    let prose = LINK_RE.replace_all(&prose, "$1");
+    NOISE_RE.replace_all(&prose, ".").into_owned()
+}
+
+/// Letters and digits, plus the hyphens and apostrophes inside words
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '-' || c == '\''
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extract_keywords() {
+        let markdown = "# The borrow checker\n\n\
+            Rust's borrow checker enforces memory safety without a garbage collector. \
+            The borrow checker tracks lifetimes of references, and memory safety is the \
+            result of these rules.\n\n\
+            ```rust\nlet borrowed_value = &x;\n```\n\n\
+            See [the book](https://doc.rust-lang.org/book/) for ownership rules.";
+        let keywords = extract_keywords(markdown, 3);
+        let terms: Vec<&str> = keywords.iter().map(|k| k.term.as_str()).collect();
+        assert_eq!(
+            terms,
+            ["borrow checker", "memory safety", "garbage collector"]
+        );
+        assert!(keywords[0].score > keywords[1].score);
+
+        // Code and link targets aren't text
+        let all = extract_keywords(markdown, 100);
+        assert!(!all.iter().any(|k| k.term.contains("borrowed_value")));
+        assert!(!all.iter().any(|k| k.term.contains("doc.rust-lang.org")));
+        assert!(all.iter().any(|k| k.term == "book"));
+    }
+
+    #[test]
+    fn test_extract_keywords_empty() {
+        assert_eq!(extract_keywords("", 10), Vec::new());
+        assert_eq!(extract_keywords("The and of it is.", 10), Vec::new());
+    }
+}
@@ -0,0 +1,253 @@
+//! Language detection for converted documents
+//!
+//! A deliberately small detector: the dominant writing system identifies most
+//! non-Latin languages outright, and Latin-script text is classified by which
+//! language's most common function words it uses. It only has to be good
+//! enough to tell a pipeline that wanted English it got German, so it answers
+//! `None` rather than guess on short or mixed text.
+
+use scraper::{Html, Selector};
+
+/// Characters of text examined, enough for a confident answer on long pages
+const SAMPLE_CHARS: usize = 20_000;
+
+/// Fewest function words needed before a Latin-script guess counts
+const MIN_STOPWORD_HITS: usize = 5;
+
+/// Share of letters a non-Latin script needs to decide the language
+const SCRIPT_SHARE: f32 = 0.4;
+
+/// Common function words per language, as ISO 639-1 codes
+const STOPWORDS: &[(&str, &[&str])] = &[
+    (
+        "en",
+        &[
+            "the", "and", "of", "to", "is", "that", "it", "was", "for", "with", "are", "this",
+            "be", "not", "have", "you", "from", "which",
+        ],
+    ),
+    (
+        "de",
+        &[
+            "der", "die", "und", "das", "ist", "nicht", "mit", "den", "von", "ein", "eine", "sich",
+            "auch", "auf", "dem", "für", "ich", "zu",
+        ],
+    ),
+    (
+        "fr",
+        &[
+            "le", "la", "les", "et", "est", "des", "une", "du", "pas", "pour", "dans", "sur",
+            "qui", "avec", "au", "ce", "sont", "il",
+        ],
+    ),
+    (
+        "es",
+        &[
+            "el", "los", "las", "y", "es", "del", "una", "por", "con", "para", "se", "como", "más",
+            "pero", "su", "lo", "está", "que",
+        ],
+    ),
+    (
+        "it",
+        &[
+            "il", "di", "che", "è", "della", "per", "non", "sono", "gli", "anche", "più", "questo",
+            "nel", "alla", "dei", "ha", "una", "con",
+        ],
+    ),
+    (
+        "pt",
+        &[
+            "o", "os", "da", "do", "não", "uma", "em", "para", "com", "dos", "das", "mais", "ao",
+            "pelo", "é", "que", "se", "foi",
+        ],
+    ),
+    (
+        "nl",
+        &[
+            "de", "het", "een", "van", "en", "dat", "niet", "met", "voor", "op", "zijn", "ook",
+            "aan", "er", "te", "wordt", "dit", "is",
+        ],
+    ),
+    (
+        "sv",
+        &[
+            "och", "att", "det", "som", "är", "en", "på", "för", "med", "inte", "av", "till",
+            "den", "har", "om", "jag", "var", "kan",
+        ],
+    ),
+    (
+        "pl",
+        &[
+            "i", "w", "nie", "się", "na", "jest", "że", "do", "to", "z", "jak", "co", "ale", "od",
+            "po", "tak", "są", "czy",
+        ],
+    ),
+];
+
+/// Writing systems that identify a language on their own
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Script {
+    Latin,
+    Cyrillic,
+    Greek,
+    Arabic,
+    Hebrew,
+    Devanagari,
+    Thai,
+    Hangul,
+    Kana,
+    Han,
+}
+
+fn script(c: char) -> Option<Script> {
+    match c {
+        'a'..='z' | 'A'..='Z' | '\u{00C0}'..='\u{024F}' => Some(Script::Latin),
+        '\u{0400}'..='\u{04FF}' => Some(Script::Cyrillic),
+        '\u{0370}'..='\u{03FF}' => Some(Script::Greek),
+        '\u{0600}'..='\u{06FF}' => Some(Script::Arabic),
+        '\u{0590}'..='\u{05FF}' => Some(Script::Hebrew),
+        '\u{0900}'..='\u{097F}' => Some(Script::Devanagari),
+        '\u{0E00}'..='\u{0E7F}' => Some(Script::Thai),
+        '\u{AC00}'..='\u{D7AF}' | '\u{1100}'..='\u{11FF}' => Some(Script::Hangul),
+        '\u{3040}'..='\u{30FF}' => Some(Script::Kana),
+        '\u{4E00}'..='\u{9FFF}' | '\u{3400}'..='\u{4DBF}' => Some(Script::Han),
+        _ => None,
+    }
+}
+
+/// Best guess at the language of `text` as an ISO 639-1 code, `None` if unsure
+pub fn detect_language(text: &str) -> Option<&'static str> {
+    let sample: String = text.chars().take(SAMPLE_CHARS).collect();
+
+    let mut counts = [0usize; 10];
+    for s in sample.chars().filter_map(script) {
+        counts[s as usize] += 1;
+    }
+    let letters: usize = counts.iter().sum();
+    if letters == 0 {
+        return None;
+    }
+    let share = |s: Script| counts[s as usize] as f32 / letters as f32;
+
+    // Japanese mixes kana into Han text, so any real amount of kana decides it
+    if share(Script::Kana) >= 0.1 {
+        return Some("ja");
+    }
+    let non_latin = [
+        (Script::Hangul, "ko"),
+        (Script::Han, "zh"),
+        (Script::Greek, "el"),
+        (Script::Arabic, "ar"),
+        (Script::Hebrew, "he"),
+        (Script::Devanagari, "hi"),
+        (Script::Thai, "th"),
+    ];
+    if let Some((_, lang)) = non_latin.iter().find(|(s, _)| share(*s) >= SCRIPT_SHARE) {
+        return Some(lang);
+    }
+    if share(Script::Cyrillic) >= SCRIPT_SHARE {
+        // Letters Russian doesn't use
+        let ukrainian = sample.chars().any(|c| matches!(c, 'і' | 'ї' | 'є' | 'ґ'));
+        return Some(if ukrainian { "uk" } else { "ru" });
+    }
+    if share(Script::Latin) < SCRIPT_SHARE {
+        return None;
+    }
+
+    let lowercase = sample.to_lowercase();
+    let words: Vec<&str> = lowercase
+        .split(|c: char| !c.is_alphabetic())
+        .filter(|w| !w.is_empty())
+        .collect();
+    let mut scores: Vec<(&'static str, usize)> = STOPWORDS
+        .iter()
+        .map(|(lang, stopwords)| {
+            (
+                *lang,
+                words.iter().filter(|w| stopwords.contains(w)).count(),
+            )
+        })
+        .collect();
+    scores.sort_by_key(|&(_, hits)| std::cmp::Reverse(hits));
+
+    // Related languages share function words, so demand a clear winner
+    let (lang, best) = scores[0];
+    let runner_up = scores.get(1).map_or(0, |s| s.1);
+    (best >= MIN_STOPWORD_HITS && best * 4 > runner_up * 5).then_some(lang)
+}
+
+/// Language the page declares on its root element, as a primary subtag
+pub fn declared_language(html: &str) -> Option<String> {
+    let document = Html::parse_document(html);
+    let selector = Selector::parse("html[lang]").expect("valid CSS selector");
+    let tag = document.select(&selector).next()?.value().attr("lang")?;
+    let primary = primary_subtag(tag);
+    (!primary.is_empty()).then(|| primary.to_string())
+}
+
+/// `en` for `en-US`, lowercased
+pub fn primary_subtag(tag: &str) -> String {
+    tag.trim()
+        .split(['-', '_'])
+        .next()
+        .unwrap_or_default()
+        .to_ascii_lowercase()
+}
+
+/// Whether `language` satisfies any of the requested language tags
+pub fn language_matches(language: &str, wanted: &[String]) -> bool {
+    let language = primary_subtag(language);
+    wanted.iter().any(|tag| primary_subtag(tag) == language)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_latin_languages() {
+        let en = "The quick brown fox jumps over the lazy dog, and this is a sentence that \
+                  is written in English for the test.";
+        assert_eq!(detect_language(en), Some("en"));
+
+        let de = "Der schnelle braune Fuchs springt über den faulen Hund, und das ist nicht \
+                  ein Satz, der auf Englisch geschrieben ist.";
+        assert_eq!(detect_language(de), Some("de"));
+
+        let fr = "Le renard brun rapide saute par-dessus le chien paresseux, et ce n'est pas \
+                  une phrase qui est écrite dans les livres pour les enfants.";
+        assert_eq!(detect_language(fr), Some("fr"));
+
+        // Too little text to tell
+        assert_eq!(detect_language("Hello world"), None);
+        assert_eq!(detect_language("12345 ---"), None);
+    }
+
+    #[test]
+    fn test_detect_scripts() {
+        assert_eq!(
+            detect_language("Привет, как дела? Это тестовый текст."),
+            Some("ru")
+        );
+        assert_eq!(
+            detect_language("Це тестовий текст українською мовою."),
+            Some("uk")
+        );
+        assert_eq!(detect_language("これは日本語のテキストです。"), Some("ja"));
+        assert_eq!(detect_language("这是一个中文测试文本。"), Some("zh"));
+        assert_eq!(detect_language("이것은 한국어 텍스트입니다."), Some("ko"));
+    }
+
+    #[test]
+    fn test_declared_language_and_matching() {
+        let html = r#"<html lang="en-GB"><body>x</body></html>"#;
+        assert_eq!(declared_language(html).as_deref(), Some("en"));
+        assert_eq!(declared_language("<html><body>x</body></html>"), None);
+
+        assert!(language_matches(
+            "en",
+            &["en-us".to_string(), "fr".to_string()]
+        ));
+        assert!(!language_matches("de", &["en".to_string()]));
+    }
+}
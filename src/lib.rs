@@ -0,0 +1,60 @@
+//! mdwn.io - a lightweight proxy that serves the markdown web
+//!
+//! The HTTP server lives in `main.rs`; this library exposes the fetching,
+//! parsing and conversion pipeline so it can be fuzzed and reused.
+//!
+//! With default features disabled only the pure parsing and conversion core is
+//! built (no tokio or reqwest), which compiles to `wasm32-unknown-unknown`. The
+//! `wasm` feature adds JavaScript bindings on top, and `edge` runs the proxy
+//! itself on Cloudflare Workers using the fetch API and a KV cache.
+
+pub mod anchors;
+#[cfg(feature = "server")]
+pub mod cache;
+pub mod convert;
+#[cfg(feature = "server")]
+pub mod cookies;
+#[cfg(feature = "server")]
+pub mod credentials;
+pub mod dates;
+#[cfg(feature = "server")]
+pub mod discourse;
+#[cfg(feature = "server")]
+pub mod dns;
+pub mod domains;
+#[cfg(feature = "edge")]
+pub mod edge;
+pub mod error;
+#[cfg(feature = "server")]
+pub mod fetch;
+pub mod forum;
+#[cfg(feature = "server")]
+pub mod github;
+#[cfg(feature = "server")]
+pub mod hosts;
+pub mod i18n;
+#[cfg(feature = "server")]
+pub mod images;
+pub mod keywords;
+pub mod lang;
+pub mod parse;
+#[cfg(feature = "server")]
+pub mod paste;
+pub mod podcast;
+pub mod postprocess;
+#[cfg(feature = "server")]
+pub mod proxy;
+#[cfg(feature = "server")]
+pub mod repo;
+pub mod rewrite;
+#[cfg(feature = "server")]
+pub mod secrets;
+pub mod semantic;
+pub mod sourcemap;
+#[cfg(any(feature = "server", feature = "edge"))]
+pub mod store;
+pub mod style;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(feature = "server")]
+pub mod youtube;
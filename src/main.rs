@@ -1,124 +1,545 @@
-mod cache;
-mod convert;
-mod error;
-mod fetch;
-mod parse;
+mod access;
+mod admin;
+mod batch;
+mod cdn;
+mod coalesce;
+mod compile;
+mod config;
+mod debug;
+mod digest;
+mod discover;
+mod flags;
+mod graph;
+mod health;
+mod politeness;
+mod prefetch;
+mod range;
+mod ratelimit;
+mod refresh;
+mod selftest;
+mod shadow;
+mod stats;
+mod templates;
+#[cfg(test)]
+mod tests;
+mod transform;
+mod usage;
+mod version;
 
 use axum::{
-    extract::{Path, State},
-    http::{header, HeaderMap, StatusCode},
+    extract::{Path, Query, Request, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    middleware,
     response::{IntoResponse, Response},
     routing::get,
-    Router,
+    Json, Router,
 };
-use cache::{ContentSource, MarkdownCache};
+use mdwnio::{
+    anchors, cache, convert, dates, discourse, error, fetch, forum, github, i18n, images, keywords,
+    lang, parse, paste, podcast, postprocess, repo, secrets, semantic, sourcemap, style, youtube,
+};
+
+use anchors::AnchorStyle;
+use cache::{CacheBackend, CachedContent, ContentSource, ContentStore, FetchOutcome};
+use cache::{ImageCache, MarkdownCache, MetadataCache};
+use cdn::Purger;
+use coalesce::Coalescer;
+use config::Config;
+use convert::PageType;
+use digest::Digests;
 use error::{MdwnError, Result};
-use fetch::{FetchConfig, Fetcher};
-use parse::{categorize_content_type, parse_html_for_markdown_link, parse_json_for_markdown};
-use parse::{ContentCategory, HtmlParseResult, JsonParseResult};
-use std::env;
+use fetch::{FetchBudget, FetchConfig, Fetcher};
+use flags::{Flag, Flags};
+use graph::LinkGraphs;
+use i18n::Locale;
+use parse::{categorize_content_type, is_markdown_alternate, parse_accept_language};
+use parse::{parse_html_byline, parse_html_license, parse_html_metadata};
+use parse::{parse_html_for_markdown_link, parse_json_for_markdown, parse_json_next_link};
+use parse::{parse_json_metadata, parse_markdown_metadata};
+use parse::{AlternatePolicy, ContentCategory, HtmlParseResult, JsonParseResult, PageMetadata};
+use politeness::CrawlThrottle;
+use postprocess::Redaction;
+use ratelimit::RateLimiter;
+use secrets::Secret;
+use serde::{Deserialize, Serialize};
+use shadow::ShadowStats;
+use stats::DomainStats;
+use std::collections::HashSet;
+use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
+use style::OutputStyle;
+use templates::{TemplateContext, TemplateMetadata};
 use tokio::net::TcpListener;
+use tokio::sync::Semaphore;
+use tower_http::limit::RequestBodyLimitLayer;
+use tower_http::timeout::TimeoutLayer;
 use tower_http::trace::TraceLayer;
-use tracing::info;
+use tracing::{info, Instrument};
+use tracing_subscriber::fmt::format::FmtSpan;
 use tracing_subscriber::EnvFilter;
+use usage::UsageReports;
 
-/// Application configuration
-#[derive(Clone)]
-struct Config {
-    port: u16,
-    cache_ttl: u64,
-    request_timeout: u64,
-    max_content_length: usize,
-    max_redirects: usize,
-    user_agent: String,
-}
-
-impl Config {
-    fn from_env() -> Self {
-        Self {
-            port: env::var("PORT")
-                .ok()
-                .and_then(|s| s.parse().ok())
-                .unwrap_or(3000),
-            cache_ttl: env::var("CACHE_TTL")
-                .ok()
-                .and_then(|s| s.parse().ok())
-                .unwrap_or(3600),
-            request_timeout: env::var("REQUEST_TIMEOUT")
-                .ok()
-                .and_then(|s| s.parse().ok())
-                .unwrap_or(10),
-            max_content_length: env::var("MAX_CONTENT_LENGTH")
-                .ok()
-                .and_then(|s| s.parse().ok())
-                .unwrap_or(10 * 1024 * 1024), // 10MB
-            max_redirects: env::var("MAX_REDIRECTS")
-                .ok()
-                .and_then(|s| s.parse().ok())
-                .unwrap_or(5),
-            user_agent: env::var("USER_AGENT")
-                .unwrap_or_else(|_| "mdwn.io/1.0 (+https://mdwn.io)".to_string()),
+/// Per-request options for the proxy route, passed as query parameters
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+struct ProxyOptions {
+    /// Preferred language for markdown alternates, overrides Accept-Language;
+    /// a document detected in another language is flagged
+    lang: Option<String>,
+    /// Fail with `LANGUAGE_MISMATCH` instead of flagging a mismatch with `?lang=`
+    #[serde(deserialize_with = "deserialize_flag")]
+    lang_strict: bool,
+    /// Number of JSON pagination `next` links to follow and concatenate
+    follow_pagination: usize,
+    /// Inline small images as base64 data URIs
+    #[serde(deserialize_with = "deserialize_flag")]
+    embed_images: bool,
+    /// Convert HTML even if it looks like a JavaScript shell
+    #[serde(deserialize_with = "deserialize_flag")]
+    force_convert: bool,
+    /// Append a provenance footer (source, title, retrieval time, license)
+    #[serde(deserialize_with = "deserialize_flag")]
+    citation: bool,
+    /// Leave out anything that varies between fetches of identical content
+    #[serde(deserialize_with = "deserialize_flag")]
+    deterministic: bool,
+    /// `0` to leave out the HTML comment noting converted content
+    #[serde(deserialize_with = "deserialize_optional_flag")]
+    notice: Option<bool>,
+    /// Prepend YAML front matter with the document's provenance
+    #[serde(deserialize_with = "deserialize_flag")]
+    front_matter: bool,
+    /// Follow relative dates ("3 days ago") with the absolute date
+    #[serde(deserialize_with = "deserialize_flag")]
+    resolve_dates: bool,
+    /// Serve headings deeper than this as bold paragraphs, overriding `MAX_HEADING_DEPTH`
+    max_heading_depth: Option<usize>,
+    /// `inline` or `map` to keep the ids of the page's headings
+    anchors: Option<AnchorStyle>,
+    /// `obsidian` to lay the page out as a note in a markdown vault
+    style: Option<OutputStyle>,
+    /// Serve JSON mapping markdown lines to the page elements they came from
+    #[serde(deserialize_with = "deserialize_flag")]
+    sourcemap: bool,
+    /// Convert upstream 4xx/5xx and soft-404 HTML pages instead of failing
+    #[serde(deserialize_with = "deserialize_flag")]
+    allow_error_pages: bool,
+    /// `pii` to redact emails, phone numbers and the operator's patterns
+    redact: Option<Redaction>,
+    /// Name of an operator template in `OUTPUT_TEMPLATES` to render the page with
+    template: Option<String>,
+    /// Skip the cache lookup and overwrite the entry with a fresh fetch; also
+    /// set by the refresh scheduler and by a client's `Cache-Control: no-cache`
+    #[serde(deserialize_with = "deserialize_flag")]
+    refresh: bool,
+}
+
+/// Accept `1`/`0` as well as `true`/`false` for boolean flags, in query strings or JSON
+fn deserialize_flag<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<bool, D::Error> {
+    struct FlagVisitor;
+
+    impl serde::de::Visitor<'_> for FlagVisitor {
+        type Value = bool;
+
+        fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            f.write_str("1, 0, true or false")
+        }
+
+        fn visit_bool<E: serde::de::Error>(self, value: bool) -> std::result::Result<bool, E> {
+            Ok(value)
+        }
+
+        fn visit_str<E: serde::de::Error>(self, value: &str) -> std::result::Result<bool, E> {
+            match value {
+                "" | "1" | "true" | "yes" | "on" => Ok(true),
+                "0" | "false" | "no" | "off" => Ok(false),
+                other => Err(E::custom(format!("invalid flag '{}', use 1 or 0", other))),
+            }
         }
     }
+
+    deserializer.deserialize_any(FlagVisitor)
+}
+
+/// [`deserialize_flag`] for flags whose default depends on configuration
+fn deserialize_optional_flag<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Option<bool>, D::Error> {
+    deserialize_flag(deserializer).map(Some)
 }
 
+impl ProxyOptions {
+    /// Language preferences from `?lang=`, or the primary language the
+    /// client's Accept-Language prefers most
+    ///
+    /// Browsers send dozens of spellings of the same preference, so keying
+    /// and negotiating on the whole header would split the cache for nothing.
+    fn preferred_langs(&self, headers: &HeaderMap) -> Vec<String> {
+        match &self.lang {
+            Some(lang) => parse_accept_language(lang),
+            None => headers
+                .get(header::ACCEPT_LANGUAGE)
+                .and_then(|v| v.to_str().ok())
+                .map(parse_accept_language)
+                .unwrap_or_default()
+                .first()
+                .map(|tag| vec![lang::primary_subtag(tag)])
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Pagination links to follow, clamped so larger requests share a cache entry
+    fn pagination_pages(&self) -> usize {
+        self.follow_pagination.min(MAX_PAGINATION_PAGES)
+    }
+
+    /// Cache variant for options that change the rendered output
+    fn cache_variant(&self, langs: &[String]) -> String {
+        let mut parts = Vec::new();
+        if !langs.is_empty() {
+            parts.push(format!("lang={}", langs.join(",")));
+        }
+        if self.pagination_pages() > 0 {
+            parts.push(format!("pages={}", self.pagination_pages()));
+        }
+        if self.embed_images {
+            parts.push("embed_images".to_string());
+        }
+        if self.force_convert {
+            parts.push("force_convert".to_string());
+        }
+        if self.notice == Some(false) {
+            parts.push("notice=0".to_string());
+        }
+        if self.redact == Some(Redaction::Pii) {
+            parts.push("redact=pii".to_string());
+        }
+        if self.sourcemap {
+            parts.push("sourcemap".to_string());
+        }
+        parts.join("&")
+    }
+}
+
+/// Upper bound on `?follow_pagination=`, the fetch budget still applies
+const MAX_PAGINATION_PAGES: usize = 20;
+
 /// Shared application state
 #[derive(Clone)]
 struct AppState {
+    config: Arc<Config>,
     fetcher: Arc<Fetcher>,
     cache: MarkdownCache,
+    metadata_cache: MetadataCache,
+    image_cache: ImageCache,
+    content_store: ContentStore,
+    stats: DomainStats,
+    usage: Option<UsageReports>,
+    digests: Option<Digests>,
+    purger: Option<Purger>,
+    crawl_throttle: CrawlThrottle,
+    /// Recent `/discover` outlines by origin
+    outlines: discover::Outlines,
+    /// Fetches in progress, joined by identical concurrent requests
+    flights: Coalescer<Result<(CachedContent, bool)>>,
+    /// Permits for background `/prefetch` fetches, shared by every request
+    prefetches: Arc<Semaphore>,
+    /// Link graphs of recent prefetches
+    graphs: LinkGraphs,
+    shadow: ShadowStats,
+    flags: Flags,
+    canary: health::CanaryCache,
+}
+
+impl AppState {
+    /// Create the fetcher and caches for a configuration
+    fn new(config: Config) -> Result<Self> {
+        // Create fetcher
+        let fetch_config = FetchConfig {
+            user_agent: config.user_agent.clone(),
+            timeout_secs: config.request_timeout,
+            max_content_length: config.max_content_length,
+            max_redirects: config.max_redirects,
+            max_cross_origin_redirects: config.max_cross_origin_redirects,
+            max_upstream_requests: config.max_upstream_requests,
+            max_upstream_bytes: config.max_upstream_bytes,
+            prerender_url: config.prerender_url.clone(),
+            prerender_timeout_secs: config.prerender_timeout,
+            #[cfg(test)]
+            allow_private_networks: config.allow_private_networks,
+            #[cfg(not(test))]
+            allow_private_networks: false,
+            dns: config.dns.clone(),
+            rewrites: config.rewrites.clone(),
+            domains: config.domains.clone(),
+            credentials: config.upstream_credentials.clone(),
+            max_host_concurrency: config.upstream_host_concurrency,
+            host_delay: Duration::from_millis(config.upstream_host_delay_ms),
+            max_attempts: config.upstream_retry_attempts,
+            retry_backoff: Duration::from_millis(config.upstream_retry_backoff_ms),
+            deadline: Some(Duration::from_secs(config.proxy_timeout)),
+            proxy: config.outbound_proxy.clone(),
+        };
+        let fetcher = Fetcher::new(fetch_config)?;
+
+        // Create caches
+        let cache = MarkdownCache::new(config.cache_ttl)
+            .with_ttl_bounds(config.cache_ttl_min, config.cache_ttl_max)
+            .with_host_ttls(&config.cache_ttl_hosts)
+            .with_max_bytes(config.cache_max_bytes)
+            .with_key_rules(config.cache_keys.clone())
+            .with_history(config.history_versions, config.history_max_bytes);
+        let metadata_cache = MetadataCache::new(config.metadata_cache_ttl);
+        let image_cache = ImageCache::new(config.cache_ttl, config.image_cache_max_bytes);
+        let content_store = ContentStore::new(config.content_store_max_bytes);
+        let usage = config.usage_report_dir.clone().map(UsageReports::new);
+        let digests = Digests::from_config(&config)?;
+        let purger = Purger::from_config(&config)?;
+        let crawl_throttle = CrawlThrottle::new(
+            Duration::from_millis(config.crawl_delay_ms),
+            &config.user_agent,
+        );
+        let outlines = discover::Outlines::new(config.cache_ttl);
+        let flags = Flags::new(config.flags.clone());
+
+        Ok(Self {
+            config: Arc::new(config),
+            fetcher: Arc::new(fetcher),
+            cache,
+            metadata_cache,
+            image_cache,
+            content_store,
+            stats: DomainStats::new(),
+            usage,
+            digests,
+            purger,
+            crawl_throttle,
+            outlines,
+            flights: Coalescer::default(),
+            prefetches: Arc::new(Semaphore::new(prefetch::PREFETCH_CONCURRENCY)),
+            graphs: LinkGraphs::default(),
+            shadow: ShadowStats::default(),
+            flags,
+            canary: health::CanaryCache::default(),
+        })
+    }
+}
+
+/// Body limit for routes that take no request body
+const SMALL_BODY_LIMIT: usize = 64 * 1024;
+
+/// Build the router with all routes and middleware
+fn build_app(state: AppState) -> Router {
+    let config = &state.config;
+    let admin = with_limits(
+        admin::routes(state.clone()),
+        config.admin_timeout,
+        SMALL_BODY_LIMIT,
+    );
+
+    let probes = Router::new()
+        .route("/health", get(health::healthz_handler))
+        .route("/healthz", get(health::healthz_handler))
+        .route("/readyz", get(health::readyz_handler));
+    let probes = with_limits(probes, config.proxy_timeout, SMALL_BODY_LIMIT);
+
+    let proxy = Router::new()
+        .route("/", get(index_handler))
+        .route("/version", get(version::version_handler))
+        .route("/title/{*url}", get(title_handler))
+        .route("/description/{*url}", get(description_handler))
+        .route("/img/{*url}", get(image_handler))
+        .route("/content/{hash}", get(content_handler))
+        .route("/history/{*url}", get(history_handler))
+        .route("/keywords/{*url}", get(keywords_handler))
+        .route("/discover/{*domain}", get(discover::discover_handler))
+        .route("/{*url}", get(proxy_handler));
+    let proxy = with_limits(proxy, config.proxy_timeout, SMALL_BODY_LIMIT);
+
+    let batch = transform::routes()
+        .merge(batch::routes())
+        .merge(compile::routes(state.clone()));
+    let batch = with_limits(batch, config.batch_timeout, config.batch_max_body);
+
+    // Probes and operators aren't limited, so they still get through under load
+    let public = proxy.merge(batch);
+    let public = match RateLimiter::from_config(config) {
+        Some(limiter) => public.layer(middleware::from_fn_with_state(
+            limiter,
+            ratelimit::rate_limit,
+        )),
+        None => public,
+    };
+
+    let app = public
+        .merge(probes)
+        .merge(admin)
+        .layer(middleware::from_fn(localize_errors))
+        .layer(TraceLayer::new_for_http());
+    let app = if config.access_log {
+        app.layer(middleware::from_fn(access::access_log))
+    } else {
+        app
+    };
+    app.with_state(state)
+}
+
+/// Bound how long a group of routes may run and how large request bodies may be
+fn with_limits(router: Router<AppState>, timeout_secs: u64, max_body: usize) -> Router<AppState> {
+    router
+        .layer(RequestBodyLimitLayer::new(max_body))
+        .layer(TimeoutLayer::with_status_code(
+            StatusCode::GATEWAY_TIMEOUT,
+            Duration::from_secs(timeout_secs),
+        ))
+        .layer(middleware::map_response(move |response: Response| {
+            limit_error_body(response, timeout_secs, max_body)
+        }))
+}
+
+/// Give timeouts and oversized bodies rejected by the limit layers the usual error body
+async fn limit_error_body(response: Response, timeout_secs: u64, max_body: usize) -> Response {
+    let error = match response.status() {
+        StatusCode::GATEWAY_TIMEOUT => MdwnError::Timeout(timeout_secs),
+        StatusCode::PAYLOAD_TOO_LARGE => MdwnError::TooLarge(max_body),
+        _ => return response,
+    };
+
+    // Errors from our handlers already have one
+    let (parts, body) = response.into_parts();
+    let body = axum::body::to_bytes(body, max_body.max(SMALL_BODY_LIMIT))
+        .await
+        .unwrap_or_default();
+    if body.starts_with(b"ERROR: ") {
+        return Response::from_parts(parts, body.into());
+    }
+    error.into_response()
+}
+
+/// Translate error details for the client's `Accept-Language`
+async fn localize_errors(request: Request, next: middleware::Next) -> Response {
+    let locale = request
+        .headers()
+        .get(header::ACCEPT_LANGUAGE)
+        .and_then(|value| value.to_str().ok())
+        .map(Locale::negotiate)
+        .unwrap_or_default();
+    let mut response = next.run(request).await;
+    let Some(error) = response.extensions().get::<MdwnError>().cloned() else {
+        return response;
+    };
+    // English or not, the body was picked by the header
+    let vary = HeaderValue::from_static("Accept-Language");
+    response.headers_mut().append(header::VARY, vary);
+    if locale == Locale::En {
+        return response;
+    }
+
+    let (mut parts, _) = response.into_parts();
+    parts.headers.remove(header::CONTENT_LENGTH);
+    let language = locale.tag().parse().expect("valid header value");
+    parts.headers.insert(header::CONTENT_LANGUAGE, language);
+    Response::from_parts(parts, error.localized_body(locale).into())
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    // Initialize tracing
+    // Initialize tracing; closing a stage's span logs its duration at debug level
     tracing_subscriber::fmt()
         .with_env_filter(
             EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
         )
+        .with_span_events(FmtSpan::CLOSE)
         .with_target(false)
+        // stdout is left to the access log
+        .with_writer(std::io::stderr)
         .init();
 
-    let config = Config::from_env();
+    // Refuse to start on mistyped settings rather than running with defaults
+    let config = Config::from_env()?;
+    let state = AppState::new(config.clone())?;
 
-    // Create fetcher
-    let fetch_config = FetchConfig {
-        user_agent: config.user_agent.clone(),
-        timeout_secs: config.request_timeout,
-        max_content_length: config.max_content_length,
-        max_redirects: config.max_redirects,
-    };
-    let fetcher = Fetcher::new(fetch_config)?;
+    // Preflight for container entrypoints: refuse to serve from a broken setup
+    if std::env::args().any(|arg| arg == "--self-test") && !selftest::run(&state).await {
+        anyhow::bail!("self-test failed");
+    }
 
-    // Create cache
-    let cache = MarkdownCache::new(config.cache_ttl);
+    // Pick up where the last process left off
+    if let Some(path) = &config.cache_snapshot {
+        match state.cache.load_snapshot(path).await {
+            Ok(loaded) => info!("Restored {} cache entries from {}", loaded, path.display()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => tracing::warn!("Ignoring cache snapshot {}: {}", path.display(), e),
+        }
+    }
 
-    let state = AppState {
-        fetcher: Arc::new(fetcher),
-        cache,
-    };
+    if let Some(usage) = &state.usage {
+        usage
+            .clone()
+            .spawn(Duration::from_secs(config.usage_report_interval));
+    }
+    if let Some(digests) = &state.digests {
+        digests
+            .clone()
+            .spawn(Duration::from_secs(config.digest_interval));
+    }
+    refresh::spawn(state.clone());
+    if config.secrets_reload_interval > 0 {
+        // Shares its secrets with the state's copy, so rotations reach every user
+        config
+            .clone()
+            .spawn_secrets_reload(Duration::from_secs(config.secrets_reload_interval));
+    }
 
-    // Build router
-    let app = Router::new()
-        .route("/", get(index_handler))
-        .route("/health", get(health_handler))
-        .route("/{*url}", get(proxy_handler))
-        .layer(TraceLayer::new_for_http())
-        .with_state(state);
+    let app = build_app(state.clone());
 
     let addr = format!("0.0.0.0:{}", config.port);
     let listener = TcpListener::bind(&addr).await?;
     info!("mdwn.io listening on {}", addr);
 
-    axum::serve(listener, app).await?;
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(shutdown_signal())
+    .await?;
+
+    if let Some(path) = &config.cache_snapshot {
+        match state.cache.save_snapshot(path).await {
+            Ok(saved) => info!("Saved {} cache entries to {}", saved, path.display()),
+            Err(e) => tracing::error!("Failed to save cache snapshot {}: {}", path.display(), e),
+        }
+    }
 
     Ok(())
 }
 
-/// Health check endpoint
-async fn health_handler() -> &'static str {
-    "OK"
+/// Resolve on Ctrl-C or SIGTERM, letting in-flight requests finish
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(_) => std::future::pending().await,
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+    info!("Shutting down");
 }
 
 /// Index handler - serve README
@@ -135,119 +556,1425 @@ async fn index_handler() -> impl IntoResponse {
 async fn proxy_handler(
     State(state): State<AppState>,
     Path(url_path): Path<String>,
+    Query(mut options): Query<ProxyOptions>,
+    request_headers: HeaderMap,
 ) -> Response {
-    match process_url(&state, &url_path).await {
-        Ok((markdown, source)) => {
+    if let Some(name) = &options.template {
+        let templates = &state.config.templates;
+        if !templates.contains(name) {
+            return MdwnError::ParseError(format!(
+                "Unknown template '{}', available: {}",
+                name,
+                templates.names().join(", ")
+            ))
+            .into_response();
+        }
+    }
+    let langs = options.preferred_langs(&request_headers);
+    options.refresh |= wants_no_cache(&request_headers);
+    let url = state.fetcher.parse_url(&url_path).ok();
+    let domain = url
+        .as_ref()
+        .and_then(|url| url.host_str().map(str::to_lowercase));
+
+    let result = process_url(&state, &url_path, &options, &langs)
+        .await
+        .and_then(|(content, cache_hit)| {
+            let mismatch = language_mismatch(&options, &content);
+            match (mismatch, options.lang_strict) {
+                (Some(language), true) => Err(MdwnError::LanguageMismatch(format!(
+                    "Document language is '{}', not {}",
+                    language,
+                    options.lang.as_deref().unwrap_or_default()
+                ))),
+                _ => Ok((content, cache_hit)),
+            }
+        });
+    let upstream = access::Upstream::new(url.as_ref(), &result);
+    if let Some(domain) = &domain {
+        record_usage(&state, domain, &result);
+    }
+
+    let mut response = match result {
+        Ok((mut content, _)) => {
             let mut headers = HeaderMap::new();
             headers.insert(
                 header::CONTENT_TYPE,
-                "text/markdown; charset=utf-8".parse().expect("valid header value"),
+                "text/markdown; charset=utf-8"
+                    .parse()
+                    .expect("valid header value"),
             );
             headers.insert(
                 "X-Mdwn-Source",
-                source.as_header_value().parse().expect("valid header value"),
+                content
+                    .source
+                    .as_header_value()
+                    .parse()
+                    .expect("valid header value"),
             );
+            if let Some(origin) = content.cross_origin.as_deref().and_then(|o| o.parse().ok()) {
+                headers.insert("X-Mdwn-Cross-Origin", origin);
+            }
+            if content.prerendered {
+                headers.insert(
+                    "X-Mdwn-Prerendered",
+                    "true".parse().expect("valid header value"),
+                );
+            }
+            if let Some(quality) = content.quality {
+                headers.insert(
+                    "X-Mdwn-Quality",
+                    format!("{:.2}", quality)
+                        .parse()
+                        .expect("valid header value"),
+                );
+            }
+            if let Some(status) = content.upstream_status {
+                headers.insert("X-Mdwn-Upstream-Status", status.into());
+            }
+            if content.soft_not_found {
+                let flag = "true".parse().expect("valid header value");
+                headers.insert("X-Mdwn-Soft-Not-Found", flag);
+            }
+            if let Some(page_type) = content.page_type {
+                let value = page_type
+                    .as_header_value()
+                    .parse()
+                    .expect("valid header value");
+                headers.insert("X-Mdwn-Page-Type", value);
+            }
+            if let Some(original) = content.duplicate_of.as_deref().and_then(|u| u.parse().ok()) {
+                headers.insert("X-Mdwn-Duplicate-Of", original);
+            }
+            if let Some(language) = content.language.as_deref().and_then(|l| l.parse().ok()) {
+                headers.insert(header::CONTENT_LANGUAGE, language);
+            }
+            if language_mismatch(&options, &content).is_some() {
+                let flag = "true".parse().expect("valid header value");
+                headers.insert("X-Mdwn-Language-Mismatch", flag);
+            }
+            if let Some(hash) = &content.content_hash {
+                insert_hash_headers(&mut headers, hash);
+            }
+            if options.resolve_dates {
+                let declared = content.modified.or(content.published);
+                content.markdown =
+                    dates::resolve_relative_dates(&content.markdown, content.fetched_at, declared);
+                // The dates depend on when this copy was fetched, not just the version
+                if let Some(hash) = &content.content_hash {
+                    insert_weak_etag(&mut headers, hash);
+                }
+            }
+            let max_heading_depth = options
+                .max_heading_depth
+                .unwrap_or(state.config.max_heading_depth);
+            content.markdown = convert::flatten_headings(&content.markdown, max_heading_depth);
+            if let Some(style) = options.anchors {
+                let anchors = &content.anchors;
+                content.markdown = anchors::with_anchors(&content.markdown, anchors, style);
+            }
+            let obsidian = options.style == Some(OutputStyle::Obsidian);
+            if let Some(url) = url.as_ref().filter(|_| obsidian) {
+                content.markdown = style::obsidian(&content.markdown, url);
+            }
+            let deterministic = options.deterministic || state.config.deterministic;
+            let source = url.as_ref().map(url::Url::as_str).unwrap_or(&url_path);
+            let retrieved = (!deterministic).then_some(content.fetched_at);
+            let date = |t: &chrono::DateTime<chrono::Utc>| {
+                t.to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+            };
+            let published = content.published.as_ref().map(date);
+            let modified = content.modified.as_ref().map(date);
+            if options.citation || options.front_matter || obsidian {
+                if obsidian {
+                    // Property names and date format of Obsidian's web clipper
+                    let day = |t: &chrono::DateTime<chrono::Utc>| t.format("%Y-%m-%d").to_string();
+                    let published = content.published.as_ref().map(day);
+                    let created = retrieved.as_ref().map(day);
+                    let properties = convert::front_matter(&[
+                        ("title", content.title.as_deref()),
+                        ("source", Some(source)),
+                        ("author", content.author.as_deref()),
+                        ("published", published.as_deref()),
+                        ("created", created.as_deref()),
+                    ]);
+                    content.markdown.insert_str(0, &properties);
+                } else if options.front_matter {
+                    let retrieved = retrieved.map(|t| t.to_rfc3339());
+                    let matter = convert::front_matter(&[
+                        ("url", Some(source)),
+                        ("title", content.title.as_deref()),
+                        ("author", content.author.as_deref()),
+                        ("published", published.as_deref()),
+                        ("modified", modified.as_deref()),
+                        ("source", Some(content.source.as_header_value())),
+                        ("retrieved", retrieved.as_deref()),
+                        ("license", content.license.as_deref()),
+                        ("page_type", content.page_type.map(|t| t.as_header_value())),
+                        ("content_hash", content.content_hash.as_deref()),
+                    ]);
+                    content.markdown.insert_str(0, &matter);
+                }
+                if options.citation {
+                    content.markdown.push_str(&convert::citation_footer(
+                        source,
+                        content.title.as_deref(),
+                        retrieved,
+                        content.license.as_deref(),
+                    ));
+                }
+                // With a retrieval time the bytes can change while the version doesn't
+                if let Some(hash) = content.content_hash.as_ref().filter(|_| !deterministic) {
+                    insert_weak_etag(&mut headers, hash);
+                }
+            }
+            if let Some(name) = &options.template {
+                let context = TemplateContext {
+                    url: source,
+                    title: content.title.as_deref(),
+                    body: &content.markdown,
+                    metadata: TemplateMetadata {
+                        source: content.source.as_header_value(),
+                        author: content.author.as_deref(),
+                        published,
+                        modified,
+                        retrieved: retrieved.map(|t| t.to_rfc3339()),
+                        language: content.language.as_deref(),
+                        license: content.license.as_deref(),
+                        page_type: content.page_type.map(|t| t.as_header_value()),
+                        content_hash: content.content_hash.as_deref(),
+                    },
+                };
+                match state.config.templates.render(name, &context) {
+                    Ok(rendered) => {
+                        content.markdown = rendered;
+                        // The template may render the retrieval time
+                        if let Some(hash) = content.content_hash.as_ref().filter(|_| !deterministic)
+                        {
+                            insert_weak_etag(&mut headers, hash);
+                        }
+                    }
+                    Err(e) => {
+                        let error = format!("Template '{}' failed: {}", name, e);
+                        let mut response = MdwnError::Internal(error).into_response();
+                        response.extensions_mut().insert(upstream);
+                        return response;
+                    }
+                }
+            }
+            // Without `?lang=` the client's Accept-Language picked the variant
+            if options.lang.is_none() && content.language_dependent {
+                headers.append(header::VARY, HeaderValue::from_static("Accept-Language"));
+            }
+            insert_freshness_headers(&mut headers, &content, state.cache.ttl(&content));
+            let shared = !content.private && !content.is_error_page();
+            if let Some(url) = url.as_ref().filter(|_| shared) {
+                cdn::insert_cdn_headers(&mut headers, url, state.config.cdn_max_age);
+            }
+
+            if options.sourcemap {
+                let mappings = sourcemap::source_map(&content.markdown, &content.sources);
+                headers.remove(header::CONTENT_TYPE);
+                let map = serde_json::json!({
+                    "url": source,
+                    "markdown": content.markdown,
+                    "mappings": mappings,
+                });
+                (headers, Json(map)).into_response()
+            } else {
+                range::ranged_response(&request_headers, headers, content.markdown)
+            }
+        }
+        Err(e) => e.into_response(),
+    };
+    response.extensions_mut().insert(upstream);
+    response
+}
+
+/// Whether the client's `Cache-Control` asks for a response not served from cache
+fn wants_no_cache(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(header::CACHE_CONTROL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|directive| directive.trim().eq_ignore_ascii_case("no-cache"))
+}
+
+/// The document's language, if it's known and not one `?lang=` asked for
+fn language_mismatch<'a>(options: &ProxyOptions, content: &'a CachedContent) -> Option<&'a str> {
+    let wanted = parse_accept_language(options.lang.as_deref()?);
+    let language = content.language.as_deref()?;
+    (!wanted.is_empty() && !lang::language_matches(language, &wanted)).then_some(language)
+}
+
+/// Add `Cache-Control` and `Age` so caches downstream keep a page as long as
+/// ours does
+///
+/// Downstream caches count `Age` against `max-age`, so `max-age` is the page's
+/// whole `ttl` and what's left of it after `Age` is our remaining TTL. Pages
+/// fetched with credentials are `private`, for the client's own cache only,
+/// and error pages, which we don't cache either, are `no-store`.
+fn insert_freshness_headers(headers: &mut HeaderMap, content: &CachedContent, ttl: Duration) {
+    if content.is_error_page() {
+        headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+        return;
+    }
+    let age = (chrono::Utc::now() - content.fetched_at)
+        .num_seconds()
+        .max(0) as u64;
+    let scope = if content.private { "private" } else { "public" };
+    headers.insert(
+        header::CACHE_CONTROL,
+        format!("{}, max-age={}", scope, ttl.as_secs())
+            .parse()
+            .expect("valid header value"),
+    );
+    headers.insert(header::AGE, age.into());
+}
+
+/// Add `X-Mdwn-Content-Hash` and the matching `ETag`
+fn insert_hash_headers(headers: &mut HeaderMap, hash: &str) {
+    headers.insert(
+        "X-Mdwn-Content-Hash",
+        hash.parse().expect("valid header value"),
+    );
+    headers.insert(
+        header::ETAG,
+        format!("\"{}\"", hash).parse().expect("valid header value"),
+    );
+}
+
+/// Replace the `ETag` with a weak one, for output that varies within a version
+fn insert_weak_etag(headers: &mut HeaderMap, hash: &str) {
+    headers.insert(
+        header::ETAG,
+        format!("W/\"{}\"", hash)
+            .parse()
+            .expect("valid header value"),
+    );
+}
+
+/// Whether a request's `If-None-Match` already names `etag`
+fn is_not_modified(request_headers: &HeaderMap, etag: &HeaderValue) -> bool {
+    request_headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|tag| tag.trim() == "*" || tag.trim() == etag)
+}
+
+/// Serve an exact document version by content hash
+async fn content_handler(
+    State(state): State<AppState>,
+    Path(hash): Path<String>,
+    request_headers: HeaderMap,
+) -> Response {
+    if hash.len() != 64 || !hash.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f')) {
+        return MdwnError::InvalidUrl(
+            "Content hash must be 64 lowercase hex characters".to_string(),
+        )
+        .into_response();
+    }
+
+    let Some(markdown) = state.content_store.get(&hash).await else {
+        return MdwnError::NoMarkdown("No cached content with this hash".to_string())
+            .into_response();
+    };
+
+    let mut headers = HeaderMap::new();
+    insert_hash_headers(&mut headers, &hash);
+    // A hash always names the same bytes
+    headers.insert(
+        header::CACHE_CONTROL,
+        "public, max-age=31536000, immutable"
+            .parse()
+            .expect("valid header value"),
+    );
+
+    if is_not_modified(&request_headers, &headers[header::ETAG]) {
+        return (StatusCode::NOT_MODIFIED, headers).into_response();
+    }
+
+    headers.insert(
+        header::CONTENT_TYPE,
+        "text/markdown; charset=utf-8"
+            .parse()
+            .expect("valid header value"),
+    );
+    range::ranged_response(&request_headers, headers, markdown.to_string())
+}
+
+/// Query options for `GET /history/{url}`
+#[derive(Deserialize)]
+struct HistoryQuery {
+    /// Serve this retained version instead of listing them
+    hash: Option<String>,
+}
+
+/// Versions of a URL, newest first
+#[derive(Serialize)]
+struct HistoryListing {
+    url: String,
+    versions: Vec<VersionSummary>,
+}
+
+#[derive(Serialize)]
+struct VersionSummary {
+    hash: String,
+    fetched_at: chrono::DateTime<chrono::Utc>,
+    bytes: usize,
+}
+
+/// List the retained versions of a URL, or serve one with `?hash=`
+async fn history_handler(
+    State(state): State<AppState>,
+    Path(url_path): Path<String>,
+    Query(query): Query<HistoryQuery>,
+) -> Response {
+    let url = match state.fetcher.parse_url(&url_path) {
+        Ok(url) => url,
+        Err(e) => return e.into_response(),
+    };
+    let Some(versions) = state.cache.history(url.as_str(), "").await else {
+        return MdwnError::NoMarkdown("Version history is disabled".to_string()).into_response();
+    };
+
+    let Some(hash) = query.hash else {
+        let listing = HistoryListing {
+            url: url.to_string(),
+            versions: versions
+                .into_iter()
+                .map(|version| VersionSummary {
+                    bytes: version.markdown.len(),
+                    hash: version.hash,
+                    fetched_at: version.fetched_at,
+                })
+                .collect(),
+        };
+        return Json(listing).into_response();
+    };
+
+    match versions.into_iter().find(|version| version.hash == hash) {
+        Some(version) => {
+            let mut headers = HeaderMap::new();
+            headers.insert(
+                header::CONTENT_TYPE,
+                "text/markdown; charset=utf-8"
+                    .parse()
+                    .expect("valid header value"),
+            );
+            insert_hash_headers(&mut headers, &version.hash);
+            (StatusCode::OK, headers, version.markdown).into_response()
+        }
+        None => {
+            MdwnError::NoMarkdown("No retained version with this hash".to_string()).into_response()
+        }
+    }
+}
+
+/// Query options for `GET /keywords/{url}`
+#[derive(Deserialize)]
+struct KeywordsQuery {
+    /// Number of terms to return, at most `MAX_KEYWORDS`
+    limit: Option<usize>,
+}
+
+/// Terms returned when no `?limit=` is given
+const DEFAULT_KEYWORDS: usize = 10;
+
+/// Upper bound on `?limit=`
+const MAX_KEYWORDS: usize = 50;
+
+/// Top keywords of a document, best first
+#[derive(Serialize)]
+struct KeywordListing {
+    url: String,
+    keywords: Vec<keywords::Keyword>,
+}
+
+/// Extract keyword phrases from the converted markdown for tagging
+async fn keywords_handler(
+    State(state): State<AppState>,
+    Path(url_path): Path<String>,
+    Query(query): Query<KeywordsQuery>,
+) -> Response {
+    let limit = query.limit.unwrap_or(DEFAULT_KEYWORDS).min(MAX_KEYWORDS);
+    let budget = state.fetcher.budget();
+    match process_url_throttled(&state, &url_path, &ProxyOptions::default(), &budget).await {
+        Ok((content, _)) => {
+            let url = state
+                .fetcher
+                .parse_url(&url_path)
+                .map(|url| url.to_string())
+                .unwrap_or(url_path);
+            let listing = KeywordListing {
+                url,
+                keywords: keywords::extract_keywords(&content.markdown, limit),
+            };
+            Json(listing).into_response()
+        }
+        Err(e) => e.into_response(),
+    }
+}
+
+/// Title-only endpoint for link previews
+async fn title_handler(
+    State(state): State<AppState>,
+    Path(url_path): Path<String>,
+    request_headers: HeaderMap,
+) -> Response {
+    metadata_response(
+        &state,
+        &url_path,
+        &request_headers,
+        |metadata| metadata.title,
+        "No title found",
+    )
+    .await
+}
+
+/// Description-only endpoint for link previews
+async fn description_handler(
+    State(state): State<AppState>,
+    Path(url_path): Path<String>,
+    request_headers: HeaderMap,
+) -> Response {
+    metadata_response(
+        &state,
+        &url_path,
+        &request_headers,
+        |metadata| metadata.description,
+        "No description found",
+    )
+    .await
+}
+
+/// Respond with a single metadata field as plain text
+///
+/// Cacheable for as long as the metadata cache keeps it, with an `ETag`
+/// of the value so previews can revalidate.
+async fn metadata_response(
+    state: &AppState,
+    url_path: &str,
+    request_headers: &HeaderMap,
+    field: fn(PageMetadata) -> Option<String>,
+    missing: &str,
+) -> Response {
+    let result = process_metadata(state, url_path)
+        .await
+        .and_then(|(metadata, private)| {
+            field(metadata)
+                .map(|value| (value, private))
+                .ok_or_else(|| MdwnError::NoMetadata(missing.to_string()))
+        });
 
-            (StatusCode::OK, headers, markdown).into_response()
+    match result {
+        Ok((value, private)) => {
+            let mut headers = HeaderMap::new();
+            let scope = if private { "private" } else { "public" };
+            headers.insert(
+                header::CACHE_CONTROL,
+                format!("{}, max-age={}", scope, state.config.metadata_cache_ttl)
+                    .parse()
+                    .expect("valid header value"),
+            );
+            headers.insert(
+                header::ETAG,
+                format!("\"{}\"", cache::content_hash(&value))
+                    .parse()
+                    .expect("valid header value"),
+            );
+            if is_not_modified(request_headers, &headers[header::ETAG]) {
+                return (StatusCode::NOT_MODIFIED, headers).into_response();
+            }
+            headers.insert(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("text/plain; charset=utf-8"),
+            );
+            (StatusCode::OK, headers, value).into_response()
         }
         Err(e) => e.into_response(),
     }
 }
 
-/// Process a URL and return markdown content
-async fn process_url(state: &AppState, url_path: &str) -> Result<(String, ContentSource)> {
+/// Image proxy endpoint for images referenced by converted documents
+async fn image_handler(State(state): State<AppState>, Path(url_path): Path<String>) -> Response {
+    match process_image(&state, &url_path).await {
+        Ok((image, private)) => (
+            StatusCode::OK,
+            [
+                (header::CONTENT_TYPE, image.mime_type),
+                (header::CACHE_CONTROL, image_cache_control(private)),
+                (header::X_CONTENT_TYPE_OPTIONS, "nosniff".to_string()),
+            ],
+            image.body,
+        )
+            .into_response(),
+        Err(e) => e.into_response(),
+    }
+}
+
+/// `Cache-Control` of a proxied image
+fn image_cache_control(private: bool) -> String {
+    let scope = if private { "private" } else { "public" };
+    format!("{}, max-age=86400", scope)
+}
+
+/// Fetch (or serve from cache) a proxied image, and whether it was fetched
+/// with the operator's credentials and mustn't be shared
+async fn process_image(state: &AppState, url_path: &str) -> Result<(images::Image, bool)> {
+    let url = state.fetcher.parse_url(url_path)?;
+    let url_str = url.as_str();
+
+    if let Some(cached) = state.image_cache.get(url_str).await {
+        tracing::debug!("Image cache hit for {}", url_str);
+        return Ok((cached, false));
+    }
+
+    let budget = state.fetcher.budget();
+    let image = images::fetch_image(
+        &state.fetcher,
+        &budget,
+        &url,
+        state.config.image_proxy_max_bytes,
+    )
+    .await?;
+
+    let private = budget.credentialed();
+    if !private {
+        state.image_cache.set(url_str, image.clone()).await;
+    }
+
+    Ok((image, private))
+}
+
+/// Process a URL and return its link preview metadata, and whether it was
+/// fetched with credentials and is only for this client
+async fn process_metadata(state: &AppState, url_path: &str) -> Result<(PageMetadata, bool)> {
+    let url = state.fetcher.parse_url(url_path)?;
+    let url_str = url.as_str();
+
+    if let Some(cached) = state.metadata_cache.get(url_str).await {
+        tracing::debug!("Metadata cache hit for {}", url_str);
+        return Ok((cached, false));
+    }
+
+    let budget = state.fetcher.budget();
+    let response = state.fetcher.fetch(&url, &budget).await?;
+    let body = response.body_as_string();
+
+    let metadata = match categorize_content_type(response.mime_type()) {
+        ContentCategory::Markdown | ContentCategory::PlainText => parse_markdown_metadata(&body),
+        ContentCategory::Html => parse_html_metadata(&body),
+        ContentCategory::Json => parse_json_metadata(&body),
+        ContentCategory::Unsupported(mime) => {
+            return Err(MdwnError::UnsupportedType(mime));
+        }
+    };
+
+    let private = budget.credentialed();
+    if !private {
+        state.metadata_cache.set(url_str, metadata.clone()).await;
+    }
+
+    Ok((metadata, private))
+}
+
+/// Count a page request in the domain stats and today's usage report
+fn record_usage(state: &AppState, domain: &str, result: &Result<(CachedContent, bool)>) {
+    let outcome = result
+        .as_ref()
+        .ok()
+        .map(|(content, cache_hit)| (*cache_hit, content.markdown.len()));
+    record_request(state, domain, outcome);
+}
+
+/// Count a request in the domain stats and today's usage report: whether it
+/// was a cache hit and the markdown bytes served, or `None` if it failed
+pub(crate) fn record_request(state: &AppState, domain: &str, outcome: Option<(bool, usize)>) {
+    let daily = state.usage.as_ref().map(|usage| usage.today());
+    for stats in std::iter::once(&state.stats).chain(daily.as_ref()) {
+        match outcome {
+            Some((cache_hit, bytes)) => stats.record_success(domain, cache_hit, bytes),
+            None => stats.record_error(domain),
+        }
+    }
+}
+
+/// `process_url` for routes that fetch pages on a client's behalf besides the
+/// proxy route: a page that isn't cached waits for its origin's crawl slot
+/// first, and the request counts in the stats like a proxied one
+pub(crate) async fn process_url_throttled(
+    state: &AppState,
+    url_path: &str,
+    options: &ProxyOptions,
+    budget: &FetchBudget,
+) -> Result<(CachedContent, bool)> {
+    let url = state.fetcher.parse_url(url_path)?;
+    let cached = !options.refresh
+        && (state
+            .cache
+            .get(url.as_str(), &options.cache_variant(&[]))
+            .await)
+            .is_some();
+    if !cached {
+        state.crawl_throttle.wait(&state.fetcher, &url).await;
+    }
+
+    let result = process_url_in(state, url_path, options, &[], budget, None).await;
+    if let Some(domain) = url.host_str() {
+        record_usage(state, &domain.to_lowercase(), &result);
+    }
+    result
+}
+
+/// Process a URL and return markdown content, and whether it came from the cache
+async fn process_url(
+    state: &AppState,
+    url_path: &str,
+    options: &ProxyOptions,
+    langs: &[String],
+) -> Result<(CachedContent, bool)> {
+    process_url_in(
+        state,
+        url_path,
+        options,
+        langs,
+        &state.fetcher.budget(),
+        None,
+    )
+    .await
+}
+
+/// [`process_url`] charging upstream work to `budget`, which may be shared
+/// with other pages, and converting `fetched` if the page was already fetched
+pub(crate) async fn process_url_in(
+    state: &AppState,
+    url_path: &str,
+    options: &ProxyOptions,
+    langs: &[String],
+    budget: &FetchBudget,
+    fetched: Option<fetch::FetchResponse>,
+) -> Result<(CachedContent, bool)> {
     // Parse and validate URL
     let url = state.fetcher.parse_url(url_path)?;
     let url_str = url.as_str();
 
+    // Options that change the output key separate cache entries
+    let variant = options.cache_variant(langs);
+
     // Check cache
-    if let Some(cached) = state.cache.get(url_str).await {
-        tracing::debug!("Cache hit for {}", url_str);
-        return Ok((cached.markdown, cached.source));
+    if !options.refresh {
+        if let Some(mut cached) = cached_for_langs(state, url_str, options, langs).await {
+            tracing::debug!("Cache hit for {}", url_str);
+            cached.fetch = None;
+            // Keep the hash advertised in the response resolvable
+            if let Some(hash) = &cached.content_hash {
+                state.content_store.set(hash, &cached.markdown).await;
+            }
+            return Ok((cached, true));
+        }
+    }
+
+    // Concurrent requests for the same page share one fetch and conversion
+    let mut flight = state.cache.key(url_str, &variant);
+    if options.allow_error_pages {
+        flight.push_str(" allow_error_pages");
+    }
+    let work = || fetch_uncached(state, &url, &variant, options, langs, budget, fetched);
+    let span = tracing::debug_span!("page", url = url_str);
+    match state.flights.run(&flight, work).instrument(span).await {
+        (Ok((content, _)), true) => {
+            tracing::debug!("Shared an in-flight fetch of {}", url_str);
+            Ok((content, true))
+        }
+        (result, _) => result,
+    }
+}
+
+/// A cached page for the client's languages: their own entry, or else the
+/// one entry every language shares when the page comes in only one
+async fn cached_for_langs(
+    state: &AppState,
+    url_str: &str,
+    options: &ProxyOptions,
+    langs: &[String],
+) -> Option<CachedContent> {
+    let variant = options.cache_variant(langs);
+    if let Some(cached) = state.cache.get(url_str, &variant).await {
+        return Some(cached);
+    }
+    if langs.is_empty() {
+        return None;
+    }
+    let shared = options.cache_variant(&[]);
+    state
+        .cache
+        .get(url_str, &shared)
+        .await
+        .filter(|cached| !cached.language_dependent)
+}
+
+/// Fetch, convert and cache a page that isn't (freshly) cached
+async fn fetch_uncached(
+    state: &AppState,
+    url: &url::Url,
+    variant: &str,
+    options: &ProxyOptions,
+    langs: &[String],
+    budget: &FetchBudget,
+    fetched: Option<fetch::FetchResponse>,
+) -> Result<(CachedContent, bool)> {
+    let url_str = url.as_str();
+
+    // Pages in one language are cached once for every client language
+    let shared_variant = options.cache_variant(&[]);
+    let variant_for = |content: &CachedContent| {
+        if content.language_dependent {
+            variant
+        } else {
+            shared_variant.as_str()
+        }
+    };
+
+    // An expired copy the origin may still vouch for
+    let stale = if options.refresh || fetched.is_some() {
+        None
+    } else {
+        match state.cache.get_stale(url_str, variant).await {
+            Some(stale) => Some(stale),
+            None => state
+                .cache
+                .get_stale(url_str, &shared_variant)
+                .await
+                .filter(|stale| !stale.language_dependent),
+        }
+    };
+
+    // Fetch the URL, the budget bounding total upstream work for the request
+    let api_content = fetch_from_api(state, budget, url, options, langs).await?;
+    let (mut content, final_url, status) = match api_content {
+        Some(mut content) => {
+            content.fetch = Some(FetchOutcome {
+                status: 200,
+                category: Some("api"),
+            });
+            (content, url.clone(), 200)
+        }
+        None => {
+            let stale = stale.as_ref();
+            fetch_and_convert(state, budget, url, options, langs, stale, fetched).await?
+        }
+    };
+    content.final_url = Some(final_url.to_string());
+    // Pages behind the operator's credentials are only for whoever asked
+    if budget.credentialed() {
+        content.private = true;
+    }
+    if status == 304 {
+        tracing::debug!("Revalidated {}", url_str);
+        state
+            .cache
+            .set(url_str, variant_for(&content), content.clone())
+            .await;
+        if let Some(hash) = &content.content_hash {
+            state.content_store.set(hash, &content.markdown).await;
+        }
+        return Ok((content, true));
+    }
+    let error_page = status >= 400;
+
+    if options.embed_images {
+        // The images' changes don't show in the page's validators
+        content.etag = None;
+        content.last_modified = None;
+        content.markdown = images::embed_images(
+            &state.fetcher,
+            budget,
+            &content.markdown,
+            &final_url,
+            state.config.embed_image_max_bytes,
+        )
+        .await;
     }
 
-    // Fetch the URL
-    let response = state.fetcher.fetch(&url).await?;
+    if content.title.is_none() {
+        content.title = parse_markdown_metadata(&content.markdown).title;
+    }
+
+    // Trust the text over the markup, templates often declare a site-wide language
+    if let Some(language) = lang::detect_language(&content.markdown) {
+        content.language = Some(language.to_string());
+    }
+
+    // Normalize text before caching
+    if state.config.normalize_text {
+        content.markdown = convert::normalize_text(&content.markdown);
+    }
+
+    // Operator-defined cleanup rules
+    if !state.config.postprocess.is_empty() {
+        let host = url.host_str().unwrap_or_default();
+        content.markdown = state.config.postprocess.apply(host, &content.markdown);
+    }
+    if let Some(redaction) = options.redact {
+        content.markdown = state
+            .config
+            .postprocess
+            .redact(redaction, &content.markdown);
+    }
+
+    // Flag mirrors of pages already in the cache
+    content.url = Some(url_str.to_string());
+    content.simhash = convert::simhash(&content.markdown);
+    if let Some(simhash) = content.simhash {
+        content.duplicate_of = state.cache.find_near_duplicate(url_str, simhash).await;
+    }
+
+    // Address this exact version by hash, unless it was fetched with
+    // credentials and mustn't be shared
+    let hash = cache::content_hash(&content.markdown);
+    if !content.private {
+        state.content_store.set(&hash, &content.markdown).await;
+    }
+    content.content_hash = Some(hash);
+
+    // Cache the result, unless it's an error page the next request shouldn't get
+    if error_page {
+        content.upstream_status = Some(status);
+    } else if !content.soft_not_found && !content.private {
+        state
+            .cache
+            .set(url_str, variant_for(&content), content.clone())
+            .await;
+    }
+
+    Ok((content, false))
+}
+
+/// The configured conversion notice, unless the request turned it off with `?notice=0`
+pub(crate) fn conversion_notice(state: &AppState, notice: Option<bool>) -> Option<&str> {
+    match notice {
+        Some(false) => None,
+        _ => state.config.conversion_notice.as_deref(),
+    }
+}
+
+/// Render content from a site's API when the URL is one mdwn knows a better
+/// source for than the page itself
+async fn fetch_from_api(
+    state: &AppState,
+    budget: &FetchBudget,
+    url: &url::Url,
+    options: &ProxyOptions,
+    langs: &[String],
+) -> Result<Option<CachedContent>> {
+    let notice = conversion_notice(state, options.notice);
+
+    if let Ok(base) = url::Url::parse(&state.config.youtube_url) {
+        let video = youtube::fetch_video(&state.fetcher, budget, &base, url, langs).await?;
+        if let Some(video) = video {
+            let markdown = youtube::video_to_markdown(&video, notice);
+            let mut content = CachedContent::new(markdown, ContentSource::Api);
+            content.title = Some(video.title);
+            content.language = video.language.as_deref().map(lang::primary_subtag);
+            // The caption track follows the client's language
+            content.language_dependent = true;
+            return Ok(Some(content));
+        }
+    }
+
+    let Ok(api_base) = url::Url::parse(&state.config.github_api_url) else {
+        return Ok(None);
+    };
+    if github::issue_api_url(url, &api_base).is_some() {
+        let token = state.config.github_token.as_ref().map(Secret::expose);
+        let token = token.as_deref();
+        let issue = github::fetch_issue(&state.fetcher, budget, url, &api_base, token).await?;
+        let Some(issue) = issue else {
+            return Ok(None);
+        };
+        let mut content = CachedContent::new(
+            github::issue_to_markdown(&issue, notice),
+            ContentSource::Api,
+        );
+        content.title = Some(issue.title);
+        content.page_type = Some(PageType::Forum);
+        return Ok(Some(content));
+    }
+
+    let paste = if github::gist_api_url(url, &api_base).is_some() {
+        let token = state.config.github_token.as_ref().map(Secret::expose);
+        let token = token.as_deref();
+        github::fetch_gist(&state.fetcher, budget, url, &api_base, token)
+            .await
+            .map(|gist| (Some(gist.title), gist.files))
+    } else {
+        paste::fetch_paste(&state.fetcher, budget, url)
+            .await
+            .map(|file| (None, vec![file]))
+    };
+    if let Some((title, files)) = paste {
+        let markdown = paste::paste_to_markdown(&files, title.as_deref(), notice);
+        let mut content = CachedContent::new(markdown, ContentSource::Api);
+        content.title = title;
+        return Ok(Some(content));
+    }
+
+    let tree = if github::tree_api_url(url, &api_base).is_some() {
+        let token = state.config.github_token.as_ref().map(Secret::expose);
+        let token = token.as_deref();
+        let tree = github::fetch_tree(&state.fetcher, budget, url, &api_base, token).await?;
+        let Some(tree) = tree else {
+            return Ok(None);
+        };
+        Some(tree)
+    } else if repo::gitlab_tree_api_url(url).is_some() {
+        let Some(tree) = repo::fetch_gitlab_tree(&state.fetcher, budget, url).await else {
+            return Ok(None);
+        };
+        Some(tree)
+    } else {
+        None
+    };
+    if let Some(tree) = tree {
+        let mut content =
+            CachedContent::new(repo::tree_to_markdown(&tree, notice), ContentSource::Api);
+        content.title = Some(tree.title());
+        content.page_type = Some(PageType::Index);
+        return Ok(Some(content));
+    }
+
+    let max_posts = state.config.discourse_max_posts;
+    if max_posts == 0 {
+        return Ok(None);
+    }
+    let Some(topic) = discourse::fetch_topic(&state.fetcher, budget, url, max_posts).await else {
+        return Ok(None);
+    };
+
+    let mut markdown = forum::posts_to_markdown(&topic.posts, url, Some(&topic.title), notice);
+    if topic.omitted > 0 {
+        let posts = if topic.omitted == 1 { "post" } else { "posts" };
+        markdown.push_str(&format!("\n*{} more {} not shown*\n", topic.omitted, posts));
+    }
+    let mut content = CachedContent::new(markdown, ContentSource::Api);
+    content.title = Some(topic.title);
+    content.page_type = Some(PageType::Forum);
+    Ok(Some(content))
+}
+
+/// Fetch a URL and turn whatever it returns into markdown, with the final URL
+/// after redirects and the upstream status
+///
+/// With a `stale` copy the request is conditional, and a 304 returns that copy
+/// refreshed rather than converting anything. A response the caller already
+/// `fetched` is converted without fetching again.
+async fn fetch_and_convert(
+    state: &AppState,
+    budget: &FetchBudget,
+    url: &url::Url,
+    options: &ProxyOptions,
+    langs: &[String],
+    stale: Option<&CachedContent>,
+    fetched: Option<fetch::FetchResponse>,
+) -> Result<(CachedContent, url::Url, u16)> {
+    let response = match (fetched, stale) {
+        (Some(response), _) => response,
+        (None, Some(stale)) => {
+            let etag = stale.etag.as_deref();
+            let last_modified = stale.last_modified.as_deref();
+            let allow_error_pages = options.allow_error_pages;
+            state
+                .fetcher
+                .revalidate(url, budget, etag, last_modified, allow_error_pages)
+                .await?
+        }
+        (None, None) if options.allow_error_pages => {
+            state.fetcher.fetch_allowing_errors(url, budget).await?
+        }
+        (None, None) => state.fetcher.fetch(url, budget).await?,
+    };
+    if let (304, Some(stale)) = (response.status, stale) {
+        let mut content = stale.clone();
+        content.fetched_at = chrono::Utc::now();
+        content.max_age = response.max_age.or(stale.max_age);
+        content.redirects = response.redirects;
+        content.fetch = Some(FetchOutcome {
+            status: 304,
+            category: None,
+        });
+        return Ok((content, response.final_url, 304));
+    }
+
+    // Only HTML error pages are worth converting
+    let category = categorize_content_type(response.mime_type());
+    if response.status >= 400 && category != ContentCategory::Html {
+        return Err(fetch::status_error(response.status));
+    }
 
     // Process based on content type
-    let (markdown, source) = match categorize_content_type(response.mime_type()) {
+    let outcome = FetchOutcome {
+        status: response.status,
+        category: Some(category.name()),
+    };
+    // Whether the body came from this response alone, so its validators can
+    // revalidate it
+    let mut self_contained = true;
+    let mut content = match category {
         ContentCategory::Markdown | ContentCategory::PlainText => {
             // Pass through directly
-            (response.body_as_string(), ContentSource::Native)
+            CachedContent::new(response.body_as_string(), ContentSource::Native)
         }
 
         ContentCategory::Html => {
-            process_html(&state.fetcher, &response).await?
+            let content = process_html(state, budget, &response, options, langs).await?;
+            // Markdown alternates and prerendered pages are other responses
+            self_contained = content.source == ContentSource::Converted && !content.prerendered;
+            content
         }
 
         ContentCategory::Json => {
-            process_json(&state.fetcher, &response).await?
+            let pages = options.pagination_pages();
+            let (content, single) = process_json(state, budget, &response, pages).await?;
+            self_contained = single;
+            content
+        }
+
+        // Podcast feeds render as their episodes, other XML stays unsupported
+        ContentCategory::Unsupported(mime) if podcast::is_feed_type(&mime) => {
+            let feed = podcast::parse_feed(&response.body_as_string())
+                .ok_or(MdwnError::UnsupportedType(mime))?;
+            let notice = conversion_notice(state, options.notice);
+            let markdown = podcast::feed_to_markdown(&feed, notice);
+            let mut content = CachedContent::new(markdown, ContentSource::Converted);
+            content.title = Some(feed.title).filter(|title| !title.is_empty());
+            content.page_type = Some(PageType::Podcast);
+            content
         }
 
         ContentCategory::Unsupported(mime) => {
             return Err(MdwnError::UnsupportedType(mime));
         }
     };
-
-    // Cache the result
-    state.cache.set(url_str, markdown.clone(), source.clone()).await;
-
-    Ok((markdown, source))
+    content.max_age = response.max_age;
+    if self_contained {
+        content.etag = response.etag;
+        content.last_modified = response.last_modified;
+    }
+    content.redirects = response.redirects;
+    content.fetch = Some(outcome);
+    Ok((content, response.final_url, response.status))
 }
 
 /// Process HTML response
 async fn process_html(
-    fetcher: &Fetcher,
+    state: &AppState,
+    budget: &FetchBudget,
     response: &fetch::FetchResponse,
-) -> Result<(String, ContentSource)> {
+    options: &ProxyOptions,
+    langs: &[String],
+) -> Result<CachedContent> {
     let html = response.body_as_string();
+    let span = tracing::debug_span!("parse", html_bytes = html.len());
+    let (title, license, byline, page_type, alternate) = span.in_scope(|| {
+        let title = parse_html_metadata(&html).title;
+        let license = parse_html_license(&html, &response.final_url);
+        let byline = parse_html_byline(&html);
+        let page_type = if response.status >= 400 {
+            PageType::Error
+        } else {
+            convert::classify_page(&html, &response.final_url)
+        };
+        let alternate = parse_html_for_markdown_link(&html, &response.final_url, langs)?;
+        let has_alternate = matches!(alternate, HtmlParseResult::MarkdownLink(_));
+        tracing::debug!(
+            ?page_type,
+            has_title = title.is_some(),
+            has_alternate,
+            "Parsed page"
+        );
+        Ok::<_, MdwnError>((title, license, byline, page_type, alternate))
+    })?;
 
     // First, check for markdown link
-    match parse_html_for_markdown_link(&html, &response.final_url)? {
-        HtmlParseResult::MarkdownLink(md_url) => {
-            // Fetch the linked markdown
-            let md_response = fetcher.fetch(&md_url).await?;
-            Ok((md_response.body_as_string(), ContentSource::Native))
-        }
+    if let HtmlParseResult::MarkdownLink(md_url) = alternate {
+        let cross_origin = md_url.origin() != response.final_url.origin();
 
-        HtmlParseResult::NeedsConversion => {
-            // Check if HTML has meaningful content
-            if !convert::is_meaningful_html(&html) {
-                return Err(MdwnError::NoMarkdown(
-                    "Page appears to require JavaScript to render content".to_string(),
-                ));
+        if cross_origin && state.config.cross_origin_alternates == AlternatePolicy::Block {
+            tracing::info!("Ignoring cross-origin markdown alternate {}", md_url);
+        } else {
+            // Fetch the linked markdown, falling back to conversion if it isn't markdown
+            match fetch_markdown_alternate(&state.fetcher, budget, &md_url).await? {
+                Some(markdown) => {
+                    let mut content = CachedContent::new(markdown, ContentSource::Native);
+                    content.title = title;
+                    content.license = license;
+                    content.set_byline(byline);
+                    content.page_type = Some(page_type);
+                    content.language_dependent = parse::has_language_alternates(&html);
+                    if cross_origin && state.config.cross_origin_alternates == AlternatePolicy::Flag
+                    {
+                        content.cross_origin = Some(md_url.origin().ascii_serialization());
+                    }
+                    return Ok(content);
+                }
+                None => tracing::warn!("Markdown alternate {} is not markdown, converting", md_url),
             }
+        }
+    }
+
+    // Check if HTML has meaningful content
+    let mut html = html;
+    let mut quality = assess_quality(&html, &response.final_url);
+    let mut prerendered = false;
+    let min_score = state.config.min_content_score;
+
+    // Before prerendering: tiny "not found" templates look like JS shells too
+    let soft_not_found = convert::is_soft_not_found(&html, &quality);
+    if soft_not_found && !options.allow_error_pages {
+        log_decision(&response.final_url, &quality, min_score, "soft_not_found");
+        return Err(MdwnError::SoftNotFound);
+    }
+    let page_type = if soft_not_found {
+        PageType::Error
+    } else {
+        page_type
+    };
+
+    let notice = conversion_notice(state, options.notice);
 
-            // Convert HTML to markdown
-            let markdown = convert::html_to_markdown(&html, &response.final_url)?;
-            Ok((markdown, ContentSource::Converted))
+    // Readability mangles listings, and their link density reads as a JS
+    // shell; `force_convert` has readability try anyway
+    let links = convert::index_links(&html, &response.final_url);
+    if page_type != PageType::Error
+        && !options.force_convert
+        && convert::is_index_page(&links, &quality)
+    {
+        log_decision(&response.final_url, &quality, min_score, "index");
+        let markdown = convert::index_to_markdown(title.as_deref(), &links, notice);
+        let mut content = CachedContent::new(markdown, ContentSource::Converted);
+        content.quality = Some(quality.score);
+        content.title = title;
+        content.license = license;
+        content.page_type = Some(PageType::Index);
+        return Ok(content);
+    }
+
+    // Every post of a thread rather than readability's pick of the first
+    if page_type == PageType::Forum {
+        if let Some(markdown) =
+            forum::thread_to_markdown(&html, &response.final_url, title.as_deref(), notice)
+        {
+            log_decision(&response.final_url, &quality, min_score, "forum");
+            let mut content = CachedContent::new(markdown, ContentSource::Converted);
+            content.quality = Some(quality.score);
+            content.title = title;
+            content.license = license;
+            content.page_type = Some(page_type);
+            return Ok(content);
         }
     }
+
+    // Episode metadata and show notes rather than the player page around them
+    if page_type == PageType::Podcast {
+        if let Some(markdown) = podcast::episode_page_to_markdown(&html, notice) {
+            log_decision(&response.final_url, &quality, min_score, "podcast");
+            let mut content = CachedContent::new(markdown, ContentSource::Converted);
+            content.quality = Some(quality.score);
+            content.title = title;
+            content.license = license;
+            content.page_type = Some(page_type);
+            return Ok(content);
+        }
+    }
+
+    // Let a prerender service run the page's JavaScript if it looks like a shell
+    if !options.force_convert
+        && !quality.is_meaningful(min_score)
+        && state.fetcher.can_prerender()
+        && state.flags.enabled(Flag::Prerender, &response.final_url)
+    {
+        log_decision(&response.final_url, &quality, min_score, "prerender");
+        html = state
+            .fetcher
+            .prerender(&response.final_url, budget)
+            .await?
+            .body_as_string();
+        quality = assess_quality(&html, &response.final_url);
+        prerendered = true;
+    }
+
+    if !options.force_convert && !quality.is_meaningful(min_score) {
+        log_decision(&response.final_url, &quality, min_score, "js_shell");
+        return Err(MdwnError::NoMarkdown(
+            "Page appears to require JavaScript to render content".to_string(),
+        ));
+    }
+
+    // Convert HTML to markdown
+    let decision = if quality.is_meaningful(min_score) {
+        "convert"
+    } else {
+        "forced"
+    };
+    log_decision(&response.final_url, &quality, min_score, decision);
+    let markdown = if state
+        .flags
+        .enabled(Flag::SemanticEngine, &response.final_url)
+    {
+        let markdown = semantic::html_to_markdown(&html, &response.final_url)?;
+        convert::with_title_and_notice(markdown, title.as_deref().unwrap_or_default(), notice)
+    } else {
+        let start = Instant::now();
+        let markdown = convert::html_to_markdown_with_notice(&html, &response.final_url, notice)?;
+        shadow::sample(
+            state,
+            &response.final_url,
+            &html,
+            &markdown,
+            start.elapsed(),
+        );
+        markdown
+    };
+    let markdown = match byline.attribution() {
+        Some(attribution) => convert::with_attribution(markdown, &attribution),
+        None => markdown,
+    };
+    let mut content = CachedContent::new(markdown, ContentSource::Converted);
+    content.quality = Some(quality.score);
+    content.prerendered = prerendered;
+    content.title = title;
+    content.license = license;
+    content.set_byline(byline);
+    content.anchors = anchors::heading_anchors(&html);
+    if options.sourcemap {
+        content.sources = sourcemap::source_elements(&html);
+    }
+    content.soft_not_found = soft_not_found;
+    content.page_type = Some(page_type);
+    content.language = lang::declared_language(&html);
+    Ok(content)
 }
 
-/// Process JSON response
-async fn process_json(
+/// Assess HTML content quality, logging the details
+fn assess_quality(html: &str, url: &url::Url) -> convert::ContentQuality {
+    let quality = convert::assess_html(html);
+    tracing::debug!(
+        "Content quality for {}: score={:.2} words={} link_density={:.2}",
+        url,
+        quality.score,
+        quality.word_count,
+        quality.link_density
+    );
+    quality
+}
+
+/// Log what the readability heuristics made of a page, and why
+///
+/// `decision` is the path taken: `convert`, `forced` (converted despite a low
+/// score), `index`, `forum`, `podcast`, `prerender`, `js_shell` or
+/// `soft_not_found`. Enabled on its own with `RUST_LOG=mdwnio::readability=debug`.
+fn log_decision(url: &url::Url, quality: &convert::ContentQuality, min_score: f32, decision: &str) {
+    tracing::debug!(
+        target: "mdwnio::readability",
+        %url,
+        decision,
+        score = quality.score,
+        min_score,
+        words = quality.word_count,
+        prose_words = quality.prose_words,
+        link_density = quality.link_density,
+        "Readability decision"
+    );
+}
+
+/// Fetch a linked markdown URL, returning None if the response isn't markdown
+async fn fetch_markdown_alternate(
     fetcher: &Fetcher,
+    budget: &FetchBudget,
+    md_url: &url::Url,
+) -> Result<Option<String>> {
+    let md_response = fetcher.fetch(md_url, budget).await?;
+
+    if !is_markdown_alternate(md_response.mime_type(), &md_response.body) {
+        return Ok(None);
+    }
+
+    Ok(Some(md_response.body_as_string()))
+}
+
+/// Process JSON response, optionally following pagination links
+///
+/// Also returns whether the markdown came from `response` alone, rather than
+/// a linked markdown URL or further pages.
+async fn process_json(
+    state: &AppState,
+    budget: &FetchBudget,
     response: &fetch::FetchResponse,
-) -> Result<(String, ContentSource)> {
+    follow_pagination: usize,
+) -> Result<(CachedContent, bool)> {
     let json = response.body_as_string();
+    let (mut content, mut self_contained) =
+        process_json_page(state, budget, &json, &response.final_url).await?;
+
+    let mut seen = HashSet::from([response.final_url.clone()]);
+    let mut next = parse_json_next_link(&json, &response.final_url);
 
-    match parse_json_for_markdown(&json, &response.final_url)? {
+    for _ in 0..follow_pagination {
+        // Stop at the last page or if the links loop back
+        let next_url = match next.take() {
+            Some(url) if seen.insert(url.clone()) => url,
+            _ => break,
+        };
+
+        let page = state.fetcher.fetch(&next_url, budget).await?;
+        if categorize_content_type(page.mime_type()) != ContentCategory::Json {
+            tracing::warn!("Pagination link {} is not JSON, stopping", next_url);
+            break;
+        }
+
+        let page_json = page.body_as_string();
+        let (page_content, _) =
+            process_json_page(state, budget, &page_json, &page.final_url).await?;
+
+        content.markdown.push_str("\n\n---\n\n");
+        content.markdown.push_str(&page_content.markdown);
+        self_contained = false;
+        next = parse_json_next_link(&page_json, &page.final_url);
+    }
+
+    Ok((content, self_contained))
+}
+
+/// Extract markdown from a single JSON document, and whether it was inline
+async fn process_json_page(
+    state: &AppState,
+    budget: &FetchBudget,
+    json: &str,
+    base_url: &url::Url,
+) -> Result<(CachedContent, bool)> {
+    match parse_json_for_markdown(json, base_url)? {
         JsonParseResult::MarkdownUrl(md_url) => {
             // Fetch the linked markdown
-            let md_response = fetcher.fetch(&md_url).await?;
-            Ok((md_response.body_as_string(), ContentSource::Native))
+            match fetch_markdown_alternate(&state.fetcher, budget, &md_url).await? {
+                Some(markdown) => Ok((CachedContent::new(markdown, ContentSource::Native), false)),
+                None => Err(MdwnError::NoMarkdown(format!(
+                    "Linked URL {} did not return markdown",
+                    md_url
+                ))),
+            }
         }
 
         JsonParseResult::MarkdownContent(content) => {
             // Return inline content directly
-            Ok((content, ContentSource::Native))
+            Ok((CachedContent::new(content, ContentSource::Native), true))
         }
 
-        JsonParseResult::NotFound => {
-            Err(MdwnError::NoMarkdown(
-                "JSON response has no 'mdwn' or 'markdown' field".to_string(),
-            ))
-        }
+        JsonParseResult::NotFound => Err(MdwnError::NoMarkdown(
+            "JSON response has no 'mdwn' or 'markdown' field".to_string(),
+        )),
     }
 }
@@ -1,8 +1,65 @@
+use crate::convert::escape_markdown;
 use crate::error::{MdwnError, Result};
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use quick_xml::events::Event;
+use quick_xml::Reader;
 use scraper::{Html, Selector};
+use serde::Serialize;
 use serde_json::Value;
+use std::time::Duration;
 use url::Url;
 
+/// Validate and parse a target URL from the request path, assuming https if no scheme is given
+pub fn parse_request_url(url_str: &str) -> Result<Url> {
+    // First, try to parse as-is to detect non-http schemes
+    if let Ok(url) = Url::parse(url_str) {
+        // Check scheme before doing anything else
+        match url.scheme() {
+            "http" | "https" => {
+                // Continue with validation below
+            }
+            scheme => {
+                return Err(MdwnError::InvalidUrl(format!(
+                    "Scheme '{}' not allowed, only http/https",
+                    scheme
+                )))
+            }
+        }
+
+        // Reject URLs with credentials
+        if url.username() != "" || url.password().is_some() {
+            return Err(MdwnError::InvalidUrl(
+                "URLs with credentials are not allowed".to_string(),
+            ));
+        }
+
+        // Validate host exists
+        if url.host_str().is_none() {
+            return Err(MdwnError::InvalidUrl("URL must have a host".to_string()));
+        }
+
+        return Ok(url);
+    }
+
+    // If parsing failed, try prepending https://
+    let url_with_scheme = format!("https://{}", url_str);
+    let url = Url::parse(&url_with_scheme).map_err(|e| MdwnError::InvalidUrl(e.to_string()))?;
+
+    // Reject URLs with credentials
+    if url.username() != "" || url.password().is_some() {
+        return Err(MdwnError::InvalidUrl(
+            "URLs with credentials are not allowed".to_string(),
+        ));
+    }
+
+    // Validate host exists
+    if url.host_str().is_none() {
+        return Err(MdwnError::InvalidUrl("URL must have a host".to_string()));
+    }
+
+    Ok(url)
+}
+
 /// Result of parsing HTML for markdown links
 pub enum HtmlParseResult {
     /// Found a markdown link in the HTML
@@ -12,7 +69,15 @@ pub enum HtmlParseResult {
 }
 
 /// Parse HTML to find <link rel="alternate" type="text/markdown"> tag
-pub fn parse_html_for_markdown_link(html: &str, base_url: &Url) -> Result<HtmlParseResult> {
+///
+/// When several alternates are declared, the one whose `hreflang` best matches
+/// `preferred_langs` (in order of preference) wins. Without a match, an alternate
+/// without `hreflang` (or `x-default`) is preferred, then document order.
+pub fn parse_html_for_markdown_link(
+    html: &str,
+    base_url: &Url,
+    preferred_langs: &[String],
+) -> Result<HtmlParseResult> {
     let document = Html::parse_document(html);
 
     // Check for <base> tag first
@@ -24,10 +89,35 @@ pub fn parse_html_for_markdown_link(html: &str, base_url: &Url) -> Result<HtmlPa
         .and_then(|href| base_url.join(href).ok())
         .unwrap_or_else(|| base_url.clone());
 
-    // Look for markdown link
+    let candidates = markdown_alternates(&document);
+    let href = match select_alternate(&candidates, preferred_langs) {
+        Some(href) => href,
+        // No markdown link found
+        None => return Ok(HtmlParseResult::NeedsConversion),
+    };
+
+    // Resolve relative URL against base
+    let markdown_url = effective_base
+        .join(href)
+        .map_err(|e| MdwnError::ParseError(format!("Invalid markdown link URL: {}", e)))?;
+
+    Ok(HtmlParseResult::MarkdownLink(markdown_url))
+}
+
+/// Whether a page's markdown alternates differ in `hreflang`, so the
+/// reader's language decides which one is served
+pub fn has_language_alternates(html: &str) -> bool {
+    let document = Html::parse_document(html);
+    let candidates = markdown_alternates(&document);
+    candidates.iter().any(|(_, lang)| *lang != candidates[0].1)
+}
+
+/// Href and lowercase `hreflang` of each markdown alternate, in document order
+fn markdown_alternates(document: &Html) -> Vec<(&str, Option<String>)> {
     // Match: <link rel="alternate" type="text/markdown"> or <link rel="alternate" type="text/x-markdown">
     let link_selector = Selector::parse("link[rel='alternate']").expect("valid CSS selector");
 
+    let mut candidates = Vec::new();
     for link in document.select(&link_selector) {
         let link_type = link.value().attr("type").unwrap_or("");
 
@@ -35,18 +125,98 @@ pub fn parse_html_for_markdown_link(html: &str, base_url: &Url) -> Result<HtmlPa
         let link_type_lower = link_type.to_lowercase();
         if link_type_lower == "text/markdown" || link_type_lower == "text/x-markdown" {
             if let Some(href) = link.value().attr("href") {
-                // Resolve relative URL against base
-                let markdown_url = effective_base
-                    .join(href)
-                    .map_err(|e| MdwnError::ParseError(format!("Invalid markdown link URL: {}", e)))?;
-
-                return Ok(HtmlParseResult::MarkdownLink(markdown_url));
+                let hreflang = link
+                    .value()
+                    .attr("hreflang")
+                    .map(|l| l.trim().to_lowercase());
+                candidates.push((href, hreflang));
             }
         }
     }
+    candidates
+}
+
+/// Pick the alternate href best matching the preferred languages
+fn select_alternate<'a>(
+    candidates: &[(&'a str, Option<String>)],
+    preferred_langs: &[String],
+) -> Option<&'a str> {
+    for pref in preferred_langs {
+        // Exact tag match first, then primary subtag ("en" vs "en-us")
+        let exact = candidates
+            .iter()
+            .find(|(_, lang)| lang.as_deref() == Some(pref.as_str()));
+        let primary = || {
+            candidates.iter().find(|(_, lang)| {
+                lang.as_deref()
+                    .is_some_and(|lang| primary_subtag(lang) == primary_subtag(pref))
+            })
+        };
+
+        if let Some((href, _)) = exact.or_else(primary) {
+            return Some(href);
+        }
+    }
+
+    candidates
+        .iter()
+        .find(|(_, lang)| lang.as_deref().is_none_or(|lang| lang == "x-default"))
+        .or_else(|| candidates.first())
+        .map(|(href, _)| *href)
+}
+
+/// Primary language subtag of a language tag ("en-us" -> "en")
+fn primary_subtag(tag: &str) -> &str {
+    tag.split('-').next().unwrap_or(tag)
+}
+
+/// Parse an Accept-Language header into lowercase tags ordered by preference
+///
+/// Wildcards and `q=0` entries are dropped; at most 5 tags are kept.
+pub fn parse_accept_language(header: &str) -> Vec<String> {
+    let mut langs: Vec<(String, f32)> = header
+        .split(',')
+        .filter_map(|part| {
+            let mut pieces = part.split(';');
+            let tag = pieces.next()?.trim().to_lowercase();
+            let quality = pieces
+                .find_map(|p| p.trim().strip_prefix("q="))
+                .and_then(|q| q.trim().parse().ok())
+                .unwrap_or(1.0);
+
+            (!tag.is_empty() && tag != "*" && quality > 0.0).then_some((tag, quality))
+        })
+        .collect();
+
+    // Stable sort keeps header order among equal qualities
+    langs.sort_by(|a, b| b.1.total_cmp(&a.1));
+    langs.into_iter().take(5).map(|(tag, _)| tag).collect()
+}
+
+/// Policy for markdown alternates hosted on a different origin than the page
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AlternatePolicy {
+    /// Follow the alternate silently
+    #[default]
+    Follow,
+    /// Ignore the alternate and convert the page instead
+    Block,
+    /// Follow the alternate but report its origin in a response header
+    Flag,
+}
 
-    // No markdown link found
-    Ok(HtmlParseResult::NeedsConversion)
+impl std::str::FromStr for AlternatePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "follow" => Ok(AlternatePolicy::Follow),
+            "block" => Ok(AlternatePolicy::Block),
+            "flag" => Ok(AlternatePolicy::Flag),
+            other => Err(format!("unknown alternate policy '{}'", other)),
+        }
+    }
 }
 
 /// Result of parsing JSON for markdown content
@@ -61,8 +231,8 @@ pub enum JsonParseResult {
 
 /// Parse JSON to find mdwn or markdown field (top-level only)
 pub fn parse_json_for_markdown(json_str: &str, base_url: &Url) -> Result<JsonParseResult> {
-    let value: Value =
-        serde_json::from_str(json_str).map_err(|e| MdwnError::ParseError(format!("Invalid JSON: {}", e)))?;
+    let value: Value = serde_json::from_str(json_str)
+        .map_err(|e| MdwnError::ParseError(format!("Invalid JSON: {}", e)))?;
 
     // Only check top-level object
     let obj = match &value {
@@ -78,8 +248,9 @@ pub fn parse_json_for_markdown(json_str: &str, base_url: &Url) -> Result<JsonPar
             // Check if it's a URL or inline content
             if s.starts_with("http://") || s.starts_with("https://") {
                 // Absolute URL
-                let url = Url::parse(s)
-                    .map_err(|e| MdwnError::ParseError(format!("Invalid URL in mdwn field: {}", e)))?;
+                let url = Url::parse(s).map_err(|e| {
+                    MdwnError::ParseError(format!("Invalid URL in mdwn field: {}", e))
+                })?;
                 Ok(JsonParseResult::MarkdownUrl(url))
             } else if s.starts_with('/') {
                 // Relative URL
@@ -96,6 +267,372 @@ pub fn parse_json_for_markdown(json_str: &str, base_url: &Url) -> Result<JsonPar
     }
 }
 
+/// Find a pagination `next` link in a JSON document
+/// - HAL: `{"_links": {"next": {"href": "..."}}}`
+/// - JSON:API: `{"links": {"next": "..."}}` or `{"links": {"next": {"href": "..."}}}`
+pub fn parse_json_next_link(json_str: &str, base_url: &Url) -> Option<Url> {
+    let value: Value = serde_json::from_str(json_str).ok()?;
+
+    let next = ["_links", "links"]
+        .iter()
+        .find_map(|key| value.get(key)?.get("next"))?;
+
+    let href = match next {
+        Value::String(href) => href.as_str(),
+        Value::Object(link) => link.get("href")?.as_str()?,
+        _ => return None,
+    };
+
+    base_url.join(href).ok()
+}
+
+/// Link preview metadata extracted from a page
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PageMetadata {
+    pub title: Option<String>,
+    pub description: Option<String>,
+}
+
+/// Maximum length of a description derived from body text
+const MAX_DESCRIPTION_CHARS: usize = 300;
+
+/// Extract title and description from HTML metadata, falling back to page content
+/// - Title: og:title, twitter:title, <title>, first <h1>
+/// - Description: meta description, og:description, twitter:description, first <p>
+pub fn parse_html_metadata(html: &str) -> PageMetadata {
+    let document = Html::parse_document(html);
+
+    let first_match = |selectors: &[&str], attr: Option<&str>| {
+        selectors.iter().find_map(|selector| {
+            let selector = Selector::parse(selector).expect("valid CSS selector");
+            document.select(&selector).find_map(|el| {
+                let text = match attr {
+                    Some(attr) => el.value().attr(attr).unwrap_or("").to_string(),
+                    None => el.text().collect::<String>(),
+                };
+                let text = collapse_whitespace(&text);
+                (!text.is_empty()).then_some(text)
+            })
+        })
+    };
+
+    let title = first_match(
+        &[
+            "meta[property='og:title'][content]",
+            "meta[name='twitter:title'][content]",
+        ],
+        Some("content"),
+    )
+    .or_else(|| first_match(&["title", "h1"], None));
+
+    let description = first_match(
+        &[
+            "meta[name='description'][content]",
+            "meta[property='og:description'][content]",
+            "meta[name='twitter:description'][content]",
+        ],
+        Some("content"),
+    )
+    .or_else(|| first_match(&["p"], None).map(|p| truncate_chars(&p, MAX_DESCRIPTION_CHARS)));
+
+    PageMetadata { title, description }
+}
+
+/// License a page declares: a `rel="license"` link or a license/rights meta tag
+pub fn parse_html_license(html: &str, base_url: &Url) -> Option<String> {
+    let document = Html::parse_document(html);
+
+    let link = Selector::parse("link[rel~='license'][href], a[rel~='license'][href]")
+        .expect("valid CSS selector");
+    if let Some(href) = document
+        .select(&link)
+        .find_map(|el| el.value().attr("href"))
+    {
+        if let Ok(url) = base_url.join(href.trim()) {
+            return Some(url.to_string());
+        }
+    }
+
+    let meta = Selector::parse(
+        "meta[name='license'][content], meta[name='dcterms.license'][content], \
+         meta[name='dc.rights'][content], meta[property='og:license'][content]",
+    )
+    .expect("valid CSS selector");
+    document
+        .select(&meta)
+        .filter_map(|el| el.value().attr("content"))
+        .map(collapse_whitespace)
+        .find(|license| !license.is_empty())
+}
+
+/// Who wrote a page and when, from its metadata
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Byline {
+    pub author: Option<String>,
+    pub published: Option<DateTime<Utc>>,
+    pub modified: Option<DateTime<Utc>>,
+}
+
+/// Longest text taken as an author's name, to skip whole bios marked up as authors
+const MAX_AUTHOR_CHARS: usize = 120;
+
+impl Byline {
+    /// Line crediting the author and dates, e.g. `*By Jane Doe · Published
+    /// 2024-03-01 · Updated 2024-03-05*`; `None` if nothing is known
+    pub fn attribution(&self) -> Option<String> {
+        let day = |date: &DateTime<Utc>| date.format("%Y-%m-%d").to_string();
+        let mut parts = Vec::new();
+        if let Some(author) = &self.author {
+            parts.push(format!("By {}", escape_markdown(author)));
+        }
+        if let Some(published) = &self.published {
+            parts.push(format!("Published {}", day(published)));
+        }
+        // Only worth a mention if it's a different day
+        if let Some(modified) = &self.modified {
+            if self
+                .published
+                .as_ref()
+                .is_none_or(|p| day(p) != day(modified))
+            {
+                parts.push(format!("Updated {}", day(modified)));
+            }
+        }
+        (!parts.is_empty()).then(|| format!("*{}*", parts.join(" · ")))
+    }
+}
+
+/// Extract author and publication dates from a page
+/// - Author: JSON-LD `author`, author meta tags, `rel="author"` or `itemprop="author"` text
+/// - Published: JSON-LD `datePublished`, published-time meta tags, `<time>`
+///   elements marked as the publication date
+/// - Modified: JSON-LD `dateModified`, modified-time meta tags
+pub fn parse_html_byline(html: &str) -> Byline {
+    let document = Html::parse_document(html);
+
+    let mut json_ld = Byline::default();
+    let scripts =
+        Selector::parse("script[type='application/ld+json']").expect("valid CSS selector");
+    for script in document.select(&scripts) {
+        if let Ok(value) = serde_json::from_str(&script.text().collect::<String>()) {
+            collect_json_ld_byline(&value, &mut json_ld);
+        }
+    }
+
+    let first_author = |selectors: &[&str]| {
+        selectors.iter().find_map(|selector| {
+            let selector = Selector::parse(selector).expect("valid CSS selector");
+            document
+                .select(&selector)
+                .find_map(|el| match el.value().attr("content") {
+                    Some(content) => author_name(content),
+                    None => author_name(&el.text().collect::<String>()),
+                })
+        })
+    };
+    let first_date = |selectors: &[&str]| {
+        selectors.iter().find_map(|selector| {
+            let selector = Selector::parse(selector).expect("valid CSS selector");
+            document
+                .select(&selector)
+                .filter_map(|el| el.value().attr("content").or(el.value().attr("datetime")))
+                .find_map(|date| parse_w3c_datetime(date.trim()))
+        })
+    };
+
+    let author = json_ld.author.or_else(|| {
+        first_author(&[
+            "meta[name='author'][content]",
+            "meta[property='article:author'][content]",
+            "[rel~='author']",
+            "[itemprop='author']",
+        ])
+    });
+    let published = json_ld.published.or_else(|| {
+        first_date(&[
+            "meta[property='article:published_time'][content]",
+            "meta[itemprop='datePublished'][content]",
+            "meta[name='date'][content]",
+            "meta[name='dc.date'][content]",
+            "time[itemprop='datePublished'][datetime]",
+            "time[pubdate][datetime]",
+        ])
+    });
+    let modified = json_ld.modified.or_else(|| {
+        first_date(&[
+            "meta[property='article:modified_time'][content]",
+            "meta[itemprop='dateModified'][content]",
+            "meta[property='og:updated_time'][content]",
+            "time[itemprop='dateModified'][datetime]",
+        ])
+    });
+
+    Byline {
+        author,
+        published,
+        modified,
+    }
+}
+
+/// Fill in whatever `byline` is missing from the first JSON-LD objects with
+/// an `author`, `datePublished` or `dateModified`, including inside `@graph`
+fn collect_json_ld_byline(value: &Value, byline: &mut Byline) {
+    match value {
+        Value::Array(items) => {
+            for item in items {
+                collect_json_ld_byline(item, byline);
+            }
+        }
+        Value::Object(object) => {
+            if byline.author.is_none() {
+                byline.author = object.get("author").and_then(json_ld_author);
+            }
+            let date = |key: &str| parse_w3c_datetime(object.get(key)?.as_str()?.trim());
+            byline.published = byline.published.or_else(|| date("datePublished"));
+            byline.modified = byline.modified.or_else(|| date("dateModified"));
+            if let Some(graph) = object.get("@graph") {
+                collect_json_ld_byline(graph, byline);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Names of a JSON-LD `author`: a string, a person or organization, or a list of them
+fn json_ld_author(value: &Value) -> Option<String> {
+    match value {
+        Value::String(name) => author_name(name),
+        Value::Object(object) => object.get("name")?.as_str().and_then(author_name),
+        Value::Array(items) => {
+            let names: Vec<_> = items.iter().filter_map(json_ld_author).collect();
+            (!names.is_empty()).then(|| names.join(", "))
+        }
+        _ => None,
+    }
+}
+
+/// An author's name, if the text looks like one rather than a profile URL or a bio
+fn author_name(text: &str) -> Option<String> {
+    let name = collapse_whitespace(text);
+    let name = name
+        .strip_prefix("By ")
+        .or(name.strip_prefix("by "))
+        .unwrap_or(&name);
+    let plausible =
+        !name.is_empty() && !name.contains("://") && name.chars().count() <= MAX_AUTHOR_CHARS;
+    plausible.then(|| name.to_string())
+}
+
+/// Extract title (first H1) and description (first paragraph) from markdown
+pub fn parse_markdown_metadata(markdown: &str) -> PageMetadata {
+    let mut title = None;
+    let mut paragraph = Vec::new();
+
+    for line in markdown.lines() {
+        let trimmed = line.trim();
+
+        if let Some(heading) = trimmed.strip_prefix("# ") {
+            if title.is_none() {
+                title = Some(collapse_whitespace(heading));
+            }
+        } else if trimmed.is_empty() || trimmed.starts_with('#') || trimmed.starts_with("<!--") {
+            // A blank line or heading ends the first paragraph
+            if !paragraph.is_empty() {
+                break;
+            }
+        } else {
+            paragraph.push(trimmed);
+        }
+    }
+
+    let description = (!paragraph.is_empty()).then(|| {
+        truncate_chars(
+            &collapse_whitespace(&paragraph.join(" ")),
+            MAX_DESCRIPTION_CHARS,
+        )
+    });
+
+    PageMetadata { title, description }
+}
+
+/// Extract top-level `title` and `description` string fields from JSON
+pub fn parse_json_metadata(json_str: &str) -> PageMetadata {
+    let value: Value = match serde_json::from_str(json_str) {
+        Ok(value) => value,
+        Err(_) => return PageMetadata::default(),
+    };
+
+    let field = |name: &str| {
+        value
+            .get(name)
+            .and_then(Value::as_str)
+            .map(collapse_whitespace)
+            .filter(|s| !s.is_empty())
+    };
+
+    PageMetadata {
+        title: field("title"),
+        description: field("description"),
+    }
+}
+
+/// Collapse runs of whitespace into single spaces and trim
+fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Truncate to a maximum number of characters, appending an ellipsis if cut
+fn truncate_chars(text: &str, max: usize) -> String {
+    match text.char_indices().nth(max) {
+        Some((idx, _)) => format!("{}…", text[..idx].trim_end()),
+        None => text.to_string(),
+    }
+}
+
+/// Check if a content type tells us nothing about the actual format
+pub fn is_generic_content_type(content_type: Option<&str>) -> bool {
+    match content_type {
+        None => true,
+        Some(ct) => {
+            let mime = ct.split(';').next().unwrap_or(ct).trim().to_lowercase();
+            matches!(
+                mime.as_str(),
+                "" | "application/octet-stream" | "binary/octet-stream" | "application/unknown"
+            )
+        }
+    }
+}
+
+/// Check that a fetched markdown alternate really is markdown or plain text
+///
+/// Declared markdown/plain types are trusted. Bodies served without a useful
+/// content type are sniffed: they must be NUL-free UTF-8 that doesn't look like
+/// an HTML or XML document.
+pub fn is_markdown_alternate(mime_type: Option<&str>, body: &[u8]) -> bool {
+    match categorize_content_type(mime_type) {
+        ContentCategory::Markdown | ContentCategory::PlainText => true,
+        ContentCategory::Html | ContentCategory::Json => false,
+        ContentCategory::Unsupported(_) => {
+            if !is_generic_content_type(mime_type) {
+                return false;
+            }
+
+            let text = match std::str::from_utf8(body) {
+                Ok(text) => text,
+                Err(_) => return false,
+            };
+            if text.contains('\0') {
+                return false;
+            }
+
+            let start = text.trim_start().to_lowercase();
+            !["<!doctype", "<html", "<?xml"]
+                .iter()
+                .any(|prefix| start.starts_with(prefix))
+        }
+    }
+}
+
 /// Determine the content type category from MIME type
 #[derive(Debug, PartialEq)]
 pub enum ContentCategory {
@@ -106,6 +643,19 @@ pub enum ContentCategory {
     Unsupported(String),
 }
 
+impl ContentCategory {
+    /// `markdown`, `plain_text`, `html`, `json` or `unsupported`
+    pub fn name(&self) -> &'static str {
+        match self {
+            ContentCategory::Markdown => "markdown",
+            ContentCategory::PlainText => "plain_text",
+            ContentCategory::Html => "html",
+            ContentCategory::Json => "json",
+            ContentCategory::Unsupported(_) => "unsupported",
+        }
+    }
+}
+
 /// Categorize a MIME type
 pub fn categorize_content_type(mime_type: Option<&str>) -> ContentCategory {
     match mime_type {
@@ -127,9 +677,190 @@ pub fn categorize_content_type(mime_type: Option<&str>) -> ContentCategory {
     }
 }
 
+/// A `<url>` or `<sitemap>` entry of a sitemap
+#[derive(Debug, PartialEq)]
+pub struct SitemapEntry {
+    pub loc: Url,
+    /// When the page last changed, if the sitemap says
+    pub lastmod: Option<DateTime<Utc>>,
+}
+
+/// Parsed sitemap document
+#[derive(Debug, PartialEq)]
+pub enum Sitemap {
+    /// `<urlset>` listing page URLs
+    Urls(Vec<SitemapEntry>),
+    /// `<sitemapindex>` listing further sitemaps
+    Index(Vec<SitemapEntry>),
+}
+
+/// Sitemap element whose text is being read
+#[derive(Clone, Copy, PartialEq)]
+enum SitemapField {
+    Loc,
+    Lastmod,
+}
+
+/// Parse a sitemap or sitemap index (sitemaps.org protocol)
+///
+/// Relative `<loc>` values are resolved against `base_url`; entries with an
+/// unparseable `<loc>` are skipped, and an unparseable `<lastmod>` is ignored.
+pub fn parse_sitemap(xml: &str, base_url: &Url) -> Result<Sitemap> {
+    let mut reader = Reader::from_str(xml);
+    reader.config_mut().trim_text(true);
+
+    let mut is_index = None;
+    let mut field = None;
+    let mut loc = String::new();
+    let mut lastmod = String::new();
+    let mut entries = Vec::new();
+
+    loop {
+        let event = reader
+            .read_event()
+            .map_err(|e| MdwnError::ParseError(format!("Invalid sitemap XML: {}", e)))?;
+        let text = match event {
+            Event::Start(e) => {
+                match e.local_name().as_ref() {
+                    b"urlset" => is_index = is_index.or(Some(false)),
+                    b"sitemapindex" => is_index = is_index.or(Some(true)),
+                    b"url" | b"sitemap" => {
+                        loc.clear();
+                        lastmod.clear();
+                    }
+                    b"loc" => field = Some(SitemapField::Loc),
+                    b"lastmod" => field = Some(SitemapField::Lastmod),
+                    _ => {}
+                }
+                continue;
+            }
+            Event::End(e) => {
+                match e.local_name().as_ref() {
+                    b"url" | b"sitemap" => {
+                        if let Ok(url) = base_url.join(loc.trim()) {
+                            entries.push(SitemapEntry {
+                                loc: url,
+                                lastmod: parse_w3c_datetime(lastmod.trim()),
+                            });
+                        }
+                    }
+                    b"loc" | b"lastmod" => field = None,
+                    _ => {}
+                }
+                continue;
+            }
+            Event::Text(text) if field.is_some() => text
+                .unescape()
+                .map_err(|e| MdwnError::ParseError(format!("Invalid sitemap XML: {}", e)))?
+                .into_owned(),
+            Event::CData(data) if field.is_some() => String::from_utf8_lossy(&data).into_owned(),
+            Event::Eof => break,
+            _ => continue,
+        };
+
+        match field {
+            Some(SitemapField::Loc) => loc.push_str(&text),
+            Some(SitemapField::Lastmod) => lastmod.push_str(&text),
+            None => {}
+        }
+    }
+
+    match is_index {
+        Some(true) => Ok(Sitemap::Index(entries)),
+        Some(false) => Ok(Sitemap::Urls(entries)),
+        None => Err(MdwnError::ParseError(
+            "Not a sitemap: no <urlset> or <sitemapindex>".to_string(),
+        )),
+    }
+}
+
+/// Parse a W3C datetime as used by sitemap `<lastmod>`
+///
+/// Accepts full timestamps with a timezone, minute precision timestamps and
+/// plain dates (taken as midnight UTC).
+fn parse_w3c_datetime(s: &str) -> Option<DateTime<Utc>> {
+    if let Ok(datetime) = DateTime::parse_from_rfc3339(s) {
+        return Some(datetime.with_timezone(&Utc));
+    }
+    if let Ok(datetime) = DateTime::parse_from_str(s, "%Y-%m-%dT%H:%M%:z") {
+        return Some(datetime.with_timezone(&Utc));
+    }
+    if let Some(minutes) = s.strip_suffix('Z') {
+        if let Ok(datetime) = NaiveDateTime::parse_from_str(minutes, "%Y-%m-%dT%H:%M") {
+            return Some(datetime.and_utc());
+        }
+    }
+    NaiveDate::parse_from_str(s, "%Y-%m-%d")
+        .ok()
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .map(|datetime| datetime.and_utc())
+}
+
+/// `Crawl-delay` that a robots.txt asks of `user_agent`
+///
+/// A group naming the agent's product token (e.g. `mdwn.io` from
+/// `mdwn.io/1.0 (+https://mdwn.io)`) wins over the `*` group. Tokens match
+/// case-insensitively but whole, so `io` or `mdwn` name other crawlers.
+pub fn parse_robots_crawl_delay(robots: &str, user_agent: &str) -> Option<Duration> {
+    let product_token = |agent: &str| {
+        agent
+            .split(['/', ' '])
+            .next()
+            .unwrap_or_default()
+            .to_ascii_lowercase()
+    };
+    let token = product_token(user_agent);
+
+    let mut specific = None;
+    let mut wildcard = None;
+    // Agents of the current group, and whether its rules have started
+    let mut agents: Vec<String> = Vec::new();
+    let mut in_rules = false;
+
+    for line in robots.lines() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        let Some((field, value)) = line.split_once(':') else {
+            continue;
+        };
+        let (field, value) = (field.trim().to_ascii_lowercase(), value.trim());
+
+        if field == "user-agent" {
+            if in_rules {
+                agents.clear();
+                in_rules = false;
+            }
+            agents.push(product_token(value));
+            continue;
+        }
+        in_rules = true;
+
+        if field != "crawl-delay" {
+            continue;
+        }
+        let Some(delay) = value
+            .parse::<f64>()
+            .ok()
+            .filter(|secs| secs.is_finite() && *secs >= 0.0)
+            .map(Duration::from_secs_f64)
+        else {
+            continue;
+        };
+        for agent in &agents {
+            if agent == "*" {
+                wildcard.get_or_insert(delay);
+            } else if !token.is_empty() && *agent == token {
+                specific.get_or_insert(delay);
+            }
+        }
+    }
+
+    specific.or(wildcard)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use chrono::TimeZone;
 
     #[test]
     fn test_parse_html_with_markdown_link() {
@@ -143,7 +874,7 @@ mod tests {
             </html>
         "#;
         let base = Url::parse("https://example.com/page").unwrap();
-        let result = parse_html_for_markdown_link(html, &base).unwrap();
+        let result = parse_html_for_markdown_link(html, &base, &[]).unwrap();
 
         match result {
             HtmlParseResult::MarkdownLink(url) => {
@@ -159,7 +890,7 @@ mod tests {
             <link rel="alternate" type="text/x-markdown" href="content.md">
         "#;
         let base = Url::parse("https://example.com/").unwrap();
-        let result = parse_html_for_markdown_link(html, &base).unwrap();
+        let result = parse_html_for_markdown_link(html, &base, &[]).unwrap();
 
         match result {
             HtmlParseResult::MarkdownLink(url) => {
@@ -181,7 +912,7 @@ mod tests {
             </html>
         "#;
         let base = Url::parse("https://example.com/").unwrap();
-        let result = parse_html_for_markdown_link(html, &base).unwrap();
+        let result = parse_html_for_markdown_link(html, &base, &[]).unwrap();
 
         assert!(matches!(result, HtmlParseResult::NeedsConversion));
     }
@@ -193,7 +924,7 @@ mod tests {
             <link rel="alternate" type="text/markdown" href="article.md">
         "#;
         let base = Url::parse("https://example.com/page").unwrap();
-        let result = parse_html_for_markdown_link(html, &base).unwrap();
+        let result = parse_html_for_markdown_link(html, &base, &[]).unwrap();
 
         match result {
             HtmlParseResult::MarkdownLink(url) => {
@@ -203,6 +934,62 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_parse_html_alternate_language_selection() {
+        let html = r#"
+            <link rel="alternate" type="text/markdown" hreflang="de" href="/de.md">
+            <link rel="alternate" type="text/markdown" href="/default.md">
+            <link rel="alternate" type="text/markdown" hreflang="fr-CA" href="/fr-ca.md">
+            <link rel="alternate" type="text/markdown" hreflang="fr" href="/fr.md">
+        "#;
+        let base = Url::parse("https://example.com/").unwrap();
+        let select = |langs: &[&str]| {
+            let langs: Vec<String> = langs.iter().map(|l| l.to_string()).collect();
+            match parse_html_for_markdown_link(html, &base, &langs).unwrap() {
+                HtmlParseResult::MarkdownLink(url) => url.path().to_string(),
+                _ => panic!("Expected MarkdownLink"),
+            }
+        };
+
+        // Exact match wins over primary subtag match
+        assert_eq!(select(&["fr"]), "/fr.md");
+        assert_eq!(select(&["fr-ca"]), "/fr-ca.md");
+
+        // Primary subtag match
+        assert_eq!(select(&["de-at"]), "/de.md");
+
+        // Preference order is respected
+        assert_eq!(select(&["ja", "de"]), "/de.md");
+
+        // No match falls back to the alternate without hreflang
+        assert_eq!(select(&["ja"]), "/default.md");
+        assert_eq!(select(&[]), "/default.md");
+
+        assert!(has_language_alternates(html));
+        assert!(!has_language_alternates(
+            r#"<link rel="alternate" type="text/markdown" hreflang="en" href="/en.md">"#
+        ));
+    }
+
+    #[test]
+    fn test_parse_accept_language() {
+        assert_eq!(
+            parse_accept_language("fr-CH, fr;q=0.9, en;q=0.8, de;q=0.7, *;q=0.5"),
+            vec!["fr-ch", "fr", "en", "de"]
+        );
+        assert_eq!(parse_accept_language("en;q=0.5, de"), vec!["de", "en"]);
+        assert_eq!(parse_accept_language("en;q=0, de"), vec!["de"]);
+        assert!(parse_accept_language("").is_empty());
+    }
+
+    #[test]
+    fn test_alternate_policy_from_str() {
+        assert_eq!("follow".parse(), Ok(AlternatePolicy::Follow));
+        assert_eq!("BLOCK".parse(), Ok(AlternatePolicy::Block));
+        assert_eq!(" flag ".parse(), Ok(AlternatePolicy::Flag));
+        assert!("maybe".parse::<AlternatePolicy>().is_err());
+    }
+
     #[test]
     fn test_parse_json_url() {
         let json = r#"{"mdwn": "https://example.com/file.md"}"#;
@@ -245,6 +1032,34 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_parse_json_next_link() {
+        let base = Url::parse("https://api.example.com/articles?page=1").unwrap();
+
+        // HAL
+        let json = r##"{"mdwn": "# One", "_links": {"next": {"href": "/articles?page=2"}}}"##;
+        assert_eq!(
+            parse_json_next_link(json, &base).unwrap().as_str(),
+            "https://api.example.com/articles?page=2"
+        );
+
+        // JSON:API string and object forms
+        let json = r##"{"markdown": "# One", "links": {"next": "https://api.example.com/p2"}}"##;
+        assert_eq!(
+            parse_json_next_link(json, &base).unwrap().as_str(),
+            "https://api.example.com/p2"
+        );
+        let json = r#"{"links": {"next": {"href": "?page=3"}}}"#;
+        assert_eq!(
+            parse_json_next_link(json, &base).unwrap().as_str(),
+            "https://api.example.com/articles?page=3"
+        );
+
+        // Missing or null next link
+        assert!(parse_json_next_link(r#"{"links": {"next": null}}"#, &base).is_none());
+        assert!(parse_json_next_link(r##"{"mdwn": "# One"}"##, &base).is_none());
+    }
+
     #[test]
     fn test_parse_json_no_field() {
         let json = r#"{"title": "Article", "content": "..."}"#;
@@ -254,17 +1069,311 @@ mod tests {
         assert!(matches!(result, JsonParseResult::NotFound));
     }
 
+    #[test]
+    fn test_parse_html_metadata() {
+        let html = r#"
+            <html>
+            <head>
+                <title>Fallback Title</title>
+                <meta property="og:title" content="  OG   Title ">
+                <meta name="description" content="A short summary.">
+            </head>
+            <body><h1>Heading</h1><p>Body text</p></body>
+            </html>
+        "#;
+        let metadata = parse_html_metadata(html);
+        assert_eq!(metadata.title.as_deref(), Some("OG Title"));
+        assert_eq!(metadata.description.as_deref(), Some("A short summary."));
+    }
+
+    #[test]
+    fn test_parse_html_metadata_fallbacks() {
+        let html = r#"
+            <html>
+            <body><h1>Only Heading</h1><p>First   paragraph
+            text.</p><p>Second</p></body>
+            </html>
+        "#;
+        let metadata = parse_html_metadata(html);
+        assert_eq!(metadata.title.as_deref(), Some("Only Heading"));
+        assert_eq!(
+            metadata.description.as_deref(),
+            Some("First paragraph text.")
+        );
+
+        let metadata = parse_html_metadata("<html><body></body></html>");
+        assert_eq!(metadata, PageMetadata::default());
+    }
+
+    #[test]
+    fn test_parse_html_license() {
+        let base = Url::parse("https://example.com/post").unwrap();
+        let html = r#"<html><body><a rel="license" href="/license">CC BY</a></body></html>"#;
+        assert_eq!(
+            parse_html_license(html, &base).as_deref(),
+            Some("https://example.com/license")
+        );
+
+        let html = r#"<html><head><meta name="dc.rights" content=" CC0 "></head></html>"#;
+        assert_eq!(parse_html_license(html, &base).as_deref(), Some("CC0"));
+
+        assert_eq!(
+            parse_html_license("<html><body></body></html>", &base),
+            None
+        );
+    }
+
+    #[test]
+    fn test_parse_html_byline() {
+        let html = r#"<html><head>
+            <meta name="author" content="Meta Author">
+            <script type="application/ld+json">{"@graph": [{"@type": "WebSite"}, {
+                "@type": "NewsArticle",
+                "author": [{"@type": "Person", "name": "Jane Doe"}, "John Roe"],
+                "datePublished": "2024-03-01T09:30:00+01:00"
+            }]}</script>
+            <meta property="article:modified_time" content="2024-03-05">
+        </head><body></body></html>"#;
+        let byline = parse_html_byline(html);
+        assert_eq!(byline.author.as_deref(), Some("Jane Doe, John Roe"));
+        assert_eq!(
+            byline.published,
+            Some(Utc.with_ymd_and_hms(2024, 3, 1, 8, 30, 0).unwrap())
+        );
+        assert_eq!(
+            byline.attribution().as_deref(),
+            Some("*By Jane Doe, John Roe · Published 2024-03-01 · Updated 2024-03-05*")
+        );
+
+        let html = r#"<html><head>
+            <meta property="article:author" content="https://facebook.com/someone">
+        </head><body><article>
+            <a rel="author" href="/about">by  Sam Poe</a>
+            <time pubdate datetime="2023-11-20">November 20</time>
+            <p>Posted in reply to <time datetime="2023-10-01">October 1</time></p>
+        </article></body></html>"#;
+        let byline = parse_html_byline(html);
+        assert_eq!(byline.author.as_deref(), Some("Sam Poe"));
+        assert_eq!(
+            byline.attribution().as_deref(),
+            Some("*By Sam Poe · Published 2023-11-20*")
+        );
+
+        // Unmarked `<time>`s are as often comments or events as the article
+        let html = r#"<html><head><meta name="author" content="<b>Jo_*Doe*</b> & Co">
+        </head><body><article><time datetime="2023-10-01">October 1</time></article></body></html>"#;
+        let byline = parse_html_byline(html);
+        assert_eq!(byline.published, None);
+        assert_eq!(
+            byline.attribution().as_deref(),
+            Some(r"*By \<b\>Jo\_\*Doe\*\</b\> \& Co*")
+        );
+
+        let byline = parse_html_byline("<html><body><p>Anonymous</p></body></html>");
+        assert_eq!(byline, Byline::default());
+        assert_eq!(byline.attribution(), None);
+    }
+
+    #[test]
+    fn test_parse_markdown_metadata() {
+        let md = "<!-- notice -->\n\n# My Doc\n\nFirst line\nsecond line.\n\nMore text.";
+        let metadata = parse_markdown_metadata(md);
+        assert_eq!(metadata.title.as_deref(), Some("My Doc"));
+        assert_eq!(
+            metadata.description.as_deref(),
+            Some("First line second line.")
+        );
+    }
+
+    #[test]
+    fn test_parse_json_metadata() {
+        let json = r#"{"title": "API Doc", "description": "Describes things", "mdwn": "x"}"#;
+        let metadata = parse_json_metadata(json);
+        assert_eq!(metadata.title.as_deref(), Some("API Doc"));
+        assert_eq!(metadata.description.as_deref(), Some("Describes things"));
+
+        assert_eq!(parse_json_metadata("not json"), PageMetadata::default());
+    }
+
+    #[test]
+    fn test_is_generic_content_type() {
+        assert!(is_generic_content_type(None));
+        assert!(is_generic_content_type(Some("application/octet-stream")));
+        assert!(is_generic_content_type(Some(
+            "Binary/Octet-Stream; charset=binary"
+        )));
+        assert!(!is_generic_content_type(Some("text/html")));
+        assert!(!is_generic_content_type(Some(
+            "text/markdown; charset=utf-8"
+        )));
+    }
+
+    #[test]
+    fn test_truncate_chars() {
+        assert_eq!(truncate_chars("short", 10), "short");
+        assert_eq!(truncate_chars("hello world", 5), "hello…");
+    }
+
+    #[test]
+    fn test_is_markdown_alternate() {
+        assert!(is_markdown_alternate(Some("text/markdown"), b"# Doc"));
+        assert!(is_markdown_alternate(Some("text/plain"), b"Doc"));
+
+        // Declared non-markdown types are rejected
+        assert!(!is_markdown_alternate(Some("text/html"), b"# Doc"));
+        assert!(!is_markdown_alternate(Some("image/png"), b"# Doc"));
+
+        // Generic types are sniffed
+        assert!(is_markdown_alternate(None, b"# Doc\n\nText"));
+        assert!(is_markdown_alternate(
+            Some("application/octet-stream"),
+            b"<!-- c -->\n# Doc"
+        ));
+        assert!(!is_markdown_alternate(
+            None,
+            b"<!DOCTYPE html><html></html>"
+        ));
+        assert!(!is_markdown_alternate(None, b"\x89PNG\r\n\x1a\n\0"));
+        assert!(!is_markdown_alternate(None, b"\xff\xfe\xfd"));
+    }
+
     #[test]
     fn test_categorize_content_type() {
-        assert_eq!(categorize_content_type(Some("text/markdown")), ContentCategory::Markdown);
-        assert_eq!(categorize_content_type(Some("text/x-markdown")), ContentCategory::Markdown);
-        assert_eq!(categorize_content_type(Some("TEXT/MARKDOWN")), ContentCategory::Markdown);
-        assert_eq!(categorize_content_type(Some("text/plain")), ContentCategory::PlainText);
-        assert_eq!(categorize_content_type(Some("text/html")), ContentCategory::Html);
-        assert_eq!(categorize_content_type(Some("application/json")), ContentCategory::Json);
+        assert_eq!(
+            categorize_content_type(Some("text/markdown")),
+            ContentCategory::Markdown
+        );
+        assert_eq!(
+            categorize_content_type(Some("text/x-markdown")),
+            ContentCategory::Markdown
+        );
+        assert_eq!(
+            categorize_content_type(Some("TEXT/MARKDOWN")),
+            ContentCategory::Markdown
+        );
+        assert_eq!(
+            categorize_content_type(Some("text/plain")),
+            ContentCategory::PlainText
+        );
+        assert_eq!(
+            categorize_content_type(Some("text/html")),
+            ContentCategory::Html
+        );
+        assert_eq!(
+            categorize_content_type(Some("application/json")),
+            ContentCategory::Json
+        );
         assert_eq!(
             categorize_content_type(Some("application/vnd.api+json")),
             ContentCategory::Json
         );
     }
+
+    /// Entry without a lastmod
+    fn entry(url: &str) -> SitemapEntry {
+        SitemapEntry {
+            loc: Url::parse(url).unwrap(),
+            lastmod: None,
+        }
+    }
+
+    #[test]
+    fn test_parse_sitemap() {
+        let base = Url::parse("https://docs.example.com/sitemap.xml").unwrap();
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+            <urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+                <url><loc>https://docs.example.com/intro</loc><lastmod>2024-01-02</lastmod></url>
+                <url><loc> /guide?a=1&amp;b=2 </loc><lastmod>yesterday</lastmod></url>
+                <url><loc><![CDATA[https://docs.example.com/faq]]></loc></url>
+            </urlset>"#;
+
+        let Sitemap::Urls(entries) = parse_sitemap(xml, &base).unwrap() else {
+            panic!("expected a urlset");
+        };
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].loc.as_str(), "https://docs.example.com/intro");
+        assert_eq!(
+            entries[0].lastmod,
+            parse_w3c_datetime("2024-01-02T00:00:00Z")
+        );
+        assert_eq!(entries[1], entry("https://docs.example.com/guide?a=1&b=2"));
+        assert_eq!(entries[2], entry("https://docs.example.com/faq"));
+    }
+
+    #[test]
+    fn test_parse_sitemap_index() {
+        let base = Url::parse("https://example.com/sitemap.xml").unwrap();
+        let xml = r#"<sitemapindex xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+                <sitemap><loc>https://example.com/sitemap-docs.xml</loc></sitemap>
+            </sitemapindex>"#;
+
+        assert_eq!(
+            parse_sitemap(xml, &base).unwrap(),
+            Sitemap::Index(vec![entry("https://example.com/sitemap-docs.xml")])
+        );
+
+        assert!(parse_sitemap("<html><body>nope</body></html>", &base).is_err());
+        assert!(parse_sitemap("<urlset><url><loc>", &base).is_ok());
+    }
+
+    #[test]
+    fn test_parse_w3c_datetime() {
+        let expected = Some(
+            NaiveDate::from_ymd_opt(2024, 5, 6)
+                .unwrap()
+                .and_hms_opt(10, 30, 0)
+                .unwrap()
+                .and_utc(),
+        );
+        assert_eq!(parse_w3c_datetime("2024-05-06T10:30:00Z"), expected);
+        assert_eq!(parse_w3c_datetime("2024-05-06T12:30:00+02:00"), expected);
+        assert_eq!(parse_w3c_datetime("2024-05-06T12:30+02:00"), expected);
+        assert_eq!(parse_w3c_datetime("2024-05-06T10:30Z"), expected);
+        assert!(parse_w3c_datetime("2024-05-06").is_some());
+        assert!(parse_w3c_datetime("last tuesday").is_none());
+    }
+
+    #[test]
+    fn test_parse_robots_crawl_delay() {
+        let robots = "
+            User-agent: Googlebot
+            Disallow: /private
+            Crawl-delay: 1
+
+            User-agent: *
+            Crawl-delay: 10 # be gentle
+
+            User-agent: mdwn.io
+            User-agent: OtherBot
+            Crawl-delay: 2.5
+        ";
+        let ua = "mdwn.io/1.0 (+https://mdwn.io)";
+        assert_eq!(
+            parse_robots_crawl_delay(robots, ua),
+            Some(Duration::from_millis(2500))
+        );
+        assert_eq!(
+            parse_robots_crawl_delay(robots, "SomeBot/2.0"),
+            Some(Duration::from_secs(10))
+        );
+        assert_eq!(
+            parse_robots_crawl_delay("User-agent: *\nDisallow:", ua),
+            None
+        );
+        assert_eq!(
+            parse_robots_crawl_delay("User-agent: *\nCrawl-delay: soon", ua),
+            None
+        );
+
+        // Whole tokens only, in any case, with or without a version
+        let partial = "User-agent: io\nCrawl-delay: 60\n\nUser-agent: *\nCrawl-delay: 3";
+        assert_eq!(
+            parse_robots_crawl_delay(partial, ua),
+            Some(Duration::from_secs(3))
+        );
+        assert_eq!(
+            parse_robots_crawl_delay("User-agent: MDWN.IO/2\nCrawl-delay: 4", ua),
+            Some(Duration::from_secs(4))
+        );
+    }
 }
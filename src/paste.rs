@@ -0,0 +1,208 @@
+//! Gists and pastebin-style services
+//!
+//! Paste pages bury the text in a syntax-highlighted table of spans. Known
+//! services are fetched from their raw endpoints instead (gists from the
+//! GitHub API, see `github`), and anything that isn't markdown is wrapped in
+//! a code block fenced with the paste's language.
+
+use crate::fetch::{FetchBudget, Fetcher};
+use crate::parse::{categorize_content_type, ContentCategory};
+use url::Url;
+
+/// One file of a paste
+#[derive(Debug)]
+pub struct PasteFile {
+    pub name: Option<String>,
+    /// Language as the service names it (`Rust`, `C++`), if known
+    pub language: Option<String>,
+    pub content: String,
+}
+
+/// Raw-text URL of a paste on a known service
+pub fn raw_paste_url(url: &Url) -> Option<Url> {
+    let host = url.host_str()?.trim_start_matches("www.");
+    let segments: Vec<&str> = url.path_segments()?.filter(|s| !s.is_empty()).collect();
+    let raw_path = match (host, segments.as_slice()) {
+        ("pastebin.com", ["raw", id]) | ("pastebin.com", [id]) => format!("/raw/{}", id),
+        ("dpaste.org" | "paste.mozilla.org", [id]) => format!("/{}/raw", id),
+        ("dpaste.org" | "paste.mozilla.org", [id, "raw"]) => format!("/{}/raw", id),
+        ("dpaste.com", [id]) => format!("/{}.txt", id.trim_end_matches(".txt")),
+        // paste.rs serves the raw text at the paste's own URL
+        ("paste.rs", [id]) => format!("/{}", id),
+        _ => return None,
+    };
+    // Paste IDs are short and alphanumeric, unlike the services' own pages
+    let id = raw_path.trim_start_matches("/raw").trim_matches('/');
+    let id = id.split(['/', '.']).next().unwrap_or_default();
+    if id.len() < 3 || !id.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return None;
+    }
+
+    let mut raw = url.clone();
+    raw.set_path(&raw_path);
+    raw.set_query(None);
+    raw.set_fragment(None);
+    Some(raw)
+}
+
+/// Fetch a paste from its raw endpoint, or `None` if the URL isn't one
+pub async fn fetch_paste(fetcher: &Fetcher, budget: &FetchBudget, url: &Url) -> Option<PasteFile> {
+    let raw_url = raw_paste_url(url)?;
+    let response = match fetcher.fetch(&raw_url, budget).await {
+        Ok(response) => response,
+        Err(e) => {
+            tracing::warn!("Raw paste unavailable for {}: {}", url, e);
+            return None;
+        }
+    };
+    // An HTML answer is a login wall or error page, not the paste
+    if categorize_content_type(response.mime_type()) == ContentCategory::Html {
+        return None;
+    }
+
+    // paste.rs picks highlighting from an extension on the URL
+    let name = url.path_segments()?.next_back().filter(|s| s.contains('.'));
+    Some(PasteFile {
+        name: name.map(str::to_string),
+        language: None,
+        content: response.body_as_string(),
+    })
+}
+
+/// Render paste files, each under its name when there are several
+pub fn paste_to_markdown(files: &[PasteFile], title: Option<&str>, notice: Option<&str>) -> String {
+    let mut markdown = String::new();
+    if let Some(notice) = notice {
+        markdown.push_str(&format!("<!-- {} -->\n\n", notice));
+    }
+    if let Some(title) = title {
+        markdown.push_str(&format!("# {}\n\n", title));
+    }
+
+    for file in files {
+        if let (Some(name), true) = (&file.name, files.len() > 1) {
+            markdown.push_str(&format!("## {}\n\n", name));
+        }
+        let language = file
+            .language
+            .as_deref()
+            .map(fence_language)
+            .or_else(|| file.name.as_deref().and_then(extension_language));
+        let content = file.content.replace("\r\n", "\n");
+        if language.as_deref() == Some("markdown") {
+            markdown.push_str(content.trim());
+        } else {
+            // A fence longer than any backtick run inside the paste
+            let longest = content.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+            let fence = "`".repeat(longest.max(2) + 1);
+            markdown.push_str(&format!(
+                "{}{}\n{}\n{}",
+                fence,
+                language.unwrap_or_default(),
+                content.trim_end(),
+                fence
+            ));
+        }
+        markdown.push_str("\n\n");
+    }
+
+    // Not `clean_markdown`: blank lines inside code are part of the paste
+    format!("{}\n", markdown.trim_end())
+}
+
+/// Info-string for a service's language name (`C++` → `cpp`)
+fn fence_language(language: &str) -> String {
+    match language.to_ascii_lowercase().as_str() {
+        "c++" => "cpp".to_string(),
+        "c#" => "csharp".to_string(),
+        "f#" => "fsharp".to_string(),
+        "shell" => "bash".to_string(),
+        "text" | "plain text" | "none" => String::new(),
+        other => other.replace(' ', "-"),
+    }
+}
+
+/// Info-string guessed from a file name's extension
+fn extension_language(name: &str) -> Option<String> {
+    let (_, extension) = name.rsplit_once('.')?;
+    let language = match extension.to_ascii_lowercase().as_str() {
+        "md" | "markdown" => "markdown",
+        "rs" => "rust",
+        "py" => "python",
+        "js" | "mjs" => "javascript",
+        "ts" => "typescript",
+        "rb" => "ruby",
+        "sh" | "bash" => "bash",
+        "yml" | "yaml" => "yaml",
+        "h" => "c",
+        "hpp" | "cc" | "cxx" => "cpp",
+        "txt" | "log" => return None,
+        other => return Some(other.to_string()),
+    };
+    Some(language.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_raw_paste_url() {
+        let raw = |url: &str| raw_paste_url(&Url::parse(url).unwrap()).map(String::from);
+        assert_eq!(
+            raw("https://pastebin.com/AbC123xy").as_deref(),
+            Some("https://pastebin.com/raw/AbC123xy")
+        );
+        assert_eq!(
+            raw("https://pastebin.com/raw/AbC123xy").as_deref(),
+            Some("https://pastebin.com/raw/AbC123xy")
+        );
+        assert_eq!(
+            raw("https://dpaste.org/Qx4p#L2").as_deref(),
+            Some("https://dpaste.org/Qx4p/raw")
+        );
+        assert_eq!(
+            raw("https://dpaste.com/7HGXK3C2L").as_deref(),
+            Some("https://dpaste.com/7HGXK3C2L.txt")
+        );
+        assert_eq!(
+            raw("https://paste.rs/Xyz.rs").as_deref(),
+            Some("https://paste.rs/Xyz.rs")
+        );
+        assert_eq!(raw("https://pastebin.com/"), None);
+        assert_eq!(raw("https://pastebin.com/u/someone"), None);
+        assert_eq!(raw("https://example.com/AbC123xy"), None);
+    }
+
+    #[test]
+    fn test_paste_to_markdown() {
+        let files = [
+            PasteFile {
+                name: Some("notes.md".to_string()),
+                language: Some("Markdown".to_string()),
+                content: "Some *notes*.\n".to_string(),
+            },
+            PasteFile {
+                name: Some("main.cpp".to_string()),
+                language: Some("C++".to_string()),
+                content: "int main() {}\r\n".to_string(),
+            },
+        ];
+        assert_eq!(
+            paste_to_markdown(&files, Some("Example"), None),
+            "# Example\n\n## notes.md\n\nSome *notes*.\n\n\
+             ## main.cpp\n\n```cpp\nint main() {}\n```\n"
+        );
+
+        // Unknown language, content with its own fence
+        let files = [PasteFile {
+            name: None,
+            language: None,
+            content: "```\nnested\n```".to_string(),
+        }];
+        assert_eq!(
+            paste_to_markdown(&files, None, None),
+            "````\n```\nnested\n```\n````\n"
+        );
+    }
+}
@@ -0,0 +1,471 @@
+//! Show notes of podcast episodes
+//!
+//! Audio has nothing to convert, but the episode around it does: title,
+//! duration, publication date and the show notes. Episode pages carry these
+//! as schema.org `PodcastEpisode` data, and podcast RSS feeds as `<item>`s
+//! with an audio `<enclosure>` and iTunes tags.
+
+use crate::convert::clean_markdown;
+use chrono::DateTime;
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use scraper::{Html, Selector};
+use serde_json::Value;
+
+/// Episodes rendered from a feed, newest first as feeds list them
+const MAX_FEED_EPISODES: usize = 20;
+
+/// One episode and its show notes
+#[derive(Debug, Default, PartialEq)]
+pub struct Episode {
+    pub title: String,
+    /// Publication date as `YYYY-MM-DD` when parseable, else as given
+    pub published: Option<String>,
+    /// `h:mm:ss` or `m:ss`
+    pub duration: Option<String>,
+    /// URL of the audio file
+    pub audio: Option<String>,
+    /// Show notes as markdown
+    pub notes: String,
+}
+
+/// A podcast feed
+#[derive(Debug, PartialEq)]
+pub struct Podcast {
+    pub title: String,
+    pub description: Option<String>,
+    pub episodes: Vec<Episode>,
+    /// Episodes past the rendering limit
+    pub omitted: usize,
+}
+
+/// Episode described by a page's schema.org data (JSON-LD or microdata)
+pub fn episode_from_html(html: &str) -> Option<Episode> {
+    let document = Html::parse_document(html);
+
+    let json_ld =
+        Selector::parse("script[type='application/ld+json']").expect("valid CSS selector");
+    for script in document.select(&json_ld) {
+        let Ok(value) = serde_json::from_str::<Value>(&script.text().collect::<String>()) else {
+            continue;
+        };
+        if let Some(episode) = find_episode(&value).and_then(json_ld_episode) {
+            return Some(episode);
+        }
+    }
+
+    let scope = Selector::parse("[itemtype$='PodcastEpisode']").expect("valid CSS selector");
+    let scope = document.select(&scope).next()?;
+    let prop = |name: &str| {
+        let selector = Selector::parse(&format!("[itemprop='{}']", name)).ok()?;
+        let element = scope.select(&selector).next()?;
+        let value = element.value();
+        let text = value
+            .attr("content")
+            .or_else(|| value.attr("datetime"))
+            .or_else(|| value.attr("href"))
+            .or_else(|| value.attr("src"))
+            .map(str::to_string)
+            .unwrap_or_else(|| element.inner_html());
+        Some(text.trim().to_string()).filter(|t| !t.is_empty())
+    };
+    Some(Episode {
+        title: html_text(&prop("name")?),
+        published: prop("datePublished").map(|d| format_date(&d)),
+        duration: prop("duration")
+            .or_else(|| prop("timeRequired"))
+            .and_then(|d| format_duration(&d)),
+        audio: prop("contentUrl"),
+        notes: prop("description")
+            .map(|d| notes_markdown(&d))
+            .unwrap_or_default(),
+    })
+}
+
+/// First `PodcastEpisode` object anywhere in a JSON-LD document
+fn find_episode(value: &Value) -> Option<&Value> {
+    match value {
+        Value::Object(object) => {
+            let is_episode = match object.get("@type") {
+                Some(Value::String(t)) => t == "PodcastEpisode",
+                Some(Value::Array(ts)) => ts.iter().any(|t| t == "PodcastEpisode"),
+                _ => false,
+            };
+            if is_episode {
+                return Some(value);
+            }
+            object.values().find_map(find_episode)
+        }
+        Value::Array(values) => values.iter().find_map(find_episode),
+        _ => None,
+    }
+}
+
+fn json_ld_episode(value: &Value) -> Option<Episode> {
+    let text = |key: &str| {
+        value
+            .get(key)
+            .and_then(Value::as_str)
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+    };
+    let media = value.get("associatedMedia").and_then(|media| match media {
+        Value::Array(items) => items.first(),
+        media => Some(media),
+    });
+    Some(Episode {
+        title: html_text(text("name")?),
+        published: text("datePublished").map(format_date),
+        duration: text("duration")
+            .or_else(|| text("timeRequired"))
+            .or_else(|| media?.get("duration")?.as_str())
+            .and_then(format_duration),
+        audio: media
+            .and_then(|media| media.get("contentUrl"))
+            .and_then(Value::as_str)
+            .map(str::to_string),
+        notes: text("description").map(notes_markdown).unwrap_or_default(),
+    })
+}
+
+/// Content types podcast feeds are served as
+pub fn is_feed_type(mime_type: &str) -> bool {
+    matches!(
+        mime_type.to_ascii_lowercase().as_str(),
+        "application/rss+xml" | "application/xml" | "text/xml"
+    )
+}
+
+/// An `<item>` being read, with the fields that still need normalizing
+#[derive(Default)]
+struct FeedItem {
+    episode: Episode,
+    description: String,
+    published: String,
+    duration: String,
+}
+
+impl FeedItem {
+    fn into_episode(self) -> Episode {
+        let mut episode = self.episode;
+        // `content:encoded` holds the full notes, `description` often a summary
+        if episode.notes.trim().is_empty() {
+            episode.notes = self.description;
+        }
+        episode.notes = notes_markdown(&episode.notes);
+        episode.title = episode.title.trim().to_string();
+        let published = self.published.trim();
+        episode.published = (!published.is_empty()).then(|| format_date(published));
+        episode.duration = format_duration(self.duration.trim());
+        episode
+    }
+}
+
+/// RSS element whose text is being read
+#[derive(Clone, Copy, PartialEq)]
+enum FeedField {
+    Title,
+    Description,
+    Notes,
+    Published,
+    Duration,
+}
+
+/// Episodes of a podcast RSS feed, or `None` if it isn't one (no item has an
+/// audio enclosure)
+pub fn parse_feed(xml: &str) -> Option<Podcast> {
+    let mut reader = Reader::from_str(xml);
+    let mut title = String::new();
+    let mut description = String::new();
+    let mut episodes = Vec::new();
+    let mut item: Option<FeedItem> = None;
+    let mut field = None;
+    let mut has_audio = false;
+    // Channel fields are children of `<rss><channel>`, not of e.g. `<image>`
+    let mut depth = 0usize;
+
+    loop {
+        let text = match reader.read_event() {
+            Ok(Event::Start(e)) => {
+                depth += 1;
+                let in_item = item.is_some();
+                field = match e.name().as_ref() {
+                    b"item" => {
+                        item = Some(FeedItem::default());
+                        None
+                    }
+                    b"title" if in_item || depth == 3 => Some(FeedField::Title),
+                    b"description" if in_item || depth == 3 => Some(FeedField::Description),
+                    b"content:encoded" if in_item => Some(FeedField::Notes),
+                    b"pubDate" if in_item => Some(FeedField::Published),
+                    b"itunes:duration" if in_item => Some(FeedField::Duration),
+                    _ => None,
+                };
+                continue;
+            }
+            Ok(Event::Empty(e)) if e.name().as_ref() == b"enclosure" => {
+                let attr = |name: &str| {
+                    e.try_get_attribute(name)
+                        .ok()
+                        .flatten()
+                        .and_then(|a| a.unescape_value().ok())
+                        .map(|v| v.into_owned())
+                };
+                let is_audio = attr("type").is_some_and(|t| t.starts_with("audio/"));
+                if let (Some(item), true) = (&mut item, is_audio) {
+                    item.episode.audio = attr("url");
+                    has_audio = true;
+                }
+                continue;
+            }
+            Ok(Event::End(e)) => {
+                depth = depth.saturating_sub(1);
+                field = None;
+                if e.name().as_ref() == b"item" {
+                    episodes.extend(item.take().map(FeedItem::into_episode));
+                }
+                continue;
+            }
+            Ok(Event::Text(text)) if field.is_some() => match text.unescape() {
+                Ok(text) => text.into_owned(),
+                Err(_) => continue,
+            },
+            Ok(Event::CData(data)) if field.is_some() => {
+                String::from_utf8_lossy(&data).into_owned()
+            }
+            Ok(Event::Eof) | Err(_) => break,
+            _ => continue,
+        };
+
+        let target = match (&mut item, field) {
+            (Some(item), Some(FeedField::Title)) => &mut item.episode.title,
+            (Some(item), Some(FeedField::Description)) => &mut item.description,
+            (Some(item), Some(FeedField::Notes)) => &mut item.episode.notes,
+            (Some(item), Some(FeedField::Published)) => &mut item.published,
+            (Some(item), Some(FeedField::Duration)) => &mut item.duration,
+            (None, Some(FeedField::Title)) => &mut title,
+            (None, Some(FeedField::Description)) => &mut description,
+            _ => continue,
+        };
+        target.push_str(&text);
+    }
+
+    if !has_audio {
+        return None;
+    }
+    let omitted = episodes.len().saturating_sub(MAX_FEED_EPISODES);
+    episodes.truncate(MAX_FEED_EPISODES);
+    let description = notes_markdown(&description);
+    Some(Podcast {
+        title: title.trim().to_string(),
+        description: (!description.is_empty()).then_some(description),
+        episodes,
+        omitted,
+    })
+}
+
+/// Markdown for an episode page, or `None` if the page doesn't describe an episode
+pub fn episode_page_to_markdown(html: &str, notice: Option<&str>) -> Option<String> {
+    let episode = episode_from_html(html)?;
+    Some(episode_to_markdown(&episode, notice))
+}
+
+/// Render an episode as its metadata followed by the show notes
+pub fn episode_to_markdown(episode: &Episode, notice: Option<&str>) -> String {
+    let mut markdown = String::new();
+    if let Some(notice) = notice {
+        markdown.push_str(&format!("<!-- {} -->\n\n", notice));
+    }
+    push_episode(&mut markdown, episode, "#");
+    clean_markdown(&markdown)
+}
+
+/// Render a feed as its description followed by each episode
+pub fn feed_to_markdown(podcast: &Podcast, notice: Option<&str>) -> String {
+    let mut markdown = String::new();
+    if let Some(notice) = notice {
+        markdown.push_str(&format!("<!-- {} -->\n\n", notice));
+    }
+    if !podcast.title.is_empty() {
+        markdown.push_str(&format!("# {}\n\n", podcast.title));
+    }
+    if let Some(description) = &podcast.description {
+        markdown.push_str(&format!("{}\n\n", description.trim()));
+    }
+    for episode in &podcast.episodes {
+        push_episode(&mut markdown, episode, "##");
+    }
+    if podcast.omitted > 0 {
+        let episodes = if podcast.omitted == 1 {
+            "episode"
+        } else {
+            "episodes"
+        };
+        markdown.push_str(&format!(
+            "*{} older {} not shown*\n",
+            podcast.omitted, episodes
+        ));
+    }
+    clean_markdown(&markdown)
+}
+
+fn push_episode(markdown: &mut String, episode: &Episode, heading: &str) {
+    markdown.push_str(&format!("{} {}\n\n", heading, episode.title));
+    let mut details = Vec::new();
+    if let Some(published) = &episode.published {
+        details.push(format!("- Published: {}", published));
+    }
+    if let Some(duration) = &episode.duration {
+        details.push(format!("- Duration: {}", duration));
+    }
+    if let Some(audio) = &episode.audio {
+        details.push(format!("- Audio: <{}>", audio));
+    }
+    if !details.is_empty() {
+        markdown.push_str(&format!("{}\n\n", details.join("\n")));
+    }
+    if !episode.notes.trim().is_empty() {
+        markdown.push_str(&format!("{}\n\n", episode.notes.trim()));
+    }
+}
+
+/// Show notes (HTML or plain text) as markdown
+fn notes_markdown(notes: &str) -> String {
+    let notes = notes.trim();
+    if !notes.contains('<') {
+        return notes.to_string();
+    }
+    htmd::convert(notes)
+        .map(|markdown| clean_markdown(&markdown).trim().to_string())
+        .unwrap_or_else(|_| html_text(notes))
+}
+
+/// Text of an HTML fragment with whitespace collapsed
+fn html_text(html: &str) -> String {
+    Html::parse_fragment(html)
+        .root_element()
+        .text()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// `YYYY-MM-DD` for RFC 2822 (RSS) and RFC 3339 (schema.org) dates
+fn format_date(date: &str) -> String {
+    DateTime::parse_from_rfc2822(date)
+        .or_else(|_| DateTime::parse_from_rfc3339(date))
+        .map(|d| d.format("%Y-%m-%d").to_string())
+        .unwrap_or_else(|_| date.to_string())
+}
+
+/// `h:mm:ss` or `m:ss` from seconds, `[h:]mm:ss` or ISO 8601 (`PT1H2M3S`)
+fn format_duration(duration: &str) -> Option<String> {
+    let secs = if let Some(iso) = duration.strip_prefix("PT") {
+        let mut secs = 0u64;
+        let mut number = String::new();
+        for c in iso.chars() {
+            match c {
+                '0'..='9' => number.push(c),
+                // Fractional seconds aren't worth showing
+                '.' => number.push(c),
+                'H' | 'M' | 'S' => {
+                    let value = number.parse::<f64>().ok()? as u64;
+                    secs += value
+                        * match c {
+                            'H' => 3600,
+                            'M' => 60,
+                            _ => 1,
+                        };
+                    number.clear();
+                }
+                _ => return None,
+            }
+        }
+        secs
+    } else {
+        duration.split(':').try_fold(0u64, |total, part| {
+            Some(total * 60 + part.trim().parse::<u64>().ok()?)
+        })?
+    };
+    if secs == 0 {
+        return None;
+    }
+    let (hours, minutes, seconds) = (secs / 3600, secs / 60 % 60, secs % 60);
+    Some(if hours > 0 {
+        format!("{}:{:02}:{:02}", hours, minutes, seconds)
+    } else {
+        format!("{}:{:02}", minutes, seconds)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_episode_from_json_ld() {
+        let html = r#"<html><head><script type="application/ld+json">
+            {"@context": "https://schema.org", "@graph": [{"@type": "WebPage"}, {
+              "@type": "PodcastEpisode", "name": "Episode 12: Lifetimes",
+              "datePublished": "2024-05-01T06:00:00+00:00", "timeRequired": "PT1H2M3S",
+              "description": "We talk about lifetimes.",
+              "associatedMedia": {"@type": "MediaObject", "contentUrl": "https://cdn.example/12.mp3"}
+            }]}
+            </script></head><body></body></html>"#;
+        let episode = episode_from_html(html).unwrap();
+        assert_eq!(episode.title, "Episode 12: Lifetimes");
+        assert_eq!(episode.published.as_deref(), Some("2024-05-01"));
+        assert_eq!(episode.duration.as_deref(), Some("1:02:03"));
+
+        assert_eq!(
+            episode_to_markdown(&episode, None),
+            "# Episode 12: Lifetimes\n\n- Published: 2024-05-01\n- Duration: 1:02:03\n\
+             - Audio: <https://cdn.example/12.mp3>\n\nWe talk about lifetimes.\n"
+        );
+        assert_eq!(
+            episode_from_html("<html><body>No episode</body></html>"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_parse_feed() {
+        let xml = r#"<?xml version="1.0"?>
+            <rss version="2.0" xmlns:itunes="http://www.itunes.com/dtds/podcast-1.0.dtd"
+                 xmlns:content="http://purl.org/rss/1.0/modules/content/">
+            <channel><title>Systems Talk</title>
+              <description>A show about systems.</description>
+              <image><title>Logo</title></image>
+              <item><title>Second</title><pubDate>Wed, 01 May 2024 06:00:00 GMT</pubDate>
+                <itunes:duration>2723</itunes:duration>
+                <description>Short summary</description>
+                <content:encoded><![CDATA[Full notes & links]]></content:encoded>
+                <enclosure url="https://cdn.example/2.mp3" type="audio/mpeg" length="1"/></item>
+              <item><title>First</title><itunes:duration>05:09</itunes:duration>
+                <description>Only a summary</description>
+                <enclosure url="https://cdn.example/1.mp3" type="audio/mpeg" length="1"/></item>
+            </channel></rss>"#;
+        let podcast = parse_feed(xml).unwrap();
+        assert_eq!(podcast.title, "Systems Talk");
+        assert_eq!(podcast.episodes.len(), 2);
+        assert_eq!(podcast.episodes[0].duration.as_deref(), Some("45:23"));
+        assert_eq!(podcast.episodes[0].notes, "Full notes & links");
+        assert_eq!(podcast.episodes[1].duration.as_deref(), Some("5:09"));
+
+        assert_eq!(
+            feed_to_markdown(&podcast, None),
+            "# Systems Talk\n\nA show about systems.\n\n\
+             ## Second\n\n- Published: 2024-05-01\n- Duration: 45:23\n\
+             - Audio: <https://cdn.example/2.mp3>\n\nFull notes & links\n\n\
+             ## First\n\n- Duration: 5:09\n- Audio: <https://cdn.example/1.mp3>\n\n\
+             Only a summary\n"
+        );
+
+        // A blog feed has no audio
+        let blog = "<rss><channel><title>Blog</title><item><title>Post</title></item>\
+                    </channel></rss>";
+        assert_eq!(parse_feed(blog), None);
+    }
+}
@@ -0,0 +1,143 @@
+//! Per-host pacing for bulk fetches such as scheduled refreshes
+//!
+//! Requests to one origin are spaced by the larger of `CRAWL_DELAY_MS` and the
+//! `Crawl-delay` its robots.txt asks of our user agent (capped at a minute), so
+//! refreshing a large sitemap doesn't hammer the site.
+
+use mdwnio::fetch::Fetcher;
+use mdwnio::parse::parse_robots_crawl_delay;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+use url::Url;
+
+/// Longest robots.txt `Crawl-delay` honored
+const MAX_CRAWL_DELAY: Duration = Duration::from_secs(60);
+
+/// How long a robots.txt `Crawl-delay` is remembered
+const ROBOTS_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Pacing state for one origin
+struct Host {
+    /// Earliest time the next request may start
+    next_slot: Instant,
+    robots_delay: Option<Duration>,
+    robots_checked: Instant,
+}
+
+/// Spaces out bulk requests per origin
+#[derive(Clone)]
+pub struct CrawlThrottle {
+    default_delay: Duration,
+    user_agent: String,
+    hosts: Arc<Mutex<HashMap<String, Host>>>,
+}
+
+impl CrawlThrottle {
+    pub fn new(default_delay: Duration, user_agent: &str) -> Self {
+        Self {
+            default_delay,
+            user_agent: user_agent.to_string(),
+            hosts: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Wait for this request's turn at the URL's origin
+    pub async fn wait(&self, fetcher: &Fetcher, url: &Url) {
+        let origin = url.origin().ascii_serialization();
+
+        // Look up robots.txt outside the lock so other hosts aren't held up
+        let stale = {
+            let hosts = self.hosts.lock().expect("crawl throttle lock poisoned");
+            hosts
+                .get(&origin)
+                .is_none_or(|host| host.robots_checked.elapsed() > ROBOTS_TTL)
+        };
+        let robots_delay = if stale {
+            Some(self.robots_delay(fetcher, url).await)
+        } else {
+            None
+        };
+
+        let slot = {
+            let mut hosts = self.hosts.lock().expect("crawl throttle lock poisoned");
+            let now = Instant::now();
+            let host = hosts.entry(origin).or_insert(Host {
+                next_slot: now,
+                robots_delay: None,
+                robots_checked: now,
+            });
+            if let Some(robots_delay) = robots_delay {
+                host.robots_delay = robots_delay;
+                host.robots_checked = now;
+            }
+
+            let delay = host
+                .robots_delay
+                .unwrap_or_default()
+                .min(MAX_CRAWL_DELAY)
+                .max(self.default_delay);
+            let slot = host.next_slot.max(now);
+            host.next_slot = slot + delay;
+            slot
+        };
+
+        tokio::time::sleep_until(slot).await;
+    }
+
+    /// `Crawl-delay` from the origin's robots.txt, if it has one
+    async fn robots_delay(&self, fetcher: &Fetcher, url: &Url) -> Option<Duration> {
+        let robots_url = url.join("/robots.txt").ok()?;
+        let response = fetcher.fetch(&robots_url, &fetcher.budget()).await.ok()?;
+        let delay = parse_robots_crawl_delay(&response.body_as_string(), &self.user_agent);
+        if let Some(delay) = delay {
+            tracing::debug!("{} asks for a crawl delay of {:?}", robots_url, delay);
+        }
+        delay
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mdwnio::fetch::FetchConfig;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn test_robots_crawl_delay_spaces_requests() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/robots.txt"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_raw("User-agent: *\nCrawl-delay: 0.1\n", "text/plain"),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        // Retrying the other origin's refused connection would add to the wait measured
+        let fetcher = Fetcher::new(FetchConfig {
+            allow_private_networks: true,
+            max_attempts: 1,
+            ..Default::default()
+        })
+        .unwrap();
+        let throttle = CrawlThrottle::new(Duration::ZERO, "mdwn.io/1.0");
+        let url = Url::parse(&format!("{}/page", server.uri())).unwrap();
+
+        let start = Instant::now();
+        for _ in 0..3 {
+            throttle.wait(&fetcher, &url).await;
+        }
+        assert!(start.elapsed() >= Duration::from_millis(200));
+
+        // Other origins have their own schedule
+        let other = Url::parse("http://127.0.0.1:1/page").unwrap();
+        let start = Instant::now();
+        throttle.wait(&fetcher, &other).await;
+        assert!(start.elapsed() < Duration::from_millis(100));
+    }
+}
@@ -0,0 +1,358 @@
+//! Operator-defined post-processing of final markdown
+//!
+//! Rules are read from a TOML file and applied in order, globally or only for
+//! specific domains:
+//!
+//! ```toml
+//! [[rule]]
+//! name = "strip-newsletter"
+//! type = "remove_lines"
+//! phrases = ["Subscribe to our newsletter"]
+//!
+//! [[rule]]
+//! name = "docs-mirror"
+//! domain = "docs.example.com"
+//! type = "rewrite_links"
+//! from = "https://docs.example.com/"
+//! to = "https://mirror.example.com/"
+//!
+//! # Extra patterns removed by `?redact=pii`, besides emails and phone numbers
+//! [redact]
+//! patterns = ["ACCT-\\d{8}"]
+//! ```
+
+use regex::Regex;
+use serde::Deserialize;
+use std::sync::LazyLock;
+
+/// What a rule does to the markdown
+#[derive(Debug)]
+pub enum Processor {
+    /// Regex replacement (`$1` style group references allowed)
+    Replace { pattern: Regex, replacement: String },
+    /// Rewrite link and image targets starting with `from` to start with `to`
+    RewriteLinks { from: String, to: String },
+    /// Drop every line containing one of the phrases (case-insensitive)
+    RemoveLines { phrases: Vec<String> },
+}
+
+/// A named processor, optionally limited to one domain and its subdomains
+#[derive(Debug)]
+pub struct Rule {
+    pub name: String,
+    pub domain: Option<String>,
+    pub processor: Processor,
+}
+
+impl Rule {
+    /// Whether the rule applies to pages on `host`
+    fn applies_to(&self, host: &str) -> bool {
+        match &self.domain {
+            None => true,
+            Some(domain) => {
+                host.eq_ignore_ascii_case(domain)
+                    || host.to_ascii_lowercase().ends_with(&format!(".{}", domain))
+            }
+        }
+    }
+}
+
+/// Ordered list of post-processing rules
+#[derive(Debug, Default)]
+pub struct Pipeline {
+    pub rules: Vec<Rule>,
+    /// Operator patterns redacted along with emails and phone numbers
+    pub redact_patterns: Vec<Regex>,
+}
+
+/// What a request asks to have redacted from the markdown
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Redaction {
+    /// Emails, phone numbers and the operator's `[redact]` patterns
+    Pii,
+}
+
+/// Email address, including inside `mailto:` links
+static EMAIL_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"[A-Za-z0-9._%+-]+@[A-Za-z0-9-]+(?:\.[A-Za-z0-9-]+)*\.[A-Za-z]{2,}")
+        .expect("valid regex")
+});
+
+/// Phone number in common national and international formats, e.g.
+/// `+1 (555) 123-4567` or `020 7946 0958`; dates like `2024-01-15` don't match
+static PHONE_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?:\+\d{1,3}[\s.-]?)?(?:\(\d{2,4}\)[\s.-]?|\b\d{2,4}[\s.-])\d{3,4}[\s.-]\d{3,4}\b")
+        .expect("valid regex")
+});
+
+/// Markdown link or image target: `](` then the target up to whitespace or `)`
+static LINK_TARGET_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(\]\(<?)([^)\s>]+)").expect("valid regex"));
+
+/// Rules file layout
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RulesFile {
+    #[serde(default)]
+    rule: Vec<RawRule>,
+    redact: Option<RawRedact>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawRedact {
+    patterns: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawRule {
+    name: String,
+    domain: Option<String>,
+    #[serde(rename = "type")]
+    kind: String,
+    pattern: Option<String>,
+    replacement: Option<String>,
+    from: Option<String>,
+    to: Option<String>,
+    phrases: Option<Vec<String>>,
+}
+
+impl Pipeline {
+    /// Parse a TOML rules file, reporting every invalid rule
+    pub fn from_toml(text: &str) -> Result<Self, Vec<String>> {
+        let file: RulesFile = toml::from_str(text).map_err(|e| vec![e.message().to_string()])?;
+
+        let mut rules = Vec::new();
+        let mut errors = Vec::new();
+        for raw in file.rule {
+            match Self::build_rule(raw) {
+                Ok(rule) => rules.push(rule),
+                Err(e) => errors.push(e),
+            }
+        }
+
+        let mut redact_patterns = Vec::new();
+        for pattern in file.redact.map(|r| r.patterns).unwrap_or_default() {
+            match Regex::new(&pattern) {
+                Ok(regex) => redact_patterns.push(regex),
+                Err(e) => errors.push(format!("redact: invalid pattern: {}", e)),
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(Self {
+                rules,
+                redact_patterns,
+            })
+        } else {
+            Err(errors)
+        }
+    }
+
+    fn build_rule(raw: RawRule) -> Result<Rule, String> {
+        let missing =
+            |field: &str| format!("rule '{}': {} requires '{}'", raw.name, raw.kind, field);
+
+        let processor = match raw.kind.as_str() {
+            "replace" => {
+                let pattern = raw.pattern.as_deref().ok_or_else(|| missing("pattern"))?;
+                Processor::Replace {
+                    pattern: Regex::new(pattern)
+                        .map_err(|e| format!("rule '{}': invalid pattern: {}", raw.name, e))?,
+                    replacement: raw.replacement.clone().unwrap_or_default(),
+                }
+            }
+            "rewrite_links" => Processor::RewriteLinks {
+                from: raw.from.clone().ok_or_else(|| missing("from"))?,
+                to: raw.to.clone().ok_or_else(|| missing("to"))?,
+            },
+            "remove_lines" => Processor::RemoveLines {
+                phrases: raw
+                    .phrases
+                    .as_ref()
+                    .filter(|p| !p.is_empty())
+                    .ok_or_else(|| missing("phrases"))?
+                    .iter()
+                    .map(|p| p.to_lowercase())
+                    .collect(),
+            },
+            other => return Err(format!("rule '{}': unknown type '{}'", raw.name, other)),
+        };
+
+        Ok(Rule {
+            domain: raw
+                .domain
+                .filter(|d| d != "*")
+                .map(|d| d.trim_start_matches('.').to_ascii_lowercase()),
+            name: raw.name,
+            processor,
+        })
+    }
+
+    /// Rules that apply to pages on `host`, in order
+    pub fn rules_for<'a>(&'a self, host: &'a str) -> impl Iterator<Item = &'a Rule> {
+        self.rules.iter().filter(move |rule| rule.applies_to(host))
+    }
+
+    /// Apply every rule for `host` in order
+    pub fn apply(&self, host: &str, markdown: &str) -> String {
+        let mut markdown = markdown.to_string();
+
+        for rule in self.rules_for(host) {
+            tracing::trace!("Applying post-processor {} to {}", rule.name, host);
+            markdown = match &rule.processor {
+                Processor::Replace {
+                    pattern,
+                    replacement,
+                } => pattern
+                    .replace_all(&markdown, replacement.as_str())
+                    .into_owned(),
+                Processor::RewriteLinks { from, to } => LINK_TARGET_RE
+                    .replace_all(&markdown, |caps: &regex::Captures| {
+                        match caps[2].strip_prefix(from.as_str()) {
+                            Some(rest) => format!("{}{}{}", &caps[1], to, rest),
+                            None => caps[0].to_string(),
+                        }
+                    })
+                    .into_owned(),
+                Processor::RemoveLines { phrases } => {
+                    let mut kept = String::with_capacity(markdown.len());
+                    for line in markdown.split_inclusive('\n') {
+                        let lower = line.to_lowercase();
+                        if !phrases.iter().any(|phrase| lower.contains(phrase)) {
+                            kept.push_str(line);
+                        }
+                    }
+                    kept
+                }
+            };
+        }
+
+        markdown
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Replace what `redaction` covers with `[email]`, `[phone]` or `[redacted]`
+    pub fn redact(&self, redaction: Redaction, markdown: &str) -> String {
+        match redaction {
+            Redaction::Pii => {
+                let mut markdown = EMAIL_RE.replace_all(markdown, "[email]").into_owned();
+                markdown = PHONE_RE.replace_all(&markdown, "[phone]").into_owned();
+                for pattern in &self.redact_patterns {
+                    markdown = pattern.replace_all(&markdown, "[redacted]").into_owned();
+                }
+                markdown
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RULES: &str = r#"
+        [[rule]]
+        name = "strip-newsletter"
+        type = "remove_lines"
+        phrases = ["Subscribe to our newsletter"]
+
+        [[rule]]
+        name = "docs-mirror"
+        domain = "example.com"
+        type = "rewrite_links"
+        from = "https://example.com/"
+        to = "https://mirror.example.org/"
+
+        [[rule]]
+        name = "brand"
+        domain = "other.example"
+        type = "replace"
+        pattern = "(?i)acme corp"
+        replacement = "ACME"
+    "#;
+
+    #[test]
+    fn test_pipeline_applies_rules_in_scope() {
+        let pipeline = Pipeline::from_toml(RULES).unwrap();
+        let markdown = "# Docs\n\
+            SUBSCRIBE to our newsletter!\n\
+            See [setup](https://example.com/setup) and ![logo](https://cdn.example.net/a.png)\n\
+            Made by Acme Corp\n";
+
+        let out = pipeline.apply("docs.example.com", markdown);
+        assert_eq!(
+            out,
+            "# Docs\n\
+            See [setup](https://mirror.example.org/setup) and ![logo](https://cdn.example.net/a.png)\n\
+            Made by Acme Corp\n"
+        );
+
+        let out = pipeline.apply("other.example", markdown);
+        assert!(out.contains("Made by ACME"));
+        assert!(out.contains("(https://example.com/setup)"));
+        assert!(!out.contains("newsletter"));
+    }
+
+    #[test]
+    fn test_domain_matching() {
+        let rule = |domain: Option<&str>| Rule {
+            name: "r".to_string(),
+            domain: domain.map(str::to_string),
+            processor: Processor::RemoveLines { phrases: vec![] },
+        };
+        assert!(rule(None).applies_to("anything.test"));
+        assert!(rule(Some("example.com")).applies_to("EXAMPLE.com"));
+        assert!(rule(Some("example.com")).applies_to("www.example.com"));
+        assert!(!rule(Some("example.com")).applies_to("badexample.com"));
+    }
+
+    #[test]
+    fn test_invalid_rules_reported() {
+        let errors = Pipeline::from_toml(
+            r#"
+            [[rule]]
+            name = "a"
+            type = "replace"
+            pattern = "("
+
+            [[rule]]
+            name = "b"
+            type = "rewrite_links"
+            from = "https://x/"
+
+            [[rule]]
+            name = "c"
+            type = "shout"
+            "#,
+        )
+        .unwrap_err();
+        assert_eq!(errors.len(), 3);
+        assert!(errors[0].contains("invalid pattern"));
+        assert!(errors[1].contains("requires 'to'"));
+        assert!(errors[2].contains("unknown type"));
+
+        assert!(Pipeline::from_toml("[[rule]]\nname = 1").is_err());
+    }
+
+    #[test]
+    fn test_redact_pii() {
+        let pipeline = Pipeline::from_toml("[redact]\npatterns = ['ACCT-\\d{4}']").unwrap();
+        let markdown = "Mail [jane](mailto:jane.doe@mail.example.com) or call +1 (555) 123-4567 \
+                        or 020 7946 0958 about ACCT-1234.\nReleased 2024-01-15, version 1.2.3.";
+
+        assert_eq!(
+            pipeline.redact(Redaction::Pii, markdown),
+            "Mail [jane](mailto:[email]) or call [phone] \
+             or [phone] about [redacted].\nReleased 2024-01-15, version 1.2.3."
+        );
+
+        let errors = Pipeline::from_toml("[redact]\npatterns = ['(']").unwrap_err();
+        assert!(errors[0].contains("redact: invalid pattern"));
+    }
+}
@@ -0,0 +1,119 @@
+//! Warming the cache ahead of traffic
+//!
+//! `POST /prefetch` takes a list of URLs, queues them and returns at once;
+//! each page is then fetched and converted in the background exactly as a
+//! proxy request for it would be, so the first real request is a cache hit.
+//! Prefetches from every request share one pool of permits, so queueing a
+//! large documentation set doesn't crowd out live traffic, and pages of one
+//! site are spaced by the crawl delay. Each prefetch has an id, under which
+//! `/graph/{id}` serves the links between its pages.
+
+use crate::graph::{LinkGraph, Page};
+use crate::{process_url_throttled, AppState, ProxyOptions};
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use futures_util::future::join_all;
+use mdwnio::error::{MdwnError, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use url::Url;
+
+/// URLs accepted in one prefetch request
+const MAX_PREFETCH_URLS: usize = 1000;
+
+/// Pages prefetched at the same time, across all requests
+pub const PREFETCH_CONCURRENCY: usize = 4;
+
+/// JSON body for `POST /prefetch`
+#[derive(Deserialize)]
+struct PrefetchRequest {
+    urls: Vec<String>,
+}
+
+/// What was queued
+#[derive(Serialize)]
+struct PrefetchResponse {
+    /// Distinct URLs queued for prefetching
+    queued: usize,
+    /// Id of the prefetch, for `/graph/{id}`
+    id: String,
+}
+
+/// Queue a JSON list of URLs to be fetched into the cache in the background
+pub async fn prefetch_handler(State(state): State<AppState>, body: Bytes) -> Result<Response> {
+    let request: PrefetchRequest = serde_json::from_slice(&body)
+        .map_err(|e| MdwnError::ParseError(format!("Invalid JSON body: {}", e)))?;
+    if request.urls.len() > MAX_PREFETCH_URLS {
+        return Err(MdwnError::ParseError(format!(
+            "Prefetch has {} URLs, at most {} are accepted",
+            request.urls.len(),
+            MAX_PREFETCH_URLS
+        )));
+    }
+
+    // Reject the whole list up front, since nobody sees per-URL errors later
+    let mut seen = HashSet::new();
+    let mut urls = Vec::new();
+    for url in request.urls {
+        let parsed = state.fetcher.parse_url(&url)?;
+        if seen.insert(parsed.to_string()) {
+            urls.push((url, parsed));
+        }
+    }
+
+    let queued = urls.len();
+    let id = state.graphs.start();
+    tokio::spawn(prefetch(state, id.clone(), urls));
+    Ok((StatusCode::ACCEPTED, Json(PrefetchResponse { queued, id })).into_response())
+}
+
+/// Fetch every URL into the cache, logging how many failed, then keep the
+/// link graph of the pages
+async fn prefetch(state: AppState, id: String, urls: Vec<(String, Url)>) {
+    let total = urls.len();
+    let pages = join_all(urls.into_iter().map(|(url, parsed)| {
+        let state = state.clone();
+        async move {
+            let _permit = state
+                .prefetches
+                .acquire()
+                .await
+                .expect("semaphore never closed");
+            let budget = state.fetcher.budget();
+            match process_url_throttled(&state, &url, &ProxyOptions::default(), &budget).await {
+                Ok((content, _)) => {
+                    // Links are relative to where the page was redirected to
+                    let final_url = content
+                        .final_url
+                        .as_deref()
+                        .and_then(|u| Url::parse(u).ok());
+                    let url = final_url.unwrap_or(parsed);
+                    Page::fetched(url, content.title, &content.markdown)
+                }
+                Err(e) => {
+                    tracing::debug!("Prefetch of {} failed: {}", url, e);
+                    Page::failed(parsed)
+                }
+            }
+        }
+    }))
+    .await;
+
+    let failed = pages.iter().filter(|page| page.links.is_none()).count();
+    if failed > 0 {
+        tracing::warn!(
+            "Prefetched {} of {} URLs, {} failed",
+            total - failed,
+            total,
+            failed
+        );
+    } else {
+        tracing::info!("Prefetched {} URLs", total);
+    }
+    state.graphs.finish(&id, LinkGraph::build(pages));
+}
@@ -0,0 +1,143 @@
+//! `Range` requests against served markdown
+//!
+//! Large documents can be fetched in pieces, or a broken download resumed.
+//! Only a single `bytes=` range is supported; anything else gets the whole
+//! document, which RFC 9110 allows a server to do with any range request.
+
+use axum::{
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+
+/// A successful response carrying `body`, or the part of it the client's
+/// `Range` header asks for
+pub fn ranged_response(
+    request_headers: &HeaderMap,
+    mut headers: HeaderMap,
+    body: String,
+) -> Response {
+    headers.insert(
+        header::ACCEPT_RANGES,
+        "bytes".parse().expect("valid header value"),
+    );
+
+    let range = request_headers
+        .get(header::RANGE)
+        .and_then(|value| value.to_str().ok())
+        .filter(|_| if_range_matches(request_headers, &headers));
+    let Some(range) = range else {
+        return (StatusCode::OK, headers, body).into_response();
+    };
+
+    let len = body.len();
+    match parse_range(range, len) {
+        Some(Ok((start, end))) => {
+            let content_range = format!("bytes {}-{}/{}", start, end, len);
+            headers.insert(
+                header::CONTENT_RANGE,
+                content_range.parse().expect("valid header value"),
+            );
+            // Byte offsets may split a character, so the part goes out as bytes
+            let part = body.into_bytes()[start..=end].to_vec();
+            (StatusCode::PARTIAL_CONTENT, headers, part).into_response()
+        }
+        Some(Err(())) => {
+            let content_range = format!("bytes */{}", len);
+            headers.insert(
+                header::CONTENT_RANGE,
+                content_range.parse().expect("valid header value"),
+            );
+            (StatusCode::RANGE_NOT_SATISFIABLE, headers).into_response()
+        }
+        None => (StatusCode::OK, headers, body).into_response(),
+    }
+}
+
+/// Whether an `If-Range` precondition (if any) matches the response's `ETag`
+///
+/// Dates aren't compared: without a last-modified time to check them
+/// against, the whole document is the safe answer.
+fn if_range_matches(request_headers: &HeaderMap, headers: &HeaderMap) -> bool {
+    let Some(if_range) = request_headers.get(header::IF_RANGE) else {
+        return true;
+    };
+    // Weak tags never match for ranges
+    headers
+        .get(header::ETAG)
+        .is_some_and(|etag| etag == if_range && !etag.as_bytes().starts_with(b"W/"))
+}
+
+/// Inclusive byte range of a single-range `Range` header for a body of `len`
+///
+/// `None` when the header should be ignored (not bytes, several ranges,
+/// malformed), `Some(Err(()))` when the range lies outside the body.
+fn parse_range(range: &str, len: usize) -> Option<Result<(usize, usize), ()>> {
+    let spec = range.trim().strip_prefix("bytes=")?;
+    if spec.contains(',') {
+        return None;
+    }
+    let (start, end) = spec.split_once('-')?;
+    let (start, end) = (start.trim(), end.trim());
+
+    let (start, end) = if start.is_empty() {
+        // Suffix range: the last N bytes
+        let suffix: usize = end.parse().ok()?;
+        if suffix == 0 {
+            return Some(Err(()));
+        }
+        (len.saturating_sub(suffix), len.checked_sub(1)?)
+    } else {
+        let start: usize = start.parse().ok()?;
+        let end = match end {
+            "" => usize::MAX,
+            end => end.parse().ok()?,
+        };
+        if end < start {
+            return None;
+        }
+        (start, end.min(len.saturating_sub(1)))
+    };
+
+    if start >= len {
+        return Some(Err(()));
+    }
+    Some(Ok((start, end)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(parse_range("bytes=0-4", 10), Some(Ok((0, 4))));
+        assert_eq!(parse_range("bytes=5-", 10), Some(Ok((5, 9))));
+        assert_eq!(parse_range("bytes=5-100", 10), Some(Ok((5, 9))));
+        assert_eq!(parse_range("bytes=-3", 10), Some(Ok((7, 9))));
+        assert_eq!(parse_range("bytes=-30", 10), Some(Ok((0, 9))));
+        assert_eq!(parse_range("bytes=10-", 10), Some(Err(())));
+        assert_eq!(parse_range("bytes=-0", 10), Some(Err(())));
+        assert_eq!(parse_range("bytes=0-1,4-5", 10), None);
+        assert_eq!(parse_range("bytes=4-1", 10), None);
+        assert_eq!(parse_range("lines=1-2", 10), None);
+        assert_eq!(parse_range("bytes=-3", 0), None);
+    }
+
+    #[test]
+    fn test_if_range() {
+        let mut request = HeaderMap::new();
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ETAG, "\"abc\"".parse().unwrap());
+        assert!(if_range_matches(&request, &headers));
+
+        request.insert(header::IF_RANGE, "\"abc\"".parse().unwrap());
+        assert!(if_range_matches(&request, &headers));
+        request.insert(header::IF_RANGE, "\"old\"".parse().unwrap());
+        assert!(!if_range_matches(&request, &headers));
+        request.insert(
+            header::IF_RANGE,
+            "Tue, 01 Oct 2024 00:00:00 GMT".parse().unwrap(),
+        );
+        assert!(!if_range_matches(&request, &headers));
+    }
+}
@@ -0,0 +1,177 @@
+//! Per-client rate limiting
+//!
+//! Every request to a page makes this server fetch one, so an open
+//! deployment lets anyone point its bandwidth at a site. With
+//! `RATE_LIMIT_RPS` set each client gets a token bucket: it refills at that
+//! rate up to `RATE_LIMIT_BURST` requests, and requests finding it empty get
+//! `429 Too Many Requests` with `Retry-After`. Clients are told apart by
+//! address, IPv6 ones by their /64 since a host usually has the whole
+//! prefix. Behind a reverse proxy, `TRUST_FORWARDED_FOR` takes the address
+//! the proxy appended to `X-Forwarded-For` instead of the proxy's own.
+
+use crate::config::Config;
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use mdwnio::error::MdwnError;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Clients tracked before full buckets are dropped; grows with the clients
+/// still limited
+const MIN_PRUNE_SIZE: usize = 1024;
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+struct Buckets {
+    clients: HashMap<IpAddr, Bucket>,
+    prune_at: usize,
+}
+
+/// Token buckets of recent clients, shared across requests
+#[derive(Clone)]
+pub struct RateLimiter {
+    /// Tokens added per second
+    rate: f64,
+    /// Most tokens a bucket holds
+    burst: f64,
+    trust_forwarded_for: bool,
+    buckets: Arc<Mutex<Buckets>>,
+}
+
+impl RateLimiter {
+    /// The configured limiter, `None` if rate limiting is off
+    pub fn from_config(config: &Config) -> Option<Self> {
+        (config.rate_limit_rps > 0.0).then(|| Self {
+            rate: config.rate_limit_rps,
+            burst: f64::from(config.rate_limit_burst),
+            trust_forwarded_for: config.trust_forwarded_for,
+            buckets: Arc::new(Mutex::new(Buckets {
+                clients: HashMap::new(),
+                prune_at: MIN_PRUNE_SIZE,
+            })),
+        })
+    }
+
+    /// Take a token from a client's bucket, or say how long until there is one
+    fn check(&self, client: IpAddr, now: Instant) -> Result<(), Duration> {
+        let mut buckets = self.buckets.lock().expect("rate limit lock poisoned");
+        if buckets.clients.len() >= buckets.prune_at {
+            let full_after = Duration::from_secs_f64(self.burst / self.rate);
+            buckets
+                .clients
+                .retain(|_, bucket| now.duration_since(bucket.updated) < full_after);
+            buckets.prune_at = (buckets.clients.len() * 2).max(MIN_PRUNE_SIZE);
+        }
+
+        let bucket = buckets.clients.entry(client).or_insert(Bucket {
+            tokens: self.burst,
+            updated: now,
+        });
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.burst);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / self.rate))
+        }
+    }
+
+    /// Address a request is limited by, `None` if it has none
+    fn client(&self, request: &Request) -> Option<IpAddr> {
+        let forwarded = self
+            .trust_forwarded_for
+            .then(|| request.headers().get("x-forwarded-for"))
+            .flatten()
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.rsplit(',').next())
+            .and_then(|last| last.trim().parse().ok());
+        let peer = || {
+            let ConnectInfo(addr) = request.extensions().get::<ConnectInfo<SocketAddr>>()?;
+            Some(addr.ip())
+        };
+        forwarded.or_else(peer).map(client_key)
+    }
+}
+
+/// IPv4 addresses as they are, IPv6 ones by their /64
+fn client_key(ip: IpAddr) -> IpAddr {
+    match ip.to_canonical() {
+        IpAddr::V6(v6) => {
+            let prefix = u128::from(v6) & !(u128::from(u64::MAX));
+            IpAddr::V6(Ipv6Addr::from(prefix))
+        }
+        v4 => v4,
+    }
+}
+
+/// Middleware answering `429` to clients out of tokens
+pub async fn rate_limit(
+    State(limiter): State<RateLimiter>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(client) = limiter.client(&request) else {
+        return next.run(request).await;
+    };
+    match limiter.check(client, Instant::now()) {
+        Ok(()) => next.run(request).await,
+        Err(wait) => MdwnError::RateLimited(wait.as_secs_f64().ceil() as u64).into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(rate: f64, burst: u32) -> RateLimiter {
+        let mut config = Config::for_tests();
+        config.rate_limit_rps = rate;
+        config.rate_limit_burst = burst;
+        RateLimiter::from_config(&config).unwrap()
+    }
+
+    #[test]
+    fn test_token_bucket() {
+        let limiter = limiter(2.0, 3);
+        let client: IpAddr = "192.0.2.1".parse().unwrap();
+        let other: IpAddr = "192.0.2.2".parse().unwrap();
+        let start = Instant::now();
+
+        for _ in 0..3 {
+            assert_eq!(limiter.check(client, start), Ok(()));
+        }
+        assert_eq!(
+            limiter.check(client, start),
+            Err(Duration::from_millis(500))
+        );
+        assert_eq!(limiter.check(other, start), Ok(()));
+
+        // Half a second refills one token, and waiting longer never more than the burst
+        let later = start + Duration::from_millis(500);
+        assert_eq!(limiter.check(client, later), Ok(()));
+        assert!(limiter.check(client, later).is_err());
+        let much_later = later + Duration::from_secs(60);
+        for _ in 0..3 {
+            assert_eq!(limiter.check(client, much_later), Ok(()));
+        }
+        assert!(limiter.check(client, much_later).is_err());
+    }
+
+    #[test]
+    fn test_client_key() {
+        let v6: IpAddr = "2001:db8:1:2:aaaa::1".parse().unwrap();
+        assert_eq!(client_key(v6), "2001:db8:1:2::".parse::<IpAddr>().unwrap());
+        let mapped: IpAddr = "::ffff:192.0.2.1".parse().unwrap();
+        assert_eq!(client_key(mapped), "192.0.2.1".parse::<IpAddr>().unwrap());
+    }
+}
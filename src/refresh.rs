@@ -0,0 +1,464 @@
+//! Background scheduler that keeps configured URLs and sitemaps warm in the cache
+//!
+//! The refresh list (`REFRESH_LIST`) is a text file with one entry per line:
+//!
+//! ```text
+//! # kind    interval  url
+//! url       10m       https://example.com/changelog
+//! sitemap   6h        https://docs.example.com/sitemap.xml
+//! ```
+
+use crate::{cdn, process_url, AppState, ProxyOptions};
+use mdwnio::cache::CacheBackend;
+use mdwnio::error::{MdwnError, Result};
+use mdwnio::parse::{parse_sitemap, Sitemap, SitemapEntry};
+use serde::Serialize;
+use std::time::Duration;
+use tokio::time::Instant;
+use url::Url;
+
+/// Longest wait before the first refresh, spreading startup load
+const MAX_INITIAL_DELAY: Duration = Duration::from_secs(60);
+
+/// Ceiling for the failure backoff (unless the interval itself is longer)
+const MAX_BACKOFF: Duration = Duration::from_secs(6 * 60 * 60);
+
+/// Upper bound on page URLs refreshed per sitemap entry
+const MAX_SITEMAP_URLS: usize = 10_000;
+
+/// Upper bound on child sitemaps followed from a sitemap index
+const MAX_CHILD_SITEMAPS: usize = 50;
+
+/// What a refresh list entry points at
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RefreshKind {
+    /// A single page
+    Url,
+    /// A sitemap (or sitemap index) whose pages are all refreshed
+    Sitemap,
+}
+
+/// One line of the refresh list
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct RefreshEntry {
+    pub kind: RefreshKind,
+    pub interval_secs: u64,
+    pub url: String,
+}
+
+/// A line without its comment: `#` at the start or after whitespace, so
+/// fragments like `https://example.com/docs#install` stay part of the URL
+fn strip_comment(line: &str) -> &str {
+    let comment = line.char_indices().find(|&(i, c)| {
+        c == '#'
+            && line[..i]
+                .chars()
+                .next_back()
+                .is_none_or(char::is_whitespace)
+    });
+    match comment {
+        Some((i, _)) => &line[..i],
+        None => line,
+    }
+}
+
+/// Parse a refresh list, reporting every bad line
+pub fn parse_refresh_list(text: &str) -> std::result::Result<Vec<RefreshEntry>, Vec<String>> {
+    let mut entries = Vec::new();
+    let mut errors = Vec::new();
+
+    for (number, line) in text.lines().enumerate() {
+        let line = strip_comment(line).trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let [kind, interval, url] = fields[..] else {
+            errors.push(format!(
+                "line {}: expected '<url|sitemap> <interval> <url>'",
+                number + 1
+            ));
+            continue;
+        };
+
+        let kind = match kind {
+            "url" => RefreshKind::Url,
+            "sitemap" => RefreshKind::Sitemap,
+            other => {
+                errors.push(format!("line {}: unknown kind '{}'", number + 1, other));
+                continue;
+            }
+        };
+        let Some(interval_secs) = parse_interval(interval) else {
+            errors.push(format!(
+                "line {}: invalid interval '{}'",
+                number + 1,
+                interval
+            ));
+            continue;
+        };
+        if Url::parse(url).is_err() {
+            errors.push(format!("line {}: invalid URL '{}'", number + 1, url));
+            continue;
+        }
+
+        entries.push(RefreshEntry {
+            kind,
+            interval_secs,
+            url: url.to_string(),
+        });
+    }
+
+    if errors.is_empty() {
+        Ok(entries)
+    } else {
+        Err(errors)
+    }
+}
+
+/// Parse an interval like `90`, `90s`, `15m`, `6h` or `1d` into nonzero seconds
+fn parse_interval(s: &str) -> Option<u64> {
+    let (number, unit) = match s.char_indices().find(|(_, c)| !c.is_ascii_digit()) {
+        Some((i, _)) => s.split_at(i),
+        None => (s, "s"),
+    };
+    let multiplier = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return None,
+    };
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .filter(|&secs| secs > 0)
+}
+
+/// Start one background task per refresh list entry
+pub fn spawn(state: AppState) {
+    for entry in state.config.refresh_list.clone() {
+        let state = state.clone();
+        tokio::spawn(async move { run(state, entry).await });
+    }
+}
+
+/// Refresh an entry forever, backing off while it keeps failing
+async fn run(state: AppState, entry: RefreshEntry) {
+    let interval = Duration::from_secs(entry.interval_secs);
+    tokio::time::sleep(interval.min(MAX_INITIAL_DELAY).mul_f64(rand::random())).await;
+
+    let mut failures = 0;
+    loop {
+        match refresh_entry(&state, &entry).await {
+            Ok(count) => {
+                failures = 0;
+                tracing::info!("Refreshed {} page(s) from {}", count, entry.url);
+            }
+            Err(e) => {
+                failures += 1;
+                tracing::warn!(
+                    "Refresh of {} failed ({} in a row): {}",
+                    entry.url,
+                    failures,
+                    e
+                );
+            }
+        }
+        tokio::time::sleep(next_delay(interval, failures)).await;
+    }
+}
+
+/// Delay before the next run: the interval, doubled per consecutive failure, +/-10% jitter
+fn next_delay(interval: Duration, failures: u32) -> Duration {
+    let backoff = interval
+        .saturating_mul(1u32 << failures.min(16))
+        .min(MAX_BACKOFF)
+        .max(interval);
+    backoff.mul_f64(rand::random_range(0.9..1.1))
+}
+
+/// Refresh everything an entry points at, returning the number of pages refreshed
+async fn refresh_entry(state: &AppState, entry: &RefreshEntry) -> Result<usize> {
+    match entry.kind {
+        RefreshKind::Url => {
+            refresh_url(state, &entry.url).await?;
+            Ok(1)
+        }
+        RefreshKind::Sitemap => {
+            let pages = sitemap_urls(state, &entry.url, None).await?;
+            let (mut refreshed, mut unchanged, mut failed) = (0, 0, 0);
+            for page in &pages {
+                if is_unchanged(state, page).await {
+                    unchanged += 1;
+                    continue;
+                }
+                match refresh_url(state, page.loc.as_str()).await {
+                    Ok(()) => refreshed += 1,
+                    Err(e) => {
+                        failed += 1;
+                        tracing::debug!("Refresh of {} failed: {}", page.loc, e);
+                    }
+                }
+            }
+            if unchanged > 0 {
+                tracing::debug!("Skipped {} unchanged pages from {}", unchanged, entry.url);
+            }
+            if refreshed == 0 && failed > 0 {
+                return Err(MdwnError::FetchFailed(format!(
+                    "None of the {} changed sitemap URLs could be refreshed",
+                    failed
+                )));
+            }
+            Ok(refreshed)
+        }
+    }
+}
+
+/// Whether the cached copy of a sitemap page is at least as new as its `lastmod`
+async fn is_unchanged(state: &AppState, page: &SitemapEntry) -> bool {
+    let Some(lastmod) = page.lastmod else {
+        return false;
+    };
+    state
+        .cache
+        .get(page.loc.as_str(), "")
+        .await
+        .is_some_and(|cached| cached.fetched_at >= lastmod)
+}
+
+/// Re-fetch a page with default options and overwrite its cache entry,
+/// queueing a digest entry and purging it from the CDN if its markdown changed
+async fn refresh_url(state: &AppState, url: &str) -> Result<()> {
+    let options = ProxyOptions {
+        refresh: true,
+        ..Default::default()
+    };
+    let parsed = state.fetcher.parse_url(url)?;
+    state.crawl_throttle.wait(&state.fetcher, &parsed).await;
+
+    let previous = if state.digests.is_some() || state.purger.is_some() {
+        state.cache.get(url, "").await.and_then(|c| c.content_hash)
+    } else {
+        None
+    };
+
+    let (content, _) = process_url(state, url, &options, &[]).await?;
+
+    let (Some(previous), Some(hash)) = (previous, &content.content_hash) else {
+        return Ok(());
+    };
+    if &previous == hash {
+        return Ok(());
+    }
+    if let Some(digests) = &state.digests {
+        digests.record(url, &previous, hash);
+    }
+    if let Some(purger) = &state.purger {
+        if let Err(e) = purger.purge(&[cdn::url_key(&parsed)]).await {
+            tracing::warn!("{}", e);
+        }
+    }
+    Ok(())
+}
+
+/// Pages listed by a sitemap, following one level of sitemap index
+///
+/// With a `deadline`, child sitemaps not fetched by then are left out.
+pub async fn sitemap_urls(
+    state: &AppState,
+    sitemap_url: &str,
+    deadline: Option<Instant>,
+) -> Result<Vec<SitemapEntry>> {
+    let url = state.fetcher.parse_url(sitemap_url)?;
+
+    let mut urls = match fetch_sitemap(state, &url, deadline).await? {
+        Sitemap::Urls(urls) => urls,
+        Sitemap::Index(children) => {
+            let mut urls = Vec::new();
+            for child in children.iter().take(MAX_CHILD_SITEMAPS) {
+                match fetch_sitemap(state, &child.loc, deadline).await {
+                    Ok(Sitemap::Urls(child_urls)) => urls.extend(child_urls),
+                    Ok(Sitemap::Index(_)) => {
+                        tracing::warn!("Skipping nested sitemap index {}", child.loc)
+                    }
+                    Err(MdwnError::Timeout(_)) => {
+                        tracing::warn!("Out of time for child sitemaps of {}", url);
+                        break;
+                    }
+                    Err(e) => tracing::warn!("Failed to fetch sitemap {}: {}", child.loc, e),
+                }
+                if urls.len() >= MAX_SITEMAP_URLS {
+                    break;
+                }
+            }
+            urls
+        }
+    };
+
+    urls.truncate(MAX_SITEMAP_URLS);
+    Ok(urls)
+}
+
+/// Fetch and parse a sitemap, giving up with a timeout at `deadline`
+async fn fetch_sitemap(state: &AppState, url: &Url, deadline: Option<Instant>) -> Result<Sitemap> {
+    let fetch = async {
+        state.crawl_throttle.wait(&state.fetcher, url).await;
+        let budget = state.fetcher.budget();
+        let response = state.fetcher.fetch(url, &budget).await?;
+        parse_sitemap(&response.body_as_string(), &response.final_url)
+    };
+    match deadline {
+        Some(deadline) => tokio::time::timeout_at(deadline, fetch)
+            .await
+            .map_err(|_| MdwnError::Timeout(state.config.proxy_timeout))?,
+        None => fetch.await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[test]
+    fn test_parse_refresh_list() {
+        let text = "
+            # Keep the docs warm
+            url      10m  https://example.com/changelog#latest
+            sitemap  6h   https://docs.example.com/sitemap.xml  # whole site
+        ";
+        assert_eq!(
+            parse_refresh_list(text).unwrap(),
+            vec![
+                RefreshEntry {
+                    kind: RefreshKind::Url,
+                    interval_secs: 600,
+                    url: "https://example.com/changelog#latest".to_string(),
+                },
+                RefreshEntry {
+                    kind: RefreshKind::Sitemap,
+                    interval_secs: 6 * 3600,
+                    url: "https://docs.example.com/sitemap.xml".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_refresh_list_errors() {
+        let text = "url 10m\nfeed 1h https://example.com/\nurl 0 https://example.com/\nurl 5 nope";
+        let errors = parse_refresh_list(text).unwrap_err();
+        assert_eq!(errors.len(), 4);
+        assert!(errors[0].starts_with("line 1:"));
+        assert!(errors[3].contains("invalid URL"));
+    }
+
+    #[test]
+    fn test_parse_interval() {
+        assert_eq!(parse_interval("90"), Some(90));
+        assert_eq!(parse_interval("15m"), Some(900));
+        assert_eq!(parse_interval("1d"), Some(86400));
+        assert_eq!(parse_interval("0s"), None);
+        assert_eq!(parse_interval("1w"), None);
+        assert_eq!(parse_interval("h"), None);
+    }
+
+    #[test]
+    fn test_next_delay_backoff() {
+        let interval = Duration::from_secs(600);
+        let within = |d: Duration, expected: u64| {
+            let expected = Duration::from_secs(expected);
+            d >= expected.mul_f64(0.9) && d <= expected.mul_f64(1.1)
+        };
+
+        assert!(within(next_delay(interval, 0), 600));
+        assert!(within(next_delay(interval, 2), 2400));
+        assert!(within(next_delay(interval, 30), 6 * 3600));
+
+        // Intervals longer than the backoff ceiling are never shortened
+        let daily = Duration::from_secs(86400);
+        assert!(within(next_delay(daily, 3), 86400));
+    }
+
+    #[tokio::test]
+    async fn test_refresh_sitemap_warms_cache() {
+        let server = MockServer::start().await;
+        let sitemap = format!(
+            "<urlset><url><loc>{0}/a.md</loc></url><url><loc>{0}/b.md</loc></url></urlset>",
+            server.uri()
+        );
+        Mock::given(method("GET"))
+            .and(path("/sitemap.xml"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(sitemap, "application/xml"))
+            .mount(&server)
+            .await;
+        for page in ["/a.md", "/b.md"] {
+            Mock::given(method("GET"))
+                .and(path(page))
+                .respond_with(ResponseTemplate::new(200).set_body_raw("# Page\n", "text/markdown"))
+                .expect(2)
+                .mount(&server)
+                .await;
+        }
+
+        let mut config = Config::for_tests();
+        config.crawl_delay_ms = 0;
+        let state = AppState::new(config).unwrap();
+        let entry = RefreshEntry {
+            kind: RefreshKind::Sitemap,
+            interval_secs: 60,
+            url: format!("{}/sitemap.xml", server.uri()),
+        };
+
+        // Each run refetches every page, bypassing the cache
+        assert_eq!(refresh_entry(&state, &entry).await.unwrap(), 2);
+        assert_eq!(refresh_entry(&state, &entry).await.unwrap(), 2);
+
+        let url = format!("{}/a.md", server.uri());
+        assert!(state.cache.get(&url, "").await.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_refresh_sitemap_skips_unchanged() {
+        let server = MockServer::start().await;
+        let sitemap = format!(
+            "<urlset>\
+                <url><loc>{0}/old.md</loc><lastmod>2000-01-01</lastmod></url>\
+                <url><loc>{0}/new.md</loc><lastmod>2999-01-01</lastmod></url>\
+            </urlset>",
+            server.uri()
+        );
+        Mock::given(method("GET"))
+            .and(path("/sitemap.xml"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(sitemap, "application/xml"))
+            .mount(&server)
+            .await;
+        // The old page is only fetched once, the one changing "in the future" every run
+        for (page, times) in [("/old.md", 1), ("/new.md", 2)] {
+            Mock::given(method("GET"))
+                .and(path(page))
+                .respond_with(ResponseTemplate::new(200).set_body_raw("# Page\n", "text/markdown"))
+                .expect(times)
+                .mount(&server)
+                .await;
+        }
+
+        let mut config = Config::for_tests();
+        config.crawl_delay_ms = 0;
+        let state = AppState::new(config).unwrap();
+        let entry = RefreshEntry {
+            kind: RefreshKind::Sitemap,
+            interval_secs: 60,
+            url: format!("{}/sitemap.xml", server.uri()),
+        };
+
+        assert_eq!(refresh_entry(&state, &entry).await.unwrap(), 2);
+        assert_eq!(refresh_entry(&state, &entry).await.unwrap(), 1);
+    }
+}
@@ -0,0 +1,298 @@
+//! Directory listings of hosted git repositories
+//!
+//! Repository browsers render file trees with JavaScript and wrap them in a lot
+//! of chrome. Tree URLs are instead listed from the host's API as markdown,
+//! with every entry linking back through the proxy: directories to their own
+//! listing, files to their raw content. GitHub lives in `github`; GitLab
+//! (including self-hosted instances) is handled here.
+
+use crate::convert::{encode_link_destination, escape_markdown};
+use crate::fetch::{FetchBudget, Fetcher};
+use serde::Deserialize;
+use url::Url;
+
+/// Entries per GitLab API page, its maximum
+const GITLAB_PER_PAGE: usize = 100;
+
+/// Entries listed at most, also the most GitHub's contents API returns
+pub(crate) const MAX_ENTRIES: usize = 1000;
+
+/// What a directory entry is
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum EntryKind {
+    Directory,
+    Submodule,
+    File,
+}
+
+/// One entry of a directory
+#[derive(Debug)]
+pub struct TreeEntry {
+    pub name: String,
+    pub kind: EntryKind,
+    /// Upstream URL the entry links to
+    pub url: String,
+}
+
+/// A repository directory
+#[derive(Debug)]
+pub struct Tree {
+    /// `owner/repo` or the GitLab project path
+    pub repository: String,
+    pub git_ref: String,
+    /// Directory within the repository, empty for the root
+    pub path: String,
+    pub entries: Vec<TreeEntry>,
+    /// Whether the directory has more entries than were listed
+    pub truncated: bool,
+}
+
+impl Tree {
+    /// Heading of the listing, e.g. `acme/widgets/src (main)`
+    pub fn title(&self) -> String {
+        if self.path.is_empty() {
+            format!("{} ({})", self.repository, self.git_ref)
+        } else {
+            format!("{}/{} ({})", self.repository, self.path, self.git_ref)
+        }
+    }
+}
+
+/// Render a directory as a list of links through the proxy, directories first
+pub fn tree_to_markdown(tree: &Tree, notice: Option<&str>) -> String {
+    let mut markdown = String::new();
+    if let Some(notice) = notice {
+        markdown.push_str(&format!("<!-- {} -->\n\n", notice));
+    }
+    markdown.push_str(&format!("# {}\n\n", tree.title()));
+
+    let mut entries: Vec<&TreeEntry> = tree.entries.iter().collect();
+    entries.sort_by(|a, b| a.kind.cmp(&b.kind).then_with(|| a.name.cmp(&b.name)));
+    if entries.is_empty() {
+        markdown.push_str("*Empty directory*\n");
+    }
+    for entry in entries {
+        let suffix = match entry.kind {
+            EntryKind::Directory => "/",
+            EntryKind::Submodule => " (submodule)",
+            EntryKind::File => "",
+        };
+        // Root-relative, so the link resolves against the proxy serving the listing
+        markdown.push_str(&format!(
+            "- [{}{}](/{})\n",
+            escape_markdown(&entry.name),
+            suffix,
+            encode_link_destination(&entry.url)
+        ));
+    }
+    if tree.truncated {
+        markdown.push_str(&format!(
+            "\n*Only the first {} entries are listed*\n",
+            tree.entries.len()
+        ));
+    }
+
+    markdown
+}
+
+#[derive(Deserialize)]
+struct GitLabEntry {
+    name: String,
+    path: String,
+    #[serde(rename = "type")]
+    kind: String,
+}
+
+/// A GitLab `/-/tree/` URL split into its parts
+struct GitLabTree {
+    project: String,
+    git_ref: String,
+    path: String,
+}
+
+fn gitlab_tree(url: &Url) -> Option<GitLabTree> {
+    let segments: Vec<&str> = url.path_segments()?.collect();
+    let dash = segments.iter().position(|s| *s == "-")?;
+    let ["tree", git_ref, path @ ..] = &segments[dash + 1..] else {
+        return None;
+    };
+    // Projects live at least one namespace deep
+    if dash < 2 || git_ref.is_empty() || segments[..dash].iter().any(|s| s.is_empty()) {
+        return None;
+    }
+    Some(GitLabTree {
+        project: segments[..dash].join("/"),
+        git_ref: git_ref.to_string(),
+        path: path.join("/").trim_end_matches('/').to_string(),
+    })
+}
+
+/// Repository tree API URL (first page) behind a GitLab `/-/tree/` URL
+pub fn gitlab_tree_api_url(url: &Url) -> Option<Url> {
+    let tree = gitlab_tree(url)?;
+    let mut api = url.clone();
+    api.set_path(&format!(
+        "/api/v4/projects/{}/repository/tree",
+        tree.project.replace('/', "%2F")
+    ));
+    api.set_fragment(None);
+    api.query_pairs_mut()
+        .clear()
+        .append_pair("ref", &tree.git_ref)
+        .append_pair("per_page", &GITLAB_PER_PAGE.to_string());
+    if !tree.path.is_empty() {
+        api.query_pairs_mut().append_pair("path", &tree.path);
+    }
+    Some(api)
+}
+
+/// Fetch the listing of a GitLab directory, or `None` if the URL isn't one or
+/// the host has no (public) API for it
+pub async fn fetch_gitlab_tree(fetcher: &Fetcher, budget: &FetchBudget, url: &Url) -> Option<Tree> {
+    let tree = gitlab_tree(url)?;
+    let api_url = gitlab_tree_api_url(url)?;
+    let origin = url.origin().ascii_serialization();
+
+    let mut listed: Vec<GitLabEntry> = Vec::new();
+    // Until a short page shows the end was reached
+    let mut truncated = true;
+    for page in 1..=MAX_ENTRIES.div_ceil(GITLAB_PER_PAGE) {
+        let mut page_url = api_url.clone();
+        page_url
+            .query_pairs_mut()
+            .append_pair("page", &page.to_string());
+        let response = match fetcher.fetch(&page_url, budget).await {
+            Ok(response) => response,
+            // Without a first page this isn't a GitLab listing after all
+            Err(e) if page == 1 => {
+                tracing::debug!("No GitLab API at {}: {}", page_url, e);
+                return None;
+            }
+            Err(e) => {
+                tracing::warn!("Stopped listing GitLab tree {}: {}", url, e);
+                break;
+            }
+        };
+        let entries: Vec<GitLabEntry> = match serde_json::from_slice(&response.body) {
+            Ok(entries) => entries,
+            Err(_) if page == 1 => return None,
+            Err(e) => {
+                tracing::warn!("Unexpected GitLab tree page from {}: {}", page_url, e);
+                break;
+            }
+        };
+        let last = entries.len() < GITLAB_PER_PAGE;
+        listed.extend(entries);
+        if last {
+            truncated = false;
+            break;
+        }
+    }
+
+    let entries = listed
+        .into_iter()
+        .filter_map(|entry| {
+            let (kind, view) = match entry.kind.as_str() {
+                "tree" => (EntryKind::Directory, "tree"),
+                "blob" => (EntryKind::File, "raw"),
+                // Submodule targets aren't in the tree, point at the entry itself
+                "commit" => (EntryKind::Submodule, "tree"),
+                _ => return None,
+            };
+            Some(TreeEntry {
+                name: entry.name,
+                kind,
+                url: format!(
+                    "{}/{}/-/{}/{}/{}",
+                    origin, tree.project, view, tree.git_ref, entry.path
+                ),
+            })
+        })
+        .collect();
+
+    Some(Tree {
+        repository: tree.project,
+        git_ref: tree.git_ref,
+        path: tree.path,
+        entries,
+        truncated,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gitlab_tree_api_url() {
+        let api = |url: &str| gitlab_tree_api_url(&Url::parse(url).unwrap()).map(String::from);
+        assert_eq!(
+            api("https://gitlab.com/group/sub/project/-/tree/main/src/lib").as_deref(),
+            Some(
+                "https://gitlab.com/api/v4/projects/group%2Fsub%2Fproject/repository/tree\
+                 ?ref=main&per_page=100&path=src%2Flib"
+            )
+        );
+        assert_eq!(
+            api("https://git.example.com/team/app/-/tree/v1.0?ref_type=tags").as_deref(),
+            Some(
+                "https://git.example.com/api/v4/projects/team%2Fapp/repository/tree\
+                 ?ref=v1.0&per_page=100"
+            )
+        );
+        assert_eq!(
+            api("https://gitlab.com/group/project/-/blob/main/README.md"),
+            None
+        );
+        assert_eq!(api("https://gitlab.com/project/-/tree/main"), None);
+    }
+
+    #[test]
+    fn test_tree_to_markdown() {
+        let entry = |name: &str, kind, url: &str| TreeEntry {
+            name: name.to_string(),
+            kind,
+            url: url.to_string(),
+        };
+        let tree = Tree {
+            repository: "acme/widgets".to_string(),
+            git_ref: "main".to_string(),
+            path: "src".to_string(),
+            entries: vec![
+                entry("main.rs", EntryKind::File, "https://raw.example/main.rs"),
+                entry(
+                    "vendor",
+                    EntryKind::Submodule,
+                    "https://github.com/acme/vendor",
+                ),
+                entry(
+                    "bin",
+                    EntryKind::Directory,
+                    "https://github.com/acme/widgets/tree/main/src/bin",
+                ),
+                entry(
+                    "notes (draft)_v2.md",
+                    EntryKind::File,
+                    "https://raw.example/notes (draft)_v2.md",
+                ),
+            ],
+            truncated: false,
+        };
+
+        assert_eq!(
+            tree_to_markdown(&tree, None),
+            "# acme/widgets/src (main)\n\n\
+             - [bin/](/https://github.com/acme/widgets/tree/main/src/bin)\n\
+             - [vendor (submodule)](/https://github.com/acme/vendor)\n\
+             - [main.rs](/https://raw.example/main.rs)\n\
+             - [notes (draft)\\_v2.md](/https://raw.example/notes%20%28draft%29_v2.md)\n"
+        );
+
+        let truncated = Tree {
+            truncated: true,
+            ..tree
+        };
+        assert!(tree_to_markdown(&truncated, None)
+            .ends_with("\n\n*Only the first 4 entries are listed*\n"));
+    }
+}
@@ -0,0 +1,239 @@
+//! Operator-defined URL rewrites applied before fetching
+//!
+//! Rules are read from a TOML file and tried in order; the first whose
+//! `from` pattern matches a requested URL replaces it with `to`:
+//!
+//! ```toml
+//! [[rewrite]]
+//! from = "medium.com/*"
+//! to = "scribe.rip/*"
+//!
+//! [[rewrite]]
+//! from = "*.m.wikipedia.org"
+//! to = "*.wikipedia.org"
+//! ```
+//!
+//! Patterns are matched against the host, path and query of a URL, without
+//! the scheme. A `*` in the host matches within one label run (no `/`), a
+//! `*` in the path matches anything, and each `*` in `to` is filled with what
+//! the corresponding `*` in `from` matched. A pattern without a path matches
+//! every path on its host, which is carried over to the target. The scheme
+//! is kept unless `to` gives one.
+
+use regex::Regex;
+use serde::Deserialize;
+use url::Url;
+
+/// A compiled `from` → `to` rewrite
+#[derive(Debug)]
+pub struct RewriteRule {
+    pub from: String,
+    pub to: String,
+    pattern: Regex,
+}
+
+/// Ordered list of rewrite rules, first match wins
+#[derive(Debug, Default)]
+pub struct RewriteRules {
+    pub rules: Vec<RewriteRule>,
+}
+
+/// Rules file layout
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RulesFile {
+    #[serde(default)]
+    rewrite: Vec<RawRewrite>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawRewrite {
+    from: String,
+    to: String,
+}
+
+impl RewriteRules {
+    /// Parse a TOML rules file, reporting every invalid rule
+    pub fn from_toml(text: &str) -> Result<Self, Vec<String>> {
+        let file: RulesFile = toml::from_str(text).map_err(|e| vec![e.message().to_string()])?;
+
+        let mut rules = Vec::new();
+        let mut errors = Vec::new();
+        for raw in file.rewrite {
+            match RewriteRule::new(&raw.from, &raw.to) {
+                Ok(rule) => rules.push(rule),
+                Err(e) => errors.push(e),
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(Self { rules })
+        } else {
+            Err(errors)
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Target of the first rule matching `url`, if any
+    ///
+    /// Rewrites aren't chained: the target is fetched even if another rule
+    /// would match it, so rules can't loop.
+    pub fn apply(&self, url: &Url) -> Option<String> {
+        let host = url.host_str()?;
+        let mut subject = match url.port() {
+            Some(port) => format!("{}:{}{}", host, port, url.path()),
+            None => format!("{}{}", host, url.path()),
+        };
+        if let Some(query) = url.query() {
+            subject.push('?');
+            subject.push_str(query);
+        }
+
+        let (rule, captures) = self
+            .rules
+            .iter()
+            .find_map(|r| Some((r, r.pattern.captures(&subject)?)))?;
+        let mut parts = rule.to.split('*');
+        let mut target = parts.next().unwrap_or_default().to_string();
+        for (i, part) in parts.enumerate() {
+            target.push_str(captures.get(i + 1).map_or("", |m| m.as_str()));
+            target.push_str(part);
+        }
+
+        if target.contains("://") {
+            Some(target)
+        } else {
+            Some(format!("{}://{}", url.scheme(), target))
+        }
+    }
+}
+
+impl RewriteRule {
+    fn new(from: &str, to: &str) -> Result<Self, String> {
+        let invalid = |reason: &str| format!("rewrite '{}': {}", from, reason);
+
+        let from_pattern = with_path(from.trim());
+        if from_pattern.starts_with('/') || from_pattern.contains("://") {
+            return Err(invalid("'from' must start with a host and have no scheme"));
+        }
+        let to = match to.trim() {
+            "" => return Err(invalid("'to' is empty")),
+            // A bare host takes the whole path along, like a bare pattern
+            to if !from.contains('/') => with_path(to),
+            to => to.to_string(),
+        };
+        if to.matches('*').count() > from_pattern.matches('*').count() {
+            return Err(invalid("'to' has more '*' than 'from'"));
+        }
+
+        // A `*` before the first `/` stays within the host
+        let mut regex = String::from("(?i)^");
+        let mut literal = String::new();
+        let mut in_path = false;
+        for c in from_pattern.chars() {
+            if c == '*' {
+                regex.push_str(&regex::escape(&literal));
+                regex.push_str(if in_path { "(.*)" } else { "([^/]*)" });
+                literal.clear();
+            } else {
+                in_path |= c == '/';
+                literal.push(c);
+            }
+        }
+        regex.push_str(&regex::escape(&literal));
+        regex.push('$');
+        let pattern = Regex::new(&regex).map_err(|e| invalid(&e.to_string()))?;
+
+        Ok(Self {
+            from: from.to_string(),
+            to,
+            pattern,
+        })
+    }
+}
+
+/// Pattern with `/*` appended if it names only a host
+fn with_path(pattern: &str) -> String {
+    if pattern.contains('/') {
+        pattern.to_string()
+    } else {
+        format!("{}/*", pattern)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RULES: &str = r#"
+[[rewrite]]
+from = "medium.com/*"
+to = "scribe.rip/*"
+
+[[rewrite]]
+from = "*.m.wikipedia.org"
+to = "*.wikipedia.org"
+
+[[rewrite]]
+from = "old.example.com/docs/*"
+to = "http://docs.example.net/*"
+"#;
+
+    fn apply(rules: &RewriteRules, url: &str) -> Option<String> {
+        rules.apply(&Url::parse(url).unwrap())
+    }
+
+    #[test]
+    fn test_apply() {
+        let rules = RewriteRules::from_toml(RULES).unwrap();
+        assert_eq!(
+            apply(&rules, "https://medium.com/@someone/post-123?source=rss").as_deref(),
+            Some("https://scribe.rip/@someone/post-123?source=rss")
+        );
+        assert_eq!(
+            apply(&rules, "https://en.m.wikipedia.org/wiki/Rust").as_deref(),
+            Some("https://en.wikipedia.org/wiki/Rust")
+        );
+        assert_eq!(
+            apply(&rules, "https://old.example.com/docs/intro").as_deref(),
+            Some("http://docs.example.net/intro")
+        );
+
+        // Host stars don't cross into the path, patterns are anchored
+        assert_eq!(
+            apply(&rules, "https://m.wikipedia.org/wiki/x.m.wikipedia.org"),
+            None
+        );
+        assert_eq!(apply(&rules, "https://notmedium.com/post"), None);
+        assert_eq!(apply(&rules, "https://medium.com.evil.test/post"), None);
+        assert_eq!(apply(&rules, "https://old.example.com/blog"), None);
+    }
+
+    #[test]
+    fn test_invalid_rules() {
+        let errors = RewriteRules::from_toml(
+            r#"
+[[rewrite]]
+from = "https://example.com/*"
+to = "example.net/*"
+
+[[rewrite]]
+from = "example.com/a"
+to = "example.net/*"
+
+[[rewrite]]
+from = "example.org"
+to = ""
+"#,
+        )
+        .unwrap_err();
+        assert_eq!(errors.len(), 3);
+        assert!(errors[1].contains("more '*'"));
+
+        assert!(RewriteRules::from_toml("[[rewrite]]\nfrom = \"a\"\nto = \"b\"\nx = 1").is_err());
+    }
+}
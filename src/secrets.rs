@@ -0,0 +1,144 @@
+//! Secrets from the environment or from mounted files
+//!
+//! A secret setting `NAME` can instead be given as `NAME_FILE`, the path of a
+//! file holding the value, which is how Docker and Kubernetes hand secrets to
+//! containers. Kubernetes rewrites mounted secrets in place when they are
+//! rotated, so file-backed secrets can be read again while running and every
+//! copy of a [`Secret`] sees the new value. Values never appear in `Debug`
+//! output or in serialized configuration.
+
+use serde::{Serialize, Serializer};
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+/// Placeholder shown instead of secret values
+pub const REDACTED: &str = "<redacted>";
+
+/// A credential whose copies share one value, updated when its file changes
+#[derive(Clone)]
+pub struct Secret {
+    value: Arc<RwLock<Arc<str>>>,
+    /// File the value is read from, `None` if it came from the environment
+    file: Option<PathBuf>,
+}
+
+impl Secret {
+    /// A fixed secret, e.g. from an environment variable
+    pub fn new(value: impl Into<String>) -> Self {
+        Self {
+            value: Arc::new(RwLock::new(value.into().into())),
+            file: None,
+        }
+    }
+
+    /// A secret read from a file, without the trailing newline
+    pub fn from_file(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        let value = read_secret_file(&path)?;
+        Ok(Self {
+            value: Arc::new(RwLock::new(value.into())),
+            file: Some(path),
+        })
+    }
+
+    /// The current value
+    pub fn expose(&self) -> Arc<str> {
+        self.value.read().expect("secret lock poisoned").clone()
+    }
+
+    pub fn file(&self) -> Option<&Path> {
+        self.file.as_deref()
+    }
+
+    /// Read a file-backed secret again, returning whether it changed
+    ///
+    /// The old value is kept if the file can't be read or is empty, as
+    /// happens for a moment while a mounted secret is swapped.
+    pub fn reload(&self) -> io::Result<bool> {
+        self.reload_checked(|_| Ok(()))
+    }
+
+    /// Like [`Secret::reload`], but also keeps the old value if `check`
+    /// rejects the new one
+    pub fn reload_checked(
+        &self,
+        check: impl FnOnce(&str) -> Result<(), String>,
+    ) -> io::Result<bool> {
+        let Some(path) = &self.file else {
+            return Ok(false);
+        };
+        let value = read_secret_file(path)?;
+        check(&value).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let mut current = self.value.write().expect("secret lock poisoned");
+        if **current == *value {
+            return Ok(false);
+        }
+        *current = value.into();
+        Ok(true)
+    }
+}
+
+fn read_secret_file(path: &Path) -> io::Result<String> {
+    let text = std::fs::read_to_string(path)?;
+    let value = text.trim_end_matches(['\r', '\n']);
+    if value.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "file is empty"));
+    }
+    Ok(value.to_string())
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Secret({})", REDACTED)
+    }
+}
+
+/// Only whether a secret is set shows
+impl Serialize for Secret {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(REDACTED)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_rotation() {
+        let path = std::env::temp_dir().join(format!("mdwn-secret-{}", std::process::id()));
+        std::fs::write(&path, "first\n").unwrap();
+        let secret = Secret::from_file(&path).unwrap();
+        let copy = secret.clone();
+        assert_eq!(&*secret.expose(), "first");
+        assert!(!secret.reload().unwrap());
+
+        std::fs::write(&path, "second\r\n").unwrap();
+        assert!(secret.reload().unwrap());
+        assert_eq!(&*copy.expose(), "second");
+
+        // Mid-swap, the old value stays
+        std::fs::write(&path, "").unwrap();
+        assert!(secret.reload().is_err());
+        assert_eq!(&*copy.expose(), "second");
+
+        std::fs::write(&path, "x").unwrap();
+        let err = secret.reload_checked(|value| match value.len() {
+            1 => Err("too short".to_string()),
+            _ => Ok(()),
+        });
+        assert_eq!(err.unwrap_err().to_string(), "too short");
+        assert_eq!(&*copy.expose(), "second");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_never_shown() {
+        let secret = Secret::new("hunter2");
+        assert_eq!(format!("{:?}", secret), "Secret(<redacted>)");
+        assert_eq!(serde_json::to_value(&secret).unwrap(), "<redacted>");
+        assert!(!secret.reload().unwrap());
+    }
+}
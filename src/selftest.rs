@@ -0,0 +1,126 @@
+//! `--self-test`: startup checks for container entrypoints
+//!
+//! Before serving, the process converts a bundled page, fetches
+//! `SELF_TEST_URL` through the same client as upstream pages (DNS, TLS and
+//! SSRF checks included) and round-trips an entry through the page cache.
+//! Any failure stops startup with a nonzero exit, so a broken image or a
+//! network policy that blocks egress shows up before traffic is routed to it.
+
+use crate::AppState;
+use mdwnio::cache::{CacheBackend, CachedContent, ContentSource};
+use mdwnio::convert;
+use std::time::Instant;
+use tracing::{error, info};
+use url::Url;
+
+/// Page converted by the parse check
+const FIXTURE: &str = r#"<!DOCTYPE html>
+<html lang="en">
+<head><meta charset="utf-8"><title>Self-test fixture</title></head>
+<body>
+  <nav><a href="/">Home</a> <a href="/about">About</a></nav>
+  <article>
+    <h1>Self-test fixture</h1>
+    <p>This page is bundled with the binary and converted when the server
+    starts with the self-test flag, so that a build whose readability or
+    markdown stages are broken, misconfigured, or missing a dependency is
+    caught before it serves a single request, rather than by the first
+    client that asks for a page and gets an error back.</p>
+    <p>Extraction has to find this article among the navigation and footer,
+    score its paragraphs, and keep them, in order, with their headings and
+    links intact. The text is long enough, and has enough commas, for the
+    scoring to pick the article element over the rest of the body.</p>
+    <h2>Second section</h2>
+    <p>It has a couple of sections and a <a href="/docs">relative link</a>,
+    so that extraction keeps more than one block and links are resolved
+    against the page URL, the way they are for upstream pages.</p>
+    <p>Nothing here is fetched from the network, and nothing is cached; only
+    the conversion pipeline is exercised, with the same settings, the same
+    parser, and the same cleanup as pages converted for clients.</p>
+  </article>
+  <footer>Copyright notice</footer>
+</body>
+</html>"#;
+
+/// URL the fixture is converted as, and the cache key of the cache check
+const FIXTURE_URL: &str = "https://self-test.mdwn.invalid/fixture";
+
+/// Run every check, logging each result; `false` if any failed
+pub async fn run(state: &AppState) -> bool {
+    let mut passed = true;
+    passed &= report("parse", Instant::now(), check_parse());
+    let start = Instant::now();
+    let egress = match &state.config.self_test_url {
+        Some(probe) => check_egress(state, probe).await,
+        None => Ok("skipped, SELF_TEST_URL is off".to_string()),
+    };
+    passed &= report("egress", start, egress);
+    let start = Instant::now();
+    passed &= report("cache", start, check_cache(state).await);
+    passed
+}
+
+fn report(check: &str, start: Instant, result: Result<String, String>) -> bool {
+    let ms = start.elapsed().as_millis();
+    match &result {
+        Ok(detail) => info!("self-test {}: ok, {} ({}ms)", check, detail, ms),
+        Err(detail) => error!("self-test {}: FAILED, {} ({}ms)", check, detail, ms),
+    }
+    result.is_ok()
+}
+
+/// Convert the bundled page and look for its content in the markdown
+fn check_parse() -> Result<String, String> {
+    let url = Url::parse(FIXTURE_URL).expect("valid fixture URL");
+    let markdown = convert::html_to_markdown(FIXTURE, &url).map_err(|e| e.to_string())?;
+    for expected in [
+        "# Self-test fixture",
+        "the conversion pipeline is exercised",
+    ] {
+        if !markdown.contains(expected) {
+            return Err(format!("'{}' missing from converted fixture", expected));
+        }
+    }
+    Ok(format!("{} bytes of markdown", markdown.len()))
+}
+
+/// Fetch a URL the way upstream pages are fetched
+pub async fn check_egress(state: &AppState, probe: &str) -> Result<String, String> {
+    let url = Url::parse(probe).map_err(|e| format!("{}: {}", probe, e))?;
+    let response = state
+        .fetcher
+        .fetch(&url, &state.fetcher.budget())
+        .await
+        .map_err(|e| format!("{}: {}", probe, e))?;
+    Ok(format!(
+        "{} returned {} ({} bytes)",
+        probe,
+        response.status,
+        response.body.len()
+    ))
+}
+
+/// Store an entry in the page cache, read it back and remove it again
+async fn check_cache(state: &AppState) -> Result<String, String> {
+    let content = CachedContent::new(FIXTURE_URL.to_string(), ContentSource::Native);
+    state.cache.set(FIXTURE_URL, "", content.clone()).await;
+    let stored = state.cache.get(FIXTURE_URL, "").await;
+    state.cache.invalidate(FIXTURE_URL).await;
+    match stored {
+        Some(stored) if stored.markdown == content.markdown => {
+            Ok("stored entry read back".to_string())
+        }
+        Some(_) => Err("read back a different entry than was stored".to_string()),
+        None => Err("stored entry not found".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fixture_converts() {
+        assert!(check_parse().is_ok(), "{:?}", check_parse());
+    }
+}
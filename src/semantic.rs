@@ -0,0 +1,166 @@
+//! Alternative extraction engine built on HTML landmarks
+//!
+//! Where readability scores blocks of text to guess the article, this engine
+//! trusts the page's own markup: it keeps `<main>` (or the largest
+//! `<article>`, or failing both the `<body>`) and drops navigation, banners,
+//! sidebars, forms and scripts inside it. It is run next to readability in
+//! shadow mode, to measure how far the two disagree before switching engines.
+
+use crate::convert::{article_to_markdown, clean_markdown};
+use crate::error::Result;
+use scraper::{ElementRef, Html, Selector};
+use url::Url;
+
+/// Elements that are page chrome rather than content
+const CHROME_ELEMENTS: &[&str] = &[
+    "aside", "button", "dialog", "footer", "form", "header", "iframe", "nav", "noscript", "script",
+    "style", "svg", "template",
+];
+
+/// ARIA roles of page chrome
+const CHROME_ROLES: &[&str] = &[
+    "banner",
+    "complementary",
+    "contentinfo",
+    "navigation",
+    "search",
+];
+
+/// Elements without a closing tag
+const VOID_ELEMENTS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "track",
+    "wbr",
+];
+
+/// Convert a page to markdown with this engine, cleaned like readability's output
+pub fn html_to_markdown(html: &str, base_url: &Url) -> Result<String> {
+    let markdown = article_to_markdown(&extract(html, base_url))?;
+    Ok(clean_markdown(&markdown))
+}
+
+/// HTML of the page's main landmark without its chrome, links and image
+/// sources made absolute
+pub fn extract(html: &str, base_url: &Url) -> String {
+    let document = Html::parse_document(html);
+    let mut content = String::new();
+    if let Some(root) = content_root(&document) {
+        serialize_children(root, base_url, &mut content);
+    }
+    content
+}
+
+/// `<main>`, else the `<article>` with the most text, else `<body>`
+fn content_root(document: &Html) -> Option<ElementRef<'_>> {
+    let select = |selector: &str| {
+        let selector = Selector::parse(selector).expect("valid selector");
+        document.select(&selector).collect::<Vec<_>>()
+    };
+    let text_len = |element: &ElementRef| element.text().map(str::len).sum::<usize>();
+
+    select("main, [role='main']")
+        .into_iter()
+        .next()
+        .or_else(|| select("article").into_iter().max_by_key(text_len))
+        .or_else(|| select("body").into_iter().next())
+}
+
+fn is_chrome(element: &ElementRef) -> bool {
+    let value = element.value();
+    CHROME_ELEMENTS.contains(&value.name())
+        || value
+            .attr("role")
+            .is_some_and(|role| CHROME_ROLES.contains(&role))
+        || value.attr("hidden").is_some()
+        || value.attr("aria-hidden") == Some("true")
+}
+
+fn serialize_children(element: ElementRef, base_url: &Url, out: &mut String) {
+    for child in element.children() {
+        if let Some(text) = child.value().as_text() {
+            out.push_str(&escape(text, false));
+        } else if let Some(child) = ElementRef::wrap(child) {
+            if !is_chrome(&child) {
+                serialize_element(child, base_url, out);
+            }
+        }
+    }
+}
+
+fn serialize_element(element: ElementRef, base_url: &Url, out: &mut String) {
+    let name = element.value().name();
+    out.push('<');
+    out.push_str(name);
+    for (attr, value) in element.value().attrs() {
+        let value = match attr {
+            "href" | "src" => base_url
+                .join(value)
+                .map(String::from)
+                .unwrap_or_else(|_| value.to_string()),
+            _ => value.to_string(),
+        };
+        out.push_str(&format!(" {}=\"{}\"", attr, escape(&value, true)));
+    }
+    out.push('>');
+    if !VOID_ELEMENTS.contains(&name) {
+        serialize_children(element, base_url, out);
+        out.push_str(&format!("</{}>", name));
+    }
+}
+
+fn escape(text: &str, attribute: bool) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' if attribute => escaped.push_str("&quot;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extract_main_without_chrome() {
+        let html = r#"<html><body>
+            <header>Site</header>
+            <main>
+                <nav><a href="/">Home</a></nav>
+                <h1>Guide</h1>
+                <p>Read the <a href="../docs">docs</a> &amp; <img src="a.png" alt="x"> more.</p>
+                <div role="complementary">Related</div>
+                <script>track()</script>
+            </main>
+            <footer>Copyright</footer>
+        </body></html>"#;
+        let base = Url::parse("https://example.com/guide/start").unwrap();
+        let content = extract(html, &base);
+
+        assert!(content.contains("<h1>Guide</h1>"));
+        assert!(content.contains(r#"<a href="https://example.com/docs">docs</a> &amp; "#));
+        assert!(content.contains(r#"<img src="https://example.com/guide/a.png" alt="x">"#));
+        for chrome in ["Site", "Home", "Related", "track()", "Copyright"] {
+            assert!(!content.contains(chrome), "{} kept", chrome);
+        }
+    }
+
+    #[test]
+    fn test_extract_falls_back_to_largest_article() {
+        let html = "<body><article>Short teaser</article>\
+                    <article><p>The full article text, which is longer.</p></article></body>";
+        let base = Url::parse("https://example.com/").unwrap();
+        assert_eq!(
+            extract(html, &base),
+            "<p>The full article text, which is longer.</p>"
+        );
+        assert_eq!(
+            extract("<p>Just a paragraph</p>", &base),
+            "<p>Just a paragraph</p>"
+        );
+    }
+}
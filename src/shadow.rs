@@ -0,0 +1,264 @@
+//! Shadow comparison of extraction engines
+//!
+//! With `SHADOW_SAMPLE_RATE` set, that fraction of converted pages is also
+//! run through the landmark engine ([`mdwnio::semantic`]) in the background.
+//! Clients always get readability's output; how much the two agree is logged
+//! per page and summed up at `/admin/shadow`, so a switch of engines can be
+//! judged on real traffic first. Only the article bodies are compared, without
+//! the conversion notice and title heading readability's output starts with,
+//! and at most a few comparisons run at once: a sample arriving while they're
+//! all busy is skipped rather than queued.
+
+use crate::AppState;
+use mdwnio::{convert, semantic};
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Semaphore;
+use url::Url;
+
+/// Buckets of the similarity histogram, each 0.1 wide
+const HISTOGRAM_BUCKETS: usize = 10;
+
+/// Pages kept as examples of the engines disagreeing most
+const MAX_DIVERGENT: usize = 20;
+
+/// Comparisons running on the blocking pool at once
+const MAX_CONCURRENT_SAMPLES: usize = 2;
+
+/// One page the engines disagreed on
+#[derive(Clone, Debug, Serialize)]
+pub struct Divergence {
+    pub url: String,
+    pub similarity: f32,
+}
+
+/// Running totals of the comparisons
+#[derive(Default)]
+struct Totals {
+    samples: u64,
+    failures: u64,
+    similarity: f64,
+    histogram: [u64; HISTOGRAM_BUCKETS],
+    primary_bytes: u64,
+    alternative_bytes: u64,
+    primary_secs: f64,
+    alternative_secs: f64,
+    /// Least similar pages, most divergent first
+    divergent: Vec<Divergence>,
+}
+
+/// Summary of the comparisons, as served by `/admin/shadow`
+#[derive(Debug, Serialize)]
+pub struct ShadowSummary {
+    pub sample_rate: f64,
+    /// Pages compared
+    pub samples: u64,
+    /// Pages the alternative engine failed on, not counted in `samples`
+    pub failures: u64,
+    pub mean_similarity: f64,
+    /// Counts of pages with similarity in [0, 0.1), [0.1, 0.2), ... [0.9, 1]
+    pub similarity_histogram: [u64; HISTOGRAM_BUCKETS],
+    pub mean_primary_bytes: u64,
+    pub mean_alternative_bytes: u64,
+    pub mean_primary_ms: f64,
+    pub mean_alternative_ms: f64,
+    pub most_divergent: Vec<Divergence>,
+}
+
+/// Comparison totals shared across handlers
+#[derive(Clone)]
+pub struct ShadowStats {
+    totals: Arc<Mutex<Totals>>,
+    permits: Arc<Semaphore>,
+}
+
+impl Default for ShadowStats {
+    fn default() -> Self {
+        Self {
+            totals: Arc::default(),
+            permits: Arc::new(Semaphore::new(MAX_CONCURRENT_SAMPLES)),
+        }
+    }
+}
+
+impl ShadowStats {
+    /// Record one page converted by both engines
+    fn record(
+        &self,
+        url: &Url,
+        similarity: f32,
+        primary: (usize, Duration),
+        alternative: (usize, Duration),
+    ) {
+        let mut totals = self.totals.lock().expect("shadow lock poisoned");
+        totals.samples += 1;
+        totals.similarity += f64::from(similarity);
+        let bucket = ((similarity * HISTOGRAM_BUCKETS as f32) as usize).min(HISTOGRAM_BUCKETS - 1);
+        totals.histogram[bucket] += 1;
+        totals.primary_bytes += primary.0 as u64;
+        totals.alternative_bytes += alternative.0 as u64;
+        totals.primary_secs += primary.1.as_secs_f64();
+        totals.alternative_secs += alternative.1.as_secs_f64();
+
+        let divergent = &mut totals.divergent;
+        if divergent.len() < MAX_DIVERGENT
+            || divergent.last().is_some_and(|d| similarity < d.similarity)
+        {
+            divergent.retain(|d| d.url != url.as_str());
+            let at = divergent.partition_point(|d| d.similarity <= similarity);
+            divergent.insert(
+                at,
+                Divergence {
+                    url: url.to_string(),
+                    similarity,
+                },
+            );
+            divergent.truncate(MAX_DIVERGENT);
+        }
+    }
+
+    fn record_failure(&self) {
+        self.totals.lock().expect("shadow lock poisoned").failures += 1;
+    }
+
+    /// Summary of every comparison since startup
+    pub fn summary(&self, sample_rate: f64) -> ShadowSummary {
+        let totals = self.totals.lock().expect("shadow lock poisoned");
+        let samples = totals.samples;
+        let mean = |total: f64| {
+            if samples == 0 {
+                0.0
+            } else {
+                total / samples as f64
+            }
+        };
+        ShadowSummary {
+            sample_rate,
+            samples,
+            failures: totals.failures,
+            mean_similarity: mean(totals.similarity),
+            similarity_histogram: totals.histogram,
+            mean_primary_bytes: totals.primary_bytes.checked_div(samples).unwrap_or(0),
+            mean_alternative_bytes: totals.alternative_bytes.checked_div(samples).unwrap_or(0),
+            mean_primary_ms: mean(totals.primary_secs) * 1000.0,
+            mean_alternative_ms: mean(totals.alternative_secs) * 1000.0,
+            most_divergent: totals.divergent.clone(),
+        }
+    }
+}
+
+/// Compare the alternative engine's output for a sample of pages with
+/// `markdown`, which took `elapsed` to convert, in the background
+pub fn sample(state: &AppState, url: &Url, html: &str, markdown: &str, elapsed: Duration) {
+    let rate = state.config.shadow_sample_rate;
+    if rate <= 0.0 || rand::random::<f64>() >= rate {
+        return;
+    }
+
+    let stats = state.shadow.clone();
+    let Ok(permit) = stats.permits.clone().try_acquire_owned() else {
+        tracing::debug!(target: "mdwnio::shadow", %url, "Comparisons busy, sample skipped");
+        return;
+    };
+    let (url, html) = (url.clone(), html.to_string());
+    let markdown = article_body(markdown).to_string();
+    tokio::task::spawn_blocking(move || {
+        let _permit = permit;
+        let start = Instant::now();
+        let alternative = match semantic::html_to_markdown(&html, &url) {
+            Ok(alternative) => alternative,
+            Err(e) => {
+                tracing::info!(
+                    target: "mdwnio::shadow",
+                    %url,
+                    error = %e,
+                    "Alternative engine failed"
+                );
+                stats.record_failure();
+                return;
+            }
+        };
+        let alternative_elapsed = start.elapsed();
+
+        let similarity = convert::similarity(&markdown, &alternative);
+        tracing::info!(
+            target: "mdwnio::shadow",
+            %url,
+            similarity,
+            primary_bytes = markdown.len(),
+            alternative_bytes = alternative.len(),
+            "Compared extraction engines"
+        );
+        stats.record(
+            &url,
+            similarity,
+            (markdown.len(), elapsed),
+            (alternative.len(), alternative_elapsed),
+        );
+    });
+}
+
+/// Markdown after readability's conversion notice and title heading, which
+/// the alternative engine doesn't produce
+fn article_body(markdown: &str) -> &str {
+    let mut body = markdown;
+    if body.starts_with("<!--") {
+        if let Some(end) = body.find("-->\n\n") {
+            body = &body[end + "-->\n\n".len()..];
+        }
+    }
+    if body.starts_with("# ") {
+        if let Some(end) = body.find("\n\n") {
+            body = &body[end + "\n\n".len()..];
+        }
+    }
+    body
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summary() {
+        let stats = ShadowStats::default();
+        let url = |path: &str| {
+            Url::parse("https://example.com/")
+                .unwrap()
+                .join(path)
+                .unwrap()
+        };
+        let timing = |bytes, ms| (bytes, Duration::from_millis(ms));
+        stats.record(&url("a"), 1.0, timing(100, 4), timing(80, 2));
+        stats.record(&url("b"), 0.25, timing(300, 8), timing(100, 2));
+        stats.record_failure();
+
+        let summary = stats.summary(0.5);
+        assert_eq!(summary.samples, 2);
+        assert_eq!(summary.failures, 1);
+        assert!((summary.mean_similarity - 0.625).abs() < 1e-6);
+        assert_eq!(summary.similarity_histogram[2], 1);
+        assert_eq!(summary.similarity_histogram[9], 1);
+        assert_eq!(summary.mean_primary_bytes, 200);
+        assert_eq!(summary.mean_alternative_bytes, 90);
+        assert!((summary.mean_primary_ms - 6.0).abs() < 1e-6);
+        let divergent: Vec<_> = summary
+            .most_divergent
+            .iter()
+            .map(|d| d.similarity)
+            .collect();
+        assert_eq!(divergent, [0.25, 1.0]);
+    }
+
+    #[test]
+    fn test_article_body() {
+        let body = "Some text.\n\n## Section\n";
+        assert_eq!(
+            article_body(&format!("<!-- notice -->\n\n# Title\n\n{}", body)),
+            body
+        );
+        assert_eq!(article_body(&format!("# Title\n\n{}", body)), body);
+        assert_eq!(article_body(body), body);
+    }
+}
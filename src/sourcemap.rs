@@ -0,0 +1,269 @@
+//! Source maps from markdown back to the original page
+//!
+//! Tools that show where a passage came from need to find it in the page it
+//! was converted from. With `?sourcemap=1` the block elements of the page
+//! (paragraphs, headings, list items, table rows, code blocks and quotes) are
+//! kept with the markdown, each under its XPath. When the markdown is served,
+//! each block of it is matched to the next element with the same text, the
+//! way heading anchors are matched, giving line ranges and the element's
+//! XPath and CSS selector.
+
+use scraper::{ElementRef, Html, Selector};
+use serde::{Deserialize, Serialize};
+
+/// Characters of text compared when matching a block to an element
+const KEY_LEN: usize = 48;
+
+/// Shortest text matched by prefix when a block and element differ in length
+const MIN_PREFIX_LEN: usize = 12;
+
+/// A block element of the original page
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SourceElement {
+    /// Start of the element's letters and digits, lowercased
+    pub key: String,
+    /// Absolute XPath, e.g. `/html/body/article/p[2]`
+    pub xpath: String,
+}
+
+/// Where a range of markdown lines came from
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SourceMapping {
+    /// First and last line of the block, counting from 1
+    pub lines: [usize; 2],
+    pub xpath: String,
+    /// The same element as a CSS selector
+    pub selector: String,
+}
+
+/// Block elements of a page with text, in document order
+pub fn source_elements(html: &str) -> Vec<SourceElement> {
+    let document = Html::parse_document(html);
+    let blocks =
+        Selector::parse("h1, h2, h3, h4, h5, h6, p, li, pre, blockquote, tr, dt, dd, figcaption")
+            .expect("valid CSS selector");
+    document
+        .select(&blocks)
+        .filter_map(|element| {
+            let key = match_key(&element.text().collect::<String>());
+            (!key.is_empty()).then(|| SourceElement {
+                key,
+                xpath: xpath(&element),
+            })
+        })
+        .collect()
+}
+
+/// Letters and digits of text, lowercased, up to [`KEY_LEN`]
+fn match_key(text: &str) -> String {
+    text.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .take(KEY_LEN)
+        .collect()
+}
+
+/// Absolute XPath of an element, indexed where it has siblings of its kind
+fn xpath(element: &ElementRef) -> String {
+    let mut steps = Vec::new();
+    let mut current = Some(*element);
+    while let Some(element) = current {
+        let name = element.value().name();
+        let same_kind = |sibling: &ElementRef| sibling.value().name() == name;
+        let before = element.prev_siblings().filter_map(ElementRef::wrap);
+        let position = before.filter(same_kind).count() + 1;
+        let mut after = element.next_siblings().filter_map(ElementRef::wrap);
+        let step = if position > 1 || after.any(|s| same_kind(&s)) {
+            format!("{}[{}]", name, position)
+        } else {
+            name.to_string()
+        };
+        steps.push(step);
+        current = element.parent().and_then(ElementRef::wrap);
+    }
+    steps.reverse();
+    format!("/{}", steps.join("/"))
+}
+
+/// CSS selector for an XPath made by [`xpath`]
+fn css_selector(xpath: &str) -> String {
+    xpath
+        .trim_start_matches('/')
+        .split('/')
+        .map(|step| match step.split_once('[') {
+            Some((name, index)) => {
+                format!("{}:nth-of-type({})", name, index.trim_end_matches(']'))
+            }
+            None => step.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(" > ")
+}
+
+/// Blocks of markdown as 0-based inclusive line ranges with their text
+///
+/// Blank lines separate blocks, as do headings, list items and table rows,
+/// which are blocks of their own; a fenced code block is one block.
+fn blocks(markdown: &str) -> Vec<(usize, usize, String)> {
+    let mut blocks = Vec::new();
+    let mut current: Option<(usize, usize, String)> = None;
+    let mut in_fence = false;
+    for (number, line) in markdown.lines().enumerate() {
+        let trimmed = line.trim_start();
+        let fence = trimmed.starts_with("```");
+        if in_fence {
+            if let Some(block) = current.as_mut() {
+                block.1 = number;
+                if !fence {
+                    block.2.push_str(line);
+                    block.2.push('\n');
+                }
+            }
+            if fence {
+                in_fence = false;
+                blocks.extend(current.take());
+            }
+            continue;
+        }
+
+        let starts_block =
+            fence || trimmed.starts_with('#') || trimmed.starts_with('|') || is_list_item(trimmed);
+        if trimmed.is_empty() || starts_block {
+            blocks.extend(current.take());
+        }
+        if trimmed.is_empty() {
+            continue;
+        }
+        if fence {
+            in_fence = true;
+            current = Some((number, number, String::new()));
+            continue;
+        }
+        let block = current.get_or_insert_with(|| (number, number, String::new()));
+        block.1 = number;
+        block.2.push_str(line);
+        block.2.push('\n');
+        if trimmed.starts_with('#') || trimmed.starts_with('|') {
+            blocks.extend(current.take());
+        }
+    }
+    blocks.extend(current);
+    blocks
+}
+
+fn is_list_item(line: &str) -> bool {
+    if ["- ", "* ", "+ "]
+        .iter()
+        .any(|marker| line.starts_with(marker))
+    {
+        return true;
+    }
+    let digits = line.chars().take_while(char::is_ascii_digit).count();
+    digits > 0 && (line[digits..].starts_with(". ") || line[digits..].starts_with(") "))
+}
+
+/// Match key of markdown text, leaving out link and image targets
+fn markdown_key(text: &str) -> String {
+    let mut plain = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("](") {
+        plain.push_str(&rest[..start]);
+        rest = match rest[start..].find(')') {
+            Some(end) => &rest[start + end + 1..],
+            None => "",
+        };
+    }
+    plain.push_str(rest);
+    match_key(&plain)
+}
+
+fn keys_match(block: &str, element: &str) -> bool {
+    let shorter = block.len().min(element.len());
+    block == element
+        || (shorter >= MIN_PREFIX_LEN && (block.starts_with(element) || element.starts_with(block)))
+}
+
+/// Map each block of `markdown` to the page element it came from
+///
+/// Elements are searched in order from the last match, then from the start
+/// for content the extraction moved; blocks without a match are left out.
+pub fn source_map(markdown: &str, elements: &[SourceElement]) -> Vec<SourceMapping> {
+    let mut next = 0;
+    let mut mappings = Vec::new();
+    for (first, last, text) in blocks(markdown) {
+        let key = markdown_key(&text);
+        if key.is_empty() {
+            continue;
+        }
+        let found = (next..elements.len())
+            .chain(0..next)
+            .find(|&i| keys_match(&key, &elements[i].key));
+        let Some(index) = found else {
+            continue;
+        };
+        next = index + 1;
+        let xpath = elements[index].xpath.clone();
+        mappings.push(SourceMapping {
+            lines: [first + 1, last + 1],
+            selector: css_selector(&xpath),
+            xpath,
+        });
+    }
+    mappings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HTML: &str = r#"<html><body>
+        <nav><p>Skip to content</p></nav>
+        <article>
+            <h1>Guide</h1>
+            <p>Install the <a href="/cli">command line tool</a> first.</p>
+            <ul><li>Linux</li><li>macOS and other Unix systems</li></ul>
+            <pre><code>cargo install mdwn</code></pre>
+            <table><tr><th>Flag</th><th>Meaning</th></tr><tr><td>-v</td><td>Verbose</td></tr></table>
+            <p>That's all.</p>
+        </article>
+    </body></html>"#;
+
+    #[test]
+    fn test_source_elements() {
+        let elements = source_elements(HTML);
+        assert_eq!(elements[0].xpath, "/html/body/nav/p");
+        assert_eq!(elements[1].key, "guide");
+        assert_eq!(elements[2].xpath, "/html/body/article/p[1]");
+        assert_eq!(elements[4].xpath, "/html/body/article/ul/li[2]");
+        assert_eq!(
+            css_selector(&elements[4].xpath),
+            "html > body > article > ul > li:nth-of-type(2)"
+        );
+    }
+
+    #[test]
+    fn test_source_map() {
+        let markdown = "<!-- mdwn.io: Converted -->\n\n# Guide\n\n\
+                        Install the [command line tool](https://example.com/cli)\nfirst.\n\n\
+                        - Linux\n- macOS and other Unix systems\n\n\
+                        ```\ncargo install mdwn\n```\n\n\
+                        | Flag | Meaning |\n| --- | --- |\n| -v | Verbose |\n\n\
+                        That's all.\n";
+        let map = source_map(markdown, &source_elements(HTML));
+        let spans: Vec<_> = map.iter().map(|m| (m.lines, m.xpath.as_str())).collect();
+        assert_eq!(
+            spans,
+            [
+                ([3, 3], "/html/body/article/h1"),
+                ([5, 6], "/html/body/article/p[1]"),
+                ([8, 8], "/html/body/article/ul/li[1]"),
+                ([9, 9], "/html/body/article/ul/li[2]"),
+                ([11, 13], "/html/body/article/pre"),
+                ([15, 15], "/html/body/article/table/tbody/tr[1]"),
+                ([17, 17], "/html/body/article/table/tbody/tr[2]"),
+                ([19, 19], "/html/body/article/p[2]"),
+            ]
+        );
+        assert_eq!(map[1].selector, "html > body > article > p:nth-of-type(1)");
+    }
+}
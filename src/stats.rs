@@ -0,0 +1,166 @@
+//! In-memory per-domain request statistics for operators
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Most domains tracked individually, the rest are pooled under `OTHER_DOMAINS`
+const MAX_TRACKED_DOMAINS: usize = 10_000;
+
+/// Bucket for domains seen after the tracking limit was reached
+const OTHER_DOMAINS: &str = "(other)";
+
+/// Raw counters for one upstream domain
+#[derive(Clone, Debug, Default)]
+struct Counters {
+    requests: u64,
+    cache_hits: u64,
+    errors: u64,
+    bytes: u64,
+}
+
+/// Summary of one domain, as served by `/admin/stats/domains`
+#[derive(Debug, Deserialize, Serialize)]
+pub struct DomainSummary {
+    pub domain: String,
+    pub requests: u64,
+    pub cache_hits: u64,
+    pub cache_hit_rate: f64,
+    pub errors: u64,
+    pub error_rate: f64,
+    /// Total markdown bytes served
+    pub bytes: u64,
+    /// Mean markdown size of successful responses, in bytes
+    pub avg_bytes: u64,
+}
+
+/// Request counters per upstream domain, shared across handlers
+#[derive(Clone, Default)]
+pub struct DomainStats {
+    domains: Arc<Mutex<HashMap<String, Counters>>>,
+}
+
+impl DomainStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a successful response of `bytes` markdown
+    pub fn record_success(&self, domain: &str, cache_hit: bool, bytes: usize) {
+        self.update(domain, |counters| {
+            counters.cache_hits += u64::from(cache_hit);
+            counters.bytes += bytes as u64;
+        });
+    }
+
+    /// Record a failed request
+    pub fn record_error(&self, domain: &str) {
+        self.update(domain, |counters| counters.errors += 1);
+    }
+
+    /// Add the counters of an earlier summary, e.g. one read back from disk
+    pub fn add_summary(&self, summary: &DomainSummary) {
+        let mut domains = self.domains.lock().expect("stats lock poisoned");
+        let counters = entry(&mut domains, &summary.domain);
+        counters.requests += summary.requests;
+        counters.cache_hits += summary.cache_hits;
+        counters.errors += summary.errors;
+        counters.bytes += summary.bytes;
+    }
+
+    fn update(&self, domain: &str, f: impl FnOnce(&mut Counters)) {
+        let mut domains = self.domains.lock().expect("stats lock poisoned");
+        let counters = entry(&mut domains, domain);
+        counters.requests += 1;
+        f(counters);
+    }
+
+    /// Summaries for all domains, busiest first
+    pub fn summaries(&self) -> Vec<DomainSummary> {
+        let domains = self.domains.lock().expect("stats lock poisoned");
+        let mut summaries: Vec<DomainSummary> = domains
+            .iter()
+            .map(|(domain, c)| {
+                let successes = c.requests - c.errors;
+                DomainSummary {
+                    domain: domain.clone(),
+                    requests: c.requests,
+                    cache_hits: c.cache_hits,
+                    cache_hit_rate: ratio(c.cache_hits, c.requests),
+                    errors: c.errors,
+                    error_rate: ratio(c.errors, c.requests),
+                    bytes: c.bytes,
+                    avg_bytes: c.bytes.checked_div(successes).unwrap_or(0),
+                }
+            })
+            .collect();
+        summaries.sort_by(|a, b| b.requests.cmp(&a.requests).then(a.domain.cmp(&b.domain)));
+        summaries
+    }
+}
+
+/// Counters for `domain`, or the shared bucket once the tracking limit is reached
+fn entry<'a>(domains: &'a mut HashMap<String, Counters>, domain: &str) -> &'a mut Counters {
+    let key = if domains.contains_key(domain) || domains.len() < MAX_TRACKED_DOMAINS {
+        domain
+    } else {
+        OTHER_DOMAINS
+    };
+    domains.entry(key.to_string()).or_default()
+}
+
+fn ratio(part: u64, total: u64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        part as f64 / total as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_domain_summaries() {
+        let stats = DomainStats::new();
+        stats.record_success("example.com", false, 300);
+        stats.record_success("example.com", true, 100);
+        stats.record_error("example.com");
+        stats.record_error("broken.example");
+
+        let summaries = stats.summaries();
+        assert_eq!(summaries.len(), 2);
+
+        let example = &summaries[0];
+        assert_eq!(example.domain, "example.com");
+        assert_eq!(example.requests, 3);
+        assert_eq!(example.cache_hits, 1);
+        assert_eq!(example.errors, 1);
+        assert_eq!(example.avg_bytes, 200);
+        assert!((example.error_rate - 1.0 / 3.0).abs() < 1e-9);
+
+        assert_eq!(summaries[1].error_rate, 1.0);
+        assert_eq!(summaries[1].avg_bytes, 0);
+    }
+
+    #[test]
+    fn test_domain_limit() {
+        let stats = DomainStats::new();
+        for i in 0..MAX_TRACKED_DOMAINS + 5 {
+            stats.record_success(&format!("d{}.example", i), false, 1);
+        }
+        // Known domains keep their own entry
+        stats.record_error("d0.example");
+
+        let summaries = stats.summaries();
+        assert_eq!(summaries.len(), MAX_TRACKED_DOMAINS + 1);
+        let other = summaries
+            .iter()
+            .find(|s| s.domain == OTHER_DOMAINS)
+            .unwrap();
+        assert_eq!(other.requests, 5);
+        let first = summaries.iter().find(|s| s.domain == "d0.example").unwrap();
+        assert_eq!(first.requests, 2);
+    }
+}
@@ -0,0 +1,536 @@
+//! Cached content and the storage interface it is kept behind
+//!
+//! The server keeps entries in memory (`cache::MarkdownCache`); the edge build
+//! stores them in Workers KV (`edge::KvCache`).
+
+use crate::anchors::HeadingAnchor;
+use crate::convert::PageType;
+use crate::parse::Byline;
+use crate::sourcemap::SourceElement;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+/// Source type indicator for cached content
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ContentSource {
+    /// Content was fetched from a native markdown source
+    Native,
+    /// Content was converted from HTML
+    Converted,
+    /// Content was rendered from a site's JSON API
+    Api,
+}
+
+impl ContentSource {
+    pub fn as_header_value(&self) -> &'static str {
+        match self {
+            ContentSource::Native => "native",
+            ContentSource::Converted => "converted",
+            ContentSource::Api => "api",
+        }
+    }
+}
+
+/// One redirect on the way to a page
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Redirect {
+    /// URL that answered with the redirect
+    pub url: String,
+    pub status: u16,
+    /// Whether it pointed to another origin
+    pub cross_origin: bool,
+}
+
+/// Cached markdown content with metadata
+#[derive(Clone, Serialize, Deserialize)]
+pub struct CachedContent {
+    pub markdown: String,
+    pub source: ContentSource,
+    /// Origin of a flagged cross-origin markdown alternate
+    pub cross_origin: Option<String>,
+    /// Content quality score of converted HTML
+    pub quality: Option<f32>,
+    /// Whether the HTML was rendered by the prerender service
+    pub prerendered: bool,
+    /// When the content was fetched from upstream
+    pub fetched_at: DateTime<Utc>,
+    /// SHA-256 of the final markdown, for `/content/{hash}`
+    #[serde(default)]
+    pub content_hash: Option<String>,
+    /// Document title, for citation footers
+    #[serde(default)]
+    pub title: Option<String>,
+    /// License the page declares, for citation footers
+    #[serde(default)]
+    pub license: Option<String>,
+    /// Author and publication dates the page declares, for front matter
+    #[serde(default)]
+    pub author: Option<String>,
+    #[serde(default)]
+    pub published: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub modified: Option<DateTime<Utc>>,
+    /// Upstream error status of a converted error page (never cached)
+    #[serde(default)]
+    pub upstream_status: Option<u16>,
+    /// Whether the page looks like a "not found" template (never cached)
+    #[serde(default)]
+    pub soft_not_found: bool,
+    /// Detected (or failing that, declared) ISO 639-1 language
+    #[serde(default)]
+    pub language: Option<String>,
+    /// Whether the page comes in several languages and the client's picked
+    /// this one, so it's cached per language rather than for every client
+    #[serde(default)]
+    pub language_dependent: bool,
+    /// URL the content was requested as
+    #[serde(default)]
+    pub url: Option<String>,
+    /// URL it was fetched from, after redirects
+    #[serde(default)]
+    pub final_url: Option<String>,
+    /// Simhash fingerprint of the markdown, for near-duplicate detection
+    #[serde(default)]
+    pub simhash: Option<u64>,
+    /// Content hash of another cached page with near-identical content, found
+    /// when this was fetched
+    #[serde(default)]
+    pub duplicate_of: Option<String>,
+    /// Kind of page, if it was converted differently because of it
+    #[serde(default)]
+    pub page_type: Option<PageType>,
+    /// Freshness lifetime the origin declared, in seconds
+    #[serde(default)]
+    pub max_age: Option<u64>,
+    /// Upstream validators for conditional revalidation once stale
+    #[serde(default)]
+    pub etag: Option<String>,
+    #[serde(default)]
+    pub last_modified: Option<String>,
+    /// Redirects followed to fetch the page, in order
+    #[serde(default)]
+    pub redirects: Vec<Redirect>,
+    /// Ids of the page's headings, for `?anchors=`
+    #[serde(default)]
+    pub anchors: Vec<HeadingAnchor>,
+    /// Block elements of the page, for `?sourcemap=`
+    #[serde(default)]
+    pub sources: Vec<SourceElement>,
+    /// The upstream fetch that produced this copy, for access logs; not
+    /// stored, and cleared on the copies cache hits return
+    #[serde(skip)]
+    pub fetch: Option<FetchOutcome>,
+    /// Whether it was fetched with the operator's credentials, so it's never
+    /// cached or shared and is served `Cache-Control: private`
+    #[serde(skip)]
+    pub private: bool,
+}
+
+/// What upstream answered when a page was fetched
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FetchOutcome {
+    pub status: u16,
+    /// Content category of the response (see `ContentCategory::name`), `api`
+    /// for pages rendered from a site's API, `None` if it was a revalidation
+    pub category: Option<&'static str>,
+}
+
+impl CachedContent {
+    pub fn new(markdown: String, source: ContentSource) -> Self {
+        Self {
+            markdown,
+            source,
+            cross_origin: None,
+            quality: None,
+            prerendered: false,
+            fetched_at: Utc::now(),
+            content_hash: None,
+            title: None,
+            license: None,
+            author: None,
+            published: None,
+            modified: None,
+            upstream_status: None,
+            soft_not_found: false,
+            language: None,
+            language_dependent: false,
+            url: None,
+            final_url: None,
+            simhash: None,
+            duplicate_of: None,
+            page_type: None,
+            max_age: None,
+            etag: None,
+            last_modified: None,
+            redirects: Vec::new(),
+            anchors: Vec::new(),
+            sources: Vec::new(),
+            fetch: None,
+            private: false,
+        }
+    }
+
+    /// Whether it's a converted upstream error page or a "not found"
+    /// template, which no cache should keep
+    pub fn is_error_page(&self) -> bool {
+        self.upstream_status.is_some() || self.soft_not_found
+    }
+
+    /// Record the author and dates a page declares
+    pub fn set_byline(&mut self, byline: Byline) {
+        self.author = byline.author;
+        self.published = byline.published;
+        self.modified = byline.modified;
+    }
+}
+
+/// Storage for converted markdown, keyed by URL and output variant
+///
+/// `variant` distinguishes request options that change the output
+/// (e.g. a language preference); use "" for the default rendering.
+// Futures aren't required to be Send: Workers KV futures wrap JavaScript promises
+#[allow(async_fn_in_trait)]
+pub trait CacheBackend {
+    /// Get cached content for a URL
+    async fn get(&self, url: &str, variant: &str) -> Option<CachedContent>;
+
+    /// Store content in cache
+    async fn set(&self, url: &str, variant: &str, content: CachedContent);
+}
+
+/// Build a cache key for a URL variant
+///
+/// Variants are appended after a space, which can't appear in a parsed URL.
+pub fn variant_cache_key(url: &str, variant: &str) -> String {
+    CacheKeyRules::default().variant_key(url, variant)
+}
+
+/// Normalize URL for cache key
+///
+/// Spellings of one URL share a key:
+/// - Scheme and host lowercased, path and query case kept
+/// - Default port, `.`/`..` segments, fragment and trailing `/` removed
+/// - Percent-escapes uppercased, unreserved characters decoded
+/// - Query string otherwise kept as-is (order matters for some APIs)
+pub fn normalize_cache_key(url: &str) -> String {
+    CacheKeyRules::default().key(url)
+}
+
+/// Operator-chosen cache key normalization beyond [`normalize_cache_key`]
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct CacheKeyRules {
+    /// `www.example.com` and `example.com` share keys
+    pub strip_www: bool,
+    /// Query parameters left out of keys, e.g. tracking tags
+    pub ignored_params: Vec<IgnoredParam>,
+    /// Domains (with their subdomains) whose query parameters are sorted,
+    /// `*` for every domain; off by default since some APIs read them in order
+    pub sorted_query_domains: Vec<String>,
+}
+
+/// A query parameter that doesn't change the page
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct IgnoredParam {
+    /// Only ignored on this domain and its subdomains
+    pub domain: Option<String>,
+    /// Parameter name, or a name prefix when it ends in `*`
+    pub name: String,
+}
+
+impl IgnoredParam {
+    fn matches(&self, host: &str, name: &str) -> bool {
+        let name_matches = match self.name.strip_suffix('*') {
+            Some(prefix) => name.starts_with(prefix),
+            None => name == self.name,
+        };
+        name_matches && self.domain.as_deref().is_none_or(|d| on_domain(host, d))
+    }
+}
+
+/// Parse comma-separated `name` or `domain:name` parameters, `*` ending a prefix
+pub fn parse_ignored_params(s: &str) -> Result<Vec<IgnoredParam>, String> {
+    s.split(',')
+        .map(str::trim)
+        .filter(|e| !e.is_empty())
+        .map(|entry| {
+            let (domain, name) = match entry.split_once(':') {
+                Some((domain, name)) => (Some(domain.trim().to_ascii_lowercase()), name.trim()),
+                None => (None, entry),
+            };
+            if name.is_empty() || name == "*" || domain.as_deref() == Some("") {
+                return Err(format!(
+                    "'{}' is not a parameter name or domain:name",
+                    entry
+                ));
+            }
+            Ok(IgnoredParam {
+                domain,
+                name: name.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Whether `host` is `domain` or one of its subdomains
+pub fn on_domain(host: &str, domain: &str) -> bool {
+    host == domain
+        || host
+            .strip_suffix(domain)
+            .is_some_and(|sub| sub.ends_with('.'))
+}
+
+impl CacheKeyRules {
+    /// Normalized cache key for a URL
+    pub fn key(&self, url: &str) -> String {
+        let Ok(mut parsed) = Url::parse(url) else {
+            // Not a URL: only the fragment can safely go
+            return url.split('#').next().unwrap_or(url).to_string();
+        };
+        parsed.set_fragment(None);
+        let host = parsed.host_str().unwrap_or_default().to_string();
+
+        if self.strip_www {
+            let bare = parsed.host_str().and_then(|h| h.strip_prefix("www."));
+            if let Some(bare) = bare.map(str::to_string) {
+                // Fails only for hosts that can't change, which have no `www.`
+                let _ = parsed.set_host(Some(&bare));
+            }
+        }
+
+        let mut path = normalize_percent_encoding(parsed.path());
+        if path.len() > 1 && path.ends_with('/') {
+            path.pop();
+        }
+        parsed.set_path(&path);
+        match parsed.query().map(|q| self.normalize_query(&host, q)) {
+            Some(query) if query.is_empty() => parsed.set_query(None),
+            Some(query) => parsed.set_query(Some(&query)),
+            None => {}
+        }
+        parsed.into()
+    }
+
+    /// Query with ignored parameters dropped, sorted by name where configured
+    fn normalize_query(&self, host: &str, query: &str) -> String {
+        let query = normalize_percent_encoding(query);
+        let sort = self
+            .sorted_query_domains
+            .iter()
+            .any(|d| d == "*" || on_domain(host, d));
+        if self.ignored_params.is_empty() && !sort {
+            return query;
+        }
+
+        let name = |pair: &&str| pair.split('=').next().unwrap_or_default().to_string();
+        let mut pairs: Vec<&str> = query
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .filter(|pair| {
+                !self
+                    .ignored_params
+                    .iter()
+                    .any(|p| p.matches(host, &name(pair)))
+            })
+            .collect();
+        if sort {
+            // Stable, so repeated parameters keep their relative order
+            pairs.sort_by_key(name);
+        }
+        pairs.join("&")
+    }
+
+    /// Cache key for a URL variant
+    ///
+    /// Variants are appended after a space, which can't appear in a parsed URL.
+    pub fn variant_key(&self, url: &str, variant: &str) -> String {
+        let key = self.key(url);
+        if variant.is_empty() {
+            key
+        } else {
+            format!("{} {}", key, variant)
+        }
+    }
+}
+
+/// Uppercase `%xx` escapes, decoding those of unreserved characters (RFC 3986 6.2.2)
+fn normalize_percent_encoding(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(pos) = rest.find('%') {
+        out.push_str(&rest[..pos]);
+        let escape = &rest[pos..];
+        let hex = escape
+            .get(1..3)
+            .filter(|h| h.bytes().all(|b| b.is_ascii_hexdigit()));
+        match hex.and_then(|h| u8::from_str_radix(h, 16).ok()) {
+            Some(byte) if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) => {
+                out.push(byte as char);
+                rest = &escape[3..];
+            }
+            Some(byte) => {
+                out.push_str(&format!("%{:02X}", byte));
+                rest = &escape[3..];
+            }
+            None => {
+                out.push('%');
+                rest = &escape[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_cache_key() {
+        // Should lowercase scheme and host only
+        assert_eq!(
+            normalize_cache_key("HTTPS://EXAMPLE.COM/Path"),
+            "https://example.com/Path"
+        );
+        assert_ne!(
+            normalize_cache_key("https://example.com/README.md"),
+            normalize_cache_key("https://example.com/readme.md")
+        );
+
+        // Should remove fragment
+        assert_eq!(
+            normalize_cache_key("https://example.com/page#section"),
+            "https://example.com/page"
+        );
+
+        // Should keep query string
+        assert_eq!(
+            normalize_cache_key("https://example.com/page?a=1&b=2"),
+            "https://example.com/page?a=1&b=2"
+        );
+
+        // Should drop default ports, dot segments and trailing slashes
+        assert_eq!(
+            normalize_cache_key("https://example.com:443/docs/./guide/../intro/"),
+            "https://example.com/docs/intro"
+        );
+        assert_eq!(
+            normalize_cache_key("http://example.com:8080/a/?"),
+            "http://example.com:8080/a"
+        );
+        assert_eq!(
+            normalize_cache_key("https://example.com"),
+            "https://example.com/"
+        );
+
+        // Should normalize percent-encoding
+        assert_eq!(
+            normalize_cache_key("https://example.com/%7euser/caf%c3%a9?q=%2fx%41"),
+            "https://example.com/~user/caf%C3%A9?q=%2FxA"
+        );
+        assert_eq!(
+            normalize_cache_key("https://example.com/100%"),
+            "https://example.com/100%"
+        );
+
+        // Should keep www unless asked
+        assert_eq!(
+            normalize_cache_key("https://www.example.com/a"),
+            "https://www.example.com/a"
+        );
+        let rules = CacheKeyRules {
+            strip_www: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            rules.key("https://WWW.example.com/a/"),
+            "https://example.com/a"
+        );
+        assert_eq!(
+            rules.key("https://www2.example.com/a"),
+            "https://www2.example.com/a"
+        );
+    }
+
+    #[test]
+    fn test_query_rules() {
+        let rules = CacheKeyRules {
+            ignored_params: parse_ignored_params("utm_*, fbclid, shop.example.com:sid").unwrap(),
+            sorted_query_domains: vec!["example.org".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(
+            rules.key("https://example.com/a?utm_source=x&id=2&fbclid=y&utm_medium=z"),
+            "https://example.com/a?id=2"
+        );
+        assert_eq!(
+            rules.key("https://example.com/a?utm_source=x"),
+            "https://example.com/a"
+        );
+        // Domain-scoped parameters, sorting only where configured
+        assert_eq!(
+            rules.key("https://www.shop.example.com/?sid=1&b=2&a=1"),
+            "https://www.shop.example.com/?b=2&a=1"
+        );
+        assert_eq!(
+            rules.key("https://example.net/?sid=1&b=2&a=1"),
+            "https://example.net/?sid=1&b=2&a=1"
+        );
+        assert_eq!(
+            rules.key("https://api.example.org/q?b=2&a=1&b=1"),
+            "https://api.example.org/q?a=1&b=2&b=1"
+        );
+
+        assert_eq!(
+            parse_ignored_params("sid,example.com:ref_*").unwrap(),
+            vec![
+                IgnoredParam {
+                    domain: None,
+                    name: "sid".to_string()
+                },
+                IgnoredParam {
+                    domain: Some("example.com".to_string()),
+                    name: "ref_*".to_string()
+                },
+            ]
+        );
+        assert!(parse_ignored_params("example.com:").is_err());
+        assert!(parse_ignored_params("*").is_err());
+    }
+
+    #[test]
+    fn test_variant_cache_key() {
+        assert_eq!(
+            variant_cache_key("https://Example.com/a#top", ""),
+            "https://example.com/a"
+        );
+        assert_eq!(
+            variant_cache_key("https://example.com/a", "lang=fr"),
+            "https://example.com/a lang=fr"
+        );
+    }
+
+    #[test]
+    fn test_content_source_header() {
+        assert_eq!(ContentSource::Native.as_header_value(), "native");
+        assert_eq!(ContentSource::Converted.as_header_value(), "converted");
+        assert_eq!(ContentSource::Api.as_header_value(), "api");
+    }
+
+    #[test]
+    fn test_cached_content_roundtrip() {
+        let mut content = CachedContent::new("# Hi".to_string(), ContentSource::Converted);
+        content.quality = Some(0.5);
+
+        let json = serde_json::to_string(&content).unwrap();
+        assert!(json.contains(r#""source":"converted""#));
+
+        let back: CachedContent = serde_json::from_str(&json).unwrap();
+        assert_eq!(back.markdown, "# Hi");
+        assert_eq!(back.source, ContentSource::Converted);
+        assert_eq!(back.quality, Some(0.5));
+        assert_eq!(back.fetched_at, content.fetched_at);
+    }
+}
@@ -0,0 +1,163 @@
+//! Output styles for markdown knowledge bases
+//!
+//! Crawled pages exported with `?style=obsidian` drop into an Obsidian (or
+//! Logseq) vault as they are: each page is a note at `{host}/{path}.md`,
+//! links between pages of the site become `[[wiki links]]` to those notes,
+//! and images point at `assets/{host}/{path}` in the vault, where the
+//! exporter saves the image found at `https://{host}/{path}`.
+
+use regex::{Captures, Regex};
+use serde::Deserialize;
+use std::sync::LazyLock;
+use url::Url;
+
+/// Inline links and images: `[text](url "title")`, `![alt](<url>)`
+static LINK_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"(?P<bang>!?)\[(?P<text>[^\[\]]*)\]\((?P<url><[^>]*>|[^)\s]+)(?:\s+"[^"]*")?\)"#)
+        .expect("valid regex")
+});
+
+/// How to lay out converted markdown for a particular tool
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputStyle {
+    /// Wiki links, YAML properties and vault-relative image paths
+    Obsidian,
+}
+
+/// Vault path of the note for a page, without the `.md` extension
+///
+/// `https://docs.example.com/guide/install.html?v=2` is
+/// `docs.example.com/guide/install`, and a directory page such as
+/// `https://docs.example.com/guide/` is `docs.example.com/guide/index`.
+pub fn note_path(url: &Url) -> String {
+    let host = url.host_str().unwrap_or_default().to_ascii_lowercase();
+    let mut segments: Vec<String> = url
+        .path_segments()
+        .into_iter()
+        .flatten()
+        .map(|segment| {
+            let segment = percent_decode(segment);
+            // Characters Obsidian doesn't allow in file names
+            segment.replace(['#', '^', '[', ']', '|', ':', '\\'], "-")
+        })
+        .collect();
+    match segments.last_mut() {
+        Some(last) if !last.is_empty() => {
+            for extension in [".html", ".htm", ".md", ".php", ".aspx"] {
+                if let Some(stem) = last.strip_suffix(extension).filter(|s| !s.is_empty()) {
+                    *last = stem.to_string();
+                    break;
+                }
+            }
+        }
+        Some(last) => *last = "index".to_string(),
+        None => segments.push("index".to_string()),
+    }
+    format!("{}/{}", host, segments.join("/"))
+}
+
+/// Vault path of a downloaded image, percent-encoded as a markdown link target
+fn asset_path(url: &Url) -> String {
+    let host = url.host_str().unwrap_or_default().to_ascii_lowercase();
+    format!("assets/{}{}", host, url.path())
+}
+
+fn percent_decode(segment: &str) -> String {
+    url::form_urlencoded::parse(format!("s={}", segment.replace('+', "%2B")).as_bytes())
+        .next()
+        .map(|(_, value)| value.into_owned())
+        .unwrap_or_else(|| segment.to_string())
+}
+
+/// Rewrite the markdown of the page at `page` for an Obsidian vault
+///
+/// Links to other pages on the same host become `[[note|text]]`, and images
+/// on any host point into `assets/`. Fragments are dropped since Obsidian
+/// links headings by text rather than id. Code blocks are left alone.
+pub fn obsidian(markdown: &str, page: &Url) -> String {
+    let host = page.host_str().unwrap_or_default();
+    let mut result = String::with_capacity(markdown.len());
+    let mut in_fence = false;
+    for line in markdown.lines() {
+        if line.trim_start().starts_with("```") {
+            in_fence = !in_fence;
+        }
+        if in_fence || !line.contains("](") {
+            result.push_str(line);
+        } else {
+            let line = LINK_RE.replace_all(line, |caps: &Captures| {
+                let target = caps["url"].trim_start_matches('<').trim_end_matches('>');
+                let target = page
+                    .join(target)
+                    .ok()
+                    .filter(|url| matches!(url.scheme(), "http" | "https"));
+                let Some(target) = target else {
+                    return caps[0].to_string();
+                };
+
+                if !caps["bang"].is_empty() {
+                    return format!("![{}]({})", &caps["text"], asset_path(&target));
+                }
+                let same_host = target
+                    .host_str()
+                    .is_some_and(|h| h.eq_ignore_ascii_case(host));
+                if !same_host {
+                    return caps[0].to_string();
+                }
+                let note = note_path(&target);
+                let text = caps["text"].trim();
+                if text.is_empty() || text == note {
+                    format!("[[{}]]", note)
+                } else {
+                    format!("[[{}|{}]]", note, text.replace('|', "\\|"))
+                }
+            });
+            result.push_str(&line);
+        }
+        result.push('\n');
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(s: &str) -> Url {
+        Url::parse(s).unwrap()
+    }
+
+    #[test]
+    fn test_note_path() {
+        let path = |s| note_path(&url(s));
+        assert_eq!(
+            path("https://Docs.Example.com/guide/install.html?v=2"),
+            "docs.example.com/guide/install"
+        );
+        assert_eq!(
+            path("https://docs.example.com/guide/"),
+            "docs.example.com/guide/index"
+        );
+        assert_eq!(path("https://docs.example.com"), "docs.example.com/index");
+        assert_eq!(path("https://example.com/a%20b/c:d"), "example.com/a b/c-d");
+    }
+
+    #[test]
+    fn test_obsidian_links_and_images() {
+        let page = url("https://docs.example.com/guide/start");
+        let markdown = "See [Install](/guide/install.html#linux) and [the API](api \"API\").\n\
+                        Also [elsewhere](https://other.example.org/x) and [mail](mailto:a@b.c).\n\
+                        ![Logo](https://cdn.example.net/img/logo.png) ![](<../diagram.svg>)\n\
+                        ```\n[kept](/guide/install)\n```\n";
+        assert_eq!(
+            obsidian(markdown, &page),
+            "See [[docs.example.com/guide/install|Install]] and \
+             [[docs.example.com/guide/api|the API]].\n\
+             Also [elsewhere](https://other.example.org/x) and [mail](mailto:a@b.c).\n\
+             ![Logo](assets/cdn.example.net/img/logo.png) \
+             ![](assets/docs.example.com/diagram.svg)\n\
+             ```\n[kept](/guide/install)\n```\n"
+        );
+    }
+}
@@ -0,0 +1,188 @@
+//! Operator output templates
+//!
+//! Knowledge bases want pages in their own shape: Obsidian properties,
+//! Dendron front matter, a fixed heading layout. `OUTPUT_TEMPLATES` names a
+//! directory of [minijinja](https://docs.rs/minijinja) templates, one per
+//! `<name>.jinja` file, and `?template=<name>` renders a page through one:
+//!
+//! ```jinja
+//! ---
+//! title: {{ title | default(url) | tojson }}
+//! source: {{ url }}
+//! {% if metadata.author %}author: {{ metadata.author | tojson }}
+//! {% endif %}tags: [clippings]
+//! ---
+//! {{ body }}
+//! ```
+//!
+//! Templates are parsed at startup, so a syntax error stops the server
+//! rather than failing requests. Values a page doesn't have are left
+//! undefined, which renders as nothing and is false in `{% if %}`.
+
+use minijinja::Environment;
+use serde::Serialize;
+use std::path::Path;
+
+/// Extension of template files in `OUTPUT_TEMPLATES`
+const TEMPLATE_EXTENSION: &str = "jinja";
+
+/// What a template can use
+#[derive(Serialize)]
+pub struct TemplateContext<'a> {
+    /// URL the page was requested as
+    pub url: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<&'a str>,
+    /// The markdown as it would otherwise be served
+    pub body: &'a str,
+    pub metadata: TemplateMetadata<'a>,
+}
+
+/// Page details for templates; dates are RFC 3339
+#[derive(Serialize)]
+pub struct TemplateMetadata<'a> {
+    /// `native`, `converted` or `api`
+    pub source: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub author: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub published: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub modified: Option<String>,
+    /// Left out for deterministic output
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retrieved: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub language: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub license: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub page_type: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_hash: Option<&'a str>,
+}
+
+/// Templates loaded from `OUTPUT_TEMPLATES`, none by default
+pub struct OutputTemplates {
+    env: Environment<'static>,
+}
+
+impl Default for OutputTemplates {
+    fn default() -> Self {
+        // `Environment::default()` would come without the builtin filters
+        let mut env = Environment::new();
+        env.set_keep_trailing_newline(true);
+        Self { env }
+    }
+}
+
+impl OutputTemplates {
+    /// Parse every template in a directory, reporting all that fail
+    pub fn load(dir: &Path) -> Result<Self, Vec<String>> {
+        let entries = std::fs::read_dir(dir)
+            .map_err(|e| vec![format!("cannot read {} ({})", dir.display(), e)])?;
+        let mut templates = Self::default();
+        let mut errors = Vec::new();
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().is_none_or(|ext| ext != TEMPLATE_EXTENSION) {
+                continue;
+            }
+            let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                continue;
+            };
+            let result = std::fs::read_to_string(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|source| {
+                    let name = name.to_string();
+                    templates
+                        .env
+                        .add_template_owned(name, source)
+                        .map_err(|e| e.to_string())
+                });
+            if let Err(e) = result {
+                errors.push(format!("{}: {}", path.display(), e));
+            }
+        }
+        if errors.is_empty() {
+            Ok(templates)
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Names of the loaded templates, sorted
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<_> = self.env.templates().map(|(name, _)| name).collect();
+        names.sort_unstable();
+        names
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.env.get_template(name).is_ok()
+    }
+
+    /// Render a page through a template
+    pub fn render(&self, name: &str, context: &TemplateContext) -> Result<String, String> {
+        let template = self.env.get_template(name).map_err(|e| e.to_string())?;
+        template.render(context).map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context<'a>(body: &'a str, author: Option<&'a str>) -> TemplateContext<'a> {
+        TemplateContext {
+            url: "https://example.com/post",
+            title: Some("A \"quoted\" title"),
+            body,
+            metadata: TemplateMetadata {
+                source: "converted",
+                author,
+                published: Some("2024-03-01T09:30:00Z".to_string()),
+                modified: None,
+                retrieved: None,
+                language: Some("en"),
+                license: None,
+                page_type: None,
+                content_hash: None,
+            },
+        }
+    }
+
+    #[test]
+    fn test_load_and_render() {
+        let dir = std::env::temp_dir().join(format!("mdwnio-templates-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("obsidian.jinja"),
+            "---\ntitle: {{ title | tojson }}\n\
+             {% if metadata.author %}author: {{ metadata.author }}\n{% endif %}\
+             date: {{ metadata.published[:10] }}\n---\n{{ body }}",
+        )
+        .unwrap();
+        std::fs::write(dir.join("notes.txt"), "{{ ignored").unwrap();
+
+        let templates = OutputTemplates::load(&dir).unwrap();
+        assert_eq!(templates.names(), ["obsidian"]);
+        assert!(!templates.contains("notes"));
+        assert_eq!(
+            templates
+                .render("obsidian", &context("# Post\n", None))
+                .unwrap(),
+            "---\ntitle: \"A \\\"quoted\\\" title\"\ndate: 2024-03-01\n---\n# Post\n"
+        );
+        let rendered = templates
+            .render("obsidian", &context("", Some("Ada")))
+            .unwrap();
+        assert!(rendered.contains("author: Ada\n"));
+
+        std::fs::write(dir.join("broken.jinja"), "{% if %}").unwrap();
+        let errors = OutputTemplates::load(&dir).err().unwrap();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].contains("broken.jinja"), "{}", errors[0]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
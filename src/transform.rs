@@ -0,0 +1,320 @@
+//! Conversion of content the client sends in the request body
+//!
+//! These endpoints never fetch anything upstream: `/convert` runs the proxy
+//! route's HTML-to-markdown pipeline on content the caller already has, and
+//! `/render` goes the other way, from markdown to sanitized HTML.
+
+use crate::AppState;
+use axum::{
+    body::Bytes,
+    extract::{DefaultBodyLimit, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::post,
+    Router,
+};
+use mdwnio::cache::{self, CachedContent, ContentSource};
+use mdwnio::convert::{self, PageType};
+use mdwnio::error::{MdwnError, Result};
+use mdwnio::forum;
+use mdwnio::parse::{parse_html_byline, parse_html_metadata, parse_markdown_metadata};
+use mdwnio::podcast;
+use mdwnio::postprocess::Redaction;
+use pulldown_cmark::{Options, Parser};
+use serde::Deserialize;
+use std::sync::LazyLock;
+use url::Url;
+
+/// Base URL for documents sent without one; relative links stay unresolved
+const DEFAULT_BASE_URL: &str = "about:blank";
+
+/// Ammonia's defaults plus the disabled checkboxes task list items render as
+static SANITIZER: LazyLock<ammonia::Builder<'static>> = LazyLock::new(|| {
+    let mut builder = ammonia::Builder::default();
+    builder
+        .add_tags(["input"])
+        .add_tag_attributes("input", ["checked", "disabled"])
+        .set_tag_attribute_value("input", "type", "checkbox");
+    builder
+});
+
+/// Routes that take a request body, limited by `BATCH_TIMEOUT` and `BATCH_MAX_BODY`
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/convert", post(convert_handler))
+        .route("/render", post(render_handler))
+        // The body limit layer applies instead of axum's default
+        .layer(DefaultBodyLimit::disable())
+}
+
+/// Conversion options, as query parameters or the JSON `options` field
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct ConvertOptions {
+    /// URL the HTML came from, for resolving relative links and post-processing rules
+    base_url: Option<String>,
+    /// Convert even if the HTML looks like a JavaScript shell
+    #[serde(deserialize_with = "crate::deserialize_flag")]
+    force_convert: bool,
+    /// `0` to leave out the HTML comment noting converted content
+    #[serde(deserialize_with = "crate::deserialize_optional_flag")]
+    notice: Option<bool>,
+    /// `pii` to redact emails, phone numbers and the operator's patterns
+    redact: Option<Redaction>,
+}
+
+/// JSON body for `POST /convert`
+#[derive(Deserialize)]
+struct ConvertRequest {
+    html: String,
+    base_url: Option<String>,
+    #[serde(default)]
+    options: ConvertOptions,
+}
+
+/// Whether the request body is JSON rather than raw content
+fn is_json(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("application/json"))
+}
+
+/// Convert HTML from the request body to markdown
+///
+/// Takes raw HTML, or JSON `{html, base_url, options}` with
+/// `Content-Type: application/json`.
+async fn convert_handler(
+    State(state): State<AppState>,
+    Query(query): Query<ConvertOptions>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response> {
+    let (html, options) = if is_json(&headers) {
+        let request: ConvertRequest = serde_json::from_slice(&body)
+            .map_err(|e| MdwnError::ParseError(format!("Invalid JSON body: {}", e)))?;
+        let options = ConvertOptions {
+            base_url: request.base_url.or(request.options.base_url),
+            ..request.options
+        };
+        (request.html, options)
+    } else {
+        (String::from_utf8_lossy(&body).into_owned(), query)
+    };
+
+    let base_url = match &options.base_url {
+        Some(base_url) => Url::parse(base_url)
+            .map_err(|e| MdwnError::InvalidUrl(format!("Invalid base_url: {}", e)))?,
+        None => Url::parse(DEFAULT_BASE_URL).expect("valid default base URL"),
+    };
+
+    let content = convert_html(&state, &html, &base_url, &options).await?;
+
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::CONTENT_TYPE,
+        "text/markdown; charset=utf-8"
+            .parse()
+            .expect("valid header value"),
+    );
+    if let Some(quality) = content.quality {
+        headers.insert(
+            "X-Mdwn-Quality",
+            format!("{:.2}", quality)
+                .parse()
+                .expect("valid header value"),
+        );
+    }
+    if let Some(page_type) = content.page_type {
+        let value = page_type
+            .as_header_value()
+            .parse()
+            .expect("valid header value");
+        headers.insert("X-Mdwn-Page-Type", value);
+    }
+    if let Some(hash) = &content.content_hash {
+        crate::insert_hash_headers(&mut headers, hash);
+    }
+    Ok((StatusCode::OK, headers, content.markdown).into_response())
+}
+
+/// Run the proxy route's HTML pipeline without fetching anything
+async fn convert_html(
+    state: &AppState,
+    html: &str,
+    base_url: &Url,
+    options: &ConvertOptions,
+) -> Result<CachedContent> {
+    let quality = convert::assess_html(html);
+    let notice = crate::conversion_notice(state, options.notice);
+
+    let links = convert::index_links(html, base_url);
+    let page_type = if !options.force_convert && convert::is_index_page(&links, &quality) {
+        PageType::Index
+    } else {
+        convert::classify_page(html, base_url)
+    };
+    if page_type != PageType::Index
+        && !options.force_convert
+        && !quality.is_meaningful(state.config.min_content_score)
+    {
+        return Err(MdwnError::NoMarkdown(
+            "HTML has too little content to convert, pass force_convert to convert anyway"
+                .to_string(),
+        ));
+    }
+
+    let title = parse_html_metadata(html).title;
+    let special = match page_type {
+        PageType::Forum => forum::thread_to_markdown(html, base_url, title.as_deref(), notice),
+        PageType::Podcast => podcast::episode_page_to_markdown(html, notice),
+        _ => None,
+    };
+    let mut markdown = match (page_type, special) {
+        (PageType::Index, _) => convert::index_to_markdown(title.as_deref(), &links, notice),
+        (_, Some(special)) => special,
+        _ => {
+            let markdown = convert::html_to_markdown_with_notice(html, base_url, notice)?;
+            match parse_html_byline(html).attribution() {
+                Some(attribution) => convert::with_attribution(markdown, &attribution),
+                None => markdown,
+            }
+        }
+    };
+    if state.config.normalize_text {
+        markdown = convert::normalize_text(&markdown);
+    }
+    if let Some(host) = base_url.host_str() {
+        if !state.config.postprocess.is_empty() {
+            markdown = state.config.postprocess.apply(host, &markdown);
+        }
+    }
+    if let Some(redaction) = options.redact {
+        markdown = state.config.postprocess.redact(redaction, &markdown);
+    }
+
+    // Hashed for the ETag, but not kept in the content store: anyone could
+    // read a client's private document back from /content/{hash}
+    let hash = cache::content_hash(&markdown);
+
+    let mut content = CachedContent::new(markdown, ContentSource::Converted);
+    content.quality = Some(quality.score);
+    content.content_hash = Some(hash);
+    content.page_type = Some(page_type);
+    Ok(content)
+}
+
+/// Rendering options, as query parameters or the JSON `options` field
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct RenderOptions {
+    /// Wrap the fragment in a complete HTML document
+    #[serde(deserialize_with = "crate::deserialize_flag")]
+    standalone: bool,
+    /// Title of the standalone document, defaulting to the markdown's first heading
+    title: Option<String>,
+}
+
+/// JSON body for `POST /render`
+#[derive(Deserialize)]
+struct RenderRequest {
+    markdown: String,
+    #[serde(default)]
+    options: RenderOptions,
+}
+
+/// Render markdown from the request body to sanitized HTML
+///
+/// Takes raw markdown, or JSON `{markdown, options}` with
+/// `Content-Type: application/json`.
+async fn render_handler(
+    Query(query): Query<RenderOptions>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response> {
+    let (markdown, options) = if is_json(&headers) {
+        let request: RenderRequest = serde_json::from_slice(&body)
+            .map_err(|e| MdwnError::ParseError(format!("Invalid JSON body: {}", e)))?;
+        (request.markdown, request.options)
+    } else {
+        (String::from_utf8_lossy(&body).into_owned(), query)
+    };
+
+    let mut html = render_markdown(&markdown);
+    if options.standalone {
+        let title = options
+            .title
+            .or_else(|| parse_markdown_metadata(&markdown).title)
+            .unwrap_or_else(|| "Untitled".to_string());
+        html = standalone_page(&title, &html);
+    }
+
+    Ok((
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, "text/html; charset=utf-8")],
+        html,
+    )
+        .into_response())
+}
+
+/// CommonMark plus GFM tables, strikethrough, task lists and footnotes, with
+/// scripts, event handlers and other unsafe markup stripped
+fn render_markdown(markdown: &str) -> String {
+    let options = Options::ENABLE_TABLES
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_TASKLISTS
+        | Options::ENABLE_FOOTNOTES;
+    let mut html = String::new();
+    pulldown_cmark::html::push_html(&mut html, Parser::new_ext(markdown, options));
+    SANITIZER.clean(&html).to_string()
+}
+
+/// Wrap an HTML fragment in a minimal document
+fn standalone_page(title: &str, body: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n\
+         <title>{}</title>\n</head>\n<body>\n{}</body>\n</html>\n",
+        escape_html(title),
+        body
+    )
+}
+
+/// Escape text for use in HTML element content
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_markdown_sanitizes() {
+        let html = render_markdown("# Title\n\n<script>alert(1)</script>\n\nText");
+        assert!(html.contains("<h1>Title</h1>"));
+        assert!(html.contains("Text"));
+        assert!(!html.contains("<script"));
+    }
+
+    #[test]
+    fn test_render_markdown_keeps_task_checkboxes() {
+        let html = render_markdown("- [x] Done\n- [ ] Todo\n\n<input type=\"text\" name=\"q\">");
+        assert!(html.contains("checked"));
+        assert_eq!(html.matches("type=\"checkbox\"").count(), 3);
+        assert_eq!(html.matches("disabled").count(), 2);
+        assert!(!html.contains("type=\"text\""));
+        assert!(!html.contains("name="));
+    }
+
+    #[test]
+    fn test_standalone_page_escapes_title() {
+        let page = standalone_page("A <b> & C", "<p>x</p>\n");
+        assert!(page.starts_with("<!DOCTYPE html>"));
+        assert!(page.contains("<title>A &lt;b&gt; &amp; C</title>"));
+        assert!(page.contains("<body>\n<p>x</p>\n</body>"));
+    }
+}
@@ -0,0 +1,271 @@
+//! Daily per-domain usage reports written to disk for billing and capacity planning
+
+use crate::stats::{DomainStats, DomainSummary};
+use chrono::{NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// A finished or in-progress daily report
+#[derive(Debug, Deserialize, Serialize)]
+pub struct UsageReport {
+    pub date: NaiveDate,
+    pub domains: Vec<DomainSummary>,
+}
+
+impl UsageReport {
+    /// Render as CSV, one row per domain
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from(
+            "date,domain,requests,cache_hits,cache_hit_rate,errors,error_rate,bytes,avg_bytes\n",
+        );
+        for d in &self.domains {
+            let _ = writeln!(
+                csv,
+                "{},{},{},{},{:.4},{},{:.4},{},{}",
+                self.date,
+                d.domain,
+                d.requests,
+                d.cache_hits,
+                d.cache_hit_rate,
+                d.errors,
+                d.error_rate,
+                d.bytes,
+                d.avg_bytes
+            );
+        }
+        csv
+    }
+}
+
+/// Counters for the current UTC day plus finished days not yet written
+struct Days {
+    today: NaiveDate,
+    stats: DomainStats,
+    finished: Vec<(NaiveDate, DomainStats)>,
+}
+
+impl Days {
+    /// Start counting `now` if the day changed, keeping the finished day to write
+    fn roll_over(&mut self, now: NaiveDate) {
+        if now != self.today {
+            let finished = std::mem::take(&mut self.stats);
+            let date = std::mem::replace(&mut self.today, now);
+            self.finished.push((date, finished));
+        }
+    }
+}
+
+/// Aggregates per-domain usage by UTC day and periodically writes
+/// `usage-YYYY-MM-DD.json` and `.csv` files to a directory
+///
+/// A report already written for today, by an earlier run of the process, is
+/// carried over so a restart adds to it instead of overwriting it.
+#[derive(Clone)]
+pub struct UsageReports {
+    dir: PathBuf,
+    days: Arc<Mutex<Days>>,
+}
+
+impl UsageReports {
+    pub fn new(dir: PathBuf) -> Self {
+        let today = Utc::now().date_naive();
+        let reports = Self {
+            dir,
+            days: Arc::new(Mutex::new(Days {
+                today,
+                stats: DomainStats::new(),
+                finished: Vec::new(),
+            })),
+        };
+        reports.carry_over(today);
+        reports
+    }
+
+    /// Seed today's counters from a report written before a restart
+    fn carry_over(&self, date: NaiveDate) {
+        let path = self.path(date, "json");
+        let Ok(json) = std::fs::read(&path) else {
+            return;
+        };
+        match serde_json::from_slice::<UsageReport>(&json) {
+            Ok(report) => {
+                let days = self.days.lock().expect("usage lock poisoned");
+                for summary in &report.domains {
+                    days.stats.add_summary(summary);
+                }
+            }
+            Err(e) => tracing::warn!("Ignoring unreadable usage report {}: {}", path.display(), e),
+        }
+    }
+
+    /// Counters for today, rolling over to a new day when the date changes
+    pub fn today(&self) -> DomainStats {
+        let mut days = self.days.lock().expect("usage lock poisoned");
+        days.roll_over(Utc::now().date_naive());
+        days.stats.clone()
+    }
+
+    /// Write finished days and the running report for today
+    pub async fn flush(&self) -> std::io::Result<()> {
+        // One lock for the rollover, the date and the counters, so a day
+        // boundary in between can't file one day's counters under the next
+        let (today, current, finished) = {
+            let mut days = self.days.lock().expect("usage lock poisoned");
+            days.roll_over(Utc::now().date_naive());
+            (
+                days.today,
+                days.stats.clone(),
+                std::mem::take(&mut days.finished),
+            )
+        };
+
+        tokio::fs::create_dir_all(&self.dir).await?;
+        for (date, stats) in finished {
+            self.write(date, &stats).await?;
+        }
+        self.write(today, &current).await
+    }
+
+    async fn write(&self, date: NaiveDate, stats: &DomainStats) -> std::io::Result<()> {
+        let report = UsageReport {
+            date,
+            domains: stats.summaries(),
+        };
+        let json = serde_json::to_vec_pretty(&report).map_err(std::io::Error::other)?;
+        tokio::fs::write(self.path(date, "json"), json).await?;
+        tokio::fs::write(self.path(date, "csv"), report.to_csv()).await
+    }
+
+    fn path(&self, date: NaiveDate, extension: &str) -> PathBuf {
+        self.dir.join(format!("usage-{}.{}", date, extension))
+    }
+
+    /// Read a written report, `extension` being `json` or `csv`
+    pub async fn read(&self, date: NaiveDate, extension: &str) -> Option<Vec<u8>> {
+        tokio::fs::read(self.path(date, extension)).await.ok()
+    }
+
+    /// Dates with a written report, newest first
+    pub async fn dates(&self) -> Vec<NaiveDate> {
+        let mut dates = Vec::new();
+        if let Ok(mut entries) = tokio::fs::read_dir(&self.dir).await {
+            while let Ok(Some(entry)) = entries.next_entry().await {
+                let name = entry.file_name();
+                let date = name
+                    .to_str()
+                    .and_then(|n| n.strip_prefix("usage-"))
+                    .and_then(|n| n.strip_suffix(".json"))
+                    .and_then(|d| d.parse::<NaiveDate>().ok());
+                dates.extend(date);
+            }
+        }
+        dates.sort_unstable_by(|a, b| b.cmp(a));
+        dates
+    }
+
+    /// Flush reports every `interval` for the life of the process
+    pub fn spawn(self, interval: Duration) {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                if let Err(e) = self.flush().await {
+                    tracing::error!(
+                        "Failed to write usage report to {}: {}",
+                        self.dir.display(),
+                        e
+                    );
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_flush_and_read() {
+        let dir = std::env::temp_dir().join(format!("mdwnio-usage-{}", std::process::id()));
+        let reports = UsageReports::new(dir.clone());
+        reports.today().record_success("example.com", false, 120);
+        reports.today().record_error("example.com");
+
+        reports.flush().await.unwrap();
+
+        let today = Utc::now().date_naive();
+        assert_eq!(reports.dates().await, vec![today]);
+
+        let json = reports.read(today, "json").await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(json["domains"][0]["domain"], "example.com");
+        assert_eq!(json["domains"][0]["requests"], 2);
+
+        let csv = String::from_utf8(reports.read(today, "csv").await.unwrap()).unwrap();
+        assert!(csv.starts_with("date,domain,"));
+        assert!(csv.contains(",example.com,2,0,0.0000,1,0.5000,120,120\n"));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_roll_over() {
+        let yesterday = NaiveDate::from_ymd_opt(2024, 3, 1).unwrap();
+        let today = yesterday.succ_opt().unwrap();
+        let mut days = Days {
+            today: yesterday,
+            stats: DomainStats::new(),
+            finished: Vec::new(),
+        };
+        days.stats.record_success("example.com", false, 10);
+
+        days.roll_over(yesterday);
+        assert!(days.finished.is_empty());
+
+        days.roll_over(today);
+        assert_eq!(days.today, today);
+        assert!(days.stats.summaries().is_empty());
+        let (date, stats) = &days.finished[0];
+        assert_eq!(*date, yesterday);
+        assert_eq!(stats.summaries()[0].requests, 1);
+    }
+
+    #[tokio::test]
+    async fn test_restart_keeps_todays_counts() {
+        let dir = std::env::temp_dir().join(format!("mdwnio-usage-restart-{}", std::process::id()));
+        let reports = UsageReports::new(dir.clone());
+        reports.today().record_success("example.com", true, 100);
+        reports.today().record_error("broken.example");
+        reports.flush().await.unwrap();
+
+        // A new process on the same day picks up where the last one stopped
+        let restarted = UsageReports::new(dir.clone());
+        restarted.today().record_success("example.com", false, 300);
+        restarted.flush().await.unwrap();
+
+        let today = Utc::now().date_naive();
+        let json = restarted.read(today, "json").await.unwrap();
+        let report: UsageReport = serde_json::from_slice(&json).unwrap();
+        let example = report
+            .domains
+            .iter()
+            .find(|d| d.domain == "example.com")
+            .unwrap();
+        assert_eq!(example.requests, 2);
+        assert_eq!(example.cache_hits, 1);
+        assert_eq!(example.avg_bytes, 200);
+        let broken = report
+            .domains
+            .iter()
+            .find(|d| d.domain == "broken.example")
+            .unwrap();
+        assert_eq!(broken.errors, 1);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
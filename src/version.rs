@@ -0,0 +1,58 @@
+//! Build and version information, embedded at compile time by `build.rs`
+
+use crate::AppState;
+use axum::{extract::State, Json};
+use serde::Serialize;
+
+mod built_info {
+    include!(concat!(env!("OUT_DIR"), "/built.rs"));
+}
+
+/// Response body for `GET /version`
+#[derive(Debug, Serialize)]
+pub struct VersionInfo {
+    pub version: &'static str,
+    pub git_commit: Option<&'static str>,
+    pub git_dirty: Option<bool>,
+    pub built_at: &'static str,
+    pub rustc: &'static str,
+    pub profile: &'static str,
+    /// Cargo features compiled in
+    pub features: Vec<&'static str>,
+    /// Optional integrations enabled by this instance's configuration
+    pub integrations: Vec<&'static str>,
+}
+
+impl VersionInfo {
+    fn new(state: &AppState) -> Self {
+        let mut integrations = Vec::new();
+        if state.config.prerender_url.is_some() {
+            integrations.push("prerender");
+        }
+        if state.config.admin_token.is_some() {
+            integrations.push("admin");
+        }
+
+        Self {
+            version: built_info::PKG_VERSION,
+            git_commit: built_info::GIT_COMMIT_HASH
+                .or(option_env!("MDWN_GIT_COMMIT"))
+                .filter(|c| !c.is_empty()),
+            git_dirty: built_info::GIT_DIRTY,
+            built_at: built_info::BUILT_TIME_UTC,
+            rustc: built_info::RUSTC_VERSION,
+            profile: built_info::PROFILE,
+            features: built_info::FEATURES_LOWERCASE
+                .iter()
+                .copied()
+                .filter(|f| !f.is_empty())
+                .collect(),
+            integrations,
+        }
+    }
+}
+
+/// Version endpoint, for telling instances in a mixed-version fleet apart
+pub async fn version_handler(State(state): State<AppState>) -> Json<VersionInfo> {
+    Json(VersionInfo::new(&state))
+}
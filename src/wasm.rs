@@ -0,0 +1,36 @@
+//! JavaScript bindings for the conversion core, for browser extensions and edge workers
+//!
+//! Build with `wasm-pack build --target web -- --no-default-features --features wasm`.
+
+use crate::convert;
+use crate::parse::{self, HtmlParseResult};
+use url::Url;
+use wasm_bindgen::prelude::*;
+
+fn parse_base_url(base_url: &str) -> Result<Url, JsError> {
+    Url::parse(base_url).map_err(|e| JsError::new(&format!("INVALID_URL: {}", e)))
+}
+
+/// Convert an HTML document to markdown exactly as the mdwn.io server does
+#[wasm_bindgen(js_name = htmlToMarkdown)]
+pub fn html_to_markdown(html: &str, base_url: &str) -> Result<String, JsError> {
+    let base_url = parse_base_url(base_url)?;
+    let markdown = convert::html_to_markdown(html, &base_url)?;
+    Ok(convert::normalize_text(&markdown))
+}
+
+/// URL of the page's `<link rel="alternate" type="text/markdown">`, if any
+#[wasm_bindgen(js_name = findMarkdownLink)]
+pub fn find_markdown_link(html: &str, base_url: &str) -> Result<Option<String>, JsError> {
+    let base_url = parse_base_url(base_url)?;
+    match parse::parse_html_for_markdown_link(html, &base_url, &[])? {
+        HtmlParseResult::MarkdownLink(url) => Ok(Some(url.into())),
+        HtmlParseResult::NeedsConversion => Ok(None),
+    }
+}
+
+/// Content quality score (0-1) used to reject JavaScript shells
+#[wasm_bindgen(js_name = contentScore)]
+pub fn content_score(html: &str) -> f32 {
+    convert::assess_html(html).score
+}
@@ -0,0 +1,375 @@
+//! YouTube videos as their transcript
+//!
+//! Watch pages are JavaScript shells with nothing to convert. The video's
+//! title and channel come from oEmbed and its captions from the `timedtext`
+//! endpoint, rendered as paragraphs that each start with a timestamp linking
+//! back into the video.
+
+use crate::error::{MdwnError, Result};
+use crate::fetch::{FetchBudget, Fetcher};
+use crate::lang::language_matches;
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use serde::Deserialize;
+use url::Url;
+
+const YOUTUBE: &str = "https://www.youtube.com";
+
+/// Where oEmbed and `timedtext` are requested, unless configured otherwise
+pub const DEFAULT_BASE_URL: &str = YOUTUBE;
+
+/// Seconds of captions gathered into one paragraph
+const PARAGRAPH_SECS: f64 = 30.0;
+
+/// A video with its transcript, if it has captions
+#[derive(Debug)]
+pub struct Video {
+    pub id: String,
+    pub title: String,
+    pub channel: Option<String>,
+    pub channel_url: Option<String>,
+    /// Language code of the transcript's caption track
+    pub language: Option<String>,
+    pub transcript: Vec<Caption>,
+}
+
+/// One caption of a transcript
+#[derive(Debug, PartialEq)]
+pub struct Caption {
+    /// Offset into the video in seconds
+    pub start: f64,
+    pub text: String,
+}
+
+/// A caption track listed for a video
+#[derive(Debug, PartialEq)]
+struct Track {
+    lang_code: String,
+    name: String,
+    /// `asr` for automatic captions
+    kind: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct OEmbed {
+    title: String,
+    author_name: Option<String>,
+    author_url: Option<String>,
+}
+
+/// ID of the video behind a watch, short, shorts or embed URL
+pub fn video_id(url: &Url) -> Option<String> {
+    let host = url.host_str()?;
+    let segments: Vec<&str> = url.path_segments()?.filter(|s| !s.is_empty()).collect();
+    let id = match (host, segments.as_slice()) {
+        ("youtu.be", [id]) => id.to_string(),
+        ("youtube.com" | "www.youtube.com" | "m.youtube.com" | "music.youtube.com", path) => {
+            match path {
+                ["watch"] => url
+                    .query_pairs()
+                    .find(|(key, _)| key == "v")
+                    .map(|(_, id)| id.into_owned())?,
+                ["shorts" | "embed" | "live", id] => id.to_string(),
+                _ => return None,
+            }
+        }
+        _ => return None,
+    };
+    // Video IDs are 11 characters of URL-safe base64
+    let valid = id.len() == 11
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    valid.then_some(id)
+}
+
+/// Fetch a video's metadata and transcript from the YouTube at `base`, or
+/// `None` if the URL isn't a video or YouTube doesn't know it
+///
+/// The caption track is the first matching `langs`, preferring captions
+/// written by people over automatic ones; a video without captions is still
+/// returned, with an empty transcript. Failing to get the captions for any
+/// other reason is an error, so a transient failure isn't taken (and cached)
+/// as a video without a transcript.
+pub async fn fetch_video(
+    fetcher: &Fetcher,
+    budget: &FetchBudget,
+    base: &Url,
+    url: &Url,
+    langs: &[String],
+) -> Result<Option<Video>> {
+    let Some(id) = video_id(url) else {
+        return Ok(None);
+    };
+    let watch_url = format!("{}/watch?v={}", YOUTUBE, id);
+    let endpoint = |path: &str, pairs: &[(&str, &str)]| {
+        let mut url = base.clone();
+        url.set_path(&format!("{}/{}", base.path().trim_end_matches('/'), path));
+        url.query_pairs_mut().extend_pairs(pairs);
+        url
+    };
+
+    let oembed_url = endpoint("oembed", &[("url", &watch_url), ("format", "json")]);
+    let oembed: OEmbed = match fetcher.fetch(&oembed_url, budget).await {
+        Ok(response) => match serde_json::from_slice(&response.body) {
+            Ok(oembed) => oembed,
+            Err(_) => return Ok(None),
+        },
+        Err(e) => {
+            tracing::warn!("No oEmbed data for video {}: {}", id, e);
+            return Ok(None);
+        }
+    };
+
+    let mut video = Video {
+        id,
+        title: oembed.title,
+        channel: oembed.author_name,
+        channel_url: oembed.author_url,
+        language: None,
+        transcript: Vec::new(),
+    };
+
+    let list_url = endpoint("api/timedtext", &[("type", "list"), ("v", &video.id)]);
+    let tracks = match fetcher.fetch(&list_url, budget).await {
+        Ok(response) => parse_track_list(&response.body_as_string()),
+        Err(MdwnError::NotFound) => {
+            tracing::debug!("No caption tracks for video {}", video.id);
+            Vec::new()
+        }
+        Err(e) => return Err(e),
+    };
+    let Some(track) = choose_track(&tracks, langs) else {
+        return Ok(Some(video));
+    };
+
+    let mut pairs = vec![
+        ("v", video.id.as_str()),
+        ("lang", track.lang_code.as_str()),
+        ("name", track.name.as_str()),
+    ];
+    if let Some(kind) = &track.kind {
+        pairs.push(("kind", kind));
+    }
+    let transcript_url = endpoint("api/timedtext", &pairs);
+    match fetcher.fetch(&transcript_url, budget).await {
+        Ok(response) => {
+            video.transcript = parse_transcript(&response.body_as_string());
+            video.language = Some(track.lang_code.clone());
+        }
+        Err(MdwnError::NotFound) => {
+            tracing::warn!("Transcript of video {} not found", video.id);
+        }
+        Err(e) => return Err(e),
+    }
+    Ok(Some(video))
+}
+
+/// Track in the first wanted language, people's captions before automatic ones
+fn choose_track<'a>(tracks: &'a [Track], langs: &[String]) -> Option<&'a Track> {
+    let by_hand = |track: &&Track| track.kind.as_deref() != Some("asr");
+    let wanted: Vec<String> = if langs.is_empty() {
+        vec!["en".to_string()]
+    } else {
+        langs.to_vec()
+    };
+    let in_language = |track: &&Track| language_matches(&track.lang_code, &wanted);
+
+    tracks
+        .iter()
+        .filter(in_language)
+        .find(by_hand)
+        .or_else(|| tracks.iter().find(in_language))
+        .or_else(|| tracks.iter().find(by_hand))
+        .or_else(|| tracks.first())
+}
+
+/// Tracks of a `type=list` response
+fn parse_track_list(xml: &str) -> Vec<Track> {
+    let mut reader = Reader::from_str(xml);
+    let mut tracks = Vec::new();
+    loop {
+        match reader.read_event() {
+            Ok(Event::Empty(e)) | Ok(Event::Start(e)) if e.local_name().as_ref() == b"track" => {
+                let attr = |name: &[u8]| {
+                    e.try_get_attribute(name)
+                        .ok()
+                        .flatten()
+                        .and_then(|a| a.unescape_value().ok())
+                        .map(|v| v.into_owned())
+                };
+                if let Some(lang_code) = attr(b"lang_code") {
+                    tracks.push(Track {
+                        lang_code,
+                        name: attr(b"name").unwrap_or_default(),
+                        kind: attr(b"kind"),
+                    });
+                }
+            }
+            Ok(Event::Eof) | Err(_) => break,
+            _ => {}
+        }
+    }
+    tracks
+}
+
+/// Captions of a transcript response
+fn parse_transcript(xml: &str) -> Vec<Caption> {
+    let mut reader = Reader::from_str(xml);
+    let mut captions = Vec::new();
+    let mut start = None;
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(e)) if e.local_name().as_ref() == b"text" => {
+                start = e
+                    .try_get_attribute("start")
+                    .ok()
+                    .flatten()
+                    .and_then(|a| String::from_utf8_lossy(&a.value).parse::<f64>().ok());
+            }
+            Ok(Event::Text(text)) => {
+                let (Some(at), Ok(text)) = (start.take(), text.unescape()) else {
+                    continue;
+                };
+                // Caption text comes HTML-escaped a second time (`&amp;#39;`)
+                let text = quick_xml::escape::unescape(&text)
+                    .map(|t| t.into_owned())
+                    .unwrap_or_else(|_| text.into_owned());
+                let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+                if !text.is_empty() {
+                    captions.push(Caption { start: at, text });
+                }
+            }
+            Ok(Event::Eof) | Err(_) => break,
+            _ => {}
+        }
+    }
+    captions
+}
+
+/// `1:02:03` or `2:03`
+fn timestamp(secs: f64) -> String {
+    let secs = secs as u64;
+    let (hours, minutes, seconds) = (secs / 3600, secs / 60 % 60, secs % 60);
+    if hours > 0 {
+        format!("{}:{:02}:{:02}", hours, minutes, seconds)
+    } else {
+        format!("{}:{:02}", minutes, seconds)
+    }
+}
+
+/// Render a video as its metadata followed by the transcript in timestamped paragraphs
+pub fn video_to_markdown(video: &Video, notice: Option<&str>) -> String {
+    let mut markdown = String::new();
+    if let Some(notice) = notice {
+        markdown.push_str(&format!("<!-- {} -->\n\n", notice));
+    }
+    markdown.push_str(&format!("# {}\n\n", video.title));
+    match (&video.channel, &video.channel_url) {
+        (Some(channel), Some(url)) => {
+            markdown.push_str(&format!("- Channel: [{}]({})\n", channel, url))
+        }
+        (Some(channel), None) => markdown.push_str(&format!("- Channel: {}\n", channel)),
+        _ => {}
+    }
+    markdown.push_str(&format!(
+        "- Video: {}/watch?v={}\n\n## Transcript\n\n",
+        YOUTUBE, video.id
+    ));
+
+    if video.transcript.is_empty() {
+        markdown.push_str("*No transcript available.*\n");
+        return markdown;
+    }
+    let mut paragraph_start: Option<f64> = None;
+    for caption in &video.transcript {
+        match paragraph_start {
+            Some(start) if caption.start - start < PARAGRAPH_SECS => markdown.push(' '),
+            _ => {
+                if paragraph_start.is_some() {
+                    markdown.push_str("\n\n");
+                }
+                paragraph_start = Some(caption.start);
+                markdown.push_str(&format!(
+                    "[{}](https://youtu.be/{}?t={}) ",
+                    timestamp(caption.start),
+                    video.id,
+                    caption.start as u64
+                ));
+            }
+        }
+        markdown.push_str(&caption.text);
+    }
+    markdown.push('\n');
+    markdown
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_video_id() {
+        let id = |url: &str| video_id(&Url::parse(url).unwrap());
+        let expected = Some("dQw4w9WgXcQ".to_string());
+        assert_eq!(
+            id("https://www.youtube.com/watch?v=dQw4w9WgXcQ&t=42s"),
+            expected
+        );
+        assert_eq!(id("https://youtu.be/dQw4w9WgXcQ?si=abc"), expected);
+        assert_eq!(id("https://m.youtube.com/shorts/dQw4w9WgXcQ"), expected);
+        assert_eq!(id("https://www.youtube.com/embed/dQw4w9WgXcQ"), expected);
+        assert_eq!(id("https://www.youtube.com/@channel"), None);
+        assert_eq!(id("https://www.youtube.com/watch?v=short"), None);
+        assert_eq!(id("https://example.com/watch?v=dQw4w9WgXcQ"), None);
+    }
+
+    #[test]
+    fn test_tracks_and_transcript() {
+        let list = r#"<?xml version="1.0" encoding="utf-8" ?><transcript_list docid="1">
+            <track id="0" name="" lang_code="de" lang_original="Deutsch"/>
+            <track id="1" name="" lang_code="en" kind="asr" lang_original="English"/>
+            <track id="2" name="CC" lang_code="en-GB" lang_original="English (UK)"/>
+            </transcript_list>"#;
+        let tracks = parse_track_list(list);
+        assert_eq!(tracks.len(), 3);
+        assert_eq!(tracks[1].kind.as_deref(), Some("asr"));
+
+        let wanted = |langs: &[&str]| {
+            let langs: Vec<String> = langs.iter().map(|l| l.to_string()).collect();
+            choose_track(&tracks, &langs).map(|t| t.lang_code.as_str())
+        };
+        assert_eq!(wanted(&[]), Some("en-GB"));
+        assert_eq!(wanted(&["de"]), Some("de"));
+        assert_eq!(wanted(&["fr"]), Some("de"));
+
+        let xml = r#"<?xml version="1.0" encoding="utf-8" ?><transcript>
+            <text start="0.5" dur="2">Hello and welcome</text>
+            <text start="3.1" dur="2">it&amp;#39;s   a test</text>
+            <text start="65.2" dur="2">Later on</text></transcript>"#;
+        let transcript = parse_transcript(xml);
+        assert_eq!(
+            transcript[1],
+            Caption {
+                start: 3.1,
+                text: "it's a test".to_string()
+            }
+        );
+
+        let video = Video {
+            id: "dQw4w9WgXcQ".to_string(),
+            title: "Test video".to_string(),
+            channel: Some("Tester".to_string()),
+            channel_url: None,
+            language: Some("en".to_string()),
+            transcript,
+        };
+        assert_eq!(
+            video_to_markdown(&video, None),
+            "# Test video\n\n- Channel: Tester\n\
+             - Video: https://www.youtube.com/watch?v=dQw4w9WgXcQ\n\n## Transcript\n\n\
+             [0:00](https://youtu.be/dQw4w9WgXcQ?t=0) Hello and welcome it's a test\n\n\
+             [1:05](https://youtu.be/dQw4w9WgXcQ?t=65) Later on\n"
+        );
+    }
+}